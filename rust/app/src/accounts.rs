@@ -0,0 +1,667 @@
+use std::collections::BTreeSet;
+
+use leptos::{prelude::*, server_fn::codec::Json};
+use leptos_router::hooks::use_query_map;
+use types::accounts::{ApiTokenSummary, Grants, Role, UserSummary};
+
+use crate::{
+  i18n::Text,
+  preferences::use_preferences,
+  settings::{field_errors, FieldMessage, SaveStatus, Section, SettingsError},
+};
+
+/// The signed in account and the account store of the current request, if
+/// the account has at least `role`.
+#[cfg(feature = "ssr")]
+pub(crate) fn require_role(
+  role: Role,
+) -> Result<(crate::auth::Identity, crate::auth::Auth), SettingsError> {
+  let identity = crate::auth::current_user().ok_or(SettingsError::Unauthorized)?;
+  if !identity.has_role(role) {
+    return Err(SettingsError::Forbidden);
+  }
+  let auth = use_context::<crate::auth::Auth>()
+    .ok_or_else(|| SettingsError::Server("accounts are not available".to_string()))?;
+  Ok((identity, auth))
+}
+
+/// Starts a browser session. Posted by the login form, so it takes the
+/// default URL encoded input and works before hydration.
+#[server(endpoint = "login")]
+pub async fn login(
+  username: String,
+  password: String,
+  next: Option<String>,
+) -> Result<(), SettingsError> {
+  use http::{header::SET_COOKIE, HeaderValue};
+
+  let auth = use_context::<crate::auth::Auth>()
+    .ok_or_else(|| SettingsError::Server("accounts are not available".to_string()))?;
+  let audit = use_context::<crate::audit::AuditLog>();
+  if !auth.verify_password(&username, &password) {
+    if let Some(audit) = audit {
+      audit
+        .record(&username, types::audit::AuditAction::LoginFailed, None, "")
+        .await;
+    }
+    return Err(SettingsError::Unauthorized);
+  }
+  if let Some(audit) = audit {
+    audit
+      .record(&username, types::audit::AuditAction::Login, None, "")
+      .await;
+  }
+  let token = auth.start_session(&username);
+  let secure = use_context::<http::request::Parts>()
+    .is_some_and(|parts| crate::auth::is_https(&parts.headers));
+  let cookie = HeaderValue::from_str(&crate::auth::session_set_cookie(Some(&token), secure))
+    .map_err(|e| SettingsError::Server(e.to_string()))?;
+  expect_context::<leptos_axum::ResponseOptions>().append_header(SET_COOKIE, cookie);
+  leptos_axum::redirect(crate::auth::safe_next(next.as_deref()));
+  Ok(())
+}
+
+#[server]
+pub async fn logout() -> Result<(), SettingsError> {
+  use http::{header::SET_COOKIE, request::Parts, HeaderValue};
+
+  let parts = use_context::<Parts>();
+  if let (Some(auth), Some(parts)) = (use_context::<crate::auth::Auth>(), &parts) {
+    if let Some(token) = crate::auth::session_cookie(&parts.headers) {
+      crate::audit::record(types::audit::AuditAction::Logout, None, "").await;
+      auth.end_session(token);
+    }
+  }
+  let secure = parts.is_some_and(|parts| crate::auth::is_https(&parts.headers));
+  expect_context::<leptos_axum::ResponseOptions>().append_header(
+    SET_COOKIE,
+    HeaderValue::from_str(&crate::auth::session_set_cookie(None, secure))
+      .map_err(|e| SettingsError::Server(e.to_string()))?,
+  );
+  leptos_axum::redirect("/login");
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn list_users() -> Result<Vec<UserSummary>, SettingsError> {
+  let (_, auth) = require_role(Role::Admin)?;
+  Ok(auth.users())
+}
+
+#[cfg(feature = "ssr")]
+fn validate_grants(username: &str, grants: &Grants, errors: &mut types::settings::FieldErrors) {
+  for device_id in &grants.devices {
+    if let Err(message) = types::settings::validate_device_id(device_id) {
+      errors.push(
+        format!("users.{username}.devices"),
+        format!("{device_id} {message}"),
+      );
+    }
+  }
+}
+
+#[cfg(feature = "ssr")]
+fn describe_grants(grants: &Grants) -> String {
+  if grants.all {
+    return "all devices".to_string();
+  }
+  let devices = grants
+    .devices
+    .iter()
+    .cloned()
+    .collect::<Vec<_>>()
+    .join(", ");
+  let locations = grants
+    .locations
+    .iter()
+    .cloned()
+    .collect::<Vec<_>>()
+    .join(", ");
+  format!("devices [{devices}], locations [{locations}]")
+}
+
+#[server(input = Json)]
+pub async fn add_user(
+  username: String,
+  password: String,
+  role: Role,
+  grants: Grants,
+) -> Result<(), SettingsError> {
+  let (_, auth) = require_role(Role::Admin)?;
+  let mut errors = types::settings::FieldErrors::default();
+  let username = username.trim().to_string();
+  if let Err(message) = types::accounts::validate_username(&username) {
+    errors.push("username", message);
+  } else if auth.has_user(&username) {
+    errors.push("username", "already exists");
+  }
+  if let Err(message) = types::accounts::validate_password(&password) {
+    errors.push("password", message);
+  }
+  validate_grants(&username, &grants, &mut errors);
+  errors.into_result()?;
+  auth.create_user(&username, &password, role, grants)?;
+  crate::audit::record(
+    types::audit::AuditAction::UserAdded,
+    None,
+    format!("{username} as {role}"),
+  )
+  .await;
+  Ok(())
+}
+
+/// Changes the role and device grants of another account.
+#[server(input = Json)]
+pub async fn set_user_access(
+  username: String,
+  role: Role,
+  grants: Grants,
+) -> Result<(), SettingsError> {
+  let (identity, auth) = require_role(Role::Admin)?;
+  let mut errors = types::settings::FieldErrors::default();
+  // Keeps at least one admin around.
+  if identity.username == username && role != Role::Admin {
+    errors.push(
+      format!("users.{username}"),
+      "you can't demote your own account",
+    );
+  }
+  validate_grants(&username, &grants, &mut errors);
+  errors.into_result()?;
+  let detail = format!("{username} as {role}, {}", describe_grants(&grants));
+  auth.set_access(&username, role, grants)?;
+  crate::audit::record(types::audit::AuditAction::UserAccessChanged, None, detail).await;
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn remove_user(username: String) -> Result<(), SettingsError> {
+  let (identity, auth) = require_role(Role::Admin)?;
+  if identity.username == username {
+    let mut errors = types::settings::FieldErrors::default();
+    errors.push(
+      format!("users.{username}"),
+      "you can't remove your own account",
+    );
+    return Err(errors.into());
+  }
+  auth.remove_user(&username)?;
+  crate::audit::record(types::audit::AuditAction::UserRemoved, None, username).await;
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn change_password(
+  current_password: String,
+  new_password: String,
+) -> Result<(), SettingsError> {
+  let (identity, auth) = require_role(Role::Viewer)?;
+  let mut errors = types::settings::FieldErrors::default();
+  if !auth.verify_password(&identity.username, &current_password) {
+    errors.push("current_password", "is incorrect");
+  }
+  if let Err(message) = types::accounts::validate_password(&new_password) {
+    errors.push("new_password", message);
+  }
+  errors.into_result()?;
+  auth.set_password(&identity.username, &new_password)?;
+  crate::audit::record(types::audit::AuditAction::PasswordChanged, None, "").await;
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn list_tokens() -> Result<Vec<ApiTokenSummary>, SettingsError> {
+  let (identity, auth) = require_role(Role::Viewer)?;
+  Ok(auth.tokens(&identity.username))
+}
+
+/// Creates an API token for the signed in account and returns its secret.
+#[server(input = Json)]
+pub async fn create_token(name: String) -> Result<String, SettingsError> {
+  let (identity, auth) = require_role(Role::Viewer)?;
+  if let Err(message) = types::accounts::validate_token_name(&name) {
+    let mut errors = types::settings::FieldErrors::default();
+    errors.push("token_name", message);
+    return Err(errors.into());
+  }
+  let secret = auth.create_token(&identity.username, &name)?;
+  crate::audit::record(types::audit::AuditAction::TokenCreated, None, name.trim()).await;
+  Ok(secret)
+}
+
+#[server(input = Json)]
+pub async fn revoke_token(id: String) -> Result<(), SettingsError> {
+  let (identity, auth) = require_role(Role::Viewer)?;
+  auth.revoke_token(&identity.username, &id)?;
+  crate::audit::record(types::audit::AuditAction::TokenRevoked, None, id).await;
+  Ok(())
+}
+
+#[component]
+pub fn LoginPage() -> impl IntoView {
+  let preferences = use_preferences();
+  let login = ServerAction::<Login>::new();
+  let query = use_query_map();
+  let next = move || query.with(|query| query.get("next")).unwrap_or_default();
+  view! {
+    <div class="flex flex-col items-center justify-center min-h-screen bg-base-100 p-4">
+      <ActionForm
+        action=login
+        attr:class="card w-full max-w-sm bg-base-100 shadow-xl border border-base-200"
+      >
+        <div class="card-body gap-4">
+          <h1 class="card-title text-2xl">{move || preferences.t(Text::SignIn)}</h1>
+          <input type="hidden" name="next" prop:value=next />
+          <label class="form-control">
+            <span class="label-text">{move || preferences.t(Text::Username)}</span>
+            <input class="input input-bordered" name="username" autocomplete="username" required />
+          </label>
+          <label class="form-control">
+            <span class="label-text">{move || preferences.t(Text::Password)}</span>
+            <input
+              class="input input-bordered"
+              type="password"
+              name="password"
+              autocomplete="current-password"
+              required
+            />
+          </label>
+          <Show when=move || matches!(login.value().get(), Some(Err(_)))>
+            <span class="text-error">{move || preferences.t(Text::InvalidCredentials)}</span>
+          </Show>
+          <button class="btn btn-primary" type="submit" disabled=login.pending()>
+            {move || preferences.t(Text::SignIn)}
+          </button>
+        </div>
+      </ActionForm>
+    </div>
+  }
+}
+
+#[component]
+pub fn SignOut() -> impl IntoView {
+  let preferences = use_preferences();
+  let logout = ServerAction::<Logout>::new();
+  view! {
+    <ActionForm action=logout>
+      <button class="btn btn-sm btn-ghost" type="submit">
+        {move || preferences.t(Text::SignOut)}
+      </button>
+    </ActionForm>
+  }
+}
+
+/// Password and API tokens of the signed in account.
+#[component]
+pub(crate) fn AccountSection() -> impl IntoView {
+  let preferences = use_preferences();
+  let current_password = RwSignal::new(String::new());
+  let new_password = RwSignal::new(String::new());
+  let change = ServerAction::<ChangePassword>::new();
+  let change_errors = Signal::derive(move || field_errors(change.value().get()));
+
+  let token_name = RwSignal::new(String::new());
+  let create = ServerAction::<CreateToken>::new();
+  let revoke = ServerAction::<RevokeToken>::new();
+  let create_errors = Signal::derive(move || match create.value().get() {
+    Some(Err(SettingsError::Invalid(errors))) => errors,
+    _ => Default::default(),
+  });
+  let tokens = Resource::new(
+    move || (create.version().get(), revoke.version().get()),
+    |_| list_tokens(),
+  );
+
+  view! {
+    <Section title=Text::Account>
+      <h3 class="font-bold">{move || preferences.t(Text::ChangePassword)}</h3>
+      <div class="flex flex-wrap gap-4 items-start">
+        <label class="form-control">
+          <span class="label-text">{move || preferences.t(Text::CurrentPassword)}</span>
+          <input
+            class="input input-bordered input-sm"
+            type="password"
+            autocomplete="current-password"
+            prop:value=current_password
+            on:input=move |ev| current_password.set(event_target_value(&ev))
+          />
+          <FieldMessage errors=change_errors field="current_password" />
+        </label>
+        <label class="form-control">
+          <span class="label-text">{move || preferences.t(Text::NewPassword)}</span>
+          <input
+            class="input input-bordered input-sm"
+            type="password"
+            autocomplete="new-password"
+            prop:value=new_password
+            on:input=move |ev| new_password.set(event_target_value(&ev))
+          />
+          <FieldMessage errors=change_errors field="new_password" />
+        </label>
+      </div>
+      <div class="card-actions items-center justify-end">
+        <SaveStatus result=change.value().into() />
+        <button
+          class="btn btn-primary"
+          disabled=change.pending()
+          on:click=move |_| {
+            change.dispatch(ChangePassword {
+              current_password: current_password.get_untracked(),
+              new_password: new_password.get_untracked(),
+            });
+          }
+        >
+          {move || preferences.t(Text::Save)}
+        </button>
+      </div>
+
+      <h3 class="font-bold">{move || preferences.t(Text::ApiTokens)}</h3>
+      <table class="table">
+        <thead>
+          <tr>
+            <th>{move || preferences.t(Text::TokenName)}</th>
+            <th>{move || preferences.t(Text::Created)}</th>
+            <th></th>
+          </tr>
+        </thead>
+        <tbody>
+          <Transition>
+            {move || {
+              tokens
+                .get()
+                .and_then(Result::ok)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|token| {
+                  let id = token.id.clone();
+                  view! {
+                    <tr>
+                      <td>{token.name}</td>
+                      <td>{preferences.format_timestamp(token.created_at)}</td>
+                      <td>
+                        <button
+                          class="btn btn-sm btn-ghost"
+                          type="button"
+                          on:click=move |_| {
+                            revoke.dispatch(RevokeToken { id: id.clone() });
+                          }
+                        >
+                          {move || preferences.t(Text::Revoke)}
+                        </button>
+                      </td>
+                    </tr>
+                  }
+                })
+                .collect_view()
+            }}
+          </Transition>
+        </tbody>
+      </table>
+      <div class="join">
+        <input
+          class="input input-bordered input-sm join-item"
+          placeholder=move || preferences.t(Text::TokenName)
+          prop:value=token_name
+          on:input=move |ev| token_name.set(event_target_value(&ev))
+        />
+        <button
+          class="btn btn-sm join-item"
+          type="button"
+          disabled=create.pending()
+          on:click=move |_| {
+            create.dispatch(CreateToken {
+              name: token_name.get_untracked(),
+            });
+            token_name.set(String::new());
+          }
+        >
+          {move || preferences.t(Text::CreateToken)}
+        </button>
+      </div>
+      <FieldMessage errors=create_errors field="token_name" />
+      {move || match create.value().get() {
+        Some(Ok(secret)) => {
+          view! {
+            <div class="alert alert-info flex flex-col items-start">
+              <span>{preferences.t(Text::TokenCreated)}</span>
+              <code class="font-mono break-all select-all">{secret}</code>
+            </div>
+          }
+            .into_any()
+        }
+        Some(Err(SettingsError::Invalid(_))) | None => ().into_any(),
+        Some(Err(e)) => view! { <span class="text-error">{e.to_string()}</span> }.into_any(),
+      }}
+    </Section>
+  }
+}
+
+/// Accounts that can sign in to the dashboard, with their role and the
+/// devices they may see.
+#[component]
+pub(crate) fn UsersSection() -> impl IntoView {
+  let preferences = use_preferences();
+  let username = RwSignal::new(String::new());
+  let password = RwSignal::new(String::new());
+  let role = RwSignal::new(Role::Viewer);
+  let add = ServerAction::<AddUser>::new();
+  let update = ServerAction::<SetUserAccess>::new();
+  let remove = ServerAction::<RemoveUser>::new();
+  let add_errors = Signal::derive(move || field_errors(add.value().get()));
+  let row_errors = Signal::derive(move || {
+    let mut errors = field_errors(update.value().get());
+    errors.0.extend(field_errors(remove.value().get()).0);
+    errors
+  });
+  let users = Resource::new(
+    move || {
+      (
+        add.version().get(),
+        update.version().get(),
+        remove.version().get(),
+      )
+    },
+    |_| list_users(),
+  );
+
+  view! {
+    <Section title=Text::Users>
+      <table class="table">
+        <thead>
+          <tr>
+            <th>{move || preferences.t(Text::Username)}</th>
+            <th>{move || preferences.t(Text::Role)}</th>
+            <th>{move || preferences.t(Text::AllDevices)}</th>
+            <th>{move || preferences.t(Text::Devices)}</th>
+            <th>{move || preferences.t(Text::Locations)}</th>
+            <th></th>
+          </tr>
+        </thead>
+        <tbody>
+          <Transition>
+            {move || {
+              users
+                .get()
+                .and_then(Result::ok)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|user| view! { <UserRow user update remove errors=row_errors /> })
+                .collect_view()
+            }}
+          </Transition>
+        </tbody>
+      </table>
+      <div class="flex flex-wrap gap-4 items-start">
+        <label class="form-control">
+          <span class="label-text">{move || preferences.t(Text::Username)}</span>
+          <input
+            class="input input-bordered input-sm"
+            autocomplete="off"
+            prop:value=username
+            on:input=move |ev| username.set(event_target_value(&ev))
+          />
+          <FieldMessage errors=add_errors field="username" />
+        </label>
+        <label class="form-control">
+          <span class="label-text">{move || preferences.t(Text::Password)}</span>
+          <input
+            class="input input-bordered input-sm"
+            type="password"
+            autocomplete="new-password"
+            prop:value=password
+            on:input=move |ev| password.set(event_target_value(&ev))
+          />
+          <FieldMessage errors=add_errors field="password" />
+        </label>
+        <label class="form-control">
+          <span class="label-text">{move || preferences.t(Text::Role)}</span>
+          <RoleSelect role />
+        </label>
+      </div>
+      <div class="card-actions items-center justify-end">
+        <SaveStatus result=add.value().into() />
+        <button
+          class="btn btn-primary"
+          disabled=add.pending()
+          on:click=move |_| {
+            add.dispatch(AddUser {
+              username: username.get_untracked(),
+              password: password.get_untracked(),
+              role: role.get_untracked(),
+              grants: Grants::default(),
+            });
+          }
+        >
+          {move || preferences.t(Text::AddUser)}
+        </button>
+      </div>
+    </Section>
+  }
+}
+
+#[component]
+fn UserRow(
+  user: UserSummary,
+  update: ServerAction<SetUserAccess>,
+  remove: ServerAction<RemoveUser>,
+  errors: Signal<types::settings::FieldErrors>,
+) -> impl IntoView {
+  let preferences = use_preferences();
+  let username = user.username;
+  let role = RwSignal::new(user.role);
+  let all = RwSignal::new(user.grants.all);
+  let devices = RwSignal::new(join_list(&user.grants.devices));
+  let locations = RwSignal::new(join_list(&user.grants.locations));
+  let list_input = move |list: RwSignal<String>| {
+    view! {
+      <input
+        class="input input-bordered input-sm w-full"
+        placeholder="a, b"
+        disabled=move || all.get() || role.get() == Role::Admin
+        prop:value=list
+        on:change=move |ev| list.set(event_target_value(&ev))
+      />
+    }
+  };
+  let save = {
+    let username = username.clone();
+    move |_| {
+      update.dispatch(SetUserAccess {
+        username: username.clone(),
+        role: role.get_untracked(),
+        grants: Grants {
+          all: all.get_untracked(),
+          devices: parse_list(&devices.get_untracked()),
+          locations: parse_list(&locations.get_untracked()),
+        },
+      });
+    }
+  };
+  let delete = {
+    let username = username.clone();
+    move |_| {
+      remove.dispatch(RemoveUser {
+        username: username.clone(),
+      });
+    }
+  };
+  view! {
+    <tr>
+      <td class="font-mono">
+        {username.clone()}
+        <br />
+        <FieldMessage errors field=format!("users.{username}") />
+      </td>
+      <td>
+        <RoleSelect role />
+      </td>
+      <td>
+        <input
+          type="checkbox"
+          class="toggle toggle-sm"
+          disabled=move || role.get() == Role::Admin
+          prop:checked=all
+          on:change=move |ev| all.set(event_target_checked(&ev))
+        />
+      </td>
+      <td>
+        {list_input(devices)}
+        <FieldMessage errors field=format!("users.{username}.devices") />
+      </td>
+      <td>{list_input(locations)}</td>
+      <td class="flex gap-1">
+        <button class="btn btn-sm" type="button" disabled=update.pending() on:click=save>
+          {move || preferences.t(Text::Save)}
+        </button>
+        <button class="btn btn-sm btn-ghost" type="button" on:click=delete>
+          {move || preferences.t(Text::Remove)}
+        </button>
+      </td>
+    </tr>
+  }
+}
+
+#[component]
+fn RoleSelect(role: RwSignal<Role>) -> impl IntoView {
+  let preferences = use_preferences();
+  view! {
+    <select
+      class="select select-bordered select-sm"
+      prop:value=move || role.get().to_string()
+      on:change=move |ev| {
+        if let Ok(value) = event_target_value(&ev).parse::<Role>() {
+          role.set(value);
+        }
+      }
+    >
+      {Role::ALL
+        .into_iter()
+        .map(|value| {
+          view! { <option value=value.to_string()>{move || preferences.t(role_text(value))}</option> }
+        })
+        .collect_view()}
+    </select>
+  }
+}
+
+fn role_text(role: Role) -> Text {
+  match role {
+    Role::Viewer => Text::Viewer,
+    Role::Operator => Text::Operator,
+    Role::Admin => Text::Admin,
+  }
+}
+
+fn join_list(items: &BTreeSet<String>) -> String {
+  items.iter().cloned().collect::<Vec<_>>().join(", ")
+}
+
+/// Comma separated entries, blanks dropped.
+fn parse_list(value: &str) -> BTreeSet<String> {
+  value
+    .split(',')
+    .map(str::trim)
+    .filter(|item| !item.is_empty())
+    .map(str::to_string)
+    .collect()
+}
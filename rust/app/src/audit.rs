@@ -0,0 +1,352 @@
+use leptos::{prelude::*, server_fn::codec::Json};
+use types::audit::{AuditAction, AuditEntry, AuditFilter};
+
+use crate::{
+  i18n::Text,
+  preferences::use_preferences,
+  settings::{Section, SettingsError},
+  util::percent_encode,
+};
+
+/// Most entries the viewer shows at once, the export has no limit.
+#[cfg(feature = "ssr")]
+const VIEWER_LIMIT: usize = 500;
+
+#[cfg(feature = "ssr")]
+pub use file::AuditLog;
+
+#[cfg(feature = "ssr")]
+mod file {
+  use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+  };
+
+  use tracing::warn;
+  use types::audit::{AuditAction, AuditEntry, AuditFilter};
+
+  /// Append-only NDJSON file of [`AuditEntry`]s. Entries are never edited or
+  /// removed, not even by retention.
+  #[derive(Debug, Clone)]
+  pub struct AuditLog {
+    path: Arc<PathBuf>,
+    /// Serializes appends so lines never interleave.
+    lock: Arc<Mutex<()>>,
+  }
+
+  impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+      Self {
+        path: Arc::new(path.into()),
+        lock: Arc::default(),
+      }
+    }
+
+    /// Appends an entry stamped with the current time, on the blocking pool.
+    /// Failures are logged rather than failing the action being audited.
+    pub async fn record(
+      &self,
+      actor: &str,
+      action: AuditAction,
+      device_id: Option<&str>,
+      detail: impl Into<String>,
+    ) {
+      let log = self.clone();
+      let entry = entry(actor, action, device_id, detail.into());
+      let result = tokio::task::spawn_blocking(move || log.append(&entry))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+      if let Err(e) = result {
+        warn!(target = "audit", case = "append", "{:?}", e);
+      }
+    }
+
+    /// [`AuditLog::record`] for callers outside the runtime, e.g. the CLI.
+    pub fn record_blocking(
+      &self,
+      actor: &str,
+      action: AuditAction,
+      device_id: Option<&str>,
+      detail: impl Into<String>,
+    ) {
+      if let Err(e) = self.append(&entry(actor, action, device_id, detail.into())) {
+        warn!(target = "audit", case = "append", "{:?}", e);
+      }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> io::Result<()> {
+      let mut line = serde_json::to_vec(entry)?;
+      line.push(b'\n');
+      let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+      if let Some(parent) = self.path.parent() {
+        fs::create_dir_all(parent)?;
+      }
+      OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(self.path.as_ref())?
+        .write_all(&line)
+    }
+
+    /// Entries matching `filter`, oldest first, read on the blocking pool.
+    pub async fn entries(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>> {
+      let log = self.clone();
+      let filter = filter.clone();
+      tokio::task::spawn_blocking(move || log.read(&filter))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+    }
+
+    fn read(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEntry>> {
+      let file = match fs::File::open(self.path.as_ref()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+      };
+      let mut entries = Vec::new();
+      for line in BufReader::new(file).lines() {
+        match serde_json::from_str::<AuditEntry>(&line?) {
+          Ok(entry) if filter.matches(&entry) => entries.push(entry),
+          Ok(_) => {}
+          Err(e) => warn!(target = "audit", case = "read", "{:?}", e),
+        }
+      }
+      Ok(entries)
+    }
+  }
+
+  fn entry(
+    actor: &str,
+    action: AuditAction,
+    device_id: Option<&str>,
+    detail: String,
+  ) -> AuditEntry {
+    AuditEntry {
+      timestamp: chrono::Utc::now().timestamp() as u64,
+      actor: actor.to_string(),
+      action,
+      device_id: device_id.map(str::to_string),
+      detail,
+    }
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_are_appended_and_filtered() {
+      let path =
+        std::env::temp_dir().join(format!("hat-monitor-audit-{}.ndjson", std::process::id()));
+      let _ = fs::remove_file(&path);
+      let log = AuditLog::new(&path);
+      assert!(log
+        .entries(&AuditFilter::default())
+        .await
+        .unwrap()
+        .is_empty());
+      log.record("admin", AuditAction::Login, None, "").await;
+      log.record_blocking(
+        "cli",
+        AuditAction::ClaimCodeCreated,
+        Some("hat-1"),
+        "expires 0",
+      );
+      let all = log.entries(&AuditFilter::default()).await.unwrap();
+      assert_eq!(
+        all.iter().map(|entry| entry.action).collect::<Vec<_>>(),
+        [AuditAction::Login, AuditAction::ClaimCodeCreated]
+      );
+      let filter = AuditFilter {
+        device: Some("hat-1".to_string()),
+        ..AuditFilter::default()
+      };
+      let entries = log.entries(&filter).await.unwrap();
+      assert_eq!(entries.len(), 1);
+      assert_eq!(entries[0].actor, "cli");
+      fs::remove_file(&path).unwrap();
+    }
+  }
+}
+
+/// Records `action` by the signed in account of the current request.
+#[cfg(feature = "ssr")]
+pub(crate) async fn record(
+  action: AuditAction,
+  device_id: Option<&str>,
+  detail: impl Into<String>,
+) {
+  if let (Some(identity), Some(audit)) = (crate::auth::current_user(), use_context::<AuditLog>()) {
+    audit
+      .record(&identity.username, action, device_id, detail)
+      .await;
+  }
+}
+
+/// The newest entries matching `filter`, newest first.
+#[server(input = Json)]
+pub async fn get_audit_log(filter: AuditFilter) -> Result<Vec<AuditEntry>, SettingsError> {
+  crate::accounts::require_role(types::accounts::Role::Admin)?;
+  let audit = use_context::<AuditLog>()
+    .ok_or_else(|| SettingsError::Server("audit log is not available".to_string()))?;
+  let mut entries = audit
+    .entries(&filter)
+    .await
+    .map_err(|e| SettingsError::Server(e.to_string()))?;
+  entries.reverse();
+  entries.truncate(VIEWER_LIMIT);
+  Ok(entries)
+}
+
+/// Query string of the JSON export matching `filter`.
+fn export_href(filter: &AuditFilter) -> String {
+  let mut params = Vec::new();
+  if let Some(actor) = &filter.actor {
+    params.push(format!("actor={}", percent_encode(actor)));
+  }
+  if let Some(action) = filter.action {
+    params.push(format!("action={action}"));
+  }
+  if let Some(device) = &filter.device {
+    params.push(format!("device={}", percent_encode(device)));
+  }
+  if let Some(from) = filter.from {
+    params.push(format!("from={from}"));
+  }
+  if let Some(to) = filter.to {
+    params.push(format!("to={to}"));
+  }
+  format!("/api/audit?{}", params.join("&"))
+}
+
+/// Unix seconds of the start of `date` (`YYYY-MM-DD`) in UTC, or of the last
+/// second of that day with `end_of_day`.
+fn parse_day(date: &str, end_of_day: bool) -> Option<u64> {
+  let day = chrono::NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()?;
+  let time = if end_of_day {
+    day.and_hms_opt(23, 59, 59)?
+  } else {
+    day.and_hms_opt(0, 0, 0)?
+  };
+  u64::try_from(time.and_utc().timestamp()).ok()
+}
+
+fn non_empty(value: String) -> Option<String> {
+  let value = value.trim().to_string();
+  (!value.is_empty()).then_some(value)
+}
+
+#[component]
+pub fn AuditPage() -> impl IntoView {
+  let preferences = use_preferences();
+  let actor = RwSignal::new(String::new());
+  let action = RwSignal::new(String::new());
+  let device = RwSignal::new(String::new());
+  let from = RwSignal::new(String::new());
+  let to = RwSignal::new(String::new());
+  let filter = Memo::new(move |_| AuditFilter {
+    actor: non_empty(actor.get()),
+    action: action.get().parse().ok(),
+    device: non_empty(device.get()),
+    from: parse_day(&from.get(), false),
+    to: parse_day(&to.get(), true),
+  });
+  let entries = Resource::new(move || filter.get(), get_audit_log);
+
+  let text_filter = move |label: Text, value: RwSignal<String>, kind: &'static str| {
+    view! {
+      <label class="form-control">
+        <span class="label-text">{move || preferences.t(label)}</span>
+        <input
+          class="input input-bordered input-sm"
+          type=kind
+          prop:value=value
+          on:change=move |ev| value.set(event_target_value(&ev))
+        />
+      </label>
+    }
+  };
+
+  view! {
+    <div class="flex flex-col items-center justify-start min-h-screen bg-base-100 gap-8 p-4">
+      <div class="flex flex-row w-full max-w-4xl items-center justify-between">
+        <h1 class="text-4xl font-black">{move || preferences.t(Text::AuditLog)}</h1>
+        <a href="/settings" class="btn btn-ghost">
+          {move || preferences.t(Text::Settings)}
+        </a>
+      </div>
+      <Section title=Text::Filter>
+        <div class="flex flex-wrap gap-4 items-end">
+          {text_filter(Text::Actor, actor, "text")}
+          <label class="form-control">
+            <span class="label-text">{move || preferences.t(Text::Action)}</span>
+            <select
+              class="select select-bordered select-sm"
+              prop:value=action
+              on:change=move |ev| action.set(event_target_value(&ev))
+            >
+              <option value="">{move || preferences.t(Text::AnyAction)}</option>
+              {AuditAction::ALL
+                .into_iter()
+                .map(|action| view! { <option value=action.name()>{action.name()}</option> })
+                .collect_view()}
+            </select>
+          </label>
+          {text_filter(Text::Device, device, "text")}
+          {text_filter(Text::From, from, "date")}
+          {text_filter(Text::To, to, "date")}
+          <a class="btn btn-sm" href=move || export_href(&filter.get()) rel="external" download>
+            {move || preferences.t(Text::ExportJson)}
+          </a>
+        </div>
+      </Section>
+      <Section title=Text::AuditLog>
+        <Transition fallback=move || view! { <span class="loading loading-spinner"></span> }>
+          {move || {
+            entries
+              .get()
+              .map(|result| match result {
+                Ok(entries) => {
+                  view! {
+                    <table class="table table-sm">
+                      <thead>
+                        <tr>
+                          <th>{move || preferences.t(Text::Time)}</th>
+                          <th>{move || preferences.t(Text::Actor)}</th>
+                          <th>{move || preferences.t(Text::Action)}</th>
+                          <th>{move || preferences.t(Text::Device)}</th>
+                          <th>{move || preferences.t(Text::Detail)}</th>
+                        </tr>
+                      </thead>
+                      <tbody>
+                        {entries
+                          .into_iter()
+                          .map(|entry| {
+                            view! {
+                              <tr>
+                                <td class="whitespace-nowrap">
+                                  {preferences.format_timestamp(entry.timestamp)}
+                                </td>
+                                <td class="font-mono">{entry.actor}</td>
+                                <td class="font-mono">{entry.action.name()}</td>
+                                <td class="font-mono">{entry.device_id}</td>
+                                <td>{entry.detail}</td>
+                              </tr>
+                            }
+                          })
+                          .collect_view()}
+                      </tbody>
+                    </table>
+                  }
+                    .into_any()
+                }
+                Err(e) => view! { <div class="alert alert-error">{e.to_string()}</div> }.into_any(),
+              })
+          }}
+        </Transition>
+      </Section>
+    </div>
+  }
+}
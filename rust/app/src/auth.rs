@@ -0,0 +1,666 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs, io,
+  path::PathBuf,
+  sync::{Arc, Mutex, OnceLock, RwLock},
+  time::{Duration, Instant},
+};
+
+use argon2::{
+  password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+  Argon2,
+};
+use axum::{
+  extract::{Request, State},
+  middleware::Next,
+  response::{IntoResponse, Redirect, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http::{
+  header::{ACCEPT, AUTHORIZATION, COOKIE, WWW_AUTHENTICATE},
+  request::Parts,
+  HeaderMap, Method, StatusCode,
+};
+use leptos::prelude::*;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use types::{
+  accounts::{ApiTokenSummary, Grants, Role, UserSummary},
+  settings::Settings,
+};
+
+use crate::{
+  store::{write_atomically, StoreError},
+  util::percent_encode,
+};
+
+/// Environment variable holding the password of the `admin` account created
+/// when no account exists yet.
+pub const ADMIN_PASSWORD_ENV: &str = "HAT_MONITOR_ADMIN_PASSWORD";
+const ADMIN_USER: &str = "admin";
+
+pub const SESSION_COOKIE: &str = "hat-monitor-session";
+const SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Prefix of API tokens, so they are easy to spot in scripts and logs.
+const TOKEN_PREFIX: &str = "hm_";
+
+/// Paths reachable without signing in.
+const PUBLIC_PATHS: &[&str] = &[
+  "/login",
+  "/api/login",
+  "/favicon.ico",
+  "/healthz",
+  "/readyz",
+  "/api/provision/claim",
+  "/api/mqtt/user",
+  "/api/mqtt/superuser",
+  "/api/mqtt/acl",
+];
+/// Firmware images are fetched by hats, which have no account.
+const PUBLIC_PREFIXES: &[&str] = &["/pkg/", "/firmware/"];
+
+/// The account an authenticated request acts as. Inserted into the request
+/// extensions by [`require_auth`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+  pub username: String,
+  pub role: Role,
+  pub grants: Grants,
+}
+
+impl Identity {
+  pub fn has_role(&self, role: Role) -> bool {
+    self.role >= role
+  }
+
+  /// Whether the live data and history of `device_id` may be shown.
+  pub fn can_see(&self, device_id: &str, settings: &Settings) -> bool {
+    self.role == Role::Admin || self.grants.allows(device_id, settings)
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Account {
+  password_hash: String,
+  #[serde(default = "legacy_role")]
+  role: Role,
+  #[serde(default)]
+  grants: Grants,
+  #[serde(default)]
+  tokens: Vec<ApiToken>,
+}
+
+/// Accounts created before roles existed had full access.
+fn legacy_role() -> Role {
+  Role::Admin
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiToken {
+  id: String,
+  name: String,
+  /// SHA-256 of the secret, hex encoded.
+  hash: String,
+  created_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Accounts {
+  #[serde(default)]
+  users: BTreeMap<String, Account>,
+}
+
+#[derive(Debug)]
+struct Session {
+  username: String,
+  expires: Instant,
+}
+
+/// Local accounts persisted as JSON with argon2 password hashes, plus the
+/// in-memory sessions of signed in browsers. Sessions don't survive a
+/// restart.
+#[derive(Debug, Clone)]
+pub struct Auth {
+  path: Arc<PathBuf>,
+  accounts: Arc<RwLock<Accounts>>,
+  /// Keyed by the SHA-256 of the session cookie.
+  sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl Auth {
+  /// Loads accounts from `path`. When there are none yet and
+  /// [`ADMIN_PASSWORD_ENV`] is set, an `admin` account is created with it.
+  pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+    let path = path.into();
+    let accounts = match fs::read(&path) {
+      Ok(bytes) => serde_json::from_slice(&bytes)?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Accounts::default(),
+      Err(e) => return Err(e.into()),
+    };
+    let auth = Self {
+      path: Arc::new(path),
+      accounts: Arc::new(RwLock::new(accounts)),
+      sessions: Arc::default(),
+    };
+    if auth.read().users.is_empty() {
+      match std::env::var(ADMIN_PASSWORD_ENV) {
+        Ok(password) => {
+          auth.create_user(ADMIN_USER, &password, Role::Admin, Grants::everything())?;
+          info!(
+            target = "auth",
+            case = "bootstrap",
+            "created the {ADMIN_USER} account"
+          );
+        }
+        Err(_) => warn!(
+          target = "auth",
+          case = "bootstrap",
+          "no accounts, set {ADMIN_PASSWORD_ENV} to create one"
+        ),
+      }
+    }
+    Ok(auth)
+  }
+
+  fn read(&self) -> std::sync::RwLockReadGuard<'_, Accounts> {
+    self.accounts.read().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Applies `f` to a copy of the accounts and persists it. Nothing changes
+  /// if `f` or writing the file fails.
+  fn update<T>(
+    &self,
+    f: impl FnOnce(&mut Accounts) -> Result<T, StoreError>,
+  ) -> Result<T, StoreError> {
+    let mut accounts = self.accounts.write().unwrap_or_else(|e| e.into_inner());
+    let mut next = accounts.clone();
+    let value = f(&mut next)?;
+    write_atomically(&self.path, &serde_json::to_vec_pretty(&next)?)?;
+    *accounts = next;
+    Ok(value)
+  }
+
+  pub fn users(&self) -> Vec<UserSummary> {
+    self
+      .read()
+      .users
+      .iter()
+      .map(|(username, account)| UserSummary {
+        username: username.clone(),
+        role: account.role,
+        grants: account.grants.clone(),
+      })
+      .collect()
+  }
+
+  pub fn has_user(&self, username: &str) -> bool {
+    self.read().users.contains_key(username)
+  }
+
+  /// Creates `username`, replacing any existing account of that name.
+  pub fn create_user(
+    &self,
+    username: &str,
+    password: &str,
+    role: Role,
+    grants: Grants,
+  ) -> Result<(), StoreError> {
+    let account = Account {
+      password_hash: hash_password(password)?,
+      role,
+      grants,
+      tokens: Vec::new(),
+    };
+    self.update(|accounts| {
+      accounts.users.insert(username.to_string(), account);
+      Ok(())
+    })
+  }
+
+  pub fn set_password(&self, username: &str, password: &str) -> Result<(), StoreError> {
+    let password_hash = hash_password(password)?;
+    self.update(|accounts| {
+      if let Some(account) = accounts.users.get_mut(username) {
+        account.password_hash = password_hash;
+      }
+      Ok(())
+    })
+  }
+
+  /// Changes what `username` may do. Signed in sessions pick it up on their
+  /// next request.
+  pub fn set_access(&self, username: &str, role: Role, grants: Grants) -> Result<(), StoreError> {
+    self.update(|accounts| {
+      if let Some(account) = accounts.users.get_mut(username) {
+        account.role = role;
+        account.grants = grants;
+      }
+      Ok(())
+    })
+  }
+
+  /// Deletes `username` along with its tokens and sessions.
+  pub fn remove_user(&self, username: &str) -> Result<(), StoreError> {
+    self.update(|accounts| {
+      accounts.users.remove(username);
+      Ok(())
+    })?;
+    self
+      .sessions()
+      .retain(|_, session| session.username != username);
+    Ok(())
+  }
+
+  pub fn verify_password(&self, username: &str, password: &str) -> bool {
+    let password_hash = self
+      .read()
+      .users
+      .get(username)
+      .map(|account| account.password_hash.clone());
+    // Unknown users are checked against a dummy hash, so the time a login
+    // takes doesn't tell which usernames exist.
+    let verified = PasswordHash::new(password_hash.as_deref().unwrap_or_else(|| dummy_hash()))
+      .is_ok_and(|parsed| {
+        Argon2::default()
+          .verify_password(password.as_bytes(), &parsed)
+          .is_ok()
+      });
+    verified && password_hash.is_some()
+  }
+
+  fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+    self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Starts a session for `username` and returns its cookie value.
+  pub fn start_session(&self, username: &str) -> String {
+    let token = random_secret();
+    let now = Instant::now();
+    let mut sessions = self.sessions();
+    sessions.retain(|_, session| session.expires > now);
+    sessions.insert(
+      digest(&token),
+      Session {
+        username: username.to_string(),
+        expires: now + SESSION_TTL,
+      },
+    );
+    token
+  }
+
+  pub fn end_session(&self, token: &str) {
+    self.sessions().remove(&digest(token));
+  }
+
+  pub fn tokens(&self, username: &str) -> Vec<ApiTokenSummary> {
+    self
+      .read()
+      .users
+      .get(username)
+      .map(|account| {
+        account
+          .tokens
+          .iter()
+          .map(|token| ApiTokenSummary {
+            id: token.id.clone(),
+            name: token.name.clone(),
+            created_at: token.created_at,
+          })
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  /// Creates an API token for `username` and returns its secret, which is
+  /// not stored and can't be shown again.
+  pub fn create_token(&self, username: &str, name: &str) -> Result<String, StoreError> {
+    let secret = format!("{TOKEN_PREFIX}{}", random_secret());
+    let token = ApiToken {
+      id: random_secret()[..8].to_string(),
+      name: name.trim().to_string(),
+      hash: digest(&secret),
+      created_at: chrono::Utc::now().timestamp() as u64,
+    };
+    self.update(|accounts| {
+      if let Some(account) = accounts.users.get_mut(username) {
+        account.tokens.push(token);
+      }
+      Ok(())
+    })?;
+    Ok(secret)
+  }
+
+  pub fn revoke_token(&self, username: &str, id: &str) -> Result<(), StoreError> {
+    self.update(|accounts| {
+      if let Some(account) = accounts.users.get_mut(username) {
+        account.tokens.retain(|token| token.id != id);
+      }
+      Ok(())
+    })
+  }
+
+  /// Identifies a request by its bearer token or session cookie.
+  pub fn authenticate(&self, headers: &HeaderMap) -> Option<Identity> {
+    if let Some(secret) = bearer_token(headers) {
+      let hash = digest(secret);
+      return self
+        .read()
+        .users
+        .iter()
+        .find(|(_, account)| account.tokens.iter().any(|token| token.hash == hash))
+        .map(|(username, account)| identity(username, account));
+    }
+    let cookie = session_cookie(headers)?;
+    let username = {
+      let sessions = self.sessions();
+      let session = sessions.get(&digest(cookie))?;
+      (session.expires > Instant::now()).then(|| session.username.clone())?
+    };
+    // Accounts removed since signing in lose their sessions.
+    self
+      .read()
+      .users
+      .get(&username)
+      .map(|account| identity(&username, account))
+  }
+}
+
+fn identity(username: &str, account: &Account) -> Identity {
+  Identity {
+    username: username.to_string(),
+    role: account.role,
+    grants: account.grants.clone(),
+  }
+}
+
+fn hash_password(password: &str) -> Result<String, StoreError> {
+  let mut bytes = [0u8; 16];
+  rand::rng().fill_bytes(&mut bytes);
+  let salt = SaltString::encode_b64(&bytes).map_err(|e| StoreError::Password(e.to_string()))?;
+  Argon2::default()
+    .hash_password(password.as_bytes(), &salt)
+    .map(|hash| hash.to_string())
+    .map_err(|e| StoreError::Password(e.to_string()))
+}
+
+/// A hash no password is checked against successfully, computed once.
+fn dummy_hash() -> &'static str {
+  static DUMMY: OnceLock<String> = OnceLock::new();
+  DUMMY.get_or_init(|| hash_password(&random_secret()).unwrap_or_default())
+}
+
+pub(crate) fn random_secret() -> String {
+  let mut bytes = [0u8; 32];
+  rand::rng().fill_bytes(&mut bytes);
+  URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(crate) fn digest(secret: &str) -> String {
+  Sha256::digest(secret.as_bytes())
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+  headers
+    .get(AUTHORIZATION)?
+    .to_str()
+    .ok()?
+    .strip_prefix("Bearer ")
+    .map(str::trim)
+}
+
+/// Value of the session cookie in the request `headers`.
+pub fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+  headers
+    .get_all(COOKIE)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(';'))
+    .filter_map(|pair| pair.trim().split_once('='))
+    .find(|(name, _)| *name == SESSION_COOKIE)
+    .map(|(_, value)| value)
+}
+
+/// Whether the browser reached the server over https, through a reverse
+/// proxy terminating TLS.
+pub fn is_https(headers: &HeaderMap) -> bool {
+  let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+  header("x-forwarded-proto").is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"))
+    || header("forwarded").is_some_and(|forwarded| {
+      forwarded
+        .split([';', ','])
+        .any(|pair| pair.trim().eq_ignore_ascii_case("proto=https"))
+    })
+    || header("origin").is_some_and(|origin| origin.starts_with("https://"))
+}
+
+/// `Set-Cookie` value carrying `token`, or clearing the cookie when `None`.
+/// `secure` keeps browsers from sending it over plain http.
+pub fn session_set_cookie(token: Option<&str>, secure: bool) -> String {
+  let secure = if secure { "; Secure" } else { "" };
+  match token {
+    Some(token) => format!(
+      "{SESSION_COOKIE}={token}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{secure}",
+      SESSION_TTL.as_secs()
+    ),
+    None => format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0{secure}"),
+  }
+}
+
+fn is_public(path: &str) -> bool {
+  PUBLIC_PATHS.contains(&path)
+    || PUBLIC_PREFIXES
+      .iter()
+      .any(|prefix| path.starts_with(prefix))
+}
+
+/// Rejects requests that are neither signed in nor carry an API token.
+/// Browsers asking for a page are sent to the login page, everything else
+/// gets `401`.
+pub async fn require_auth(State(auth): State<Auth>, mut request: Request, next: Next) -> Response {
+  if let Some(identity) = auth.authenticate(request.headers()) {
+    request.extensions_mut().insert(identity);
+    return next.run(request).await;
+  }
+  if is_public(request.uri().path()) {
+    return next.run(request).await;
+  }
+  let wants_page = request.method() == Method::GET
+    && request
+      .headers()
+      .get(ACCEPT)
+      .and_then(|value| value.to_str().ok())
+      .is_some_and(|accept| accept.contains("text/html"));
+  if wants_page {
+    let target = request
+      .uri()
+      .path_and_query()
+      .map_or("/", |target| target.as_str());
+    return Redirect::to(&format!("/login?next={}", percent_encode(target))).into_response();
+  }
+  (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response()
+}
+
+/// The account of the current server function or SSR request.
+pub fn current_user() -> Option<Identity> {
+  use_context::<Parts>()?
+    .extensions
+    .get::<Identity>()
+    .cloned()
+}
+
+/// Only same-site paths are followed after signing in. Browsers read `\` as
+/// `/` and drop tabs and newlines, so `/\host` or `/\t/host` would leave the
+/// site as well.
+pub fn safe_next(next: Option<&str>) -> &str {
+  match next {
+    Some(next)
+      if next.starts_with('/')
+        && !next.starts_with("//")
+        && !next.contains('\\')
+        && !next.chars().any(char::is_control) =>
+    {
+      next
+    }
+    _ => "/",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  use http::HeaderValue;
+
+  use super::*;
+
+  /// Accounts in a file of their own, with the operator `ana`.
+  fn auth() -> (Auth, PathBuf) {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let path = std::env::temp_dir().join(format!(
+      "hat-monitor-accounts-{}-{}.json",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let auth = Auth::open(&path).unwrap();
+    auth
+      .create_user("ana", "correct horse", Role::Operator, Grants::default())
+      .unwrap();
+    (auth, path)
+  }
+
+  fn headers(name: &'static str, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_str(value).unwrap());
+    headers
+  }
+
+  fn username(auth: &Auth, headers: &HeaderMap) -> Option<String> {
+    auth.authenticate(headers).map(|identity| identity.username)
+  }
+
+  #[test]
+  fn next_paths_stay_on_the_site() {
+    for next in ["/", "/settings", "/audit?actor=ana&from=1"] {
+      assert_eq!(safe_next(Some(next)), next);
+    }
+    for next in [
+      "",
+      "settings",
+      "https://evil.example",
+      "//evil.example",
+      "/\\evil.example",
+      "/settings\\..\\",
+      "/\t/evil.example",
+      "/\n/evil.example",
+    ] {
+      assert_eq!(safe_next(Some(next)), "/", "{next:?}");
+    }
+    assert_eq!(safe_next(None), "/");
+  }
+
+  #[test]
+  fn passwords_are_verified() {
+    let (auth, path) = auth();
+    assert!(auth.verify_password("ana", "correct horse"));
+    assert!(!auth.verify_password("ana", "Correct horse"));
+    assert!(!auth.verify_password("bob", "correct horse"));
+    auth.set_password("ana", "battery staple").unwrap();
+    assert!(auth.verify_password("ana", "battery staple"));
+    assert!(!auth.verify_password("ana", "correct horse"));
+    // The file keeps hashes only.
+    let stored = fs::read_to_string(&path).unwrap();
+    assert!(!stored.contains("battery staple"));
+    assert!(Auth::open(&path)
+      .unwrap()
+      .verify_password("ana", "battery staple"));
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn sessions_end_when_they_expire_or_are_ended() {
+    let (auth, path) = auth();
+    let token = auth.start_session("ana");
+    let cookie = headers("cookie", &format!("theme=dark; {SESSION_COOKIE}={token}"));
+    assert_eq!(username(&auth, &cookie).as_deref(), Some("ana"));
+
+    auth.sessions().get_mut(&digest(&token)).unwrap().expires = Instant::now();
+    assert_eq!(username(&auth, &cookie), None);
+
+    let token = auth.start_session("ana");
+    let cookie = headers("cookie", &format!("{SESSION_COOKIE}={token}"));
+    auth.end_session(&token);
+    assert_eq!(username(&auth, &cookie), None);
+
+    let token = auth.start_session("ana");
+    let cookie = headers("cookie", &format!("{SESSION_COOKIE}={token}"));
+    auth.remove_user("ana").unwrap();
+    assert_eq!(username(&auth, &cookie), None);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn api_tokens_work_until_revoked() {
+    let (auth, path) = auth();
+    let secret = auth.create_token("ana", " ci ").unwrap();
+    assert!(secret.starts_with(TOKEN_PREFIX));
+    let bearer = headers("authorization", &format!("Bearer {secret}"));
+    assert_eq!(username(&auth, &bearer).as_deref(), Some("ana"));
+    assert_eq!(
+      username(&auth, &headers("authorization", "Bearer hm_guessed")),
+      None
+    );
+
+    let tokens = auth.tokens("ana");
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].name, "ci");
+    assert!(!fs::read_to_string(&path).unwrap().contains(&secret));
+
+    auth.revoke_token("ana", &tokens[0].id).unwrap();
+    assert_eq!(username(&auth, &bearer), None);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn only_admins_see_ungranted_devices() {
+    let mut settings = Settings::default();
+    settings.devices.insert(
+      "hat-1".to_string(),
+      types::settings::DeviceInfo {
+        location: "Lab".to_string(),
+        ..Default::default()
+      },
+    );
+    let identity = |role| Identity {
+      username: "ana".to_string(),
+      role,
+      grants: Grants {
+        locations: ["Lab".to_string()].into(),
+        ..Grants::default()
+      },
+    };
+    for role in [Role::Viewer, Role::Operator] {
+      assert!(identity(role).can_see("hat-1", &settings));
+      assert!(!identity(role).can_see("hat-2", &settings));
+    }
+    assert!(identity(Role::Admin).can_see("hat-2", &settings));
+    assert!(identity(Role::Operator).has_role(Role::Viewer));
+    assert!(!identity(Role::Operator).has_role(Role::Admin));
+  }
+
+  #[test]
+  fn session_cookies_are_secure_over_https() {
+    assert!(!session_set_cookie(Some("t"), false).contains("Secure"));
+    assert!(session_set_cookie(Some("t"), true).ends_with("; Secure"));
+    assert!(session_set_cookie(None, true).contains("Max-Age=0; Secure"));
+    assert!(is_https(&headers("x-forwarded-proto", "https")));
+    assert!(is_https(&headers("forwarded", "for=10.0.0.1;proto=https")));
+    assert!(is_https(&headers("origin", "https://hats.example")));
+    assert!(!is_https(&headers("x-forwarded-proto", "http")));
+    assert!(!is_https(&HeaderMap::new()));
+  }
+}
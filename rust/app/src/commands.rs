@@ -0,0 +1,528 @@
+use leptos::{prelude::*, server_fn::codec::Json};
+use types::{
+  commands::{Command, CommandRecord, CommandState},
+  DEFAULT_DEVICE_ID,
+};
+
+use crate::{
+  i18n::Text,
+  preferences::use_preferences,
+  settings::{Section, SettingsError},
+};
+
+#[cfg(feature = "ssr")]
+pub use hub::{CommandError, CommandHub, Outbound};
+
+#[cfg(feature = "ssr")]
+mod hub {
+  use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+  };
+
+  use rand::RngCore;
+  use tokio::sync::{mpsc, watch};
+  use types::{
+    commands::{Command, CommandAck, CommandRecord, CommandRequest, CommandState},
+    mqtt::{ConnectionState, MqttStatus},
+    settings::validate_device_id,
+  };
+
+  /// Commands kept for the history, oldest dropped first.
+  const HISTORY: usize = 200;
+
+  #[derive(Debug, Clone, PartialEq, thiserror::Error)]
+  pub enum CommandError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("the broker is not connected")]
+    Unavailable,
+  }
+
+  /// A command for the MQTT worker to publish.
+  #[derive(Debug, Clone)]
+  pub struct Outbound {
+    pub device_id: String,
+    pub request: CommandRequest,
+  }
+
+  /// Sends commands through the MQTT worker and tracks their
+  /// acknowledgements. Shared by the REST API and the settings page.
+  #[derive(Debug, Clone)]
+  pub struct CommandHub {
+    records: Arc<Mutex<VecDeque<CommandRecord>>>,
+    outbox: mpsc::UnboundedSender<Outbound>,
+    mqtt: watch::Receiver<MqttStatus>,
+  }
+
+  impl CommandHub {
+    /// The hub and the queue the MQTT worker publishes from.
+    pub fn new(mqtt: watch::Receiver<MqttStatus>) -> (Self, mpsc::UnboundedReceiver<Outbound>) {
+      let (outbox, queue) = mpsc::unbounded_channel();
+      let hub = Self {
+        records: Arc::default(),
+        outbox,
+        mqtt,
+      };
+      (hub, queue)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<CommandRecord>> {
+      self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues `command` for `device_id`. Refused while the broker is
+    /// unreachable rather than delivered whenever it comes back. Firmware
+    /// updates are only sent by rolling out a hosted image.
+    pub fn send(
+      &self,
+      issued_by: &str,
+      device_id: &str,
+      command: Command,
+    ) -> Result<CommandRecord, CommandError> {
+      if matches!(command, Command::UpdateFirmware { .. }) {
+        return Err(CommandError::Invalid(
+          "firmware updates are sent by rolling out an image".to_string(),
+        ));
+      }
+      self.queue(issued_by, device_id, command)
+    }
+
+    /// [`CommandHub::send`] for any command, the firmware rollout's way in.
+    pub(crate) fn queue(
+      &self,
+      issued_by: &str,
+      device_id: &str,
+      command: Command,
+    ) -> Result<CommandRecord, CommandError> {
+      validate_device_id(device_id).map_err(|e| CommandError::Invalid(format!("device {e}")))?;
+      command.validate().map_err(CommandError::Invalid)?;
+      if self.mqtt.borrow().state != ConnectionState::Subscribed {
+        return Err(CommandError::Unavailable);
+      }
+      let mut id = [0u8; 8];
+      rand::rng().fill_bytes(&mut id);
+      let record = CommandRecord {
+        id: id.iter().map(|byte| format!("{byte:02x}")).collect(),
+        device_id: device_id.to_string(),
+        command: command.clone(),
+        issued_by: issued_by.to_string(),
+        issued_at: chrono::Utc::now().timestamp() as u64,
+        state: CommandState::Pending,
+        acked_at: None,
+        message: None,
+      };
+      let outbound = Outbound {
+        device_id: record.device_id.clone(),
+        request: CommandRequest {
+          id: record.id.clone(),
+          issued_at: record.issued_at,
+          command,
+        },
+      };
+      self
+        .outbox
+        .send(outbound)
+        .map_err(|_| CommandError::Unavailable)?;
+      let mut records = self.lock();
+      if records.len() == HISTORY {
+        records.pop_front();
+      }
+      records.push_back(record.clone());
+      Ok(record)
+    }
+
+    /// Settles the command `ack` answers. Returns `false` for unknown ids,
+    /// or ids of commands sent to another device.
+    pub fn acknowledge(&self, device_id: &str, ack: CommandAck) -> bool {
+      let mut records = self.lock();
+      let Some(record) = records
+        .iter_mut()
+        .find(|record| record.id == ack.id && record.device_id == device_id)
+      else {
+        return false;
+      };
+      record.state = if ack.ok {
+        CommandState::Acknowledged
+      } else {
+        CommandState::Failed
+      };
+      record.acked_at = Some(chrono::Utc::now().timestamp() as u64);
+      record.message = ack.message;
+      true
+    }
+
+    /// Commands sent to `device_id`, newest first, with timeouts applied.
+    pub fn records(&self, device_id: &str) -> Vec<CommandRecord> {
+      let now = chrono::Utc::now().timestamp() as u64;
+      self
+        .lock()
+        .iter()
+        .rev()
+        .filter(|record| record.device_id == device_id)
+        .map(|record| CommandRecord {
+          state: record.state_at(now),
+          ..record.clone()
+        })
+        .collect()
+    }
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+
+    fn hub(state: ConnectionState) -> (CommandHub, mpsc::UnboundedReceiver<Outbound>) {
+      let (_, mqtt) = watch::channel(MqttStatus {
+        state,
+        ..MqttStatus::default()
+      });
+      CommandHub::new(mqtt)
+    }
+
+    fn ack(id: &str, ok: bool) -> CommandAck {
+      CommandAck {
+        id: id.to_string(),
+        ok,
+        message: Some("done".to_string()),
+      }
+    }
+
+    #[test]
+    fn commands_need_a_subscribed_broker() {
+      for state in [ConnectionState::Connecting, ConnectionState::Degraded] {
+        let (hub, _queue) = hub(state);
+        assert_eq!(
+          hub.send("admin", "hat", Command::Reboot),
+          Err(CommandError::Unavailable)
+        );
+      }
+      let (hub, _queue) = hub(ConnectionState::Subscribed);
+      assert!(matches!(
+        hub.send("admin", "hat", Command::SetInterval { seconds: 0 }),
+        Err(CommandError::Invalid(_))
+      ));
+      assert!(matches!(
+        hub.send("admin", "hat/1", Command::Reboot),
+        Err(CommandError::Invalid(_))
+      ));
+    }
+
+    #[test]
+    fn firmware_updates_need_a_rollout() {
+      let (hub, mut queue) = hub(ConnectionState::Subscribed);
+      let update = Command::UpdateFirmware {
+        version: "1.2.0".to_string(),
+        url: "https://example.com/evil.bin".to_string(),
+        sha256: "ab".repeat(32),
+        size: 1024,
+      };
+      assert!(matches!(
+        hub.send("operator", "hat", update.clone()),
+        Err(CommandError::Invalid(_))
+      ));
+      assert!(queue.try_recv().is_err());
+      assert!(hub.records("hat").is_empty());
+      assert!(hub.queue("admin", "hat", update).is_ok());
+      assert!(queue.try_recv().is_ok());
+    }
+
+    #[test]
+    fn acks_settle_the_device_command() {
+      let (hub, mut queue) = hub(ConnectionState::Subscribed);
+      let reboot = hub.send("admin", "hat", Command::Reboot).unwrap();
+      let identify = hub
+        .send("admin", "hat", Command::Identify { seconds: 5 })
+        .unwrap();
+      let outbound = queue.try_recv().unwrap();
+      assert_eq!(outbound.device_id, "hat");
+      assert_eq!(outbound.request.id, reboot.id);
+      assert_eq!(outbound.request.command, Command::Reboot);
+
+      assert!(!hub.acknowledge("other", ack(&reboot.id, true)));
+      assert!(!hub.acknowledge("hat", ack("unknown", true)));
+      assert!(hub.acknowledge("hat", ack(&reboot.id, true)));
+      assert!(hub.acknowledge("hat", ack(&identify.id, false)));
+
+      let records = hub.records("hat");
+      assert_eq!(
+        records
+          .iter()
+          .map(|record| (record.id.as_str(), record.state))
+          .collect::<Vec<_>>(),
+        [
+          (identify.id.as_str(), CommandState::Failed),
+          (reboot.id.as_str(), CommandState::Acknowledged),
+        ]
+      );
+      assert!(records[0].acked_at.is_some());
+      assert_eq!(records[0].message.as_deref(), Some("done"));
+      assert!(hub.records("other").is_empty());
+    }
+  }
+}
+
+/// Records a sent command in the audit log. Calibrations are recorded as
+/// such, like the `calibrate` subcommand.
+#[cfg(feature = "ssr")]
+pub async fn audit(log: &crate::audit::AuditLog, actor: &str, record: &CommandRecord) {
+  let action = if record.command.is_calibration() {
+    types::audit::AuditAction::CalibrationRun
+  } else {
+    types::audit::AuditAction::CommandSent
+  };
+  let detail = serde_json::to_string(&record.command).unwrap_or_default();
+  log
+    .record(
+      actor,
+      action,
+      Some(&record.device_id),
+      format!("{} {detail}", record.id),
+    )
+    .await;
+}
+
+#[cfg(feature = "ssr")]
+fn hub_for(device_id: &str) -> Result<(crate::auth::Identity, CommandHub), SettingsError> {
+  let (identity, _) = crate::accounts::require_role(types::accounts::Role::Operator)?;
+  let store = use_context::<crate::store::SettingsStore>()
+    .ok_or_else(|| SettingsError::Server("settings store is not available".to_string()))?;
+  if !store.with(|settings| identity.can_see(device_id, settings)) {
+    return Err(SettingsError::Forbidden);
+  }
+  let hub = use_context::<CommandHub>()
+    .ok_or_else(|| SettingsError::Server("commands are not available".to_string()))?;
+  Ok((identity, hub))
+}
+
+#[server(input = Json)]
+pub async fn send_command(
+  device_id: String,
+  command: Command,
+) -> Result<CommandRecord, SettingsError> {
+  let (identity, hub) = hub_for(&device_id)?;
+  if !identity.has_role(command.required_role()) {
+    return Err(SettingsError::Forbidden);
+  }
+  let record = hub
+    .send(&identity.username, &device_id, command)
+    .map_err(|e| match e {
+      CommandError::Invalid(message) => {
+        let mut errors = types::settings::FieldErrors::default();
+        errors.push("command", message);
+        SettingsError::Invalid(errors)
+      }
+      CommandError::Unavailable => SettingsError::Server(e.to_string()),
+    })?;
+  if let Some(log) = use_context::<crate::audit::AuditLog>() {
+    audit(&log, &identity.username, &record).await;
+  }
+  Ok(record)
+}
+
+#[server(input = Json)]
+pub async fn get_commands(device_id: String) -> Result<Vec<CommandRecord>, SettingsError> {
+  let (_, hub) = hub_for(&device_id)?;
+  Ok(hub.records(&device_id))
+}
+
+fn describe(command: &Command) -> String {
+  match command {
+    Command::SetInterval { seconds } => format!("set_interval {seconds}s"),
+    Command::StartCalibration { minutes } => format!("start_calibration {minutes}min"),
+    Command::SetRZero { r_zero } => format!("set_r_zero {r_zero}"),
+    Command::Reboot => "reboot".to_string(),
+    Command::Identify { seconds } => format!("identify {seconds}s"),
+    Command::UpdateFirmware { version, .. } => format!("update_firmware {version}"),
+  }
+}
+
+/// Sends commands to a hat and lists the recent ones with their
+/// acknowledgements.
+#[component]
+pub(crate) fn CommandsSection(devices: Vec<String>) -> impl IntoView {
+  let preferences = use_preferences();
+  let mut devices = devices;
+  if !devices
+    .iter()
+    .any(|device_id| device_id == DEFAULT_DEVICE_ID)
+  {
+    devices.insert(0, DEFAULT_DEVICE_ID.to_string());
+  }
+  let device = RwSignal::new(devices[0].clone());
+  let interval = RwSignal::new(5u32);
+  let minutes = RwSignal::new(30u32);
+  let r_zero = RwSignal::new(String::new());
+  let identify = RwSignal::new(10u32);
+  let send = ServerAction::<SendCommand>::new();
+  let refresh = RwSignal::new(0u32);
+  let records = Resource::new(
+    move || (device.get(), send.version().get(), refresh.get()),
+    |(device_id, _, _)| get_commands(device_id),
+  );
+  let dispatch = move |command: Command| {
+    send.dispatch(SendCommand {
+      device_id: device.get_untracked(),
+      command,
+    });
+  };
+  let number_input = move |value: RwSignal<u32>| {
+    view! {
+      <input
+        class="input input-bordered input-sm w-24"
+        type="number"
+        min="1"
+        prop:value=move || value.get().to_string()
+        on:change=move |ev| {
+          if let Ok(parsed) = event_target_value(&ev).parse() {
+            value.set(parsed);
+          }
+        }
+      />
+    }
+  };
+  let state_badge = move |state: CommandState| {
+    let (class, text) = match state {
+      CommandState::Pending => ("badge-warning", Text::Pending),
+      CommandState::Acknowledged => ("badge-success", Text::Acknowledged),
+      CommandState::Failed => ("badge-error", Text::Failed),
+      CommandState::TimedOut => ("badge-ghost", Text::TimedOut),
+    };
+    view! { <span class=format!("badge {class}")>{preferences.t(text)}</span> }
+  };
+
+  view! {
+    <Section title=Text::Commands>
+      <label class="flex items-center gap-2">
+        {move || preferences.t(Text::Device)}
+        <select
+          class="select select-bordered select-sm"
+          prop:value=device
+          on:change=move |ev| device.set(event_target_value(&ev))
+        >
+          {devices
+            .into_iter()
+            .map(|device_id| view! { <option value=device_id.clone()>{device_id.clone()}</option> })
+            .collect_view()}
+        </select>
+      </label>
+      <div class="grid grid-cols-1 md:grid-cols-2 gap-2">
+        <div class="flex items-center gap-2">
+          <span class="w-40">{move || preferences.t(Text::Interval)}</span>
+          {number_input(interval)}
+          {move || preferences.t(Text::Seconds)}
+          <button
+            class="btn btn-sm"
+            disabled=send.pending()
+            on:click=move |_| dispatch(Command::SetInterval { seconds: interval.get_untracked() })
+          >
+            {move || preferences.t(Text::Send)}
+          </button>
+        </div>
+        <div class="flex items-center gap-2">
+          <span class="w-40">{move || preferences.t(Text::Calibrate)}</span>
+          {number_input(minutes)}
+          {move || preferences.t(Text::Minutes)}
+          <button
+            class="btn btn-sm"
+            disabled=send.pending()
+            on:click=move |_| dispatch(Command::StartCalibration { minutes: minutes.get_untracked() })
+          >
+            {move || preferences.t(Text::Send)}
+          </button>
+        </div>
+        <div class="flex items-center gap-2">
+          <span class="w-40">{move || preferences.t(Text::RZero)}</span>
+          <input
+            class="input input-bordered input-sm w-24"
+            type="number"
+            step="any"
+            prop:value=r_zero
+            on:input=move |ev| r_zero.set(event_target_value(&ev))
+          />
+          <button
+            class="btn btn-sm"
+            disabled=send.pending()
+            on:click=move |_| {
+              let r_zero = r_zero.get_untracked().trim().parse().unwrap_or(f32::NAN);
+              dispatch(Command::SetRZero { r_zero });
+            }
+          >
+            {move || preferences.t(Text::Send)}
+          </button>
+        </div>
+        <div class="flex items-center gap-2">
+          <span class="w-40">{move || preferences.t(Text::Identify)}</span>
+          {number_input(identify)}
+          {move || preferences.t(Text::Seconds)}
+          <button
+            class="btn btn-sm"
+            disabled=send.pending()
+            on:click=move |_| dispatch(Command::Identify { seconds: identify.get_untracked() })
+          >
+            {move || preferences.t(Text::Send)}
+          </button>
+        </div>
+      </div>
+      <div class="card-actions items-center justify-between">
+        <span>
+          {move || match send.value().get() {
+            Some(Ok(_)) => view! { <span class="text-success">{preferences.t(Text::Sent)}</span> }.into_any(),
+            Some(Err(SettingsError::Invalid(errors))) => {
+              view! { <span class="text-error">{errors.get("command").map(str::to_string)}</span> }
+                .into_any()
+            }
+            Some(Err(e)) => view! { <span class="text-error">{e.to_string()}</span> }.into_any(),
+            None => ().into_any(),
+          }}
+        </span>
+        <div class="flex gap-2">
+          <button class="btn btn-sm btn-ghost" on:click=move |_| refresh.update(|n| *n += 1)>
+            "↻"
+          </button>
+          <button
+            class="btn btn-sm btn-error"
+            disabled=send.pending()
+            on:click=move |_| dispatch(Command::Reboot)
+          >
+            {move || preferences.t(Text::Reboot)}
+          </button>
+        </div>
+      </div>
+      <table class="table table-sm">
+        <thead>
+          <tr>
+            <th>{move || preferences.t(Text::Time)}</th>
+            <th>{move || preferences.t(Text::Action)}</th>
+            <th>{move || preferences.t(Text::IssuedBy)}</th>
+            <th></th>
+            <th>{move || preferences.t(Text::Detail)}</th>
+          </tr>
+        </thead>
+        <tbody>
+          <Transition>
+            {move || {
+              records
+                .get()
+                .and_then(Result::ok)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|record| {
+                  view! {
+                    <tr>
+                      <td class="font-mono">{preferences.format_timestamp(record.issued_at)}</td>
+                      <td class="font-mono">{describe(&record.command)}</td>
+                      <td>{record.issued_by}</td>
+                      <td>{state_badge(record.state)}</td>
+                      <td>{record.message}</td>
+                    </tr>
+                  }
+                })
+                .collect_view()
+            }}
+          </Transition>
+        </tbody>
+      </table>
+    </Section>
+  }
+}
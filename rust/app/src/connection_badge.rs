@@ -1,14 +1,78 @@
-use leptos::prelude::*;
-use leptos_use::core::ConnectionReadyState;
+use leptos::{prelude::*, server::codee::string::JsonSerdeCodec};
+use leptos_use::{core::ConnectionReadyState, use_websocket, UseWebSocketReturn};
+use types::mqtt::{ConnectionState, ErrorCategory, MqttStatus};
+
+use crate::{i18n::Text, preferences::use_preferences};
 
 #[component]
 pub fn ConnectionBadge(ready: Signal<ConnectionReadyState>) -> impl IntoView {
   println!("{:#?}", ready);
+  let preferences = use_preferences();
   view! {
     {move || match ready.get() {
-      ConnectionReadyState::Open => view! { <div class="badge badge-success gap-2">"Online"</div> },
-      ConnectionReadyState::Closed => view! { <div class="badge badge-error gap-2">"Offline"</div> },
-      _ => view! { <div class="badge badge-warning gap-2">"Connecting..."</div> },
+      ConnectionReadyState::Open => view! { <div class="badge badge-success gap-2">{preferences.t(Text::Online)}</div> },
+      ConnectionReadyState::Closed => view! { <div class="badge badge-error gap-2">{preferences.t(Text::Offline)}</div> },
+      _ => view! { <div class="badge badge-warning gap-2">{preferences.t(Text::Connecting)}</div> },
+    }}
+  }
+}
+
+/// State of the server's connection to the MQTT broker, pushed on `/ws/mqtt`.
+/// The last error shows on hover.
+#[component]
+pub fn BrokerBadge() -> impl IntoView {
+  let UseWebSocketReturn { message, .. } =
+    use_websocket::<MqttStatus, MqttStatus, JsonSerdeCodec>("/ws/mqtt");
+  let preferences = use_preferences();
+  let title = move || {
+    message.with(|status| {
+      status
+        .as_ref()
+        .and_then(|status| status.last_error.as_ref())
+        .map(|error| {
+          format!(
+            "{} {}: {}",
+            preferences.t(error_text(error.category)),
+            preferences.format_timestamp(error.at),
+            error.message
+          )
+        })
+    })
+  };
+  view! {
+    {move || {
+      let Some(status) = message.get() else {
+        return ().into_any();
+      };
+      let (class, text) = match status.state {
+        ConnectionState::Connecting => ("badge-warning", Text::BrokerConnecting),
+        ConnectionState::Connected => ("badge-warning", Text::BrokerSubscribing),
+        ConnectionState::Subscribed => ("badge-success", Text::BrokerReceiving),
+        ConnectionState::BackingOff => ("badge-error", Text::BrokerRetrying),
+        ConnectionState::Degraded => ("badge-warning", Text::BrokerDegraded),
+        ConnectionState::Replaying => ("badge-info", Text::BrokerReplaying),
+        ConnectionState::Stopped => ("badge-error", Text::BrokerStopped),
+      };
+      let retry_at = status.retry_at.map(|retry_at| preferences.format_timestamp(retry_at));
+      view! {
+        <div class=format!("badge {class} gap-2") title=title>
+          {preferences.t(text)}
+          {retry_at}
+        </div>
+      }
+        .into_any()
     }}
   }
 }
+
+fn error_text(category: ErrorCategory) -> Text {
+  match category {
+    ErrorCategory::Network => Text::ErrorNetwork,
+    ErrorCategory::Timeout => Text::ErrorTimeout,
+    ErrorCategory::Unauthorized => Text::ErrorUnauthorized,
+    ErrorCategory::Refused => Text::ErrorRefused,
+    ErrorCategory::Subscription => Text::ErrorSubscription,
+    ErrorCategory::Disconnected => Text::ErrorDisconnected,
+    ErrorCategory::Protocol => Text::ErrorProtocol,
+  }
+}
@@ -0,0 +1,142 @@
+use leptos::prelude::*;
+use types::export::{ExportFormat, ExportRequest};
+
+use crate::{i18n::Text, preferences::use_preferences};
+
+/// Ranges offered by the download menu, in days.
+const RANGES: [(u64, Text); 4] = [
+  (1, Text::Last24Hours),
+  (7, Text::Last7Days),
+  (30, Text::Last30Days),
+  (90, Text::Last90Days),
+];
+
+/// Devices the signed in account may export: the registered ones and the
+/// default hat.
+#[server]
+pub async fn export_devices() -> Result<Vec<String>, ServerFnError> {
+  let identity = crate::auth::current_user().ok_or_else(|| ServerFnError::new("not signed in"))?;
+  let store = use_context::<crate::store::SettingsStore>()
+    .ok_or_else(|| ServerFnError::new("settings store is not available"))?;
+  Ok(store.with(|settings| {
+    let mut devices = settings.devices.keys().cloned().collect::<Vec<_>>();
+    if !devices
+      .iter()
+      .any(|device| device == types::DEFAULT_DEVICE_ID)
+    {
+      devices.insert(0, types::DEFAULT_DEVICE_ID.to_string());
+    }
+    devices.retain(|device| identity.can_see(device, settings));
+    devices
+  }))
+}
+
+/// Menu downloading the stored history of a device, or of every device, as
+/// CSV, NDJSON, Parquet or Arrow IPC.
+#[component]
+pub fn DownloadButton(
+  /// Device of the latest sample, preselected.
+  #[prop(into)]
+  device: Signal<Option<String>>,
+) -> impl IntoView {
+  let preferences = use_preferences();
+  let devices = LocalResource::new(export_devices);
+  let selected = RwSignal::new(None::<String>);
+  let days = RwSignal::new(1u64);
+  let format = RwSignal::new(ExportFormat::Csv);
+  let derived = RwSignal::new(false);
+  let current = move || {
+    selected
+      .get()
+      .or_else(|| device.get().filter(|device| !device.is_empty()))
+      .unwrap_or_else(|| types::DEFAULT_DEVICE_ID.to_string())
+  };
+
+  let download = move |_| {
+    let to = chrono::Utc::now().timestamp() as u64;
+    let request = ExportRequest {
+      device: current(),
+      from: Some(to.saturating_sub(days.get_untracked() * 24 * 60 * 60)),
+      to: Some(to),
+      format: format.get_untracked(),
+      derived: derived.get_untracked(),
+      units: preferences.units.get_untracked(),
+    };
+    // The response is an attachment, so the page stays where it is.
+    let _ = window().location().set_href(&request.href());
+  };
+
+  view! {
+    <details class="dropdown">
+      <summary class="btn btn-sm btn-ghost">{move || preferences.t(Text::Download)}</summary>
+      <div class="dropdown-content z-10 card card-compact w-64 p-2 shadow bg-base-200">
+        <div class="card-body gap-2">
+          <select
+            class="select select-bordered select-sm"
+            prop:value=current
+            on:change=move |ev| selected.set(Some(event_target_value(&ev)))
+          >
+            <option value="">{move || preferences.t(Text::EveryDevice)}</option>
+            {move || {
+              devices
+                .get()
+                .and_then(Result::ok)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|device| {
+                  let label = device.clone();
+                  view! { <option value=device>{label}</option> }
+                })
+                .collect_view()
+            }}
+          </select>
+          <select
+            class="select select-bordered select-sm"
+            prop:value=move || days.get().to_string()
+            on:change=move |ev| {
+              if let Ok(value) = event_target_value(&ev).parse() {
+                days.set(value);
+              }
+            }
+          >
+            {RANGES
+              .into_iter()
+              .map(|(value, label)| {
+                view! { <option value=value.to_string()>{move || preferences.t(label)}</option> }
+              })
+              .collect_view()}
+          </select>
+          <select
+            class="select select-bordered select-sm"
+            prop:value=move || format.get().to_string()
+            on:change=move |ev| {
+              if let Ok(value) = event_target_value(&ev).parse() {
+                format.set(value);
+              }
+            }
+          >
+            {ExportFormat::ALL
+              .into_iter()
+              .map(|value| {
+                view! { <option value=value.to_string()>{value.label()}</option> }
+              })
+              .collect_view()}
+          </select>
+          <label class="label cursor-pointer gap-2">
+            <span class="label-text">{move || preferences.t(Text::IncludeDerived)}</span>
+            <input
+              type="checkbox"
+              class="checkbox checkbox-sm"
+              prop:checked=derived
+              on:change=move |ev| derived.set(event_target_checked(&ev))
+            />
+          </label>
+          <button class="btn btn-sm btn-primary" type="button" on:click=download>
+            {move || preferences.t(Text::Download)}
+          </button>
+        </div>
+      </div>
+    </details>
+  }
+  .into_any()
+}
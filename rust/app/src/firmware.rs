@@ -0,0 +1,534 @@
+use leptos::{prelude::*, server_fn::codec::Json};
+use types::firmware::{FirmwareOverview, Rollout, RolloutResult};
+
+use crate::{
+  i18n::Text,
+  preferences::use_preferences,
+  settings::{Section, SettingsError},
+};
+
+#[cfg(feature = "ssr")]
+pub use files::{FirmwareError, FirmwareStore};
+
+#[cfg(feature = "ssr")]
+mod files {
+  use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+  };
+
+  use sha2::{Digest, Sha256};
+  use thiserror::Error;
+  use types::{
+    commands::CommandRecord,
+    firmware::{
+      validate_version, DeviceFirmware, FirmwareImage, FirmwareOverview, Rollout, RolloutResult,
+      MAX_IMAGE_BYTES,
+    },
+  };
+
+  use crate::{commands::CommandHub, store::write_atomically};
+
+  #[derive(Debug, Error)]
+  pub enum FirmwareError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("version {0} already exists")]
+    Exists(String),
+    #[error("version {0} not found")]
+    NotFound(String),
+    #[error("the public URL of the server is not configured")]
+    NoPublicUrl,
+    #[error("firmware io: {0}")]
+    Io(#[from] io::Error),
+    #[error("firmware json: {0}")]
+    Json(#[from] serde_json::Error),
+  }
+
+  /// Firmware images in a directory, `<version>.bin` each, listed in
+  /// `index.json`. Also keeps the versions the hats report, which they
+  /// publish retained so they are known again after a restart.
+  #[derive(Debug, Clone)]
+  pub struct FirmwareStore {
+    dir: Arc<PathBuf>,
+    /// Base URL the hats download images from.
+    public_url: Option<Arc<str>>,
+    images: Arc<Mutex<Vec<FirmwareImage>>>,
+    devices: Arc<Mutex<BTreeMap<String, DeviceFirmware>>>,
+  }
+
+  impl FirmwareStore {
+    pub fn open(dir: impl Into<PathBuf>, public_url: Option<&str>) -> Result<Self, FirmwareError> {
+      let dir = dir.into();
+      let images = match fs::read(dir.join("index.json")) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+      };
+      Ok(Self {
+        dir: Arc::new(dir),
+        public_url: public_url.map(|url| Arc::from(url.trim_end_matches('/'))),
+        images: Arc::new(Mutex::new(images)),
+        devices: Arc::default(),
+      })
+    }
+
+    fn lock_images(&self) -> std::sync::MutexGuard<'_, Vec<FirmwareImage>> {
+      self.images.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_devices(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, DeviceFirmware>> {
+      self.devices.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Where the image of `version` is stored. The version must be valid.
+    pub fn image_path(&self, version: &str) -> PathBuf {
+      self.dir.join(format!("{version}.bin"))
+    }
+
+    pub fn image(&self, version: &str) -> Option<FirmwareImage> {
+      self
+        .lock_images()
+        .iter()
+        .find(|image| image.version == version)
+        .cloned()
+    }
+
+    /// Stores `bytes` as `version`, on the blocking pool. Versions are
+    /// immutable, a changed build needs a new one.
+    pub async fn add(
+      &self,
+      version: &str,
+      notes: &str,
+      uploaded_by: &str,
+      bytes: impl AsRef<[u8]> + Send + 'static,
+    ) -> Result<FirmwareImage, FirmwareError> {
+      let store = self.clone();
+      let (version, notes, uploaded_by) = (
+        version.to_string(),
+        notes.to_string(),
+        uploaded_by.to_string(),
+      );
+      tokio::task::spawn_blocking(move || {
+        store.insert(&version, &notes, &uploaded_by, bytes.as_ref())
+      })
+      .await
+      .unwrap_or_else(|e| Err(io::Error::other(e).into()))
+    }
+
+    fn insert(
+      &self,
+      version: &str,
+      notes: &str,
+      uploaded_by: &str,
+      bytes: &[u8],
+    ) -> Result<FirmwareImage, FirmwareError> {
+      validate_version(version).map_err(|e| FirmwareError::Invalid(format!("version {e}")))?;
+      if bytes.is_empty() || bytes.len() as u64 > MAX_IMAGE_BYTES {
+        return Err(FirmwareError::Invalid(format!(
+          "image must be 1 to {MAX_IMAGE_BYTES} bytes"
+        )));
+      }
+      let mut images = self.lock_images();
+      if images.iter().any(|image| image.version == version) {
+        return Err(FirmwareError::Exists(version.to_string()));
+      }
+      let image = FirmwareImage {
+        version: version.to_string(),
+        size: bytes.len() as u64,
+        sha256: Sha256::digest(bytes)
+          .iter()
+          .map(|byte| format!("{byte:02x}"))
+          .collect(),
+        uploaded_by: uploaded_by.to_string(),
+        uploaded_at: chrono::Utc::now().timestamp() as u64,
+        notes: notes.to_string(),
+      };
+      write_atomically(&self.image_path(version), bytes)?;
+      let mut next = images.clone();
+      next.push(image.clone());
+      write_atomically(
+        &self.dir.join("index.json"),
+        &serde_json::to_vec_pretty(&next)?,
+      )?;
+      *images = next;
+      Ok(image)
+    }
+
+    /// Drops `version` from the index and deletes its image, on the
+    /// blocking pool.
+    pub async fn remove(&self, version: &str) -> Result<FirmwareImage, FirmwareError> {
+      let store = self.clone();
+      let version = version.to_string();
+      tokio::task::spawn_blocking(move || store.delete(&version))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e).into()))
+    }
+
+    fn delete(&self, version: &str) -> Result<FirmwareImage, FirmwareError> {
+      let mut images = self.lock_images();
+      let Some(position) = images.iter().position(|image| image.version == version) else {
+        return Err(FirmwareError::NotFound(version.to_string()));
+      };
+      let mut next = images.clone();
+      let image = next.remove(position);
+      write_atomically(
+        &self.dir.join("index.json"),
+        &serde_json::to_vec_pretty(&next)?,
+      )?;
+      *images = next;
+      if let Err(e) = fs::remove_file(self.image_path(version)) {
+        tracing::warn!(target = "firmware", case = "remove", "{:?}", e);
+      }
+      Ok(image)
+    }
+
+    /// Records the version `device_id` reported.
+    pub fn report(&self, device_id: &str, version: &str) {
+      self.lock_devices().insert(
+        device_id.to_string(),
+        DeviceFirmware {
+          device_id: device_id.to_string(),
+          version: version.to_string(),
+          reported_at: chrono::Utc::now().timestamp() as u64,
+        },
+      );
+    }
+
+    /// Images and the versions of the hats `visible` lets through.
+    pub fn overview(&self, visible: impl Fn(&str) -> bool) -> FirmwareOverview {
+      let mut images = self.lock_images().clone();
+      images.reverse();
+      let devices = self
+        .lock_devices()
+        .values()
+        .filter(|device| visible(&device.device_id))
+        .cloned()
+        .collect();
+      FirmwareOverview { images, devices }
+    }
+
+    /// Sends the update command to each hat of `rollout`. A hat that can't
+    /// be told doesn't stop the others.
+    pub fn roll_out(
+      &self,
+      hub: &CommandHub,
+      issued_by: &str,
+      rollout: &Rollout,
+    ) -> Result<Vec<RolloutResult>, FirmwareError> {
+      let image = self
+        .image(&rollout.version)
+        .ok_or_else(|| FirmwareError::NotFound(rollout.version.clone()))?;
+      let public_url = self.public_url.as_ref().ok_or(FirmwareError::NoPublicUrl)?;
+      let command = image.command(format!("{public_url}/firmware/{}.bin", image.version));
+      Ok(
+        rollout
+          .devices
+          .iter()
+          .map(|device_id| {
+            let sent: Result<CommandRecord, _> = hub.queue(issued_by, device_id, command.clone());
+            RolloutResult {
+              device_id: device_id.clone(),
+              error: sent.as_ref().err().map(ToString::to_string),
+              record: sent.ok(),
+            }
+          })
+          .collect(),
+      )
+    }
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn images_are_added_once_and_removed() {
+      let dir = std::env::temp_dir().join(format!("hat-monitor-firmware-{}", std::process::id()));
+      let _ = fs::remove_dir_all(&dir);
+      fs::create_dir_all(&dir).unwrap();
+      let store = FirmwareStore::open(&dir, None).unwrap();
+      let image = store
+        .add("1.0.0", "first", "admin", b"image".to_vec())
+        .await
+        .unwrap();
+      assert_eq!(image.size, 5);
+      assert_eq!(image.sha256.len(), 64);
+      assert_eq!(fs::read(store.image_path("1.0.0")).unwrap(), b"image");
+      assert!(matches!(
+        store.add("1.0.0", "", "admin", b"other".to_vec()).await,
+        Err(FirmwareError::Exists(_))
+      ));
+      for (version, bytes) in [("../x", b"image".to_vec()), ("1.0.1", Vec::new())] {
+        assert!(matches!(
+          store.add(version, "", "admin", bytes).await,
+          Err(FirmwareError::Invalid(_))
+        ));
+      }
+
+      let reopened = FirmwareStore::open(&dir, None).unwrap();
+      assert_eq!(reopened.image("1.0.0"), Some(image));
+      reopened.remove("1.0.0").await.unwrap();
+      assert!(!reopened.image_path("1.0.0").exists());
+      assert!(matches!(
+        reopened.remove("1.0.0").await,
+        Err(FirmwareError::NotFound(_))
+      ));
+      assert_eq!(
+        FirmwareStore::open(&dir, None).unwrap().image("1.0.0"),
+        None
+      );
+      fs::remove_dir_all(&dir).unwrap();
+    }
+  }
+}
+
+#[cfg(feature = "ssr")]
+impl From<FirmwareError> for SettingsError {
+  fn from(e: FirmwareError) -> Self {
+    match e {
+      FirmwareError::Io(_) | FirmwareError::Json(_) => SettingsError::Server(e.to_string()),
+      e => {
+        let mut errors = types::settings::FieldErrors::default();
+        errors.push("version", e.to_string());
+        SettingsError::Invalid(errors)
+      }
+    }
+  }
+}
+
+#[cfg(feature = "ssr")]
+fn firmware_store() -> Result<FirmwareStore, SettingsError> {
+  use_context::<FirmwareStore>()
+    .ok_or_else(|| SettingsError::Server("firmware is not available".to_string()))
+}
+
+#[server(input = Json)]
+pub async fn get_firmware() -> Result<FirmwareOverview, SettingsError> {
+  let (identity, _) = crate::accounts::require_role(types::accounts::Role::Operator)?;
+  let settings = use_context::<crate::store::SettingsStore>()
+    .ok_or_else(|| SettingsError::Server("settings store is not available".to_string()))?
+    .current();
+  Ok(firmware_store()?.overview(|device_id| identity.can_see(device_id, &settings)))
+}
+
+#[server(input = Json)]
+pub async fn roll_out_firmware(rollout: Rollout) -> Result<Vec<RolloutResult>, SettingsError> {
+  let (identity, _) = crate::accounts::require_role(types::accounts::Role::Admin)?;
+  let hub = use_context::<crate::commands::CommandHub>()
+    .ok_or_else(|| SettingsError::Server("commands are not available".to_string()))?;
+  let results = firmware_store()?.roll_out(&hub, &identity.username, &rollout)?;
+  if let Some(log) = use_context::<crate::audit::AuditLog>() {
+    for record in results.iter().filter_map(|result| result.record.as_ref()) {
+      crate::commands::audit(&log, &identity.username, record).await;
+    }
+  }
+  Ok(results)
+}
+
+#[server(input = Json)]
+pub async fn remove_firmware(version: String) -> Result<(), SettingsError> {
+  crate::accounts::require_role(types::accounts::Role::Admin)?;
+  let image = firmware_store()?.remove(&version).await?;
+  crate::audit::record(
+    types::audit::AuditAction::FirmwareRemoved,
+    None,
+    format!("{} {}", image.version, image.sha256),
+  )
+  .await;
+  Ok(())
+}
+
+/// Hosted images, the version each hat runs and staged rollouts. Images are
+/// uploaded with `POST /api/firmware/<version>`.
+#[component]
+pub(crate) fn FirmwareSection(devices: Vec<String>) -> impl IntoView {
+  let preferences = use_preferences();
+  let roll_out = ServerAction::<RollOutFirmware>::new();
+  let remove = ServerAction::<RemoveFirmware>::new();
+  let overview = Resource::new(
+    move || (roll_out.version().get(), remove.version().get()),
+    |_| get_firmware(),
+  );
+  let version = RwSignal::new(String::new());
+  let selected = RwSignal::new(Vec::<String>::new());
+
+  view! {
+    <Section title=Text::Firmware>
+      <Transition>
+        {move || {
+          let current = overview.get().and_then(Result::ok).unwrap_or_default();
+          let running = current.devices.clone();
+          view! {
+            <table class="table table-sm">
+              <thead>
+                <tr>
+                  <th>{move || preferences.t(Text::Version)}</th>
+                  <th>{move || preferences.t(Text::Size)}</th>
+                  <th>"SHA-256"</th>
+                  <th>{move || preferences.t(Text::Time)}</th>
+                  <th>{move || preferences.t(Text::Detail)}</th>
+                  <th></th>
+                </tr>
+              </thead>
+              <tbody>
+                {current
+                  .images
+                  .into_iter()
+                  .map(|image| {
+                    let removed = image.version.clone();
+                    view! {
+                      <tr>
+                        <td class="font-mono">{image.version}</td>
+                        <td>{format!("{} KiB", image.size.div_ceil(1024))}</td>
+                        <td class="font-mono" title=image.sha256.clone()>
+                          {image.sha256.get(..12).unwrap_or(&image.sha256).to_string()}
+                        </td>
+                        <td class="font-mono">{preferences.format_timestamp(image.uploaded_at)}</td>
+                        <td>{image.notes}</td>
+                        <td>
+                          <button
+                            class="btn btn-sm btn-ghost"
+                            disabled=remove.pending()
+                            on:click=move |_| {
+                              remove.dispatch(RemoveFirmware { version: removed.clone() });
+                            }
+                          >
+                            {move || preferences.t(Text::Remove)}
+                          </button>
+                        </td>
+                      </tr>
+                    }
+                  })
+                  .collect_view()}
+              </tbody>
+            </table>
+            <table class="table table-sm">
+              <thead>
+                <tr>
+                  <th></th>
+                  <th>{move || preferences.t(Text::Device)}</th>
+                  <th>{move || preferences.t(Text::Version)}</th>
+                </tr>
+              </thead>
+              <tbody>
+                {devices
+                  .clone()
+                  .into_iter()
+                  .chain(
+                    running
+                      .iter()
+                      .map(|device| device.device_id.clone())
+                      .filter(|device_id| !devices.contains(device_id))
+                      .collect::<Vec<_>>(),
+                  )
+                  .map(|device_id| {
+                    let reported = running
+                      .iter()
+                      .find(|device| device.device_id == device_id)
+                      .map(|device| device.version.clone());
+                    let checked = device_id.clone();
+                    let toggled = device_id.clone();
+                    view! {
+                      <tr>
+                        <td>
+                          <input
+                            type="checkbox"
+                            class="checkbox checkbox-sm"
+                            prop:checked=move || selected.with(|selected| selected.contains(&checked))
+                            on:change=move |ev| {
+                              let on = event_target_checked(&ev);
+                              selected
+                                .update(|selected| {
+                                  selected.retain(|device_id| *device_id != toggled);
+                                  if on {
+                                    selected.push(toggled.clone());
+                                  }
+                                });
+                            }
+                          />
+                        </td>
+                        <td>{device_id}</td>
+                        <td class="font-mono">{reported.unwrap_or_else(|| "—".to_string())}</td>
+                      </tr>
+                    }
+                  })
+                  .collect_view()}
+              </tbody>
+            </table>
+          }
+        }}
+      </Transition>
+      <div class="card-actions items-center justify-between">
+        <span>
+          {move || match roll_out.value().get() {
+            Some(Ok(results)) => {
+              results
+                .into_iter()
+                .map(|result| {
+                  let (class, text) = match result.error {
+                    Some(error) => ("text-error", error),
+                    None => ("text-success", preferences.t(Text::Sent).to_string()),
+                  };
+                  view! { <div class=class>{format!("{}: {text}", result.device_id)}</div> }
+                })
+                .collect_view()
+                .into_any()
+            }
+            Some(Err(SettingsError::Invalid(errors))) => {
+              view! { <span class="text-error">{errors.get("version").map(str::to_string)}</span> }
+                .into_any()
+            }
+            Some(Err(e)) => view! { <span class="text-error">{e.to_string()}</span> }.into_any(),
+            None => ().into_any(),
+          }}
+        </span>
+        <div class="flex gap-2">
+          <select
+            class="select select-bordered select-sm"
+            prop:value=version
+            on:change=move |ev| version.set(event_target_value(&ev))
+          >
+            {move || {
+              overview
+                .get()
+                .and_then(Result::ok)
+                .unwrap_or_default()
+                .images
+                .into_iter()
+                .map(|image| view! { <option value=image.version.clone()>{image.version.clone()}</option> })
+                .collect_view()
+            }}
+          </select>
+          <button
+            class="btn btn-sm btn-primary"
+            disabled=move || roll_out.pending().get() || selected.with(Vec::is_empty)
+            on:click=move |_| {
+              // The select shows the newest image until another is picked.
+              let version = Some(version.get_untracked())
+                .filter(|version| !version.is_empty())
+                .or_else(|| {
+                  overview
+                    .get_untracked()
+                    .and_then(Result::ok)
+                    .and_then(|current| current.images.first().map(|image| image.version.clone()))
+                })
+                .unwrap_or_default();
+              roll_out
+                .dispatch(RollOutFirmware {
+                  rollout: Rollout {
+                    version,
+                    devices: selected.get_untracked(),
+                  },
+                });
+            }
+          >
+            {move || preferences.t(Text::RollOut)}
+          </button>
+        </div>
+      </div>
+    </Section>
+  }
+}
@@ -5,9 +5,7 @@ use charming::{
   Chart, WasmRenderer,
 };
 use leptos::prelude::*;
-use leptos_use::{
-  use_interval_fn_with_options, utils::Pausable, UseIntervalFnOptions,
-};
+use leptos_use::{use_interval_fn_with_options, utils::Pausable, UseIntervalFnOptions};
 
 #[component]
 pub fn Graph() -> impl IntoView {
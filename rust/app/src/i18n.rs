@@ -0,0 +1,662 @@
+use leptos::{prelude::*, server::codee::string::FromToStringCodec};
+use leptos_use::{use_cookie_with_options, UseCookieOptions};
+
+use crate::locale::Locale;
+
+/// Cookie mirroring the chosen language so the server renders in it too.
+pub const LOCALE_COOKIE: &str = "hat-monitor-lang";
+const LOCALE_COOKIE_MAX_AGE_MS: i64 = 365 * 24 * 60 * 60 * 1000;
+
+/// Every user-visible string on the dashboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+  DocumentTitle,
+  Heading,
+  PageNotFound,
+  Online,
+  Offline,
+  Connecting,
+  Live,
+  WaitingForData,
+  UpdatedAt,
+  InvalidTime,
+  BrowserTimeZone,
+  Language,
+  Temperature,
+  SafeRange,
+  Humidity,
+  RelativeHumidity,
+  AirQuality,
+  Calibrating,
+  UncorrectedPpm,
+  Resistance,
+  CorrectedRZero,
+  Pm25,
+  Pressure,
+  Light,
+  NoReading,
+  SensorError,
+  /// Title of a metric's chart, `{}` being the metric.
+  MetricChart,
+  Settings,
+  Dashboard,
+  Thresholds,
+  Device,
+  AllDevices,
+  Low,
+  Warning,
+  Critical,
+  Save,
+  Saved,
+  Devices,
+  Name,
+  Location,
+  Notes,
+  AddDevice,
+  Remove,
+  Notifications,
+  WebhookUrl,
+  MinSeverity,
+  TargetUnits,
+  ServerUnitsOption,
+  Enabled,
+  AddTarget,
+  Retention,
+  RetentionDays,
+  ServerUnits,
+  Unauthorized,
+  FixErrors,
+  SignIn,
+  SignOut,
+  Username,
+  Password,
+  InvalidCredentials,
+  Account,
+  ChangePassword,
+  CurrentPassword,
+  NewPassword,
+  ApiTokens,
+  TokenName,
+  CreateToken,
+  TokenCreated,
+  Revoke,
+  Created,
+  Users,
+  AddUser,
+  Role,
+  Viewer,
+  Operator,
+  Admin,
+  Locations,
+  Forbidden,
+  AuditLog,
+  Filter,
+  Actor,
+  Action,
+  AnyAction,
+  From,
+  To,
+  ExportJson,
+  Time,
+  Detail,
+  Download,
+  IncludeDerived,
+  EveryDevice,
+  BrokerConnecting,
+  BrokerSubscribing,
+  BrokerReceiving,
+  BrokerRetrying,
+  BrokerReplaying,
+  BrokerDegraded,
+  BrokerStopped,
+  ErrorNetwork,
+  ErrorTimeout,
+  ErrorUnauthorized,
+  ErrorRefused,
+  ErrorSubscription,
+  ErrorDisconnected,
+  ErrorProtocol,
+  Last24Hours,
+  Last7Days,
+  Last30Days,
+  Last90Days,
+  Commands,
+  Interval,
+  Calibrate,
+  RZero,
+  Reboot,
+  Identify,
+  Send,
+  Sent,
+  Pending,
+  Acknowledged,
+  Failed,
+  TimedOut,
+  IssuedBy,
+  Seconds,
+  Minutes,
+  Firmware,
+  Version,
+  Size,
+  RollOut,
+  Provisioning,
+  Claimed,
+  Active,
+  Unclaimed,
+  Revoked,
+  Expires,
+  CreateClaimCode,
+}
+
+impl Text {
+  pub fn translate(self, locale: Locale) -> &'static str {
+    match locale {
+      Locale::Vi => vi(self),
+      Locale::En => en(self),
+    }
+  }
+}
+
+fn vi(text: Text) -> &'static str {
+  match text {
+    Text::DocumentTitle => "Theo dõi độ ẩm, chất lượng không khí và nhiệt độ",
+    Text::Heading => "Giám sát độ ẩm, chất lượng không khí và nhiệt độ",
+    Text::PageNotFound => "Không tìm thấy trang.",
+    Text::Online => "Trực tuyến",
+    Text::Offline => "Ngoại tuyến",
+    Text::Connecting => "Đang kết nối...",
+    Text::Live => "TRỰC TIẾP",
+    Text::WaitingForData => "Đang chờ dữ liệu...",
+    Text::UpdatedAt => "Cập nhật lúc: ",
+    Text::InvalidTime => "Thời gian không hợp lệ",
+    Text::BrowserTimeZone => "Múi giờ trình duyệt",
+    Text::Language => "Ngôn ngữ",
+    Text::Temperature => "Nhiệt độ",
+    Text::SafeRange => "Ngưỡng an toàn",
+    Text::Humidity => "Độ ẩm",
+    Text::RelativeHumidity => "Độ ẩm tương đối (RH)",
+    Text::AirQuality => "Chất lượng khí (CO2)",
+    Text::Calibrating => "Đang hiệu chuẩn...",
+    Text::UncorrectedPpm => "CO2, chưa hiệu chỉnh",
+    Text::Resistance => "Điện trở",
+    Text::CorrectedRZero => "R0, đã hiệu chỉnh",
+    Text::Pm25 => "Bụi mịn PM2.5",
+    Text::Pressure => "Áp suất",
+    Text::Light => "Ánh sáng",
+    Text::NoReading => "Không có số đo",
+    Text::SensorError => "Lỗi cảm biến",
+    Text::MetricChart => "Biểu đồ {}",
+    Text::Settings => "Cài đặt",
+    Text::Dashboard => "Bảng điều khiển",
+    Text::Thresholds => "Ngưỡng cảnh báo",
+    Text::Device => "Thiết bị",
+    Text::AllDevices => "Mặc định cho mọi thiết bị",
+    Text::Low => "Thấp",
+    Text::Warning => "Cảnh báo",
+    Text::Critical => "Nguy hiểm",
+    Text::Save => "Lưu",
+    Text::Saved => "Đã lưu",
+    Text::Devices => "Thiết bị",
+    Text::Name => "Tên",
+    Text::Location => "Vị trí",
+    Text::Notes => "Ghi chú",
+    Text::AddDevice => "Thêm thiết bị",
+    Text::Remove => "Xoá",
+    Text::Notifications => "Thông báo",
+    Text::WebhookUrl => "Webhook URL",
+    Text::MinSeverity => "Mức tối thiểu",
+    Text::TargetUnits => "Đơn vị",
+    Text::ServerUnitsOption => "Như máy chủ",
+    Text::Enabled => "Bật",
+    Text::AddTarget => "Thêm đích thông báo",
+    Text::Retention => "Lưu trữ dữ liệu",
+    Text::RetentionDays => "Số ngày lưu (0 = giữ mãi)",
+    Text::ServerUnits => "Đơn vị cho cảnh báo và xuất dữ liệu",
+    Text::Unauthorized => "Bạn cần đăng nhập để xem cài đặt.",
+    Text::FixErrors => "Vui lòng sửa các trường được đánh dấu.",
+    Text::SignIn => "Đăng nhập",
+    Text::SignOut => "Đăng xuất",
+    Text::Username => "Tên đăng nhập",
+    Text::Password => "Mật khẩu",
+    Text::InvalidCredentials => "Sai tên đăng nhập hoặc mật khẩu.",
+    Text::Account => "Tài khoản",
+    Text::ChangePassword => "Đổi mật khẩu",
+    Text::CurrentPassword => "Mật khẩu hiện tại",
+    Text::NewPassword => "Mật khẩu mới",
+    Text::ApiTokens => "API token",
+    Text::TokenName => "Tên token",
+    Text::CreateToken => "Tạo token",
+    Text::TokenCreated => "Hãy sao chép token này ngay, nó sẽ không được hiển thị lại:",
+    Text::Revoke => "Thu hồi",
+    Text::Created => "Ngày tạo",
+    Text::Users => "Người dùng",
+    Text::AddUser => "Thêm người dùng",
+    Text::Role => "Vai trò",
+    Text::Viewer => "Người xem",
+    Text::Operator => "Vận hành",
+    Text::Admin => "Quản trị",
+    Text::Locations => "Vị trí",
+    Text::Forbidden => "Tài khoản của bạn không có quyền chỉnh sửa cài đặt.",
+    Text::AuditLog => "Nhật ký kiểm tra",
+    Text::Filter => "Bộ lọc",
+    Text::Actor => "Người thực hiện",
+    Text::Action => "Hành động",
+    Text::AnyAction => "Mọi hành động",
+    Text::From => "Từ ngày",
+    Text::To => "Đến ngày",
+    Text::ExportJson => "Xuất JSON",
+    Text::Time => "Thời gian",
+    Text::Detail => "Chi tiết",
+    Text::Download => "Tải xuống",
+    Text::IncludeDerived => "Kèm chỉ số dẫn xuất (điểm sương, độ ẩm tuyệt đối, chỉ số nóng)",
+    Text::EveryDevice => "Tất cả thiết bị",
+    Text::BrokerConnecting => "MQTT: đang kết nối",
+    Text::BrokerSubscribing => "MQTT: đang đăng ký",
+    Text::BrokerReceiving => "MQTT: đang nhận",
+    Text::BrokerRetrying => "MQTT: thử lại lúc",
+    Text::BrokerReplaying => "Phát lại bản ghi",
+    Text::BrokerDegraded => "MQTT: một số đăng ký bị từ chối",
+    Text::BrokerStopped => "MQTT: đã dừng",
+    Text::ErrorNetwork => "Lỗi mạng",
+    Text::ErrorTimeout => "Hết thời gian chờ",
+    Text::ErrorUnauthorized => "Không được phép",
+    Text::ErrorRefused => "Bị từ chối kết nối",
+    Text::ErrorSubscription => "Đăng ký bị từ chối",
+    Text::ErrorDisconnected => "Broker đã ngắt kết nối",
+    Text::ErrorProtocol => "Lỗi giao thức",
+    Text::Last24Hours => "24 giờ qua",
+    Text::Last7Days => "7 ngày qua",
+    Text::Last30Days => "30 ngày qua",
+    Text::Last90Days => "90 ngày qua",
+    Text::Commands => "Lệnh thiết bị",
+    Text::Interval => "Chu kỳ gửi",
+    Text::Calibrate => "Hiệu chuẩn",
+    Text::RZero => "R0",
+    Text::Reboot => "Khởi động lại",
+    Text::Identify => "Nháy đèn nhận diện",
+    Text::Send => "Gửi",
+    Text::Sent => "Đã gửi",
+    Text::Pending => "Đang chờ",
+    Text::Acknowledged => "Đã xác nhận",
+    Text::Failed => "Thất bại",
+    Text::TimedOut => "Hết thời gian chờ",
+    Text::IssuedBy => "Người gửi",
+    Text::Seconds => "giây",
+    Text::Minutes => "phút",
+    Text::Firmware => "Firmware",
+    Text::Version => "Phiên bản",
+    Text::Size => "Kích thước",
+    Text::RollOut => "Cập nhật các thiết bị đã chọn",
+    Text::Provisioning => "Cấp phép thiết bị",
+    Text::Claimed => "Kích hoạt lúc",
+    Text::Active => "Đang hoạt động",
+    Text::Unclaimed => "Chưa kích hoạt",
+    Text::Revoked => "Đã thu hồi",
+    Text::Expires => "hết hạn",
+    Text::CreateClaimCode => "Tạo mã kích hoạt",
+  }
+}
+
+fn en(text: Text) -> &'static str {
+  match text {
+    Text::DocumentTitle => "Welcome to humidity, air quality and temperature monitor",
+    Text::Heading => "Humidity, air quality and temperature monitor",
+    Text::PageNotFound => "Page not found.",
+    Text::Online => "Online",
+    Text::Offline => "Offline",
+    Text::Connecting => "Connecting...",
+    Text::Live => "LIVE",
+    Text::WaitingForData => "Waiting for data...",
+    Text::UpdatedAt => "Updated at: ",
+    Text::InvalidTime => "Invalid Time",
+    Text::BrowserTimeZone => "Browser time zone",
+    Text::Language => "Language",
+    Text::Temperature => "Temperature",
+    Text::SafeRange => "Safe range",
+    Text::Humidity => "Humidity",
+    Text::RelativeHumidity => "Relative humidity (RH)",
+    Text::AirQuality => "Air quality (CO2)",
+    Text::Calibrating => "Calibrating...",
+    Text::UncorrectedPpm => "CO2, uncorrected",
+    Text::Resistance => "Resistance",
+    Text::CorrectedRZero => "R0, corrected",
+    Text::Pm25 => "PM2.5",
+    Text::Pressure => "Pressure",
+    Text::Light => "Light",
+    Text::NoReading => "No reading",
+    Text::SensorError => "Sensor error",
+    Text::MetricChart => "{} chart",
+    Text::Settings => "Settings",
+    Text::Dashboard => "Dashboard",
+    Text::Thresholds => "Thresholds",
+    Text::Device => "Device",
+    Text::AllDevices => "Default for all devices",
+    Text::Low => "Low",
+    Text::Warning => "Warning",
+    Text::Critical => "Critical",
+    Text::Save => "Save",
+    Text::Saved => "Saved",
+    Text::Devices => "Devices",
+    Text::Name => "Name",
+    Text::Location => "Location",
+    Text::Notes => "Notes",
+    Text::AddDevice => "Add device",
+    Text::Remove => "Remove",
+    Text::Notifications => "Notifications",
+    Text::WebhookUrl => "Webhook URL",
+    Text::MinSeverity => "Minimum severity",
+    Text::TargetUnits => "Units",
+    Text::ServerUnitsOption => "Server units",
+    Text::Enabled => "Enabled",
+    Text::AddTarget => "Add target",
+    Text::Retention => "Data retention",
+    Text::RetentionDays => "Days to keep (0 = forever)",
+    Text::ServerUnits => "Units for alerts and exports",
+    Text::Unauthorized => "You need to sign in to view settings.",
+    Text::FixErrors => "Please fix the highlighted fields.",
+    Text::SignIn => "Sign in",
+    Text::SignOut => "Sign out",
+    Text::Username => "Username",
+    Text::Password => "Password",
+    Text::InvalidCredentials => "Invalid username or password.",
+    Text::Account => "Account",
+    Text::ChangePassword => "Change password",
+    Text::CurrentPassword => "Current password",
+    Text::NewPassword => "New password",
+    Text::ApiTokens => "API tokens",
+    Text::TokenName => "Token name",
+    Text::CreateToken => "Create token",
+    Text::TokenCreated => "Copy this token now, it won't be shown again:",
+    Text::Revoke => "Revoke",
+    Text::Created => "Created",
+    Text::Users => "Users",
+    Text::AddUser => "Add user",
+    Text::Role => "Role",
+    Text::Viewer => "Viewer",
+    Text::Operator => "Operator",
+    Text::Admin => "Admin",
+    Text::Locations => "Locations",
+    Text::Forbidden => "Your account isn't allowed to change settings.",
+    Text::AuditLog => "Audit log",
+    Text::Filter => "Filter",
+    Text::Actor => "Actor",
+    Text::Action => "Action",
+    Text::AnyAction => "Any action",
+    Text::From => "From",
+    Text::To => "To",
+    Text::ExportJson => "Export JSON",
+    Text::Time => "Time",
+    Text::Detail => "Detail",
+    Text::Download => "Download",
+    Text::IncludeDerived => "Include derived metrics (dew point, absolute humidity, heat index)",
+    Text::EveryDevice => "Every device",
+    Text::BrokerConnecting => "MQTT: connecting",
+    Text::BrokerSubscribing => "MQTT: subscribing",
+    Text::BrokerReceiving => "MQTT: receiving",
+    Text::BrokerRetrying => "MQTT: retrying at",
+    Text::BrokerReplaying => "Replaying recording",
+    Text::BrokerDegraded => "MQTT: some subscriptions rejected",
+    Text::BrokerStopped => "MQTT: stopped",
+    Text::ErrorNetwork => "Network error",
+    Text::ErrorTimeout => "Timed out",
+    Text::ErrorUnauthorized => "Not authorized",
+    Text::ErrorRefused => "Connection refused",
+    Text::ErrorSubscription => "Subscription rejected",
+    Text::ErrorDisconnected => "Disconnected by the broker",
+    Text::ErrorProtocol => "Protocol error",
+    Text::Last24Hours => "Last 24 hours",
+    Text::Last7Days => "Last 7 days",
+    Text::Last30Days => "Last 30 days",
+    Text::Last90Days => "Last 90 days",
+    Text::Commands => "Device commands",
+    Text::Interval => "Publish interval",
+    Text::Calibrate => "Calibrate",
+    Text::RZero => "R0",
+    Text::Reboot => "Reboot",
+    Text::Identify => "Blink to identify",
+    Text::Send => "Send",
+    Text::Sent => "Sent",
+    Text::Pending => "Pending",
+    Text::Acknowledged => "Acknowledged",
+    Text::Failed => "Failed",
+    Text::TimedOut => "Timed out",
+    Text::IssuedBy => "Sent by",
+    Text::Seconds => "seconds",
+    Text::Minutes => "minutes",
+    Text::Firmware => "Firmware",
+    Text::Version => "Version",
+    Text::Size => "Size",
+    Text::RollOut => "Update selected devices",
+    Text::Provisioning => "Provisioning",
+    Text::Claimed => "Claimed",
+    Text::Active => "Active",
+    Text::Unclaimed => "Unclaimed",
+    Text::Revoked => "Revoked",
+    Text::Expires => "expires",
+    Text::CreateClaimCode => "Create claim code",
+  }
+}
+
+/// Human readable name of a locale, written in that locale.
+pub fn native_name(locale: Locale) -> &'static str {
+  match locale {
+    Locale::Vi => "Tiếng Việt",
+    Locale::En => "English",
+  }
+}
+
+pub(crate) fn use_locale_cookie() -> (Signal<Option<Locale>>, WriteSignal<Option<Locale>>) {
+  use_cookie_with_options::<Locale, FromToStringCodec>(
+    LOCALE_COOKIE,
+    UseCookieOptions::default()
+      .path("/".to_string())
+      .max_age(LOCALE_COOKIE_MAX_AGE_MS),
+  )
+}
+
+/// Locale the page is rendered in: the language cookie if present, otherwise
+/// the request's `Accept-Language` on the server, or the `lang` attribute the
+/// server stamped on `<html>` when hydrating.
+pub fn negotiate_locale() -> Locale {
+  let (cookie, _) = use_locale_cookie();
+  if let Some(locale) = cookie.get_untracked() {
+    return locale;
+  }
+  rendered_locale()
+}
+
+#[cfg(feature = "ssr")]
+fn rendered_locale() -> Locale {
+  Locale::negotiate(&leptos_use::use_locales().get_untracked())
+}
+
+#[cfg(not(feature = "ssr"))]
+fn rendered_locale() -> Locale {
+  document()
+    .document_element()
+    .and_then(|html| html.get_attribute("lang"))
+    .and_then(|tag| Locale::from_tag(&tag))
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Every [`Text`], checked against the declaration of the enum.
+  const ALL: [Text; 135] = [
+    Text::DocumentTitle,
+    Text::Heading,
+    Text::PageNotFound,
+    Text::Online,
+    Text::Offline,
+    Text::Connecting,
+    Text::Live,
+    Text::WaitingForData,
+    Text::UpdatedAt,
+    Text::InvalidTime,
+    Text::BrowserTimeZone,
+    Text::Language,
+    Text::Temperature,
+    Text::SafeRange,
+    Text::Humidity,
+    Text::RelativeHumidity,
+    Text::AirQuality,
+    Text::Calibrating,
+    Text::UncorrectedPpm,
+    Text::Resistance,
+    Text::CorrectedRZero,
+    Text::Pm25,
+    Text::Pressure,
+    Text::Light,
+    Text::NoReading,
+    Text::SensorError,
+    Text::MetricChart,
+    Text::Settings,
+    Text::Dashboard,
+    Text::Thresholds,
+    Text::Device,
+    Text::AllDevices,
+    Text::Low,
+    Text::Warning,
+    Text::Critical,
+    Text::Save,
+    Text::Saved,
+    Text::Devices,
+    Text::Name,
+    Text::Location,
+    Text::Notes,
+    Text::AddDevice,
+    Text::Remove,
+    Text::Notifications,
+    Text::WebhookUrl,
+    Text::MinSeverity,
+    Text::TargetUnits,
+    Text::ServerUnitsOption,
+    Text::Enabled,
+    Text::AddTarget,
+    Text::Retention,
+    Text::RetentionDays,
+    Text::ServerUnits,
+    Text::Unauthorized,
+    Text::FixErrors,
+    Text::SignIn,
+    Text::SignOut,
+    Text::Username,
+    Text::Password,
+    Text::InvalidCredentials,
+    Text::Account,
+    Text::ChangePassword,
+    Text::CurrentPassword,
+    Text::NewPassword,
+    Text::ApiTokens,
+    Text::TokenName,
+    Text::CreateToken,
+    Text::TokenCreated,
+    Text::Revoke,
+    Text::Created,
+    Text::Users,
+    Text::AddUser,
+    Text::Role,
+    Text::Viewer,
+    Text::Operator,
+    Text::Admin,
+    Text::Locations,
+    Text::Forbidden,
+    Text::AuditLog,
+    Text::Filter,
+    Text::Actor,
+    Text::Action,
+    Text::AnyAction,
+    Text::From,
+    Text::To,
+    Text::ExportJson,
+    Text::Time,
+    Text::Detail,
+    Text::Download,
+    Text::IncludeDerived,
+    Text::EveryDevice,
+    Text::BrokerConnecting,
+    Text::BrokerSubscribing,
+    Text::BrokerReceiving,
+    Text::BrokerRetrying,
+    Text::BrokerReplaying,
+    Text::BrokerDegraded,
+    Text::BrokerStopped,
+    Text::ErrorNetwork,
+    Text::ErrorTimeout,
+    Text::ErrorUnauthorized,
+    Text::ErrorRefused,
+    Text::ErrorSubscription,
+    Text::ErrorDisconnected,
+    Text::ErrorProtocol,
+    Text::Last24Hours,
+    Text::Last7Days,
+    Text::Last30Days,
+    Text::Last90Days,
+    Text::Commands,
+    Text::Interval,
+    Text::Calibrate,
+    Text::RZero,
+    Text::Reboot,
+    Text::Identify,
+    Text::Send,
+    Text::Sent,
+    Text::Pending,
+    Text::Acknowledged,
+    Text::Failed,
+    Text::TimedOut,
+    Text::IssuedBy,
+    Text::Seconds,
+    Text::Minutes,
+    Text::Firmware,
+    Text::Version,
+    Text::Size,
+    Text::RollOut,
+    Text::Provisioning,
+    Text::Claimed,
+    Text::Active,
+    Text::Unclaimed,
+    Text::Revoked,
+    Text::Expires,
+    Text::CreateClaimCode,
+  ];
+
+  /// Names of the variants as declared in this file.
+  fn declared() -> Vec<&'static str> {
+    let source = include_str!("i18n.rs");
+    let start = source.find("pub enum Text {").unwrap();
+    source[start..]
+      .lines()
+      .skip(1)
+      .map(str::trim)
+      .take_while(|line| *line != "}")
+      .filter(|line| !line.is_empty() && !line.starts_with("//"))
+      .map(|line| line.trim_end_matches(','))
+      .collect()
+  }
+
+  #[test]
+  fn every_text_is_listed() {
+    let listed = ALL.map(|text| format!("{text:?}"));
+    assert_eq!(listed.as_slice(), declared().as_slice());
+  }
+
+  #[test]
+  fn every_text_is_translated_in_every_locale() {
+    for text in ALL {
+      let placeholders = |locale| text.translate(locale).matches("{}").count();
+      for locale in Locale::ALL {
+        assert!(
+          !text.translate(locale).trim().is_empty(),
+          "{text:?} in {locale}"
+        );
+        assert_eq!(
+          placeholders(locale),
+          placeholders(Locale::default()),
+          "{text:?} in {locale}"
+        );
+      }
+    }
+  }
+}
@@ -0,0 +1,245 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::i18n::Text;
+
+/// Locale used to format numbers and dates on the dashboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+  #[default]
+  Vi,
+  En,
+}
+
+impl Locale {
+  pub const ALL: [Locale; 2] = [Locale::Vi, Locale::En];
+
+  pub fn tag(self) -> &'static str {
+    match self {
+      Locale::Vi => "vi",
+      Locale::En => "en",
+    }
+  }
+
+  /// Matches a BCP 47 tag such as `en-US` or `vi` on its primary language.
+  pub fn from_tag(tag: &str) -> Option<Self> {
+    let primary = tag.split(['-', '_']).next()?.trim();
+    Locale::ALL
+      .into_iter()
+      .find(|locale| locale.tag().eq_ignore_ascii_case(primary))
+  }
+
+  /// Picks the first supported locale from a preference-ordered list of tags.
+  #[cfg(feature = "ssr")]
+  pub fn negotiate<S: AsRef<str>>(tags: &[S]) -> Self {
+    tags
+      .iter()
+      .find_map(|tag| Locale::from_tag(tag.as_ref()))
+      .unwrap_or_default()
+  }
+
+  fn decimal_separator(self) -> char {
+    match self {
+      Locale::Vi => ',',
+      Locale::En => '.',
+    }
+  }
+
+  fn group_separator(self) -> char {
+    match self {
+      Locale::Vi => '.',
+      Locale::En => ',',
+    }
+  }
+
+  fn datetime_format(self) -> &'static str {
+    match self {
+      Locale::Vi => "%d/%m/%Y %H:%M:%S (UTC%:z)",
+      Locale::En => "%Y-%m-%d %H:%M:%S (UTC%:z)",
+    }
+  }
+
+  /// Formats `value` with `precision` fraction digits and the locale's
+  /// grouping and decimal separators, e.g. `1.234,5` in Vietnamese.
+  pub fn format_number(self, value: f32, precision: usize) -> String {
+    if !value.is_finite() {
+      return "--".to_string();
+    }
+    let raw = format!("{:.*}", precision, value.abs());
+    let (int_part, frac_part) = match raw.split_once('.') {
+      Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+      None => (raw.as_str(), None),
+    };
+
+    let mut out = String::with_capacity(raw.len() + int_part.len() / 3 + 1);
+    if value.is_sign_negative() && raw.bytes().any(|b| b.is_ascii_digit() && b != b'0') {
+      out.push('-');
+    }
+    for (i, digit) in int_part.chars().enumerate() {
+      if i > 0 && (int_part.len() - i) % 3 == 0 {
+        out.push(self.group_separator());
+      }
+      out.push(digit);
+    }
+    if let Some(frac_part) = frac_part {
+      out.push(self.decimal_separator());
+      out.push_str(frac_part);
+    }
+    out
+  }
+}
+
+impl fmt::Display for Locale {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.tag())
+  }
+}
+
+impl FromStr for Locale {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Locale::from_tag(s).ok_or_else(|| format!("unsupported locale: {s}"))
+  }
+}
+
+/// Time zone timestamps are rendered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayZone {
+  /// Whatever zone the browser reports for the given instant.
+  #[default]
+  Browser,
+  /// A user-chosen IANA zone such as `Asia/Ho_Chi_Minh`.
+  Named(Tz),
+}
+
+impl fmt::Display for DisplayZone {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DisplayZone::Browser => f.write_str("browser"),
+      DisplayZone::Named(tz) => f.write_str(tz.name()),
+    }
+  }
+}
+
+impl FromStr for DisplayZone {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() || s == "browser" {
+      return Ok(DisplayZone::Browser);
+    }
+    s.parse::<Tz>()
+      .map(DisplayZone::Named)
+      .map_err(|e| e.to_string())
+  }
+}
+
+/// Formats a unix timestamp (seconds) in `zone` using the conventions of
+/// `locale`. `browser` is the zone of [`DisplayZone::Browser`], UTC while it
+/// isn't known.
+pub fn format_timestamp(ts: u64, zone: DisplayZone, browser: Option<Tz>, locale: Locale) -> String {
+  let Some(utc) = DateTime::<Utc>::from_timestamp(ts as i64, 0) else {
+    return Text::InvalidTime.translate(locale).to_string();
+  };
+  let pattern = locale.datetime_format();
+  match (zone, browser) {
+    (DisplayZone::Named(tz), _) | (DisplayZone::Browser, Some(tz)) => {
+      utc.with_timezone(&tz).format(pattern).to_string()
+    }
+    (DisplayZone::Browser, None) => utc.format(pattern).to_string(),
+  }
+}
+
+/// The IANA zone the browser is set to.
+#[cfg(target_arch = "wasm32")]
+pub fn browser_time_zone() -> Option<Tz> {
+  let options = js_sys::Intl::DateTimeFormat::new(&js_sys::Array::new(), &js_sys::Object::new())
+    .resolved_options();
+  js_sys::Reflect::get(&options, &"timeZone".into())
+    .ok()?
+    .as_string()?
+    .parse()
+    .ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn browser_time_zone() -> Option<Tz> {
+  // There is no browser during server rendering.
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn numbers_use_the_locale_separators() {
+    assert_eq!(Locale::Vi.format_number(1234.5, 1), "1.234,5");
+    assert_eq!(Locale::En.format_number(1234.5, 1), "1,234.5");
+    assert_eq!(Locale::En.format_number(1234567.0, 0), "1,234,567");
+    assert_eq!(Locale::En.format_number(123.0, 0), "123");
+    assert_eq!(Locale::Vi.format_number(-1000.25, 2), "-1.000,25");
+    assert_eq!(Locale::En.format_number(0.5, 0), "0");
+    // Rounds to zero without keeping the sign.
+    assert_eq!(Locale::En.format_number(-0.04, 1), "0.0");
+    assert_eq!(Locale::Vi.format_number(f32::NAN, 1), "--");
+    assert_eq!(Locale::En.format_number(f32::INFINITY, 1), "--");
+  }
+
+  #[test]
+  fn locales_match_on_the_primary_language() {
+    assert_eq!(Locale::from_tag("en-US"), Some(Locale::En));
+    assert_eq!(Locale::from_tag("VI_vn"), Some(Locale::Vi));
+    assert_eq!(Locale::from_tag("fr"), None);
+    for locale in Locale::ALL {
+      assert_eq!(locale.to_string().parse(), Ok(locale));
+    }
+  }
+
+  #[test]
+  fn timestamps_render_in_the_chosen_zone() {
+    // 2025-01-01 00:30:00 UTC.
+    let ts = 1_735_691_400;
+    let saigon = DisplayZone::Named(chrono_tz::Asia::Ho_Chi_Minh);
+    assert_eq!(
+      format_timestamp(ts, saigon, None, Locale::Vi),
+      "01/01/2025 07:30:00 (UTC+07:00)"
+    );
+    assert_eq!(
+      format_timestamp(ts, saigon, Some(chrono_tz::America::New_York), Locale::En),
+      "2025-01-01 07:30:00 (UTC+07:00)"
+    );
+    assert_eq!(
+      format_timestamp(
+        ts,
+        DisplayZone::Browser,
+        Some(chrono_tz::America::New_York),
+        Locale::En
+      ),
+      "2024-12-31 19:30:00 (UTC-05:00)"
+    );
+    assert_eq!(
+      format_timestamp(ts, DisplayZone::Browser, None, Locale::En),
+      "2025-01-01 00:30:00 (UTC+00:00)"
+    );
+    assert_eq!(
+      format_timestamp(i64::MAX as u64, saigon, None, Locale::En),
+      Text::InvalidTime.translate(Locale::En)
+    );
+  }
+
+  #[test]
+  fn zones_parse_back_from_their_names() {
+    for zone in [
+      DisplayZone::Browser,
+      DisplayZone::Named(chrono_tz::Europe::Berlin),
+    ] {
+      assert_eq!(zone.to_string().parse(), Ok(zone));
+    }
+    assert_eq!("".parse(), Ok(DisplayZone::Browser));
+    assert!("Mars/Olympus".parse::<DisplayZone>().is_err());
+  }
+}
@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use charming::{
+  component::Axis,
+  element::{AxisType, Symbol},
+  series::Line,
+  Chart, WasmRenderer,
+};
+use leptos::prelude::*;
+use types::{
+  measurement::{MetricCatalog, MetricDefinition},
+  thresholds::Thresholds,
+  HatSample,
+};
+
+use crate::{
+  i18n::Text,
+  preferences::{use_preferences, Preferences},
+  thresholds::{band_class, describe_safe_range, metric_text, use_thresholds},
+};
+
+/// Samples the charts show.
+pub const HISTORY_LEN: usize = 20;
+
+/// The last [`HISTORY_LEN`] samples of every device that sent one, by
+/// device id.
+pub type Histories = BTreeMap<String, VecDeque<HatSample>>;
+
+/// Appends `sample` to the history of its device, dropping that device's
+/// oldest sample past [`HISTORY_LEN`].
+pub fn record(histories: &mut Histories, sample: HatSample) {
+  let history = histories
+    .entry(sample.device_id.clone())
+    .or_insert_with(|| VecDeque::with_capacity(HISTORY_LEN));
+  if history.len() == HISTORY_LEN {
+    history.pop_front();
+  }
+  history.push_back(sample);
+}
+
+/// Live samples of every hat, and the one the dashboard shows.
+#[derive(Debug, Clone, Copy)]
+pub struct LiveDevices {
+  histories: RwSignal<Histories>,
+  selected: RwSignal<Option<String>>,
+  /// The picked device, or the first one that sent a sample.
+  pub device: Memo<Option<String>>,
+}
+
+impl LiveDevices {
+  /// Files every sample arriving on `message` under its device.
+  pub fn new(message: Signal<Option<HatSample>>) -> Self {
+    let histories = RwSignal::new(Histories::new());
+    let selected = RwSignal::new(None::<String>);
+    Effect::new(move |_| {
+      if let Some(sample) = message.get() {
+        histories.update(|histories| record(histories, sample));
+      }
+    });
+    let device = Memo::new(move |_| {
+      histories.with(|histories| {
+        selected
+          .get()
+          .filter(|device| histories.contains_key(device))
+          .or_else(|| histories.keys().next().cloned())
+      })
+    });
+    Self {
+      histories,
+      selected,
+      device,
+    }
+  }
+
+  /// Ids of the devices that sent a sample.
+  pub fn devices(self) -> Vec<String> {
+    self
+      .histories
+      .with(|histories| histories.keys().cloned().collect())
+  }
+
+  pub fn select(self, device: String) {
+    self.selected.set(Some(device));
+  }
+
+  /// History of the shown device, oldest first.
+  pub fn history(self) -> Signal<VecDeque<HatSample>> {
+    Signal::derive(move || {
+      let device = self.device.get();
+      self.histories.with(|histories| {
+        device
+          .and_then(|device| histories.get(&device).cloned())
+          .unwrap_or_default()
+      })
+    })
+  }
+
+  /// Latest sample of the shown device.
+  pub fn latest(self) -> Signal<Option<HatSample>> {
+    Signal::derive(move || {
+      let device = self.device.get();
+      self
+        .histories
+        .with(|histories| device.and_then(|device| histories.get(&device)?.back().cloned()))
+    })
+  }
+}
+
+/// Picks the device the dashboard shows among those that sent a sample.
+#[component]
+pub fn DeviceSelect(live: LiveDevices) -> impl IntoView {
+  let preferences = use_preferences();
+  view! {
+    <Show when=move || !live.devices().is_empty()>
+      <select
+        class="select select-sm select-bordered"
+        aria-label=move || preferences.t(Text::Device)
+        prop:value=move || live.device.get().unwrap_or_default()
+        on:change=move |ev| live.select(event_target_value(&ev))
+      >
+        <For each=move || live.devices() key=|device| device.clone() let(device)>
+          <option value=device.clone()>{device.clone()}</option>
+        </For>
+      </select>
+    </Show>
+  }
+}
+
+/// The built-in catalog with the definitions of the JSON array at `path`
+/// over it, or just the built-in one if there's no file.
+#[cfg(feature = "ssr")]
+pub fn load_catalog(
+  path: impl AsRef<std::path::Path>,
+) -> Result<MetricCatalog, crate::store::StoreError> {
+  let mut catalog = MetricCatalog::builtin();
+  match std::fs::read(path) {
+    Ok(bytes) => catalog.extend(serde_json::from_slice::<Vec<MetricDefinition>>(&bytes)?),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+    Err(e) => return Err(e.into()),
+  }
+  Ok(catalog)
+}
+
+#[server]
+pub async fn get_metric_catalog() -> Result<MetricCatalog, ServerFnError> {
+  crate::auth::current_user().ok_or_else(|| ServerFnError::new("not signed in"))?;
+  use_context::<MetricCatalog>()
+    .ok_or_else(|| ServerFnError::new("metric catalog is not available"))
+}
+
+/// Metric catalog fetched from the server, shared via context. The built-in
+/// one until it arrives.
+#[derive(Debug, Clone, Copy)]
+pub struct CatalogContext(Signal<MetricCatalog>);
+
+pub fn provide_catalog() -> Signal<MetricCatalog> {
+  let catalog = LocalResource::new(get_metric_catalog);
+  let catalog = Signal::derive(move || catalog.get().and_then(Result::ok).unwrap_or_default());
+  provide_context(CatalogContext(catalog));
+  catalog
+}
+
+fn use_definition(metric: String) -> Signal<Option<MetricDefinition>> {
+  let CatalogContext(catalog) = expect_context();
+  Signal::derive(move || catalog.get().get(&metric).cloned())
+}
+
+/// Translation of a built-in metric's label. Labels the operator changed in
+/// the catalog are shown as they are.
+fn label(preferences: Preferences, definition: &MetricDefinition) -> String {
+  let builtin = MetricCatalog::builtin()
+    .get(&definition.key)
+    .is_some_and(|builtin| builtin.label == definition.label);
+  if !builtin {
+    return definition.label.clone();
+  }
+  let text = match definition.threshold_metric() {
+    Some(metric) => Some(metric_text(metric)),
+    None => match definition.key.as_str() {
+      "ppm" => Some(Text::UncorrectedPpm),
+      "resistance" => Some(Text::Resistance),
+      "r_zero" => Some(Text::RZero),
+      "corrected_r_zero" => Some(Text::CorrectedRZero),
+      "pm25" => Some(Text::Pm25),
+      "pressure" => Some(Text::Pressure),
+      "light" => Some(Text::Light),
+      _ => None,
+    },
+  };
+  match text {
+    Some(text) => preferences.t(text).to_string(),
+    None => definition.label.clone(),
+  }
+}
+
+/// Below the reading: the sensor's resistances for the CO2, what the
+/// humidity is relative to and the safe range of other metrics with
+/// thresholds.
+fn description(
+  preferences: Preferences,
+  definition: &MetricDefinition,
+  sample: Option<&HatSample>,
+  thresholds: &Thresholds,
+) -> Option<String> {
+  let number = |value: Option<f32>| {
+    value
+      .map(|value| preferences.format_number(value, 1))
+      .unwrap_or_else(|| "--".to_string())
+  };
+  match definition.key.as_str() {
+    "corrected_ppm" => Some(match sample {
+      Some(s) => format!(
+        "R: {} Ω | R0: {} Ω",
+        number(s.resistance),
+        number(s.corrected_r_zero),
+      ),
+      None => preferences.t(Text::Calibrating).to_string(),
+    }),
+    "humidity" => Some(preferences.t(Text::RelativeHumidity).to_string()),
+    _ => {
+      let metric = definition.threshold_metric()?;
+      Some(describe_safe_range(
+        thresholds.get(metric),
+        preferences.t(Text::SafeRange),
+        |value| preferences.format_value(definition, value, 0),
+      ))
+    }
+  }
+}
+
+/// Color and outline icon of a metric.
+fn icon(metric: &str) -> (&'static str, &'static str) {
+  match metric {
+    "temperature" => (
+      "text-secondary",
+      "M12 9v3.75m9-.75a9 9 0 11-18 0 9 9 0 0118 0zm-9 3.75h.008v.008H12v-.008z",
+    ),
+    "humidity" => (
+      "text-info",
+      "M12 21a9.004 9.004 0 008.716-6.747M12 21a9.004 9.004 0 01-8.716-6.747M12 21c2.485 0 4.5-4.03 4.5-9S12 5.625 12 5.625 7.5 7.375 7.5 12S9.515 21 12 21z",
+    ),
+    "corrected_ppm" | "ppm" => (
+      "text-accent",
+      "M12 16.5V9.75m0 0l3 3m-3-3l-3 3M6.75 19.5a4.5 4.5 0 01-1.41-8.775 5.25 5.25 0 0110.233-2.33 3 3 0 013.758 3.848A3.752 3.752 0 0118 19.5H6.75z",
+    ),
+    _ => (
+      "text-primary",
+      "M3 13.125C3 12.504 3.504 12 4.125 12h2.25c.621 0 1.125.504 1.125 1.125v6.75C7.5 20.496 6.996 21 6.375 21h-2.25A1.125 1.125 0 013 19.875v-6.75zM9.75 8.625c0-.621.504-1.125 1.125-1.125h2.25c.621 0 1.125.504 1.125 1.125v11.25c0 .621-.504 1.125-1.125 1.125h-2.25a1.125 1.125 0 01-1.125-1.125V8.625zM16.5 4.125c0-.621.504-1.125 1.125-1.125h2.25C20.496 3 21 3.504 21 4.125v15.75c0 .621-.504 1.125-1.125 1.125h-2.25a1.125 1.125 0 01-1.125-1.125V4.125z",
+    ),
+  }
+}
+
+/// The latest reading of `metric`, colored by its threshold band when it
+/// has thresholds.
+#[component]
+pub fn MetricCard(metric: String, sample: Signal<Option<HatSample>>) -> impl IntoView {
+  let preferences = use_preferences();
+  let thresholds = use_thresholds(sample);
+  let (color, path) = icon(&metric);
+  let definition = use_definition(metric);
+  let value_class = move || {
+    definition
+      .get()
+      .and_then(|definition| definition.threshold_metric())
+      .zip(sample.get())
+      .and_then(|(metric, s)| thresholds.get().classify(metric, &s))
+      .map(band_class)
+      .unwrap_or("text-base-content")
+  };
+  view! {
+    <div class="stat">
+      <div class=format!("stat-figure {color}")>
+        <svg
+          xmlns="http://www.w3.org/2000/svg"
+          fill="none"
+          viewBox="0 0 24 24"
+          stroke-width="1.5"
+          stroke="currentColor"
+          class="w-8 h-8"
+        >
+          <path stroke-linecap="round" stroke-linejoin="round" d=path />
+        </svg>
+      </div>
+      <div class="stat-title">
+        {move || definition.get().map(|definition| label(preferences, &definition))}
+      </div>
+      <div class=move || format!("stat-value {}", value_class())>
+        {move || {
+          definition
+            .get()
+            .zip(sample.get())
+            .and_then(|(definition, s)| {
+              s.measurement(&definition.key)
+                .map(|measurement| preferences.format_measurement(&definition, &measurement))
+            })
+            .unwrap_or_else(|| "--".to_string())
+        }}
+      </div>
+      <div class="stat-desc">
+        {move || {
+          definition.get().and_then(|definition| {
+            description(preferences, &definition, sample.get().as_ref(), &thresholds.get())
+          })
+        }}
+      </div>
+    </div>
+  }
+  .into_any()
+}
+
+/// `metric` over the samples in `history`, in the user's units. Samples
+/// without a reading leave a gap.
+#[component]
+pub fn MetricChart(
+  metric: String,
+  #[prop(into)] history: Signal<VecDeque<HatSample>>,
+) -> impl IntoView {
+  let preferences = use_preferences();
+  let (_, path) = icon(&metric);
+  let id = format!("{metric}-chart");
+  let definition = use_definition(metric);
+
+  Effect::new({
+    let id = id.clone();
+    move |_| {
+      let Some(definition) = definition.get() else {
+        return;
+      };
+      let units = preferences.units.get();
+      let metric = definition.threshold_metric();
+      let data = history
+        .get()
+        .iter()
+        .map(|sample| {
+          let value = sample.measurement(&definition.key)?.value?;
+          Some(metric.map_or(value, |metric| units.convert(metric, value)))
+        })
+        .collect::<Vec<_>>();
+      let unit = metric.map_or(definition.unit.clone(), |metric| {
+        units.symbol(metric).to_string()
+      });
+      let chart = Chart::new()
+        .x_axis(Axis::new().type_(AxisType::Category))
+        .y_axis(Axis::new().type_(AxisType::Value).name(unit))
+        .series(Line::new().smooth(true).symbol(Symbol::Circle).data(data));
+      let mut width = 800;
+      let mut height = 400;
+
+      if let Some(element) = document().get_element_by_id(&id) {
+        if element.client_width() > 0 {
+          width = element.client_width() as u32;
+        }
+        if element.client_height() > 0 {
+          height = element.client_height() as u32;
+        }
+      }
+      WasmRenderer::new(width, height)
+        .render(&id, &chart)
+        .unwrap();
+    }
+  });
+  view! {
+    <div class="card w-full max-w-4xl bg-base-100 shadow-xl border border-base-200 mx-auto">
+      <div class="card-body p-6">
+        <div class="flex flex-row justify-between items-center mb-4">
+          <h2 class="card-title text-primary text-xl flex gap-2 items-center">
+            <svg
+              xmlns="http://www.w3.org/2000/svg"
+              fill="none"
+              viewBox="0 0 24 24"
+              stroke-width="1.5"
+              stroke="currentColor"
+              class="w-6 h-6"
+            >
+              <path stroke-linecap="round" stroke-linejoin="round" d=path />
+            </svg>
+            {move || {
+              definition.get().map(|definition| {
+                preferences.t(Text::MetricChart).replace("{}", &label(preferences, &definition))
+              })
+            }}
+          </h2>
+
+          // Badge trạng thái "Live" nhấp nháy
+          <div class="badge badge-secondary badge-outline gap-2 animate-pulse">
+            <div class="w-2 h-2 bg-secondary rounded-full"></div>
+            {move || preferences.t(Text::Live)}
+          </div>
+        </div>
+
+        <div class="w-full flex justify-center">
+          <div id=id class="w-full h-100"></div>
+        </div>
+      </div>
+    </div>
+  }
+  .into_any()
+}
+
+/// A card and a chart for every metric of the catalog on the dashboard, for
+/// the last [`HISTORY_LEN`] samples of the device picked in `live`.
+#[component]
+pub fn Dashboard(live: LiveDevices) -> impl IntoView {
+  let catalog = provide_catalog();
+  let sample = live.latest();
+  let history = live.history();
+  // Keys of the metrics shown, other sensors' once a hat reports them.
+  let shown = Memo::new(move |_| {
+    catalog
+      .get()
+      .dashboard(history.get().iter())
+      .into_iter()
+      .map(|definition| definition.key)
+      .collect::<Vec<_>>()
+  });
+  view! {
+    // CONTAINER STATS CHÍNH
+    // stats-vertical: Mặc định xếp dọc (cho mobile)
+    // lg:stats-horizontal: Màn hình lớn sẽ xếp ngang
+    <div class="stats stats-vertical lg:stats-horizontal shadow bg-base-100 w-full max-w-4xl border border-base-200">
+      <For each=move || shown.get() key=|metric| metric.clone() let(metric)>
+        <MetricCard metric sample />
+      </For>
+    </div>
+    <For each=move || shown.get() key=|metric| metric.clone() let(metric)>
+      <MetricChart metric history />
+    </For>
+  }
+  .into_any()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sample(device_id: &str, timestamp: u64) -> HatSample {
+    HatSample {
+      device_id: device_id.to_string(),
+      timestamp,
+      ..HatSample::default()
+    }
+  }
+
+  #[test]
+  fn histories_are_kept_per_device() {
+    let mut histories = Histories::new();
+    for timestamp in 0..HISTORY_LEN as u64 + 2 {
+      record(&mut histories, sample("hat-1", timestamp));
+    }
+    record(&mut histories, sample("hat-2", 100));
+
+    let hat_1 = &histories["hat-1"];
+    assert_eq!(hat_1.len(), HISTORY_LEN);
+    assert_eq!(hat_1.front().unwrap().timestamp, 2);
+    assert_eq!(hat_1.back().unwrap().timestamp, HISTORY_LEN as u64 + 1);
+    assert!(hat_1.iter().all(|sample| sample.device_id == "hat-1"));
+    assert_eq!(
+      histories["hat-2"]
+        .iter()
+        .map(|s| s.timestamp)
+        .collect::<Vec<_>>(),
+      [100]
+    );
+  }
+}
@@ -0,0 +1,312 @@
+use chrono_tz::Tz;
+use leptos::{
+  prelude::*,
+  server::codee::string::{FromToStringCodec, JsonSerdeCodec},
+};
+use leptos_use::{storage::use_local_storage, use_cookie_with_options, UseCookieOptions};
+use types::{
+  measurement::{Measurement, MetricDefinition},
+  schema::Quality,
+  thresholds::Metric,
+  units::{GasUnit, TemperatureUnit, Units},
+};
+
+use crate::{
+  i18n::{self, Text},
+  locale::{DisplayZone, Locale},
+};
+
+/// Cookie holding the chosen [`Units`], so server-rendered values match.
+const UNITS_COOKIE: &str = "hat-monitor-units";
+const UNITS_COOKIE_MAX_AGE_MS: i64 = 365 * 24 * 60 * 60 * 1000;
+
+/// Cookie holding the chosen [`DisplayZone`], so the server renders
+/// timestamps in it too.
+const ZONE_COOKIE: &str = "hat-monitor-zone";
+const ZONE_COOKIE_MAX_AGE_MS: i64 = 365 * 24 * 60 * 60 * 1000;
+
+/// Cookie holding the browser's IANA zone, so the server renders timestamps
+/// in the zone the browser hydrates them in.
+const BROWSER_ZONE_COOKIE: &str = "hat-monitor-browser-zone";
+/// The browser reports its zone again on every visit, a stale one only
+/// needs to outlive the gaps between visits.
+const BROWSER_ZONE_COOKIE_MAX_AGE_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+/// Per-browser display preferences, shared with every component via context.
+#[derive(Debug, Clone, Copy)]
+pub struct Preferences {
+  pub zone: Signal<DisplayZone>,
+  set_stored_zone: WriteSignal<DisplayZone>,
+  set_zone_cookie: WriteSignal<Option<DisplayZone>>,
+  /// Zone of [`DisplayZone::Browser`]: the cookie's until the browser
+  /// reports its own after hydration.
+  browser_zone: Signal<Option<Tz>>,
+  pub locale: Signal<Locale>,
+  pub units: Signal<Units>,
+  set_units: WriteSignal<Option<Units>>,
+  set_locale: WriteSignal<Locale>,
+  set_stored_locale: WriteSignal<String>,
+  set_locale_cookie: WriteSignal<Option<Locale>>,
+}
+
+impl Preferences {
+  pub fn t(&self, text: Text) -> &'static str {
+    text.translate(self.locale.get())
+  }
+
+  pub fn format_timestamp(&self, ts: u64) -> String {
+    crate::locale::format_timestamp(
+      ts,
+      self.zone.get(),
+      self.browser_zone.get(),
+      self.locale.get(),
+    )
+  }
+
+  pub fn format_number(&self, value: f32, precision: usize) -> String {
+    self.locale.get().format_number(value, precision)
+  }
+
+  /// Formats a °C reading in the user's temperature unit, e.g. `86.0°F`.
+  pub fn format_temperature(&self, celsius: f32, precision: usize) -> String {
+    let unit = self.units.get().temperature;
+    let value = self.format_number(unit.from_celsius(celsius), precision);
+    match unit {
+      TemperatureUnit::Kelvin => format!("{value} {}", unit.symbol()),
+      _ => format!("{value}{}", unit.symbol()),
+    }
+  }
+
+  /// Formats a CO2 ppm reading in the user's gas unit.
+  pub fn format_gas(&self, ppm: f32, precision: usize) -> String {
+    let unit = self.units.get().gas;
+    format!(
+      "{} {}",
+      self.format_number(unit.from_ppm(ppm), precision),
+      unit.symbol()
+    )
+  }
+
+  /// A value of the metric of `definition` in the user's units.
+  pub fn format_value(
+    &self,
+    definition: &MetricDefinition,
+    value: f32,
+    precision: usize,
+  ) -> String {
+    match definition.threshold_metric() {
+      Some(Metric::Temperature) => self.format_temperature(value, precision),
+      Some(Metric::Ppm) => self.format_gas(value, precision),
+      Some(Metric::Humidity) => format!("{}%", self.format_number(value, precision)),
+      None => format!(
+        "{} {}",
+        self.format_number(value, precision),
+        definition.unit
+      ),
+    }
+  }
+
+  /// `measurement` formatted with [`Preferences::format_value`], or why
+  /// there's no reading.
+  pub fn format_measurement(
+    &self,
+    definition: &MetricDefinition,
+    measurement: &Measurement,
+  ) -> String {
+    match (measurement.value, measurement.quality) {
+      (Some(value), _) => self.format_value(definition, value, definition.precision),
+      (None, Quality::Invalid) => self.t(Text::SensorError).to_string(),
+      (None, _) => self.t(Text::NoReading).to_string(),
+    }
+  }
+
+  /// Switches the display zone, remembering it in local storage and in a
+  /// cookie so the next server render matches.
+  pub fn set_zone(&self, zone: DisplayZone) {
+    self.set_stored_zone.set(zone);
+    self.set_zone_cookie.set(Some(zone));
+  }
+
+  pub fn set_units(&self, units: Units) {
+    self.set_units.set(Some(units));
+  }
+
+  /// Switches the UI language, remembering it in local storage and in a
+  /// cookie so the next server render matches.
+  pub fn set_locale(&self, locale: Locale) {
+    self.set_locale.set(locale);
+    self.set_stored_locale.set(locale.to_string());
+    self.set_locale_cookie.set(Some(locale));
+  }
+}
+
+pub fn provide_preferences() -> Preferences {
+  let (stored_zone, set_stored_zone, _) =
+    use_local_storage::<DisplayZone, FromToStringCodec>("hat-monitor.zone");
+  let (zone_cookie, set_zone_cookie) = use_cookie_with_options::<DisplayZone, FromToStringCodec>(
+    ZONE_COOKIE,
+    UseCookieOptions::default()
+      .path("/".to_string())
+      .max_age(ZONE_COOKIE_MAX_AGE_MS),
+  );
+  let zone = Signal::derive(move || zone_cookie.get().unwrap_or_default());
+  let (stored_locale, set_stored_locale, _) =
+    use_local_storage::<String, FromToStringCodec>("hat-monitor.lang");
+  let (_, set_locale_cookie) = i18n::use_locale_cookie();
+  let (locale, set_locale) = signal(i18n::negotiate_locale());
+  let (units_cookie, set_units) = use_cookie_with_options::<Units, JsonSerdeCodec>(
+    UNITS_COOKIE,
+    UseCookieOptions::default()
+      .path("/".to_string())
+      .max_age(UNITS_COOKIE_MAX_AGE_MS),
+  );
+  let units = Signal::derive(move || units_cookie.get().unwrap_or_default());
+  let (browser_zone_cookie, set_browser_zone_cookie) =
+    use_cookie_with_options::<String, FromToStringCodec>(
+      BROWSER_ZONE_COOKIE,
+      UseCookieOptions::default()
+        .path("/".to_string())
+        .max_age(BROWSER_ZONE_COOKIE_MAX_AGE_MS),
+    );
+  let browser_zone = Signal::derive(move || {
+    browser_zone_cookie
+      .get()
+      .and_then(|name| name.parse::<Tz>().ok())
+  });
+
+  let preferences = Preferences {
+    zone,
+    set_stored_zone,
+    set_zone_cookie,
+    browser_zone,
+    locale: locale.into(),
+    set_locale,
+    units,
+    set_units,
+    set_stored_locale,
+    set_locale_cookie,
+  };
+
+  // Effects only run in the browser, after hydration. Local storage wins over
+  // a missing or stale cookie.
+  Effect::new(move |_| {
+    if let Ok(stored) = stored_locale.get_untracked().parse::<Locale>() {
+      if stored != locale.get_untracked() {
+        preferences.set_locale(stored);
+      }
+    }
+    // Zones chosen before the cookie existed were kept in local storage only.
+    let stored = stored_zone.get_untracked();
+    if stored != zone.get_untracked() {
+      preferences.set_zone(stored);
+    }
+  });
+  // Hydration renders in the cookie's zone like the server did, then the
+  // browser's actual zone takes over.
+  Effect::new(move |_| {
+    if let Some(zone) = crate::locale::browser_time_zone() {
+      if browser_zone.get_untracked() != Some(zone) {
+        set_browser_zone_cookie.set(Some(zone.name().to_string()));
+      }
+    }
+  });
+
+  provide_context(preferences);
+  preferences
+}
+
+pub fn use_preferences() -> Preferences {
+  expect_context::<Preferences>()
+}
+
+#[component]
+pub fn ZonePicker() -> impl IntoView {
+  let preferences = use_preferences();
+  view! {
+    <select
+      class="select select-sm select-bordered"
+      prop:value=move || preferences.zone.get().to_string()
+      on:change=move |ev| {
+        if let Ok(zone) = event_target_value(&ev).parse::<DisplayZone>() {
+          preferences.set_zone(zone);
+        }
+      }
+    >
+      <option value="browser">{move || preferences.t(Text::BrowserTimeZone)}</option>
+      {chrono_tz::TZ_VARIANTS
+        .iter()
+        .map(|tz| {
+          let name = tz.name();
+          view! { <option value=name>{name}</option> }
+        })
+        .collect_view()}
+    </select>
+  }
+}
+
+#[component]
+pub fn LocalePicker() -> impl IntoView {
+  let preferences = use_preferences();
+  view! {
+    <select
+      class="select select-sm select-bordered"
+      aria-label=move || preferences.t(Text::Language)
+      prop:value=move || preferences.locale.get().to_string()
+      on:change=move |ev| {
+        if let Ok(locale) = event_target_value(&ev).parse::<Locale>() {
+          preferences.set_locale(locale);
+        }
+      }
+    >
+      {Locale::ALL
+        .into_iter()
+        .map(|locale| {
+          view! { <option value=locale.tag()>{i18n::native_name(locale)}</option> }
+        })
+        .collect_view()}
+    </select>
+  }
+}
+
+#[component]
+pub fn UnitsPicker() -> impl IntoView {
+  let preferences = use_preferences();
+  view! {
+    <select
+      class="select select-sm select-bordered"
+      aria-label=move || preferences.t(Text::Temperature)
+      prop:value=move || preferences.units.get().temperature.to_string()
+      on:change=move |ev| {
+        if let Ok(temperature) = event_target_value(&ev).parse::<TemperatureUnit>() {
+          preferences.set_units(Units {
+            temperature,
+            ..preferences.units.get_untracked()
+          });
+        }
+      }
+    >
+      {TemperatureUnit::ALL
+        .into_iter()
+        .map(|unit| view! { <option value=unit.to_string()>{unit.symbol()}</option> })
+        .collect_view()}
+    </select>
+    <select
+      class="select select-sm select-bordered"
+      aria-label=move || preferences.t(Text::AirQuality)
+      prop:value=move || preferences.units.get().gas.to_string()
+      on:change=move |ev| {
+        if let Ok(gas) = event_target_value(&ev).parse::<GasUnit>() {
+          preferences.set_units(Units {
+            gas,
+            ..preferences.units.get_untracked()
+          });
+        }
+      }
+    >
+      {GasUnit::ALL
+        .into_iter()
+        .map(|unit| view! { <option value=unit.to_string()>{unit.symbol()}</option> })
+        .collect_view()}
+    </select>
+  }
+}
@@ -0,0 +1,533 @@
+use leptos::{prelude::*, server_fn::codec::Json};
+use types::provisioning::{ClaimCode, ProvisioningOverview};
+
+use crate::{
+  i18n::Text,
+  preferences::use_preferences,
+  settings::{Section, SettingsError},
+};
+
+#[cfg(feature = "ssr")]
+pub use registry::{DeviceRegistry, ProvisioningError};
+
+#[cfg(feature = "ssr")]
+mod registry {
+  use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+  };
+
+  use rand::Rng;
+  use serde::{Deserialize, Serialize};
+  use thiserror::Error;
+  use types::{
+    provisioning::{
+      normalize_claim_code, ClaimCode, PendingClaim, ProvisionedDevice, ProvisioningOverview,
+      CLAIM_CODE_TTL_SECS,
+    },
+    settings::validate_device_id,
+  };
+
+  use crate::{
+    auth::{digest, random_secret},
+    store::{write_atomically, StoreError},
+  };
+
+  /// Prefix of device tokens, API tokens start with `hm_`.
+  const DEVICE_TOKEN_PREFIX: &str = "hmd_";
+  /// Crockford's base32, without the letters that read like digits.
+  const CLAIM_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+  #[derive(Debug, Error)]
+  pub enum ProvisioningError {
+    #[error("device {0}")]
+    Invalid(&'static str),
+    #[error("unknown or expired claim code")]
+    InvalidCode,
+    #[error("device {0} is not provisioned")]
+    NotFound(String),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+  }
+
+  #[derive(Debug, Clone, Serialize, Deserialize)]
+  struct Device {
+    /// SHA-256 of the token, none until claimed or once revoked.
+    #[serde(default)]
+    token_hash: Option<String>,
+    created_by: String,
+    created_at: u64,
+    #[serde(default)]
+    claimed_at: Option<u64>,
+    #[serde(default)]
+    revoked_at: Option<u64>,
+  }
+
+  #[derive(Debug, Clone, Serialize, Deserialize)]
+  struct Pending {
+    code_hash: String,
+    device_id: String,
+    created_by: String,
+    expires_at: u64,
+  }
+
+  #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+  struct Registry {
+    #[serde(default)]
+    devices: BTreeMap<String, Device>,
+    #[serde(default)]
+    claims: Vec<Pending>,
+  }
+
+  /// JSON file backed device identities and claim codes. Only hashes of
+  /// tokens and codes are kept. Every change starts from the file, so codes
+  /// the CLI created meanwhile aren't lost.
+  #[derive(Debug, Clone)]
+  pub struct DeviceRegistry {
+    path: Arc<PathBuf>,
+    registry: Arc<RwLock<Registry>>,
+  }
+
+  fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+  }
+
+  fn load(path: &Path) -> Result<Registry, StoreError> {
+    match fs::read(path) {
+      Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  impl DeviceRegistry {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+      let path = path.into();
+      let registry = load(&path)?;
+      Ok(Self {
+        path: Arc::new(path),
+        registry: Arc::new(RwLock::new(registry)),
+      })
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Registry> {
+      self.registry.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies `f` to the registry as stored and persists it, dropping
+    /// expired claim codes. Nothing changes if `f` or writing fails.
+    fn update<T>(
+      &self,
+      f: impl FnOnce(&mut Registry) -> Result<T, ProvisioningError>,
+    ) -> Result<T, ProvisioningError> {
+      let mut registry = self.registry.write().unwrap_or_else(|e| e.into_inner());
+      let mut next = load(&self.path)?;
+      let now = now();
+      next.claims.retain(|claim| claim.expires_at > now);
+      let value = f(&mut next)?;
+      let bytes = serde_json::to_vec_pretty(&next).map_err(StoreError::from)?;
+      write_atomically(&self.path, &bytes).map_err(StoreError::from)?;
+      *registry = next;
+      Ok(value)
+    }
+
+    /// Creates a claim code for `device_id`, replacing any earlier one.
+    /// Redeeming it replaces the token of an already provisioned device.
+    pub fn create_claim(
+      &self,
+      device_id: &str,
+      created_by: &str,
+    ) -> Result<ClaimCode, ProvisioningError> {
+      validate_device_id(device_id).map_err(ProvisioningError::Invalid)?;
+      let mut rng = rand::rng();
+      let code = (0..8)
+        .map(|_| CLAIM_ALPHABET[rng.random_range(0..CLAIM_ALPHABET.len())] as char)
+        .collect::<String>();
+      let expires_at = now() + CLAIM_CODE_TTL_SECS;
+      self.update(|registry| {
+        registry
+          .devices
+          .entry(device_id.to_string())
+          .or_insert_with(|| Device {
+            token_hash: None,
+            created_by: created_by.to_string(),
+            created_at: now(),
+            claimed_at: None,
+            revoked_at: None,
+          });
+        registry.claims.retain(|claim| claim.device_id != device_id);
+        registry.claims.push(Pending {
+          code_hash: digest(&code),
+          device_id: device_id.to_string(),
+          created_by: created_by.to_string(),
+          expires_at,
+        });
+        Ok(())
+      })?;
+      Ok(ClaimCode {
+        device_id: device_id.to_string(),
+        code: format!("{}-{}", &code[..4], &code[4..]),
+        expires_at,
+      })
+    }
+
+    /// Redeems `code`, once, for the device id and its new token.
+    pub fn claim(&self, code: &str) -> Result<(String, String), ProvisioningError> {
+      let hash = digest(&normalize_claim_code(code));
+      let token = format!("{DEVICE_TOKEN_PREFIX}{}", random_secret());
+      self
+        .update(|registry| {
+          let position = registry
+            .claims
+            .iter()
+            .position(|claim| claim.code_hash == hash)
+            .ok_or(ProvisioningError::InvalidCode)?;
+          let claim = registry.claims.remove(position);
+          let device = registry
+            .devices
+            .get_mut(&claim.device_id)
+            .ok_or_else(|| ProvisioningError::NotFound(claim.device_id.clone()))?;
+          device.token_hash = Some(digest(&token));
+          device.claimed_at = Some(now());
+          device.revoked_at = None;
+          Ok(claim.device_id)
+        })
+        .map(|device_id| (device_id, token))
+    }
+
+    /// Invalidates the token and open claim code of `device_id`.
+    pub fn revoke(&self, device_id: &str) -> Result<(), ProvisioningError> {
+      self.update(|registry| {
+        registry.claims.retain(|claim| claim.device_id != device_id);
+        let device = registry
+          .devices
+          .get_mut(device_id)
+          .ok_or_else(|| ProvisioningError::NotFound(device_id.to_string()))?;
+        device.token_hash = None;
+        device.revoked_at = Some(now());
+        Ok(())
+      })
+    }
+
+    /// Whether `device_id` was claimed and not revoked since.
+    pub fn is_active(&self, device_id: &str) -> bool {
+      self
+        .read()
+        .devices
+        .get(device_id)
+        .is_some_and(|device| device.token_hash.is_some())
+    }
+
+    /// Whether `token` is the current token of `device_id`.
+    pub fn verify(&self, device_id: &str, token: &str) -> bool {
+      let hash = digest(token);
+      self
+        .read()
+        .devices
+        .get(device_id)
+        .is_some_and(|device| device.token_hash.as_deref() == Some(hash.as_str()))
+    }
+
+    pub fn overview(&self) -> ProvisioningOverview {
+      let now = now();
+      let registry = self.read();
+      ProvisioningOverview {
+        devices: registry
+          .devices
+          .iter()
+          .map(|(device_id, device)| ProvisionedDevice {
+            device_id: device_id.clone(),
+            created_by: device.created_by.clone(),
+            created_at: device.created_at,
+            claimed_at: device.claimed_at,
+            revoked_at: device.revoked_at,
+          })
+          .collect(),
+        claims: registry
+          .claims
+          .iter()
+          .filter(|claim| claim.expires_at > now)
+          .map(|claim| PendingClaim {
+            device_id: claim.device_id.clone(),
+            created_by: claim.created_by.clone(),
+            expires_at: claim.expires_at,
+          })
+          .collect(),
+      }
+    }
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    fn path() -> PathBuf {
+      static NEXT: AtomicU64 = AtomicU64::new(0);
+      let path = std::env::temp_dir().join(format!(
+        "hat-monitor-devices-{}-{}.json",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+      ));
+      let _ = fs::remove_file(&path);
+      path
+    }
+
+    #[test]
+    fn codes_are_redeemed_once() {
+      let path = path();
+      let registry = DeviceRegistry::open(&path).unwrap();
+      assert!(registry.create_claim("hat/1", "admin").is_err());
+      let claim = registry.create_claim("hat-1", "admin").unwrap();
+      assert!(!registry.is_active("hat-1"));
+
+      let (device_id, token) = registry.claim(&claim.code.to_lowercase()).unwrap();
+      assert_eq!(device_id, "hat-1");
+      assert!(registry.is_active("hat-1"));
+      assert!(registry.verify("hat-1", &token));
+      assert!(!registry.verify("hat-1", "hmd_wrong"));
+      assert!(!registry.verify("hat-2", &token));
+      assert!(matches!(
+        registry.claim(&claim.code),
+        Err(ProvisioningError::InvalidCode)
+      ));
+      fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn expired_codes_are_refused() {
+      let path = path();
+      let registry = DeviceRegistry::open(&path).unwrap();
+      let claim = registry.create_claim("hat-1", "admin").unwrap();
+      let mut stored = load(&path).unwrap();
+      stored.claims[0].expires_at = now() - 1;
+      fs::write(&path, serde_json::to_vec(&stored).unwrap()).unwrap();
+      assert!(matches!(
+        registry.claim(&claim.code),
+        Err(ProvisioningError::InvalidCode)
+      ));
+      fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn revoking_invalidates_the_token() {
+      let path = path();
+      let registry = DeviceRegistry::open(&path).unwrap();
+      let claim = registry.create_claim("hat-1", "admin").unwrap();
+      let (_, token) = registry.claim(&claim.code).unwrap();
+      registry.revoke("hat-1").unwrap();
+      assert!(!registry.is_active("hat-1"));
+      assert!(!registry.verify("hat-1", &token));
+      assert!(matches!(
+        registry.revoke("hat-2"),
+        Err(ProvisioningError::NotFound(_))
+      ));
+
+      let claim = registry.create_claim("hat-1", "admin").unwrap();
+      let (_, renewed) = registry.claim(&claim.code).unwrap();
+      assert!(registry.verify("hat-1", &renewed));
+      fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn codes_created_elsewhere_are_redeemable() {
+      let path = path();
+      let server = DeviceRegistry::open(&path).unwrap();
+      let cli = DeviceRegistry::open(&path).unwrap();
+      let claim = cli.create_claim("hat-1", "cli").unwrap();
+      let (device_id, _) = server.claim(&claim.code).unwrap();
+      assert_eq!(device_id, "hat-1");
+      assert!(server.is_active("hat-1"));
+      fs::remove_file(&path).unwrap();
+    }
+  }
+}
+
+#[cfg(feature = "ssr")]
+impl From<ProvisioningError> for SettingsError {
+  fn from(e: ProvisioningError) -> Self {
+    match e {
+      ProvisioningError::Store(e) => e.into(),
+      e => {
+        let mut errors = types::settings::FieldErrors::default();
+        errors.push("device_id", e.to_string());
+        SettingsError::Invalid(errors)
+      }
+    }
+  }
+}
+
+#[cfg(feature = "ssr")]
+fn authorized_registry() -> Result<(crate::auth::Identity, DeviceRegistry), SettingsError> {
+  let (identity, _) = crate::accounts::require_role(types::accounts::Role::Admin)?;
+  let registry = use_context::<DeviceRegistry>()
+    .ok_or_else(|| SettingsError::Server("device registry is not available".to_string()))?;
+  Ok((identity, registry))
+}
+
+#[server(input = Json)]
+pub async fn get_provisioning() -> Result<ProvisioningOverview, SettingsError> {
+  Ok(authorized_registry()?.1.overview())
+}
+
+#[server(input = Json)]
+pub async fn create_claim_code(device_id: String) -> Result<ClaimCode, SettingsError> {
+  let (identity, registry) = authorized_registry()?;
+  let device_id = device_id.trim().to_string();
+  let claim = registry.create_claim(&device_id, &identity.username)?;
+  crate::audit::record(
+    types::audit::AuditAction::ClaimCodeCreated,
+    Some(&device_id),
+    format!("expires {}", claim.expires_at),
+  )
+  .await;
+  Ok(claim)
+}
+
+#[server(input = Json)]
+pub async fn revoke_device(device_id: String) -> Result<(), SettingsError> {
+  let (_, registry) = authorized_registry()?;
+  registry.revoke(&device_id)?;
+  crate::audit::record(
+    types::audit::AuditAction::DeviceRevoked,
+    Some(&device_id),
+    "",
+  )
+  .await;
+  Ok(())
+}
+
+/// Claim codes for new hats and the provisioned ones, which can be revoked.
+#[component]
+pub(crate) fn ProvisioningSection(devices: Vec<String>) -> impl IntoView {
+  let preferences = use_preferences();
+  let create = ServerAction::<CreateClaimCode>::new();
+  let revoke = ServerAction::<RevokeDevice>::new();
+  let overview = Resource::new(
+    move || (create.version().get(), revoke.version().get()),
+    |_| get_provisioning(),
+  );
+  let device_id = RwSignal::new(String::new());
+
+  view! {
+    <Section title=Text::Provisioning>
+      <table class="table table-sm">
+        <thead>
+          <tr>
+            <th>{move || preferences.t(Text::Device)}</th>
+            <th>{move || preferences.t(Text::Created)}</th>
+            <th>{move || preferences.t(Text::Claimed)}</th>
+            <th></th>
+            <th></th>
+          </tr>
+        </thead>
+        <tbody>
+          <Transition>
+            {move || {
+              let current = overview.get().and_then(Result::ok).unwrap_or_default();
+              let claims = current.claims;
+              current
+                .devices
+                .into_iter()
+                .map(|device| {
+                  let pending = claims.iter().find(|claim| claim.device_id == device.device_id);
+                  let (class, text) = match (device.is_active(), device.revoked_at, pending) {
+                    (true, _, _) => ("badge-success", Text::Active),
+                    (false, _, Some(_)) => ("badge-warning", Text::Unclaimed),
+                    (false, Some(_), None) => ("badge-error", Text::Revoked),
+                    (false, None, None) => ("badge-ghost", Text::Unclaimed),
+                  };
+                  let expires = pending
+                    .map(|claim| {
+                      format!(
+                        "{} {}",
+                        preferences.t(Text::Expires),
+                        preferences.format_timestamp(claim.expires_at),
+                      )
+                    });
+                  let revoked = device.device_id.clone();
+                  view! {
+                    <tr>
+                      <td>{device.device_id}</td>
+                      <td class="font-mono">{preferences.format_timestamp(device.created_at)}</td>
+                      <td class="font-mono">
+                        {device.claimed_at.map(|at| preferences.format_timestamp(at))}
+                      </td>
+                      <td>
+                        <span class=format!("badge {class}")>{preferences.t(text)}</span>
+                        " "
+                        {expires}
+                      </td>
+                      <td>
+                        <button
+                          class="btn btn-sm btn-ghost"
+                          disabled=revoke.pending()
+                          on:click=move |_| {
+                            revoke.dispatch(RevokeDevice { device_id: revoked.clone() });
+                          }
+                        >
+                          {move || preferences.t(Text::Revoke)}
+                        </button>
+                      </td>
+                    </tr>
+                  }
+                })
+                .collect_view()
+            }}
+          </Transition>
+        </tbody>
+      </table>
+      <div class="card-actions items-center justify-between">
+        <span>
+          {move || match create.value().get() {
+            Some(Ok(claim)) => {
+              view! {
+                <span>
+                  {format!("{}: ", claim.device_id)} <code class="text-lg font-bold">{claim.code}</code>
+                  " " {preferences.t(Text::Expires)} " "
+                  {preferences.format_timestamp(claim.expires_at)}
+                </span>
+              }
+                .into_any()
+            }
+            Some(Err(SettingsError::Invalid(errors))) => {
+              view! {
+                <span class="text-error">{errors.get("device_id").map(str::to_string)}</span>
+              }
+                .into_any()
+            }
+            Some(Err(e)) => view! { <span class="text-error">{e.to_string()}</span> }.into_any(),
+            None => ().into_any(),
+          }}
+        </span>
+        <div class="flex gap-2">
+          <input
+            class="input input-bordered input-sm"
+            list="provisioning-devices"
+            placeholder=move || preferences.t(Text::Device)
+            prop:value=device_id
+            on:input=move |ev| device_id.set(event_target_value(&ev))
+          />
+          <datalist id="provisioning-devices">
+            {devices
+              .into_iter()
+              .map(|device_id| view! { <option value=device_id></option> })
+              .collect_view()}
+          </datalist>
+          <button
+            class="btn btn-sm btn-primary"
+            disabled=create.pending()
+            on:click=move |_| {
+              create.dispatch(CreateClaimCode { device_id: device_id.get_untracked() });
+            }
+          >
+            {move || preferences.t(Text::CreateClaimCode)}
+          </button>
+        </div>
+      </div>
+    </Section>
+  }
+}
@@ -0,0 +1,678 @@
+use std::collections::BTreeMap;
+
+use leptos::{
+  prelude::*,
+  server_fn::{
+    codec::{Json, JsonEncoding},
+    error::{FromServerFnError, ServerFnErrorErr},
+  },
+};
+use serde::{Deserialize, Serialize};
+use types::{
+  settings::{DeviceInfo, FieldErrors, NotificationTarget, Settings, MAX_RETENTION_DAYS},
+  thresholds::Band,
+  units::{GasUnit, TemperatureUnit, Units},
+};
+
+use crate::{
+  accounts::{AccountSection, SignOut, UsersSection},
+  commands::CommandsSection,
+  firmware::FirmwareSection,
+  i18n::Text,
+  preferences::use_preferences,
+  provisioning::ProvisioningSection,
+  thresholds::ThresholdsEditor,
+};
+
+/// Error of the settings server functions. Validation failures carry the
+/// offending fields so forms can show them inline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, thiserror::Error)]
+pub enum SettingsError {
+  #[error("not signed in")]
+  Unauthorized,
+  #[error("not allowed")]
+  Forbidden,
+  #[error("{0}")]
+  Invalid(FieldErrors),
+  #[error("{0}")]
+  Server(String),
+}
+
+impl FromServerFnError for SettingsError {
+  type Encoder = JsonEncoding;
+
+  fn from_server_fn_error(value: ServerFnErrorErr) -> Self {
+    SettingsError::Server(value.to_string())
+  }
+}
+
+impl From<FieldErrors> for SettingsError {
+  fn from(errors: FieldErrors) -> Self {
+    SettingsError::Invalid(errors)
+  }
+}
+
+#[cfg(feature = "ssr")]
+impl From<crate::store::StoreError> for SettingsError {
+  fn from(e: crate::store::StoreError) -> Self {
+    SettingsError::Server(e.to_string())
+  }
+}
+
+/// The settings store, if the signed in account has at least `role`.
+#[cfg(feature = "ssr")]
+pub(crate) fn authorized_store(
+  role: types::accounts::Role,
+) -> Result<crate::store::SettingsStore, SettingsError> {
+  crate::accounts::require_role(role)?;
+  use_context::<crate::store::SettingsStore>()
+    .ok_or_else(|| SettingsError::Server("settings store is not available".to_string()))
+}
+
+#[server(input = Json)]
+pub async fn get_settings() -> Result<Settings, SettingsError> {
+  Ok(authorized_store(types::accounts::Role::Admin)?.current())
+}
+
+#[server(input = Json)]
+pub async fn save_devices(devices: BTreeMap<String, DeviceInfo>) -> Result<(), SettingsError> {
+  let store = authorized_store(types::accounts::Role::Admin)?;
+  let mut errors = FieldErrors::default();
+  for (device_id, info) in &devices {
+    if let Err(message) = types::settings::validate_device_id(device_id) {
+      errors.push(format!("devices.{device_id}.id"), message);
+    }
+    if let Err(info_errors) = info.validate() {
+      for error in info_errors.0 {
+        errors.push(
+          format!("devices.{device_id}.{}", error.field),
+          error.message,
+        );
+      }
+    }
+  }
+  errors.into_result()?;
+  let detail = devices.keys().cloned().collect::<Vec<_>>().join(", ");
+  store.update(|settings| {
+    // Thresholds of removed devices go with them.
+    settings
+      .thresholds
+      .devices
+      .retain(|device_id, _| devices.contains_key(device_id));
+    settings.devices = devices;
+  })?;
+  crate::audit::record(types::audit::AuditAction::DevicesChanged, None, detail).await;
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn save_notifications(targets: Vec<NotificationTarget>) -> Result<(), SettingsError> {
+  let store = authorized_store(types::accounts::Role::Admin)?;
+  types::settings::validate_notifications(&targets)?;
+  let detail = format!("{} targets", targets.len());
+  store.update(|settings| settings.notifications = targets)?;
+  crate::audit::record(
+    types::audit::AuditAction::NotificationsChanged,
+    None,
+    detail,
+  )
+  .await;
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn save_retention(retention_days: u32) -> Result<(), SettingsError> {
+  let store = authorized_store(types::accounts::Role::Admin)?;
+  types::settings::validate_retention(retention_days)?;
+  store.update(|settings| settings.retention_days = retention_days)?;
+  crate::audit::record(
+    types::audit::AuditAction::RetentionChanged,
+    None,
+    format!("{retention_days} days"),
+  )
+  .await;
+  Ok(())
+}
+
+#[server(input = Json)]
+pub async fn save_units(units: Units) -> Result<(), SettingsError> {
+  let store = authorized_store(types::accounts::Role::Admin)?;
+  store.update(|settings| settings.units = units)?;
+  crate::audit::record(
+    types::audit::AuditAction::UnitsChanged,
+    None,
+    format!("{}, {}", units.temperature, units.gas),
+  )
+  .await;
+  Ok(())
+}
+
+/// Field errors of the last save, empty unless it failed validation.
+pub(crate) fn field_errors(result: Option<Result<(), SettingsError>>) -> FieldErrors {
+  match result {
+    Some(Err(SettingsError::Invalid(errors))) => errors,
+    _ => FieldErrors::default(),
+  }
+}
+
+#[component]
+pub(crate) fn FieldMessage(
+  errors: Signal<FieldErrors>,
+  #[prop(into)] field: String,
+) -> impl IntoView {
+  view! {
+    <span class="text-error text-xs">
+      {move || errors.with(|errors| errors.get(&field).map(str::to_string))}
+    </span>
+  }
+}
+
+/// Outcome of the last save of a form.
+#[component]
+pub(crate) fn SaveStatus(result: Signal<Option<Result<(), SettingsError>>>) -> impl IntoView {
+  let preferences = use_preferences();
+  move || match result.get() {
+    Some(Ok(())) => {
+      view! { <span class="text-success">{preferences.t(Text::Saved)}</span> }.into_any()
+    }
+    Some(Err(SettingsError::Invalid(_))) => {
+      view! { <span class="text-error">{preferences.t(Text::FixErrors)}</span> }.into_any()
+    }
+    Some(Err(e)) => view! { <span class="text-error">{e.to_string()}</span> }.into_any(),
+    None => ().into_any(),
+  }
+}
+
+/// Runtime configuration of the monitor. Every section saves on its own and
+/// takes effect immediately on the server.
+#[component]
+pub fn SettingsPage() -> impl IntoView {
+  let preferences = use_preferences();
+  let settings = Resource::new(|| (), |_| get_settings());
+  view! {
+    <div class="flex flex-col items-center justify-start min-h-screen bg-base-100 gap-8 p-4">
+      <div class="flex flex-row w-full max-w-4xl items-center justify-between">
+        <h1 class="text-4xl font-black">{move || preferences.t(Text::Settings)}</h1>
+        <div class="flex items-center gap-2">
+          <a href="/" class="btn btn-ghost">
+            {move || preferences.t(Text::Dashboard)}
+          </a>
+          <SignOut />
+        </div>
+      </div>
+      <Suspense fallback=move || view! { <span class="loading loading-spinner"></span> }>
+        {move || {
+          settings
+            .get()
+            .map(|result| match result {
+              Ok(current) => {
+                let device_ids = current.devices.keys().cloned().collect::<Vec<_>>();
+                view! {
+                  <a href="/audit" class="btn btn-sm btn-ghost self-end">
+                    {move || preferences.t(Text::AuditLog)}
+                  </a>
+                  <DevicesSection devices=current.devices />
+                  <CommandsSection devices=device_ids.clone() />
+                  <ProvisioningSection devices=device_ids.clone() />
+                  <FirmwareSection devices=device_ids />
+                  <ThresholdsEditor />
+                  <NotificationsSection targets=current.notifications />
+                  <RetentionSection retention_days=current.retention_days />
+                  <UnitsSection units=current.units />
+                  <AccountSection />
+                  <UsersSection />
+                }
+                  .into_any()
+              }
+              // Everyone may still manage their own account.
+              Err(SettingsError::Forbidden) => {
+                view! {
+                  <div class="alert alert-warning w-full max-w-4xl">
+                    {move || preferences.t(Text::Forbidden)}
+                  </div>
+                  <AccountSection />
+                }
+                  .into_any()
+              }
+              Err(SettingsError::Unauthorized) => {
+                view! {
+                  <div class="alert alert-error w-full max-w-4xl">
+                    {move || preferences.t(Text::Unauthorized)}
+                  </div>
+                }
+                  .into_any()
+              }
+              Err(e) => {
+                view! { <div class="alert alert-error w-full max-w-4xl">{e.to_string()}</div> }
+                  .into_any()
+              }
+            })
+        }}
+      </Suspense>
+    </div>
+  }
+}
+
+#[component]
+pub(crate) fn Section(title: Text, children: Children) -> impl IntoView {
+  let preferences = use_preferences();
+  view! {
+    <section class="card w-full max-w-4xl bg-base-100 shadow-xl border border-base-200">
+      <div class="card-body p-6 gap-4">
+        <h2 class="card-title">{move || preferences.t(title)}</h2>
+        {children()}
+      </div>
+    </section>
+  }
+  .into_any()
+}
+
+#[component]
+fn DevicesSection(devices: BTreeMap<String, DeviceInfo>) -> impl IntoView {
+  let preferences = use_preferences();
+  let draft = RwSignal::new(devices);
+  let new_id = RwSignal::new(String::new());
+  let save = ServerAction::<SaveDevices>::new();
+  let errors = Signal::derive(move || field_errors(save.value().get()));
+
+  let field = move |device_id: String, pick: fn(&mut DeviceInfo) -> &mut String| {
+    let name = device_id.clone();
+    view! {
+      <input
+        class="input input-bordered input-sm w-full"
+        prop:value=move || {
+          draft
+            .with(|draft| draft.get(&device_id).cloned())
+            .map(|mut info| pick(&mut info).clone())
+            .unwrap_or_default()
+        }
+        on:change=move |ev| {
+          let value = event_target_value(&ev);
+          draft
+            .update(|draft| {
+              if let Some(info) = draft.get_mut(&name) {
+                *pick(info) = value;
+              }
+            });
+        }
+      />
+    }
+  };
+
+  view! {
+    <Section title=Text::Devices>
+      <table class="table">
+        <thead>
+          <tr>
+            <th>{move || preferences.t(Text::Device)}</th>
+            <th>{move || preferences.t(Text::Name)}</th>
+            <th>{move || preferences.t(Text::Location)}</th>
+            <th>{move || preferences.t(Text::Notes)}</th>
+            <th></th>
+          </tr>
+        </thead>
+        <tbody>
+          <For
+            each=move || draft.with(|draft| draft.keys().cloned().collect::<Vec<_>>())
+            key=|device_id| device_id.clone()
+            let(device_id)
+          >
+            <tr>
+              <td class="font-mono">
+                {device_id.clone()}
+                <br />
+                <FieldMessage errors field=format!("devices.{device_id}.id") />
+              </td>
+              <td>
+                {field(device_id.clone(), |info| &mut info.name)}
+                <FieldMessage errors field=format!("devices.{device_id}.name") />
+              </td>
+              <td>
+                {field(device_id.clone(), |info| &mut info.location)}
+                <FieldMessage errors field=format!("devices.{device_id}.location") />
+              </td>
+              <td>{field(device_id.clone(), |info| &mut info.notes)}</td>
+              <td>
+                <button
+                  class="btn btn-sm btn-ghost"
+                  type="button"
+                  on:click={
+                    let device_id = device_id.clone();
+                    move |_| draft.update(|draft| {
+                      draft.remove(&device_id);
+                    })
+                  }
+                >
+                  {move || preferences.t(Text::Remove)}
+                </button>
+              </td>
+            </tr>
+          </For>
+        </tbody>
+      </table>
+      <div class="join">
+        <input
+          class="input input-bordered input-sm join-item"
+          placeholder=move || preferences.t(Text::Device)
+          prop:value=new_id
+          on:input=move |ev| new_id.set(event_target_value(&ev))
+        />
+        <button
+          class="btn btn-sm join-item"
+          type="button"
+          on:click=move |_| {
+            let device_id = new_id.get_untracked().trim().to_string();
+            if !device_id.is_empty() {
+              draft.update(|draft| {
+                draft.entry(device_id).or_default();
+              });
+              new_id.set(String::new());
+            }
+          }
+        >
+          {move || preferences.t(Text::AddDevice)}
+        </button>
+      </div>
+      <div class="card-actions items-center justify-end">
+        <SaveStatus result=save.value().into() />
+        <button
+          class="btn btn-primary"
+          disabled=save.pending()
+          on:click=move |_| {
+            save.dispatch(SaveDevices {
+              devices: draft.get_untracked(),
+            });
+          }
+        >
+          {move || preferences.t(Text::Save)}
+        </button>
+      </div>
+    </Section>
+  }
+}
+
+#[component]
+fn NotificationsSection(targets: Vec<NotificationTarget>) -> impl IntoView {
+  let preferences = use_preferences();
+  let draft = RwSignal::new(targets);
+  let save = ServerAction::<SaveNotifications>::new();
+  let errors = Signal::derive(move || field_errors(save.value().get()));
+
+  let row = move |i: usize| {
+    let text_input = move |pick: fn(&mut NotificationTarget) -> &mut String| {
+      view! {
+        <input
+          class="input input-bordered input-sm w-full"
+          prop:value=move || {
+            draft
+              .with(|draft| draft.get(i).cloned())
+              .map(|mut target| pick(&mut target).clone())
+              .unwrap_or_default()
+          }
+          on:change=move |ev| {
+            let value = event_target_value(&ev);
+            draft
+              .update(|draft| {
+                if let Some(target) = draft.get_mut(i) {
+                  *pick(target) = value;
+                }
+              });
+          }
+        />
+      }
+    };
+    view! {
+      <tr>
+        <td>
+          {text_input(|target| &mut target.name)}
+          <FieldMessage errors field=format!("notifications.{i}.name") />
+        </td>
+        <td>
+          {text_input(|target| &mut target.url)}
+          <FieldMessage errors field=format!("notifications.{i}.url") />
+        </td>
+        <td>
+          <select
+            class="select select-bordered select-sm"
+            prop:value=move || {
+              draft.with(|draft| draft.get(i).map(|target| band_value(target.min_band)))
+            }
+            on:change=move |ev| {
+              let band = parse_band(&event_target_value(&ev));
+              draft
+                .update(|draft| {
+                  if let Some(target) = draft.get_mut(i) {
+                    target.min_band = band;
+                  }
+                });
+            }
+          >
+            <option value="warning">{move || preferences.t(Text::Warning)}</option>
+            <option value="critical">{move || preferences.t(Text::Critical)}</option>
+          </select>
+        </td>
+        <td>
+          <select
+            class="select select-bordered select-sm"
+            prop:value=move || {
+              draft
+                .with(|draft| draft.get(i).and_then(|target| target.units))
+                .map(|units| units.to_string())
+                .unwrap_or_default()
+            }
+            on:change=move |ev| {
+              let units = event_target_value(&ev).parse::<Units>().ok();
+              draft
+                .update(|draft| {
+                  if let Some(target) = draft.get_mut(i) {
+                    target.units = units;
+                  }
+                });
+            }
+          >
+            <option value="">{move || preferences.t(Text::ServerUnitsOption)}</option>
+            {TemperatureUnit::ALL
+              .into_iter()
+              .flat_map(|temperature| {
+                GasUnit::ALL.into_iter().map(move |gas| Units { temperature, gas })
+              })
+              .map(|units| {
+                view! {
+                  <option value=units.to_string()>
+                    {format!("{}, {}", units.temperature.symbol(), units.gas.symbol())}
+                  </option>
+                }
+              })
+              .collect_view()}
+          </select>
+        </td>
+        <td>
+          <input
+            type="checkbox"
+            class="toggle toggle-sm"
+            prop:checked=move || draft.with(|draft| draft.get(i).is_some_and(|target| target.enabled))
+            on:change=move |ev| {
+              let enabled = event_target_checked(&ev);
+              draft
+                .update(|draft| {
+                  if let Some(target) = draft.get_mut(i) {
+                    target.enabled = enabled;
+                  }
+                });
+            }
+          />
+        </td>
+        <td>
+          <button
+            class="btn btn-sm btn-ghost"
+            type="button"
+            on:click=move |_| draft.update(|draft| {
+              if i < draft.len() {
+                draft.remove(i);
+              }
+            })
+          >
+            {move || preferences.t(Text::Remove)}
+          </button>
+        </td>
+      </tr>
+    }
+  };
+
+  view! {
+    <Section title=Text::Notifications>
+      <table class="table">
+        <thead>
+          <tr>
+            <th>{move || preferences.t(Text::Name)}</th>
+            <th>{move || preferences.t(Text::WebhookUrl)}</th>
+            <th>{move || preferences.t(Text::MinSeverity)}</th>
+            <th>{move || preferences.t(Text::TargetUnits)}</th>
+            <th>{move || preferences.t(Text::Enabled)}</th>
+            <th></th>
+          </tr>
+        </thead>
+        <tbody>
+          {move || (0..draft.with(Vec::len)).map(row).collect_view()}
+        </tbody>
+      </table>
+      <div>
+        <button
+          class="btn btn-sm"
+          type="button"
+          on:click=move |_| draft.update(|draft| draft.push(NotificationTarget::default()))
+        >
+          {move || preferences.t(Text::AddTarget)}
+        </button>
+      </div>
+      <div class="card-actions items-center justify-end">
+        <SaveStatus result=save.value().into() />
+        <button
+          class="btn btn-primary"
+          disabled=save.pending()
+          on:click=move |_| {
+            save.dispatch(SaveNotifications {
+              targets: draft.get_untracked(),
+            });
+          }
+        >
+          {move || preferences.t(Text::Save)}
+        </button>
+      </div>
+    </Section>
+  }
+}
+
+fn band_value(band: Band) -> &'static str {
+  match band {
+    Band::Critical => "critical",
+    _ => "warning",
+  }
+}
+
+fn parse_band(value: &str) -> Band {
+  match value {
+    "critical" => Band::Critical,
+    _ => Band::Warning,
+  }
+}
+
+#[component]
+fn RetentionSection(retention_days: u32) -> impl IntoView {
+  let preferences = use_preferences();
+  let draft = RwSignal::new(retention_days.to_string());
+  let save = ServerAction::<SaveRetention>::new();
+  let errors = Signal::derive(move || field_errors(save.value().get()));
+  view! {
+    <Section title=Text::Retention>
+      <label class="form-control">
+        <span class="label-text">{move || preferences.t(Text::RetentionDays)}</span>
+        <input
+          class="input input-bordered w-40"
+          type="number"
+          min="0"
+          max=MAX_RETENTION_DAYS
+          prop:value=draft
+          on:input=move |ev| draft.set(event_target_value(&ev))
+        />
+        <FieldMessage errors field="retention_days" />
+      </label>
+      <div class="card-actions items-center justify-end">
+        <SaveStatus result=save.value().into() />
+        <button
+          class="btn btn-primary"
+          disabled=save.pending()
+          on:click=move |_| {
+            match draft.get_untracked().trim().parse::<u32>() {
+              Ok(retention_days) => {
+                save.dispatch(SaveRetention { retention_days });
+              }
+              Err(_) => {
+                let mut errors = FieldErrors::default();
+                errors.push("retention_days", "must be a whole number of days");
+                save.value().set(Some(Err(SettingsError::Invalid(errors))));
+              }
+            }
+          }
+        >
+          {move || preferences.t(Text::Save)}
+        </button>
+      </div>
+    </Section>
+  }
+}
+
+#[component]
+fn UnitsSection(units: Units) -> impl IntoView {
+  let preferences = use_preferences();
+  let draft = RwSignal::new(units);
+  let save = ServerAction::<SaveUnits>::new();
+  view! {
+    <Section title=Text::ServerUnits>
+      <div class="flex gap-4">
+        <select
+          class="select select-bordered"
+          prop:value=move || draft.get().temperature.to_string()
+          on:change=move |ev| {
+            if let Ok(temperature) = event_target_value(&ev).parse::<TemperatureUnit>() {
+              draft.update(|units| units.temperature = temperature);
+            }
+          }
+        >
+          {TemperatureUnit::ALL
+            .into_iter()
+            .map(|unit| view! { <option value=unit.to_string()>{unit.symbol()}</option> })
+            .collect_view()}
+        </select>
+        <select
+          class="select select-bordered"
+          prop:value=move || draft.get().gas.to_string()
+          on:change=move |ev| {
+            if let Ok(gas) = event_target_value(&ev).parse::<GasUnit>() {
+              draft.update(|units| units.gas = gas);
+            }
+          }
+        >
+          {GasUnit::ALL
+            .into_iter()
+            .map(|unit| view! { <option value=unit.to_string()>{unit.symbol()}</option> })
+            .collect_view()}
+        </select>
+      </div>
+      <div class="card-actions items-center justify-end">
+        <SaveStatus result=save.value().into() />
+        <button
+          class="btn btn-primary"
+          disabled=save.pending()
+          on:click=move |_| {
+            save.dispatch(SaveUnits {
+              units: draft.get_untracked(),
+            });
+          }
+        >
+          {move || preferences.t(Text::Save)}
+        </button>
+      </div>
+    </Section>
+  }
+}
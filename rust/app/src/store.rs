@@ -0,0 +1,91 @@
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use thiserror::Error;
+use tokio::sync::watch;
+use types::settings::Settings;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+  #[error("store io: {0}")]
+  Io(#[from] io::Error),
+  #[error("store json: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("password hash: {0}")]
+  Password(String),
+}
+
+/// JSON file backed [`Settings`]. Every update is written to disk before it
+/// is published to subscribers, so the dashboard and the alert engine always
+/// see the same values.
+#[derive(Debug, Clone)]
+pub struct SettingsStore {
+  path: Arc<PathBuf>,
+  tx: Arc<watch::Sender<Settings>>,
+}
+
+impl SettingsStore {
+  /// Loads settings from `path`, starting from defaults if it doesn't exist.
+  pub fn open(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
+    let path = path.into();
+    let settings = match fs::read(&path) {
+      Ok(bytes) => serde_json::from_slice(&bytes)?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Settings::default(),
+      Err(e) => return Err(e.into()),
+    };
+    let (tx, _) = watch::channel(settings);
+    Ok(Self {
+      path: Arc::new(path),
+      tx: Arc::new(tx),
+    })
+  }
+
+  pub fn current(&self) -> Settings {
+    self.tx.borrow().clone()
+  }
+
+  /// Reads the settings in place, without cloning them.
+  pub fn with<T>(&self, f: impl FnOnce(&Settings) -> T) -> T {
+    f(&self.tx.borrow())
+  }
+
+  pub fn subscribe(&self) -> watch::Receiver<Settings> {
+    self.tx.subscribe()
+  }
+
+  /// Applies `f` to a copy of the settings, persists it and publishes it.
+  /// Nothing changes if writing the file fails.
+  pub fn update(&self, f: impl FnOnce(&mut Settings)) -> Result<(), StoreError> {
+    let mut result = Ok(());
+    self.tx.send_if_modified(|settings| {
+      let mut next = settings.clone();
+      f(&mut next);
+      let persisted = serde_json::to_vec_pretty(&next)
+        .map_err(StoreError::from)
+        .and_then(|bytes| Ok(write_atomically(&self.path, &bytes)?));
+      match persisted {
+        Ok(()) => {
+          *settings = next;
+          true
+        }
+        Err(e) => {
+          result = Err(e);
+          false
+        }
+      }
+    });
+    result
+  }
+}
+
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let tmp = path.with_extension("json.tmp");
+  fs::write(&tmp, bytes)?;
+  fs::rename(tmp, path)
+}
@@ -0,0 +1,252 @@
+use leptos::{prelude::*, server_fn::codec::Json};
+use types::{
+  thresholds::{Band, Metric, Threshold, ThresholdConfig, Thresholds},
+  HatSample,
+};
+
+use crate::{
+  i18n::Text,
+  preferences::use_preferences,
+  settings::{field_errors, FieldMessage, SaveStatus, SettingsError},
+};
+
+#[server]
+pub async fn get_threshold_config() -> Result<ThresholdConfig, ServerFnError> {
+  let identity = crate::auth::current_user().ok_or_else(|| ServerFnError::new("not signed in"))?;
+  let store = use_context::<crate::store::SettingsStore>()
+    .ok_or_else(|| ServerFnError::new("settings store is not available"))?;
+  let settings = store.current();
+  let mut config = settings.thresholds.clone();
+  config
+    .devices
+    .retain(|device_id, _| identity.can_see(device_id, &settings));
+  Ok(config)
+}
+
+/// Saves thresholds for `device_id`, or the defaults when it is empty.
+#[server(input = Json)]
+pub async fn set_thresholds(
+  device_id: String,
+  thresholds: Thresholds,
+) -> Result<(), SettingsError> {
+  let store = crate::settings::authorized_store(types::accounts::Role::Admin)?;
+  types::settings::validate_thresholds(&thresholds)?;
+  let device_id = device_id.trim().to_string();
+  if !device_id.is_empty() {
+    if let Err(message) = types::settings::validate_device_id(&device_id) {
+      let mut errors = types::settings::FieldErrors::default();
+      errors.push("device_id", message);
+      return Err(errors.into());
+    }
+  }
+  let mut previous = None;
+  store.update(|settings| {
+    previous = Some(*settings.thresholds.for_device(&device_id));
+    if device_id.is_empty() {
+      settings.thresholds.default = thresholds;
+    } else {
+      settings
+        .thresholds
+        .devices
+        .insert(device_id.clone(), thresholds);
+    }
+  })?;
+  let json = |thresholds: &Thresholds| serde_json::to_string(thresholds).unwrap_or_default();
+  crate::audit::record(
+    types::audit::AuditAction::ThresholdsChanged,
+    (!device_id.is_empty()).then_some(device_id.as_str()),
+    format!(
+      "{} -> {}",
+      previous.as_ref().map(json).unwrap_or_default(),
+      json(&thresholds)
+    ),
+  )
+  .await;
+  Ok(())
+}
+
+/// Threshold configuration fetched from the server, shared via context.
+#[derive(Debug, Clone, Copy)]
+pub struct ThresholdContext(Signal<ThresholdConfig>);
+
+pub fn provide_thresholds() {
+  let config = LocalResource::new(get_threshold_config);
+  let config = Signal::derive(move || config.get().and_then(Result::ok).unwrap_or_default());
+  provide_context(ThresholdContext(config));
+}
+
+/// Thresholds that apply to the device `sample` came from.
+pub fn use_thresholds(sample: Signal<Option<HatSample>>) -> Signal<Thresholds> {
+  let ThresholdContext(config) = expect_context();
+  Signal::derive(move || {
+    let config = config.get();
+    match sample.get() {
+      Some(sample) => *config.for_device(&sample.device_id),
+      None => config.default,
+    }
+  })
+}
+
+/// Text color for a reading in `band`.
+pub fn band_class(band: Band) -> &'static str {
+  match band {
+    Band::Low => "text-info",
+    Band::Normal => "text-success",
+    Band::Warning => "text-warning",
+    Band::Critical => "text-error",
+  }
+}
+
+/// Describes the normal band of `threshold`, e.g. `Safe range: 20°C - 30°C`.
+pub fn describe_safe_range(
+  threshold: &Threshold,
+  label: &str,
+  format: impl Fn(f32) -> String,
+) -> String {
+  match (threshold.low, threshold.normal_high()) {
+    (Some(low), Some(high)) => format!("{label}: {} - {}", format(low), format(high)),
+    (Some(low), None) => format!("{label}: ≥ {}", format(low)),
+    (None, Some(high)) => format!("{label}: ≤ {}", format(high)),
+    (None, None) => format!("{label}: --"),
+  }
+}
+
+pub fn metric_text(metric: Metric) -> Text {
+  match metric {
+    Metric::Temperature => Text::Temperature,
+    Metric::Humidity => Text::Humidity,
+    Metric::Ppm => Text::AirQuality,
+  }
+}
+
+#[component]
+pub fn ThresholdsEditor() -> impl IntoView {
+  let preferences = use_preferences();
+  let config = Resource::new(|| (), |_| get_threshold_config());
+  let save = ServerAction::<SetThresholds>::new();
+  let errors = Signal::derive(move || field_errors(save.value().get()));
+  let device_id = RwSignal::new(String::new());
+  let draft = RwSignal::new(Thresholds::default());
+
+  // Reload the form whenever the device or the stored config changes.
+  Effect::new(move |_| {
+    if let Some(Ok(config)) = config.get() {
+      let device_id = device_id.get();
+      draft.set(*config.for_device(device_id.trim()));
+    }
+  });
+  Effect::new(move |_| {
+    if let Some(Ok(())) = save.value().get() {
+      config.refetch();
+    }
+  });
+
+  let devices = move || {
+    config
+      .get()
+      .and_then(Result::ok)
+      .map(|config| config.devices.into_keys().collect::<Vec<_>>())
+      .unwrap_or_default()
+  };
+
+  view! {
+    <form
+      class="card w-full max-w-4xl bg-base-100 shadow-xl border border-base-200"
+      on:submit=move |ev| {
+        ev.prevent_default();
+        save.dispatch(SetThresholds {
+          device_id: device_id.get_untracked(),
+          thresholds: draft.get_untracked(),
+        });
+      }
+    >
+      <div class="card-body p-6 gap-4">
+        <h2 class="card-title">{move || preferences.t(Text::Thresholds)}</h2>
+        <label class="form-control">
+          <span class="label-text">{move || preferences.t(Text::Device)}</span>
+          <input
+            class="input input-bordered"
+            list="threshold-devices"
+            placeholder=move || preferences.t(Text::AllDevices)
+            prop:value=device_id
+            on:change=move |ev| device_id.set(event_target_value(&ev))
+          />
+          <datalist id="threshold-devices">
+            <Transition>
+              {move || {
+                devices()
+                  .into_iter()
+                  .map(|id| view! { <option value=id /> })
+                  .collect_view()
+              }}
+            </Transition>
+          </datalist>
+          <FieldMessage errors field="device_id" />
+        </label>
+        <table class="table">
+          <thead>
+            <tr>
+              <th></th>
+              <th>{move || preferences.t(Text::Low)}</th>
+              <th>{move || preferences.t(Text::Warning)}</th>
+              <th>{move || preferences.t(Text::Critical)}</th>
+            </tr>
+          </thead>
+          <tbody>
+            {Metric::ALL
+              .into_iter()
+              .map(|metric| view! { <ThresholdRow metric draft errors /> })
+              .collect_view()}
+          </tbody>
+        </table>
+        <div class="card-actions items-center justify-end">
+          <SaveStatus result=save.value().into() />
+          <button class="btn btn-primary" type="submit" disabled=save.pending()>
+            {move || preferences.t(Text::Save)}
+          </button>
+        </div>
+      </div>
+    </form>
+  }
+  .into_any()
+}
+
+#[component]
+fn ThresholdRow(
+  metric: Metric,
+  draft: RwSignal<Thresholds>,
+  errors: Signal<types::settings::FieldErrors>,
+) -> impl IntoView {
+  let preferences = use_preferences();
+  let bound = move |pick: fn(&mut Threshold) -> &mut Option<f32>| {
+    view! {
+      <td>
+        <input
+          class="input input-bordered input-sm w-28"
+          type="number"
+          step="any"
+          prop:value=move || {
+            let mut threshold = *draft.get().get(metric);
+            pick(&mut threshold).map(|value| value.to_string()).unwrap_or_default()
+          }
+          on:change=move |ev| {
+            let value = event_target_value(&ev).trim().parse::<f32>().ok();
+            draft.update(|draft| *pick(draft.get_mut(metric)) = value);
+          }
+        />
+      </td>
+    }
+  };
+  view! {
+    <tr>
+      <th>
+        {move || format!("{} ({})", preferences.t(metric_text(metric)), metric.unit())}
+        <br />
+        <FieldMessage errors field=format!("thresholds.{metric}") />
+      </th>
+      {bound(|threshold| &mut threshold.low)}
+      {bound(|threshold| &mut threshold.warning)}
+      {bound(|threshold| &mut threshold.critical)}
+    </tr>
+  }
+}
@@ -0,0 +1,12 @@
+//! Helpers shared by the pages and the server.
+
+/// Percent-encodes `value` for a query string.
+pub(crate) fn percent_encode(value: &str) -> String {
+  value
+    .bytes()
+    .map(|byte| match byte {
+      b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => (byte as char).to_string(),
+      _ => format!("%{byte:02X}"),
+    })
+    .collect()
+}
@@ -0,0 +1,276 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::{Arc, Mutex},
+};
+
+use app::{audit::AuditLog, auth::Identity, store::SettingsStore};
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+use types::{
+  accounts::Role,
+  alerts::{Acknowledgement, ActiveAlert},
+  audit::AuditAction,
+  measurement::MetricCatalog,
+  thresholds::{Band, Metric, Threshold},
+  units::Units,
+  HatSample,
+};
+
+use crate::pipeline::SampleQueue;
+
+/// Raised when a reading moves into a different [`Band`].
+#[derive(Debug, Clone)]
+pub(crate) struct AlertEvent {
+  pub device_id: String,
+  /// A [`Metric`] name, or the key of a catalog metric with a threshold.
+  pub metric: String,
+  pub previous: Band,
+  pub band: Band,
+  /// In `unit`, the canonical unit of the metric.
+  pub value: f32,
+  pub unit: String,
+  pub timestamp: u64,
+}
+
+impl AlertEvent {
+  /// The value in the user's `units` and its symbol. Only the metrics of
+  /// the settings' thresholds have units to choose from.
+  pub fn display(&self, units: &Units) -> (f32, String) {
+    match Metric::from_name(&self.metric) {
+      Some(metric) => (
+        units.convert(metric, self.value),
+        units.symbol(metric).to_string(),
+      ),
+      None => (self.value, self.unit.clone()),
+    }
+  }
+
+  pub fn message(&self, units: &Units) -> String {
+    let (value, unit) = self.display(units);
+    format!(
+      "{}: {} {:.1}{} is {:?} (was {:?})",
+      self.device_id, self.metric, value, unit, self.band, self.previous,
+    )
+  }
+}
+
+/// Alerts outside the normal band, keyed by device and metric. Shared between
+/// the alert engine and the REST API.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ActiveAlerts(Arc<Mutex<BTreeMap<(String, String), ActiveAlert>>>);
+
+impl ActiveAlerts {
+  fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, String), ActiveAlert>> {
+    self.0.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// Tracks `event` and tells whether it should be notified. Acknowledged
+  /// alerts stay quiet until they escalate or clear.
+  fn update(&self, event: &AlertEvent) -> bool {
+    let key = (event.device_id.clone(), event.metric.clone());
+    let mut alerts = self.lock();
+    if event.band == Band::Normal {
+      alerts.remove(&key);
+      return true;
+    }
+    let alert = alerts.entry(key).or_insert_with(|| ActiveAlert {
+      device_id: event.device_id.clone(),
+      metric: event.metric.clone(),
+      band: event.band,
+      value: event.value,
+      since: event.timestamp,
+      acknowledged_by: None,
+    });
+    if event.band.severity() > alert.band.severity() {
+      alert.acknowledged_by = None;
+    }
+    alert.band = event.band;
+    alert.value = event.value;
+    alert.acknowledged_by.is_none()
+  }
+
+  fn refresh(&self, sample: &HatSample) {
+    for ((device_id, metric), alert) in self.lock().iter_mut() {
+      if *device_id != sample.device_id {
+        continue;
+      }
+      if let Some(value) = value_of(sample, metric) {
+        alert.value = value;
+      }
+    }
+  }
+}
+
+/// The usable reading of the metric named `metric` in `sample`.
+fn value_of(sample: &HatSample, metric: &str) -> Option<f32> {
+  match Metric::from_name(metric) {
+    Some(metric) => metric.value(sample),
+    None => sample.measurement(metric)?.value,
+  }
+}
+
+/// Evaluates every usable reading against the thresholds in `settings`, the
+/// same ones the dashboard colors its stats with, and the readings of other
+/// sensors against the thresholds of their `catalog` definitions. Band
+/// changes are forwarded to `events` unless they were acknowledged. Every
+/// band change, acknowledged or not, also goes to `published`.
+pub(crate) async fn run(
+  samples: SampleQueue,
+  settings: SettingsStore,
+  catalog: MetricCatalog,
+  active: ActiveAlerts,
+  events: mpsc::Sender<AlertEvent>,
+  published: broadcast::Sender<AlertEvent>,
+) {
+  let settings = settings.subscribe();
+  let mut bands = HashMap::<(String, String), Band>::new();
+  let mut samples = samples.lock().await;
+  while let Some(sample) = samples.recv().await {
+    let (thresholds, units) = {
+      let settings = settings.borrow();
+      (
+        *settings.thresholds.for_device(&sample.device_id),
+        settings.units,
+      )
+    };
+    active.refresh(&sample);
+    let checks = Metric::ALL
+      .into_iter()
+      .map(|metric| {
+        (
+          metric.name().to_string(),
+          metric.unit().to_string(),
+          *thresholds.get(metric),
+        )
+      })
+      .chain(sample.measurements.iter().filter_map(|measurement| {
+        let threshold = catalog.get(&measurement.metric)?.threshold?;
+        Some((
+          measurement.metric.clone(),
+          measurement.unit.clone(),
+          threshold,
+        ))
+      }))
+      .collect::<Vec<(String, String, Threshold)>>();
+    for (metric, unit, threshold) in checks {
+      // A missing or invalid reading leaves the metric in its band until the
+      // next usable one, it's neither an alert nor a recovery.
+      let Some(value) = value_of(&sample, &metric) else {
+        continue;
+      };
+      let band = threshold.classify(value);
+      let previous = bands
+        .insert((sample.device_id.clone(), metric.clone()), band)
+        .unwrap_or(Band::Normal);
+      if band == previous {
+        continue;
+      }
+      let event = AlertEvent {
+        device_id: sample.device_id.clone(),
+        metric,
+        previous,
+        band,
+        value,
+        unit,
+        timestamp: sample.timestamp,
+      };
+      let message = event.message(&units);
+      match band {
+        Band::Normal => info!(target = "alerts", timestamp = event.timestamp, "{message}"),
+        _ => warn!(target = "alerts", timestamp = event.timestamp, "{message}"),
+      }
+      // Fails only without subscribers.
+      let _ = published.send(event.clone());
+      if !active.update(&event) {
+        continue;
+      }
+      if events.try_send(event).is_err() {
+        warn!(target = "alerts", "notifier queue full, dropping event");
+      }
+    }
+  }
+}
+
+/// `GET /api/alerts`: active alerts of the devices the caller may see.
+pub(crate) async fn list(
+  State((active, settings, _)): State<(ActiveAlerts, SettingsStore, AuditLog)>,
+  Extension(identity): Extension<Identity>,
+) -> Json<Vec<ActiveAlert>> {
+  let alerts = active.lock().values().cloned().collect::<Vec<_>>();
+  Json(settings.with(|settings| {
+    alerts
+      .into_iter()
+      .filter(|alert| identity.can_see(&alert.device_id, settings))
+      .collect()
+  }))
+}
+
+/// `POST /api/alerts/ack`: silences an active alert. Operators and admins
+/// only, recorded in the audit log.
+pub(crate) async fn acknowledge(
+  State((active, settings, audit)): State<(ActiveAlerts, SettingsStore, AuditLog)>,
+  Extension(identity): Extension<Identity>,
+  Json(ack): Json<Acknowledgement>,
+) -> StatusCode {
+  if !identity.has_role(Role::Operator)
+    || !settings.with(|settings| identity.can_see(&ack.device_id, settings))
+  {
+    return StatusCode::FORBIDDEN;
+  }
+  let detail = {
+    let mut alerts = active.lock();
+    let Some(alert) = alerts.get_mut(&(ack.device_id.clone(), ack.metric)) else {
+      return StatusCode::NOT_FOUND;
+    };
+    alert.acknowledged_by = Some(identity.username.clone());
+    format!("{} {:?} at {:.1}", alert.metric, alert.band, alert.value)
+  };
+  audit
+    .record(
+      &identity.username,
+      AuditAction::AlertAcknowledged,
+      Some(&ack.device_id),
+      detail,
+    )
+    .await;
+  StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sample() -> HatSample {
+    serde_json::from_str(
+      r#"{"timestamp":1760000000,"temperature":30.0,"measurements":[{"metric":"pm25","unit":"µg/m³","value":40.0,"quality":"good"}]}"#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn catalog_metrics_are_looked_up_by_key() {
+    let sample = sample();
+    assert_eq!(value_of(&sample, "temperature"), Some(30.0));
+    assert_eq!(value_of(&sample, "pm25"), Some(40.0));
+    assert_eq!(value_of(&sample, "ppm"), None);
+    assert_eq!(value_of(&sample, "pressure"), None);
+  }
+
+  #[test]
+  fn catalog_metrics_keep_their_unit() {
+    let event = AlertEvent {
+      device_id: "hat-1".to_string(),
+      metric: "pm25".to_string(),
+      previous: Band::Normal,
+      band: Band::Warning,
+      value: 40.0,
+      unit: "µg/m³".to_string(),
+      timestamp: 1760000000,
+    };
+    assert_eq!(
+      event.message(&Units::default()),
+      "hat-1: pm25 40.0µg/m³ is Warning (was Normal)"
+    );
+  }
+}
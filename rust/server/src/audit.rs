@@ -0,0 +1,32 @@
+use app::{audit::AuditLog, auth::Identity};
+use axum::{
+  extract::{Query, State},
+  http::{header, StatusCode},
+  response::IntoResponse,
+  Extension, Json,
+};
+use tracing::warn;
+use types::{accounts::Role, audit::AuditFilter};
+
+/// `GET /api/audit?actor=&action=&device=&from=&to=`: every matching audit
+/// entry as a JSON download, oldest first. Admins only.
+pub(crate) async fn export(
+  State(audit): State<AuditLog>,
+  Extension(identity): Extension<Identity>,
+  Query(filter): Query<AuditFilter>,
+) -> Result<impl IntoResponse, StatusCode> {
+  if !identity.has_role(Role::Admin) {
+    return Err(StatusCode::FORBIDDEN);
+  }
+  let entries = audit.entries(&filter).await.map_err(|e| {
+    warn!(target = "audit", case = "export", "{:?}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+  })?;
+  Ok((
+    [(
+      header::CONTENT_DISPOSITION,
+      "attachment; filename=\"audit.json\"",
+    )],
+    Json(entries),
+  ))
+}
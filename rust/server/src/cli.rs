@@ -0,0 +1,349 @@
+use std::{collections::BTreeSet, path::PathBuf, process::ExitCode};
+
+use chrono::{DateTime, Utc};
+use clap::{Args, FromArgMatches, Parser, Subcommand};
+use serde::Serialize;
+use types::{
+  audit::AuditAction, schema::SCHEMA_VERSION, settings::validate_device_id, DEFAULT_DEVICE_ID,
+};
+
+use crate::{
+  config::Config, export::ExportArgs, homeassistant::HomeAssistantArgs, import::ImportArgs,
+  replay::ReplayArgs, republish::RepublishArgs, simulate::SimulateArgs,
+};
+
+/// Hat monitor server and the tools to operate it. Without a subcommand it
+/// serves the dashboard like `serve`, with the options of `serve` taken
+/// from the environment.
+#[derive(Debug, Parser)]
+#[command(name = "server", version)]
+pub(crate) struct Cli {
+  #[command(flatten)]
+  pub config: Config,
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+  /// Serves the dashboard with samples from the MQTT broker.
+  Serve(ServeArgs),
+  /// Publishes random samples to the broker as fake hats.
+  Simulate(SimulateArgs),
+  /// Serves the dashboard with samples from a recording instead of the
+  /// broker.
+  Replay {
+    #[command(flatten)]
+    replay: ReplayArgs,
+    #[command(flatten)]
+    integrations: IntegrationArgs,
+  },
+  /// Imports CSV or NDJSON samples into the sample history.
+  Import(ImportArgs),
+  /// Writes stored samples as CSV, NDJSON, Parquet or Arrow IPC.
+  Export(ExportArgs),
+  /// Computes the clean air R0 of a hat from its recent samples.
+  Calibrate(CalibrateArgs),
+  /// Validates the configuration and the files in the data directory.
+  CheckConfig,
+  /// Rewrites stored samples of older schema versions in the current one.
+  Migrate(MigrateArgs),
+  /// Inspects registered and reporting devices.
+  #[command(subcommand)]
+  Devices(DevicesCommand),
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct ServeArgs {
+  /// Records the raw MQTT traffic to this file, see `replay`.
+  #[arg(long, env = "HAT_MONITOR_RECORD")]
+  pub record: Option<PathBuf>,
+  #[command(flatten)]
+  pub integrations: IntegrationArgs,
+}
+
+impl ServeArgs {
+  /// The options of a bare `server`, from the environment alone. Exits with
+  /// the usual message when one of them is invalid.
+  pub fn from_env() -> Self {
+    let matches = Self::augment_args(clap::Command::new("server")).get_matches_from(["server"]);
+    Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+  }
+}
+
+/// What the server publishes to the broker while serving, from live traffic
+/// or a replay.
+#[derive(Debug, Clone, Args)]
+pub(crate) struct IntegrationArgs {
+  #[command(flatten)]
+  pub home_assistant: HomeAssistantArgs,
+  #[command(flatten)]
+  pub republish: RepublishArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum DevicesCommand {
+  /// Lists registered devices and devices with stored samples.
+  List,
+  /// Prints a one-time claim code a hat trades for its token.
+  Claim { device_id: String },
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct MigrateArgs {
+  /// Only reports what would be rewritten.
+  #[arg(long)]
+  dry_run: bool,
+}
+
+/// `server migrate`: brings the sample history to the current schema
+/// version. History of older versions reads fine without it, this saves
+/// migrating it again on every read.
+pub(crate) async fn migrate(config: &Config, args: MigrateArgs) -> ExitCode {
+  let catalog = match config.metrics() {
+    Ok(catalog) => catalog,
+    Err(e) => {
+      eprintln!("metric catalog: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  match config.samples().migrate(&catalog, args.dry_run).await {
+    Ok(migration) => {
+      println!(
+        "{} {} day files, {} samples, {} unreadable lines, schema version {SCHEMA_VERSION}",
+        if args.dry_run {
+          "would rewrite"
+        } else {
+          "rewrote"
+        },
+        migration.files,
+        migration.samples,
+        migration.unreadable
+      );
+      ExitCode::SUCCESS
+    }
+    Err(e) => {
+      eprintln!("samples: {e}");
+      ExitCode::FAILURE
+    }
+  }
+}
+
+/// `server check-config`: prints every problem found, fails if there is one.
+pub(crate) fn check_config(config: &Config) -> ExitCode {
+  let problems = config.check();
+  if problems.is_empty() {
+    println!(
+      "ok: data dir {}, mqtt {}:{} on {}",
+      config.data_dir.display(),
+      config.mqtt_host,
+      config.mqtt_port,
+      config.mqtt_topic
+    );
+    return ExitCode::SUCCESS;
+  }
+  for problem in &problems {
+    eprintln!("{problem}");
+  }
+  ExitCode::FAILURE
+}
+
+/// `server devices list`: one line per device with its name, location and
+/// the time of its latest stored sample.
+pub(crate) async fn list_devices(config: &Config) -> ExitCode {
+  let settings = match config.settings() {
+    Ok(settings) => settings.current(),
+    Err(e) => {
+      eprintln!("settings: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  let store = config.samples();
+  let stored = match store.devices().await {
+    Ok(stored) => stored,
+    Err(e) => {
+      eprintln!("samples: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  let devices = settings
+    .devices
+    .keys()
+    .cloned()
+    .chain(stored)
+    .chain([DEFAULT_DEVICE_ID.to_string()])
+    .collect::<BTreeSet<_>>();
+  println!("DEVICE\tNAME\tLOCATION\tLAST SAMPLE");
+  for device_id in devices {
+    let info = settings
+      .devices
+      .get(&device_id)
+      .cloned()
+      .unwrap_or_default();
+    let last = match store.latest_timestamp(&device_id).await {
+      Ok(Some(timestamp)) => format_time(timestamp),
+      Ok(None) => "-".to_string(),
+      Err(e) => format!("error: {e}"),
+    };
+    println!("{device_id}\t{}\t{}\t{last}", info.name, info.location);
+  }
+  ExitCode::SUCCESS
+}
+
+/// `server devices claim <device id>`: creates a claim code, recorded in the
+/// audit log as created by the CLI.
+pub(crate) fn create_claim(config: &Config, device_id: &str) -> ExitCode {
+  let registry = match config.devices() {
+    Ok(registry) => registry,
+    Err(e) => {
+      eprintln!("devices: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  match registry.create_claim(device_id, "cli") {
+    Ok(claim) => {
+      config.audit_log().record_blocking(
+        "cli",
+        AuditAction::ClaimCodeCreated,
+        Some(device_id),
+        format!("expires {}", claim.expires_at),
+      );
+      println!(
+        "{}\t{}\texpires {}",
+        claim.device_id,
+        claim.code,
+        format_time(claim.expires_at)
+      );
+      ExitCode::SUCCESS
+    }
+    Err(e) => {
+      eprintln!("{e}");
+      ExitCode::from(2)
+    }
+  }
+}
+
+fn format_time(timestamp: u64) -> String {
+  DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+    .map(|time| time.to_rfc3339())
+    .unwrap_or_else(|| timestamp.to_string())
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct CalibrateArgs {
+  /// Device that sat in clean air.
+  #[arg(long, default_value = DEFAULT_DEVICE_ID)]
+  device: String,
+  /// Minutes of samples, up to now, to average.
+  #[arg(long, default_value_t = 30)]
+  minutes: u64,
+}
+
+/// Averages of a calibration window.
+#[derive(Debug, Serialize)]
+struct Calibration {
+  device_id: String,
+  from: u64,
+  to: u64,
+  samples: usize,
+  r_zero: f32,
+  corrected_r_zero: f32,
+}
+
+/// `server calibrate`: the mean R0 of the hat's samples in the window, to be
+/// configured on the hat. Samples without an R0 reading are skipped.
+pub(crate) async fn calibrate(config: &Config, args: CalibrateArgs) -> ExitCode {
+  if let Err(e) = validate_device_id(&args.device) {
+    eprintln!("invalid device id: {e}");
+    return ExitCode::from(2);
+  }
+  let to = Utc::now().timestamp() as u64;
+  let from = to.saturating_sub(args.minutes * 60);
+  let samples = match config.samples().read_range(&args.device, from, to).await {
+    Ok(samples) => samples,
+    Err(e) => {
+      eprintln!("samples: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  let readings = samples
+    .iter()
+    .filter_map(|sample| match (sample.r_zero, sample.corrected_r_zero) {
+      (Some(r_zero), Some(corrected)) if r_zero > 0.0 && corrected > 0.0 => {
+        Some((r_zero, corrected))
+      }
+      _ => None,
+    })
+    .collect::<Vec<_>>();
+  if readings.is_empty() {
+    eprintln!(
+      "no R0 readings of {} in the last {} minutes",
+      args.device, args.minutes
+    );
+    return ExitCode::FAILURE;
+  }
+  let mean =
+    |value: fn(&(f32, f32)) -> f32| readings.iter().map(value).sum::<f32>() / readings.len() as f32;
+  let calibration = Calibration {
+    device_id: args.device.clone(),
+    from,
+    to,
+    samples: readings.len(),
+    r_zero: mean(|(r_zero, _)| *r_zero),
+    corrected_r_zero: mean(|(_, corrected)| *corrected),
+  };
+  config.audit_log().record_blocking(
+    "cli",
+    AuditAction::CalibrationRun,
+    Some(&args.device),
+    format!(
+      "R0 {:.2}, corrected R0 {:.2} from {} samples",
+      calibration.r_zero, calibration.corrected_r_zero, calibration.samples
+    ),
+  );
+  println!(
+    "{}",
+    serde_json::to_string_pretty(&calibration).expect("calibration should be serialized")
+  );
+  ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+  use clap::CommandFactory;
+
+  use super::*;
+
+  #[test]
+  fn the_cli_is_consistent() {
+    Cli::command().debug_assert();
+  }
+
+  #[test]
+  fn serve_options_only_apply_to_serving() {
+    let cli =
+      Cli::try_parse_from(["server", "serve", "--record", "r.ndjson", "--ha-discovery"]).unwrap();
+    let Some(Command::Serve(args)) = cli.command else {
+      panic!("expected serve");
+    };
+    assert_eq!(args.record, Some(PathBuf::from("r.ndjson")));
+    assert!(args.integrations.home_assistant.enabled);
+    let cli = Cli::try_parse_from(["server", "replay", "--republish", "r.ndjson"]).unwrap();
+    assert!(matches!(
+      cli.command,
+      Some(Command::Replay { integrations, .. }) if integrations.republish.enabled
+    ));
+    for args in [
+      &["server", "--record", "r.ndjson"][..],
+      &["server", "import", "--record", "r.ndjson", "f.csv"],
+      &["server", "replay", "--record", "r.ndjson", "r.ndjson"],
+      &["server", "check-config", "--ha-discovery"],
+      &["server", "--republish", "migrate"],
+    ] {
+      assert!(Cli::try_parse_from(args).is_err(), "{args:?}");
+    }
+    // Configuration options apply to every subcommand.
+    assert!(Cli::try_parse_from(["server", "--data-dir", "d", "check-config"]).is_ok());
+    assert!(Cli::try_parse_from(["server", "migrate", "--data-dir", "d"]).is_ok());
+  }
+}
@@ -0,0 +1,498 @@
+//! Parquet and Arrow IPC encodings of the sample history export.
+
+use std::{
+  collections::HashMap,
+  fs::{self, File, OpenOptions},
+  io::{self, Seek, Write},
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+};
+
+use arrow_array::{ArrayRef, Float32Array, RecordBatch, StringArray, TimestampSecondArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use axum::body::Bytes;
+use parquet::{
+  arrow::ArrowWriter, basic::Compression, errors::ParquetError, file::properties::WriterProperties,
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+use types::{derived::DerivedMetrics, schema::Field as Reading, units::Units, HatSample};
+use zip::{result::ZipError, write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+  export::{Columns, CHUNK_SIZE},
+  storage,
+};
+
+#[derive(Debug, Error)]
+pub(crate) enum ColumnarError {
+  #[error("columnar io: {0}")]
+  Io(#[from] io::Error),
+  #[error("columnar arrow: {0}")]
+  Arrow(#[from] ArrowError),
+  #[error("columnar parquet: {0}")]
+  Parquet(#[from] ParquetError),
+  #[error("columnar zip: {0}")]
+  Zip(#[from] ZipError),
+}
+
+/// Day files of one device within the export range.
+pub(crate) struct DeviceFiles {
+  pub device_id: String,
+  pub paths: Vec<PathBuf>,
+}
+
+/// Float column `name` of values in `unit`, which its `unit` metadata names.
+fn reading(name: &str, unit: &str) -> Field {
+  Field::new(name, DataType::Float32, true)
+    .with_metadata(HashMap::from([("unit".to_string(), unit.to_string())]))
+}
+
+/// Columns of an export. `device_id` is left out of partitioned files, where
+/// the directory name carries it.
+fn schema(device_column: bool, columns: Columns) -> SchemaRef {
+  let mut fields = Vec::new();
+  if device_column {
+    fields.push(Field::new("device_id", DataType::Utf8, false));
+  }
+  fields.push(Field::new(
+    "timestamp",
+    DataType::Timestamp(TimeUnit::Second, Some("UTC".into())),
+    false,
+  ));
+  // Readings are null where the hat had none.
+  for field in Reading::ALL {
+    fields.push(reading(field.name(), columns.units.field_symbol(field)));
+  }
+  // Readings of other sensors as a JSON array.
+  fields.push(Field::new("measurements", DataType::Utf8, true));
+  if columns.derived {
+    for (name, unit) in DerivedMetrics::NAMES
+      .into_iter()
+      .zip(DerivedMetrics::symbols(&columns.units))
+    {
+      fields.push(reading(name, unit));
+    }
+  }
+  Arc::new(Schema::new(fields))
+}
+
+/// The columns of `schema` for `samples`, with the readings in `units`.
+fn batch(
+  schema: &SchemaRef,
+  samples: &[HatSample],
+  units: &Units,
+) -> Result<RecordBatch, ArrowError> {
+  let derived = samples
+    .iter()
+    .map(|sample| DerivedMetrics::of(sample).map(|derived| derived.in_units(units)))
+    .collect::<Vec<_>>();
+  let samples = samples
+    .iter()
+    .map(|sample| units.convert_sample(sample))
+    .collect::<Vec<_>>();
+  let float = |value: fn(&HatSample) -> Option<f32>| -> ArrayRef {
+    Arc::new(samples.iter().map(value).collect::<Float32Array>())
+  };
+  let derived_float = |value: fn(&DerivedMetrics) -> f32| -> ArrayRef {
+    Arc::new(
+      derived
+        .iter()
+        .map(|derived| derived.as_ref().map(value))
+        .collect::<Float32Array>(),
+    )
+  };
+  let columns = schema
+    .fields()
+    .iter()
+    .map(|field| {
+      Ok(match field.name().as_str() {
+        "device_id" => Arc::new(
+          samples
+            .iter()
+            .map(|sample| Some(sample.device_id.as_str()))
+            .collect::<StringArray>(),
+        ) as ArrayRef,
+        "timestamp" => Arc::new(
+          TimestampSecondArray::from(
+            samples
+              .iter()
+              .map(|sample| sample.timestamp as i64)
+              .collect::<Vec<_>>(),
+          )
+          .with_timezone("UTC"),
+        ),
+        "temperature" => float(|sample| sample.temperature),
+        "humidity" => float(|sample| sample.humidity),
+        "r_zero" => float(|sample| sample.r_zero),
+        "corrected_r_zero" => float(|sample| sample.corrected_r_zero),
+        "resistance" => float(|sample| sample.resistance),
+        "ppm" => float(|sample| sample.ppm),
+        "corrected_ppm" => float(|sample| sample.corrected_ppm),
+        "measurements" => Arc::new(
+          samples
+            .iter()
+            .map(|sample| {
+              (!sample.measurements.is_empty())
+                .then(|| serde_json::to_string(&sample.measurements).ok())
+                .flatten()
+            })
+            .collect::<StringArray>(),
+        ),
+        "dew_point" => derived_float(|derived| derived.dew_point),
+        "absolute_humidity" => derived_float(|derived| derived.absolute_humidity),
+        "heat_index" => derived_float(|derived| derived.heat_index),
+        name => {
+          return Err(ArrowError::SchemaError(format!(
+            "no values for column {name}"
+          )))
+        }
+      })
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+  RecordBatch::try_new(schema.clone(), columns)
+}
+
+/// Write target whose bytes are taken out after every batch and sent to the
+/// client, so only one day of samples is held at a time.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+  fn take(&self) -> Vec<u8> {
+    std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+  }
+}
+
+impl Write for SharedBuffer {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self
+      .0
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn properties() -> WriterProperties {
+  WriterProperties::builder()
+    .set_compression(Compression::SNAPPY)
+    .build()
+}
+
+/// Sends what `buffer` holds, false once the client went away.
+async fn send(buffer: &SharedBuffer, tx: &mpsc::Sender<io::Result<Bytes>>) -> bool {
+  let chunk = buffer.take();
+  chunk.is_empty() || tx.send(Ok(Bytes::from(chunk))).await.is_ok()
+}
+
+/// Streams an Arrow IPC stream with one record batch per device and day.
+pub(crate) async fn write_arrow(
+  devices: Vec<DeviceFiles>,
+  from: u64,
+  to: u64,
+  columns: Columns,
+  tx: mpsc::Sender<io::Result<Bytes>>,
+) {
+  let buffer = SharedBuffer::default();
+  let result = async {
+    let schema = schema(true, columns);
+    let mut writer = StreamWriter::try_new(buffer.clone(), &schema)?;
+    for device in devices {
+      for path in device.paths {
+        let samples = storage::read_day_file(&path, from, to).await?;
+        if samples.is_empty() {
+          continue;
+        }
+        writer.write(&batch(&schema, &samples, &columns.units)?)?;
+        if !send(&buffer, &tx).await {
+          return Ok(());
+        }
+      }
+    }
+    writer.finish()?;
+    send(&buffer, &tx).await;
+    Ok::<_, ColumnarError>(())
+  }
+  .await;
+  finish(result, &tx).await;
+}
+
+/// Streams the Parquet file of one device, a row group per day.
+pub(crate) async fn write_parquet(
+  device: DeviceFiles,
+  from: u64,
+  to: u64,
+  columns: Columns,
+  tx: mpsc::Sender<io::Result<Bytes>>,
+) {
+  let buffer = SharedBuffer::default();
+  let result = async {
+    let schema = schema(true, columns);
+    let mut writer = ArrowWriter::try_new(buffer.clone(), schema.clone(), Some(properties()))?;
+    for path in device.paths {
+      let samples = storage::read_day_file(&path, from, to).await?;
+      if samples.is_empty() {
+        continue;
+      }
+      writer.write(&batch(&schema, &samples, &columns.units)?)?;
+      writer.flush()?;
+      if !send(&buffer, &tx).await {
+        return Ok(());
+      }
+    }
+    writer.close()?;
+    send(&buffer, &tx).await;
+    Ok::<_, ColumnarError>(())
+  }
+  .await;
+  finish(result, &tx).await;
+}
+
+/// Streams a zip archive of Parquet files partitioned by device, laid out
+/// as `device_id=<id>/<from>-<to>.parquet` so analytics tools read the
+/// directory name as a column. Zip needs to seek back, so the archive is
+/// spooled to a temp file on the blocking pool and sent once complete.
+pub(crate) async fn partitioned_parquet(
+  devices: Vec<DeviceFiles>,
+  from: u64,
+  to: u64,
+  columns: Columns,
+  tx: mpsc::Sender<io::Result<Bytes>>,
+) {
+  let result = async {
+    let schema = schema(false, columns);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut archive = blocking(|| Ok(ZipWriter::new(spool_file()?))).await?;
+    for device in devices {
+      let name = format!("device_id={}/{from}-{to}.parquet", device.device_id);
+      let file_schema = schema.clone();
+      let mut writer = blocking(move || {
+        archive.start_file(name, options)?;
+        Ok(ArrowWriter::try_new(
+          archive,
+          file_schema,
+          Some(properties()),
+        )?)
+      })
+      .await?;
+      for path in device.paths {
+        let samples = storage::read_day_file(&path, from, to).await?;
+        if samples.is_empty() {
+          continue;
+        }
+        let batch = batch(&schema, &samples, &columns.units)?;
+        writer = blocking(move || {
+          writer.write(&batch)?;
+          writer.flush()?;
+          Ok(writer)
+        })
+        .await?;
+      }
+      archive = blocking(move || Ok(writer.into_inner()?)).await?;
+    }
+    let file = blocking(move || {
+      let mut file = archive.finish()?;
+      file.rewind()?;
+      Ok(file)
+    })
+    .await?;
+    let mut chunks = ReaderStream::with_capacity(tokio::fs::File::from_std(file), CHUNK_SIZE);
+    while let Some(chunk) = chunks.next().await {
+      if tx.send(Ok(chunk?)).await.is_err() {
+        break;
+      }
+    }
+    Ok::<_, ColumnarError>(())
+  }
+  .await;
+  finish(result, &tx).await;
+}
+
+/// Runs `f`, which writes to a file, on the blocking pool.
+async fn blocking<T: Send + 'static>(
+  f: impl FnOnce() -> Result<T, ColumnarError> + Send + 'static,
+) -> Result<T, ColumnarError> {
+  tokio::task::spawn_blocking(f)
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// New temp file, unlinked right away so it is removed with its handle,
+/// also when the client goes away mid download.
+fn spool_file() -> io::Result<File> {
+  static NEXT: AtomicU64 = AtomicU64::new(0);
+  let path = std::env::temp_dir().join(format!(
+    "hat-monitor-export-{}-{}.zip",
+    std::process::id(),
+    NEXT.fetch_add(1, Ordering::Relaxed)
+  ));
+  let file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create_new(true)
+    .open(&path)?;
+  fs::remove_file(&path)?;
+  Ok(file)
+}
+
+async fn finish(result: Result<(), ColumnarError>, tx: &mpsc::Sender<io::Result<Bytes>>) {
+  if let Err(e) = result {
+    warn!(target = "export", case = "columnar", "{:?}", e);
+    // Ends the download with an error instead of a silently short file.
+    let _ = tx.send(Err(io::Error::other(e))).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Cursor, Read};
+
+  use arrow_array::Array;
+
+  use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+  use types::units::TemperatureUnit;
+  use zip::ZipArchive;
+
+  use super::*;
+
+  fn day_file(name: &str, lines: &[&str]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+      "hat-monitor-columnar-{}-{name}.ndjson",
+      std::process::id()
+    ));
+    fs::write(&path, lines.join("\n")).unwrap();
+    path
+  }
+
+  #[tokio::test]
+  async fn partitioned_parquet_streams_one_file_per_device() {
+    let a = day_file(
+      "a",
+      &[
+        r#"{"device_id":"a","timestamp":10,"temperature":20.0,"humidity":50.0}"#,
+        r#"{"device_id":"a","timestamp":20,"temperature":21.0}"#,
+        r#"{"device_id":"a","timestamp":99,"temperature":22.0}"#,
+      ],
+    );
+    let b = day_file(
+      "b",
+      &[r#"{"device_id":"b","timestamp":15,"humidity":40.0}"#],
+    );
+    let devices = vec![
+      DeviceFiles {
+        device_id: "a".to_string(),
+        paths: vec![a.clone()],
+      },
+      DeviceFiles {
+        device_id: "b".to_string(),
+        paths: vec![b.clone()],
+      },
+    ];
+    let (tx, mut rx) = mpsc::channel(4);
+    let columns = Columns {
+      derived: true,
+      units: Units {
+        temperature: TemperatureUnit::Fahrenheit,
+        ..Units::default()
+      },
+    };
+    tokio::spawn(partitioned_parquet(devices, 0, 50, columns, tx));
+    let mut archive = Vec::new();
+    while let Some(chunk) = rx.recv().await {
+      archive.extend_from_slice(&chunk.unwrap());
+    }
+    fs::remove_file(a).unwrap();
+    fs::remove_file(b).unwrap();
+
+    let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+    let mut rows = Vec::new();
+    let mut temperatures = Vec::new();
+    for name in ["device_id=a/0-50.parquet", "device_id=b/0-50.parquet"] {
+      let mut file = Vec::new();
+      archive
+        .by_name(name)
+        .unwrap()
+        .read_to_end(&mut file)
+        .unwrap();
+      let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(file))
+        .unwrap()
+        .build()
+        .unwrap();
+      let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+      assert!(batches[0].schema().field_with_name("device_id").is_err());
+      let schema = batches[0].schema();
+      assert_eq!(
+        schema.field_with_name("heat_index").unwrap().metadata()["unit"],
+        "°F"
+      );
+      assert_eq!(
+        schema.field_with_name("ppm").unwrap().metadata()["unit"],
+        "ppm"
+      );
+      rows.push(batches.iter().map(RecordBatch::num_rows).sum::<usize>());
+      let column = batches[0]
+        .column_by_name("temperature")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Float32Array>()
+        .unwrap()
+        .clone();
+      temperatures.extend(column.iter());
+    }
+    assert_eq!(rows, [2, 1]);
+    assert_eq!(temperatures, [Some(68.0), Some(69.8), None]);
+  }
+
+  #[test]
+  fn batches_reject_unknown_columns() {
+    let schema = Arc::new(Schema::new(vec![Field::new(
+      "pm25",
+      DataType::Float32,
+      true,
+    )]));
+    let columns = Columns {
+      derived: true,
+      units: Units::default(),
+    };
+    assert!(batch(&schema, &[], &columns.units).is_err());
+    assert!(batch(&super::schema(true, columns), &[], &columns.units).is_ok());
+  }
+
+  #[test]
+  fn measurements_are_a_json_column() {
+    let samples = [
+      r#"{"timestamp":10,"measurements":[{"metric":"pm25","unit":"µg/m³","value":12.0}]}"#,
+      r#"{"timestamp":20,"temperature":21.0}"#,
+    ]
+    .map(|line| serde_json::from_str::<HatSample>(line).unwrap());
+    let batch = batch(
+      &super::schema(false, Columns::default()),
+      &samples,
+      &Units::default(),
+    )
+    .unwrap();
+    let column = batch
+      .column_by_name("measurements")
+      .unwrap()
+      .as_any()
+      .downcast_ref::<StringArray>()
+      .unwrap();
+    let measurements: Vec<types::measurement::Measurement> =
+      serde_json::from_str(column.value(0)).unwrap();
+    assert_eq!(measurements, samples[0].measurements);
+    assert!(column.is_null(1));
+  }
+}
@@ -0,0 +1,140 @@
+//! REST API of the downlink commands, for scripts. The settings page sends
+//! them through server functions sharing the same [`CommandHub`].
+
+use app::{
+  audit::AuditLog,
+  auth::Identity,
+  commands::{self, CommandError, CommandHub},
+  store::SettingsStore,
+};
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  Extension, Json,
+};
+use types::{
+  accounts::Role,
+  commands::{Command, CommandRecord},
+};
+
+/// `POST /api/devices/{device}/commands`: sends a command to a hat.
+/// Operators and admins only, calibrations admins only. Recorded in the
+/// audit log. Answers 503 while
+/// the broker is unreachable. Firmware updates are refused, they go through
+/// `POST /api/rollouts`.
+pub(crate) async fn send(
+  State((hub, settings, audit)): State<(CommandHub, SettingsStore, AuditLog)>,
+  Extension(identity): Extension<Identity>,
+  Path(device_id): Path<String>,
+  Json(command): Json<Command>,
+) -> Result<Json<CommandRecord>, (StatusCode, String)> {
+  if !identity.has_role(command.required_role())
+    || !settings.with(|settings| identity.can_see(&device_id, settings))
+  {
+    return Err((StatusCode::FORBIDDEN, "not allowed".to_string()));
+  }
+  let record = hub
+    .send(&identity.username, &device_id, command)
+    .map_err(|e| match e {
+      CommandError::Invalid(_) => (StatusCode::BAD_REQUEST, e.to_string()),
+      CommandError::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    })?;
+  commands::audit(&audit, &identity.username, &record).await;
+  Ok(Json(record))
+}
+
+/// `GET /api/devices/{device}/commands`: the commands recently sent to a
+/// hat, newest first.
+pub(crate) async fn list(
+  State((hub, settings, _)): State<(CommandHub, SettingsStore, AuditLog)>,
+  Extension(identity): Extension<Identity>,
+  Path(device_id): Path<String>,
+) -> Result<Json<Vec<CommandRecord>>, StatusCode> {
+  if !identity.has_role(Role::Operator)
+    || !settings.with(|settings| identity.can_see(&device_id, settings))
+  {
+    return Err(StatusCode::FORBIDDEN);
+  }
+  Ok(Json(hub.records(&device_id)))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  use app::commands::Outbound;
+  use tokio::sync::{mpsc, watch};
+  use types::{
+    accounts::Grants,
+    mqtt::{ConnectionState, MqttStatus},
+  };
+
+  use super::*;
+
+  type Queue = mpsc::UnboundedReceiver<Outbound>;
+
+  /// State of the handlers and the queue the hub sends to.
+  fn state() -> ((CommandHub, SettingsStore, AuditLog), Queue) {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let dir = std::env::temp_dir().join(format!(
+      "hat-monitor-commands-{}-{}",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let (_, mqtt) = watch::channel(MqttStatus {
+      state: ConnectionState::Subscribed,
+      ..MqttStatus::default()
+    });
+    let (hub, queue) = CommandHub::new(mqtt);
+    let settings = SettingsStore::open(dir.join("settings.json")).unwrap();
+    (
+      (hub, settings, AuditLog::new(dir.join("audit.ndjson"))),
+      queue,
+    )
+  }
+
+  fn identity(role: Role) -> Identity {
+    Identity {
+      username: role.to_string(),
+      role,
+      grants: Grants {
+        all: true,
+        ..Grants::default()
+      },
+    }
+  }
+
+  #[tokio::test]
+  async fn operators_may_not_calibrate() {
+    let (state, _queue) = state();
+    for command in [
+      Command::StartCalibration { minutes: 30 },
+      Command::SetRZero { r_zero: 76.6 },
+    ] {
+      let refused = send(
+        State(state.clone()),
+        Extension(identity(Role::Operator)),
+        Path("hat".to_string()),
+        Json(command),
+      )
+      .await;
+      assert_eq!(refused.unwrap_err().0, StatusCode::FORBIDDEN);
+    }
+    let sent = send(
+      State(state.clone()),
+      Extension(identity(Role::Operator)),
+      Path("hat".to_string()),
+      Json(Command::Reboot),
+    )
+    .await;
+    assert!(sent.is_ok());
+    let sent = send(
+      State(state),
+      Extension(identity(Role::Admin)),
+      Path("hat".to_string()),
+      Json(Command::SetRZero { r_zero: 76.6 }),
+    )
+    .await;
+    assert!(sent.is_ok());
+  }
+}
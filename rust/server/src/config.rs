@@ -0,0 +1,242 @@
+use std::{
+  fs,
+  io::{self, Write},
+  net::{IpAddr, ToSocketAddrs},
+  path::PathBuf,
+  time::Duration,
+};
+
+use app::{
+  audit::AuditLog,
+  auth::Auth,
+  firmware::{FirmwareError, FirmwareStore},
+  provisioning::DeviceRegistry,
+  store::SettingsStore,
+};
+use clap::Args;
+use rumqttc::v5::MqttOptions;
+use types::{
+  measurement::MetricCatalog,
+  settings::{validate_device_id, validate_notifications, validate_retention, validate_thresholds},
+};
+
+use crate::storage::SampleStore;
+
+/// Configuration shared by every subcommand, from flags or the environment.
+#[derive(Debug, Clone, Args)]
+pub(crate) struct Config {
+  /// Directory of the settings, accounts, audit log and sample history.
+  #[arg(
+    long,
+    env = "HAT_MONITOR_DATA_DIR",
+    default_value = "data",
+    global = true
+  )]
+  pub data_dir: PathBuf,
+  /// MQTT broker the hats publish to.
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_HOST",
+    default_value = "localhost",
+    global = true
+  )]
+  pub mqtt_host: String,
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_PORT",
+    default_value_t = 1883,
+    global = true
+  )]
+  pub mqtt_port: u16,
+  /// Hats publish on this topic or on `<topic>/<device id>`.
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_TOPIC",
+    default_value = "iot/hat",
+    global = true
+  )]
+  pub mqtt_topic: String,
+  /// Seconds to wait before reconnecting after the first failure, doubled
+  /// on every further failure.
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_BACKOFF_MIN",
+    default_value_t = 1,
+    global = true
+  )]
+  pub mqtt_backoff_min: u64,
+  /// Upper bound of the reconnect delay, in seconds.
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_BACKOFF_MAX",
+    default_value_t = 60,
+    global = true
+  )]
+  pub mqtt_backoff_max: u64,
+  /// User name the server's own MQTT clients connect with.
+  #[arg(long, env = "HAT_MONITOR_MQTT_USERNAME", global = true)]
+  pub mqtt_username: Option<String>,
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_PASSWORD",
+    hide_env_values = true,
+    requires = "mqtt_username",
+    global = true
+  )]
+  pub mqtt_password: Option<String>,
+  /// Drops samples, acknowledgements and reports of hats that weren't
+  /// provisioned or were revoked. Hats could still publish on each other's
+  /// topics unless the broker checks them with `/api/mqtt/acl` too.
+  #[arg(
+    id = "require_provisioning",
+    long = "require-provisioning",
+    env = "HAT_MONITOR_REQUIRE_PROVISIONING",
+    global = true
+  )]
+  pub require_provisioning: bool,
+  /// Addresses of the brokers allowed to ask `/api/mqtt/user`,
+  /// `/api/mqtt/superuser` and `/api/mqtt/acl`.
+  #[arg(
+    long,
+    env = "HAT_MONITOR_MQTT_AUTH_ALLOW",
+    value_delimiter = ',',
+    default_value = "127.0.0.1,::1",
+    global = true
+  )]
+  pub mqtt_auth_allow: Vec<IpAddr>,
+  /// URL the hats reach the server at, firmware updates download from it.
+  #[arg(long, env = "HAT_MONITOR_PUBLIC_URL", global = true)]
+  pub public_url: Option<String>,
+}
+
+impl Config {
+  pub fn settings(&self) -> Result<SettingsStore, app::store::StoreError> {
+    SettingsStore::open(self.data_dir.join("settings.json"))
+  }
+
+  pub fn auth(&self) -> Result<Auth, app::store::StoreError> {
+    Auth::open(self.data_dir.join("accounts.json"))
+  }
+
+  pub fn audit_log(&self) -> AuditLog {
+    AuditLog::new(self.data_dir.join("audit.ndjson"))
+  }
+
+  pub fn devices(&self) -> Result<DeviceRegistry, app::store::StoreError> {
+    DeviceRegistry::open(self.data_dir.join("devices.json"))
+  }
+
+  /// Options of a server MQTT client, with the server's credentials.
+  pub fn mqtt_options(&self, client_id: &str) -> MqttOptions {
+    let mut options = MqttOptions::new(client_id, self.mqtt_host.clone(), self.mqtt_port);
+    options.set_keep_alive(Duration::from_secs(5));
+    if let Some(username) = &self.mqtt_username {
+      options.set_credentials(
+        username.clone(),
+        self.mqtt_password.clone().unwrap_or_default(),
+      );
+    }
+    options
+  }
+
+  pub fn firmware(&self) -> Result<FirmwareStore, FirmwareError> {
+    FirmwareStore::open(self.data_dir.join("firmware"), self.public_url.as_deref())
+  }
+
+  /// The built-in metrics with the ones of `metrics.json` over them.
+  pub fn metrics(&self) -> Result<MetricCatalog, app::store::StoreError> {
+    app::metrics::load_catalog(self.data_dir.join("metrics.json"))
+  }
+
+  pub fn samples(&self) -> SampleStore {
+    SampleStore::new(self.data_dir.join("samples"))
+  }
+
+  /// Problems that would stop `serve` or make it misbehave, empty when the
+  /// configuration is usable.
+  pub fn check(&self) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Err(e) = check_writable(&self.data_dir) {
+      problems.push(format!(
+        "data dir {} is not writable: {e}",
+        self.data_dir.display()
+      ));
+    }
+    match self.settings() {
+      Ok(store) => store.with(|settings| {
+        for device_id in settings.devices.keys() {
+          if let Err(e) = validate_device_id(device_id) {
+            problems.push(format!("settings: device {device_id:?} {e}"));
+          }
+        }
+        for (device_id, info) in &settings.devices {
+          if let Err(e) = info.validate() {
+            problems.push(format!("settings: device {device_id}: {e}"));
+          }
+        }
+        let thresholds =
+          std::iter::once(&settings.thresholds.default).chain(settings.thresholds.devices.values());
+        for thresholds in thresholds {
+          if let Err(e) = validate_thresholds(thresholds) {
+            problems.push(format!("settings: {e}"));
+          }
+        }
+        if let Err(e) = validate_notifications(&settings.notifications) {
+          problems.push(format!("settings: {e}"));
+        }
+        if let Err(e) = validate_retention(settings.retention_days) {
+          problems.push(format!("settings: {e}"));
+        }
+      }),
+      Err(e) => problems.push(format!("settings: {e}")),
+    }
+    if let Err(e) = self.auth() {
+      problems.push(format!("accounts: {e}"));
+    }
+    if let Err(e) = self.devices() {
+      problems.push(format!("devices: {e}"));
+    }
+    if let Err(e) = self.firmware() {
+      problems.push(format!("firmware: {e}"));
+    }
+    match self.metrics() {
+      Ok(catalog) => {
+        if let Err(e) = catalog.validate() {
+          problems.push(format!("metrics: {e}"));
+        }
+      }
+      Err(e) => problems.push(format!("metrics: {e}")),
+    }
+    if let Some(url) = &self.public_url {
+      if !(url.starts_with("http://") || url.starts_with("https://")) {
+        problems.push(format!("public url {url:?} must be http or https"));
+      }
+    }
+    if let Err(e) = leptos::prelude::get_configuration(None) {
+      problems.push(format!("leptos: {e}"));
+    }
+    if let Err(e) = (self.mqtt_host.as_str(), self.mqtt_port).to_socket_addrs() {
+      problems.push(format!("mqtt host {}: {e}", self.mqtt_host));
+    }
+    if self.mqtt_topic.is_empty() || self.mqtt_topic.contains(['+', '#']) {
+      problems.push(format!(
+        "mqtt topic {:?} must be a plain topic without wildcards",
+        self.mqtt_topic
+      ));
+    }
+    if self.mqtt_backoff_min == 0 || self.mqtt_backoff_min > self.mqtt_backoff_max {
+      problems.push(format!(
+        "mqtt backoff {}s..{}s must be positive and increasing",
+        self.mqtt_backoff_min, self.mqtt_backoff_max
+      ));
+    }
+    problems
+  }
+}
+
+fn check_writable(dir: &PathBuf) -> io::Result<()> {
+  fs::create_dir_all(dir)?;
+  let probe = dir.join(".check-config");
+  fs::File::create(&probe)?.write_all(b"ok")?;
+  fs::remove_file(probe)
+}
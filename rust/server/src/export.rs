@@ -0,0 +1,479 @@
+use std::{fmt::Write as _, io, path::PathBuf, process::ExitCode};
+
+use app::{auth::Identity, store::SettingsStore};
+use axum::{
+  body::{Body, Bytes},
+  extract::{Query, State},
+  http::{header, StatusCode},
+  response::{IntoResponse, Response},
+  Extension,
+};
+use chrono::Utc;
+use clap::Args;
+use serde::Serialize;
+use tokio::{
+  io::{AsyncWrite, AsyncWriteExt},
+  sync::mpsc,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::warn;
+use types::{
+  derived::DerivedMetrics,
+  export::{column_header, ExportFormat, ExportRequest},
+  schema::Field,
+  settings::validate_device_id,
+  units::Units,
+  HatSample,
+};
+
+use crate::{
+  columnar::{self, DeviceFiles},
+  config::Config,
+  import,
+  storage::{self, SampleStore},
+};
+
+/// Bytes buffered before a chunk is sent to the client.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks in flight, bounding memory when the client reads slowly.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// What an export holds besides the stored readings.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Columns {
+  /// Adds dew point, absolute humidity and heat index.
+  pub derived: bool,
+  /// Units the readings are converted into, and named in the column headers.
+  pub units: Units,
+}
+
+/// NDJSON line of the export. `units` tells what the readings are in.
+#[derive(Serialize)]
+struct Row<'a> {
+  #[serde(flatten)]
+  sample: &'a HatSample,
+  #[serde(flatten, skip_serializing_if = "Option::is_none")]
+  derived: Option<DerivedMetrics>,
+  units: Units,
+}
+
+/// `GET /api/export?device=&from=&to=&format=csv|ndjson|parquet|arrows&derived=&units=`:
+/// stored samples of a device, or of every visible device when `device` is
+/// empty, as a download, with the readings in `units`. Day files are read
+/// one at a time and streamed, so long ranges don't build up in memory.
+pub(crate) async fn export(
+  State((store, settings)): State<(SampleStore, SettingsStore)>,
+  Extension(identity): Extension<Identity>,
+  Query(request): Query<ExportRequest>,
+) -> Result<Response, StatusCode> {
+  let to = request.to.unwrap_or_else(|| Utc::now().timestamp() as u64);
+  let from = request.from.unwrap_or(to.saturating_sub(24 * 60 * 60));
+  if from > to {
+    return Err(StatusCode::BAD_REQUEST);
+  }
+  let device_ids = if request.device.is_empty() {
+    let stored = store.devices().await.map_err(|e| {
+      warn!(target = "export", case = "devices", "{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    settings.with(|settings| {
+      stored
+        .into_iter()
+        .filter(|device| validate_device_id(device).is_ok() && identity.can_see(device, settings))
+        .collect::<Vec<_>>()
+    })
+  } else {
+    if validate_device_id(&request.device).is_err() {
+      return Err(StatusCode::BAD_REQUEST);
+    }
+    if !settings.with(|settings| identity.can_see(&request.device, settings)) {
+      return Err(StatusCode::FORBIDDEN);
+    }
+    vec![request.device.clone()]
+  };
+  let devices = device_files(&store, device_ids, from, to)
+    .await
+    .map_err(|e| {
+      warn!(target = "export", case = "list", "{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+  let name = if request.device.is_empty() {
+    "all"
+  } else {
+    request.device.as_str()
+  };
+  let attachment =
+    |extension: &str| format!("attachment; filename=\"{name}-{from}-{to}.{extension}\"");
+  let format = request.format;
+  let partitioned = format == ExportFormat::Parquet && request.device.is_empty();
+  let (content_type, extension) = if partitioned {
+    ("application/zip", "zip")
+  } else {
+    (format.content_type(), format.extension())
+  };
+  let columns = Columns {
+    derived: request.derived,
+    units: request.units,
+  };
+  let rx = stream(devices, from, to, format, partitioned, columns);
+  Ok(
+    (
+      [
+        (header::CONTENT_TYPE, content_type.to_string()),
+        (header::CONTENT_DISPOSITION, attachment(extension)),
+      ],
+      Body::from_stream(ReceiverStream::new(rx)),
+    )
+      .into_response(),
+  )
+}
+
+async fn device_files(
+  store: &SampleStore,
+  device_ids: Vec<String>,
+  from: u64,
+  to: u64,
+) -> io::Result<Vec<DeviceFiles>> {
+  let mut devices = Vec::with_capacity(device_ids.len());
+  for device_id in device_ids {
+    let paths = store.day_files_between(&device_id, from, to).await?;
+    devices.push(DeviceFiles { device_id, paths });
+  }
+  Ok(devices)
+}
+
+/// Chunks of the export of `devices` in `format`. Parquet is a single file
+/// of one device, or a zip archive of one file per device when
+/// `partitioned`.
+fn stream(
+  mut devices: Vec<DeviceFiles>,
+  from: u64,
+  to: u64,
+  format: ExportFormat,
+  partitioned: bool,
+  columns: Columns,
+) -> mpsc::Receiver<io::Result<Bytes>> {
+  let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+  match format {
+    ExportFormat::Csv | ExportFormat::Ndjson => {
+      tokio::spawn(write_rows(devices, from, to, format, columns, tx));
+    }
+    ExportFormat::Arrow => {
+      tokio::spawn(columnar::write_arrow(devices, from, to, columns, tx));
+    }
+    ExportFormat::Parquet if partitioned => {
+      tokio::spawn(columnar::partitioned_parquet(
+        devices, from, to, columns, tx,
+      ));
+    }
+    ExportFormat::Parquet => {
+      if let Some(device) = devices.pop() {
+        tokio::spawn(columnar::write_parquet(device, from, to, columns, tx));
+      }
+    }
+  }
+  rx
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct ExportArgs {
+  /// Device to export, every stored device by default.
+  #[arg(long)]
+  device: Option<String>,
+  /// Unix seconds or RFC 3339, a day before `--to` by default.
+  #[arg(long, value_parser = import::parse_timestamp)]
+  from: Option<u64>,
+  /// Unix seconds or RFC 3339, now by default.
+  #[arg(long, value_parser = import::parse_timestamp)]
+  to: Option<u64>,
+  /// `csv`, `ndjson`, `parquet` or `arrows`.
+  #[arg(long, default_value_t)]
+  format: ExportFormat,
+  /// Adds dew point, absolute humidity and heat index.
+  #[arg(long)]
+  derived: bool,
+  /// Units of the readings, `<temperature>,<gas>` such as
+  /// `fahrenheit,mg_per_m3`.
+  #[arg(long, default_value_t)]
+  units: Units,
+  /// File to write, stdout by default. Parquet of every device is a zip
+  /// archive partitioned by device.
+  #[arg(long, short)]
+  output: Option<PathBuf>,
+}
+
+/// `server export`: writes stored samples like the download of the
+/// dashboard does.
+pub(crate) async fn cli(config: &Config, args: ExportArgs) -> ExitCode {
+  let to = args.to.unwrap_or_else(|| Utc::now().timestamp() as u64);
+  let from = args.from.unwrap_or(to.saturating_sub(24 * 60 * 60));
+  if from > to {
+    eprintln!("--from must not be after --to");
+    return ExitCode::from(2);
+  }
+  let store = config.samples();
+  let device_ids = match &args.device {
+    Some(device) => match validate_device_id(device) {
+      Ok(()) => vec![device.clone()],
+      Err(e) => {
+        eprintln!("invalid device id: {e}");
+        return ExitCode::from(2);
+      }
+    },
+    None => match store.devices().await {
+      Ok(devices) => devices
+        .into_iter()
+        .filter(|device| validate_device_id(device).is_ok())
+        .collect(),
+      Err(e) => {
+        eprintln!("{e}");
+        return ExitCode::FAILURE;
+      }
+    },
+  };
+  let result = async {
+    let devices = device_files(&store, device_ids, from, to).await?;
+    let mut output: Box<dyn AsyncWrite + Unpin + Send> = match &args.output {
+      Some(path) => Box::new(tokio::fs::File::create(path).await?),
+      None => Box::new(tokio::io::stdout()),
+    };
+    let partitioned = args.format == ExportFormat::Parquet && args.device.is_none();
+    let columns = Columns {
+      derived: args.derived,
+      units: args.units,
+    };
+    let mut rx = stream(devices, from, to, args.format, partitioned, columns);
+    while let Some(chunk) = rx.recv().await {
+      output.write_all(&chunk?).await?;
+    }
+    output.flush().await
+  }
+  .await;
+  match result {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("{e}");
+      ExitCode::FAILURE
+    }
+  }
+}
+
+async fn write_rows(
+  devices: Vec<DeviceFiles>,
+  from: u64,
+  to: u64,
+  format: ExportFormat,
+  columns: Columns,
+  tx: mpsc::Sender<io::Result<Bytes>>,
+) {
+  let mut buffer = String::with_capacity(CHUNK_SIZE);
+  if format == ExportFormat::Csv {
+    buffer.push_str(&csv_header(columns));
+  }
+  for path in devices.into_iter().flat_map(|device| device.paths) {
+    let samples = match storage::read_day_file(&path, from, to).await {
+      Ok(samples) => samples,
+      Err(e) => {
+        warn!(target = "export", case = "read", "{:?}", e);
+        // Ends the download with an error instead of a silently short file.
+        let _ = tx.send(Err(e)).await;
+        return;
+      }
+    };
+    for sample in &samples {
+      match format {
+        ExportFormat::Csv => write_csv_row(&mut buffer, sample, columns),
+        ExportFormat::Ndjson => {
+          let row = Row {
+            sample: &columns.units.convert_sample(sample),
+            derived: derived(sample, columns),
+            units: columns.units,
+          };
+          match serde_json::to_string(&row) {
+            Ok(line) => {
+              buffer.push_str(&line);
+              buffer.push('\n');
+            }
+            Err(e) => warn!(target = "export", case = "serde json err", "{:?}", e),
+          }
+        }
+        ExportFormat::Parquet | ExportFormat::Arrow => {
+          unreachable!("{format} is written by `columnar`")
+        }
+      }
+      if buffer.len() >= CHUNK_SIZE {
+        let chunk = std::mem::replace(&mut buffer, String::with_capacity(CHUNK_SIZE));
+        if tx.send(Ok(Bytes::from(chunk))).await.is_err() {
+          // The client went away.
+          return;
+        }
+      }
+    }
+  }
+  if !buffer.is_empty() {
+    let _ = tx.send(Ok(Bytes::from(buffer))).await;
+  }
+}
+
+/// The CSV header line, with the unit of every column that has one.
+fn csv_header(columns: Columns) -> String {
+  let mut names = vec!["device_id".to_string(), "timestamp".to_string()];
+  names
+    .extend(Field::ALL.map(|field| column_header(field.name(), columns.units.field_symbol(field))));
+  names.push("measurements".to_string());
+  if columns.derived {
+    names.extend(
+      DerivedMetrics::NAMES
+        .into_iter()
+        .zip(DerivedMetrics::symbols(&columns.units))
+        .map(|(name, unit)| column_header(name, unit)),
+    );
+  }
+  names.join(",") + "\n"
+}
+
+/// The derived metrics of `sample`, in `columns.units`, if they were asked
+/// for.
+fn derived(sample: &HatSample, columns: Columns) -> Option<DerivedMetrics> {
+  columns
+    .derived
+    .then(|| DerivedMetrics::of(sample))
+    .flatten()
+    .map(|derived| derived.in_units(&columns.units))
+}
+
+fn write_csv_row(buffer: &mut String, sample: &HatSample, columns: Columns) {
+  let derived = derived(sample, columns);
+  let sample = columns.units.convert_sample(sample);
+  // Device ids are restricted to characters that never need quoting.
+  let _ = write!(
+    buffer,
+    "{},{},{},{},{},{},{},{},{},{}",
+    sample.device_id,
+    sample.timestamp,
+    cell(sample.temperature),
+    cell(sample.humidity),
+    cell(sample.r_zero),
+    cell(sample.corrected_r_zero),
+    cell(sample.resistance),
+    cell(sample.ppm),
+    cell(sample.corrected_ppm),
+    measurements_cell(&sample),
+  );
+  if columns.derived {
+    let _ = write!(
+      buffer,
+      ",{},{},{}",
+      cell(derived.map(|derived| derived.dew_point)),
+      cell(derived.map(|derived| derived.absolute_humidity)),
+      cell(derived.map(|derived| derived.heat_index)),
+    );
+  }
+  buffer.push('\n');
+}
+
+/// A reading as a CSV field, empty when it's missing.
+fn cell(value: Option<f32>) -> String {
+  value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// The readings of other sensors as a quoted JSON array, empty when the hat
+/// reported none.
+fn measurements_cell(sample: &HatSample) -> String {
+  if sample.measurements.is_empty() {
+    return String::new();
+  }
+  match serde_json::to_string(&sample.measurements) {
+    Ok(json) => format!("\"{}\"", json.replace('"', "\"\"")),
+    Err(e) => {
+      warn!(target = "export", case = "serde json err", "{:?}", e);
+      String::new()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use types::{
+    import::ImportOptions,
+    measurement::MetricCatalog,
+    units::{GasUnit, TemperatureUnit},
+  };
+
+  use super::*;
+  use crate::import;
+
+  #[tokio::test]
+  async fn exports_in_other_units_import_back() {
+    let root = std::env::temp_dir().join(format!("hat-monitor-export-{}", std::process::id()));
+    let store = SampleStore::new(root.join("source"));
+    store
+      .append(&HatSample {
+        device_id: "hat-1".to_string(),
+        timestamp: 1_760_000_000,
+        temperature: Some(20.0),
+        humidity: Some(50.0),
+        ppm: Some(400.0),
+        ..HatSample::default()
+      })
+      .await
+      .unwrap();
+    let columns = Columns {
+      derived: true,
+      units: Units {
+        temperature: TemperatureUnit::Fahrenheit,
+        gas: GasUnit::MilligramsPerCubicMetre,
+      },
+    };
+    for format in [ExportFormat::Csv, ExportFormat::Ndjson] {
+      let (from, to) = (1_760_000_000, 1_760_000_000);
+      let devices = device_files(&store, vec!["hat-1".to_string()], from, to)
+        .await
+        .unwrap();
+      let (tx, mut rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+      write_rows(devices, from, to, format, columns, tx).await;
+      let mut export = Vec::new();
+      while let Some(chunk) = rx.recv().await {
+        export.extend_from_slice(&chunk.unwrap());
+      }
+      let text = String::from_utf8(export.clone()).unwrap();
+      match format {
+        ExportFormat::Csv => assert!(
+          text.starts_with(
+            "device_id,timestamp,temperature (°F),humidity (%),r_zero (Ω),\
+          corrected_r_zero (Ω),resistance (Ω),ppm (mg/m³),corrected_ppm (mg/m³),\
+          measurements,dew_point (°F),absolute_humidity (g/m³),heat_index (°F)\n\
+          hat-1,1760000000,68,50,,,,720,"
+          ),
+          "{text}"
+        ),
+        _ => assert!(text.contains(r#""temperature":68.0"#)),
+      }
+
+      let target = SampleStore::new(root.join(format.extension()));
+      let options = ImportOptions {
+        format,
+        device: None,
+        dry_run: false,
+      };
+      let report = import::import(
+        &target,
+        &MetricCatalog::builtin(),
+        export.as_slice(),
+        &options,
+      )
+      .await
+      .unwrap();
+      assert_eq!(report.imported, 1, "{format}: {report:?}");
+      let (_, path) = target.day_files("hat-1").await.unwrap().remove(0);
+      let sample = storage::read_day_file(&path, 0, u64::MAX)
+        .await
+        .unwrap()
+        .remove(0);
+      assert!((sample.temperature.unwrap() - 20.0).abs() < 0.01);
+      assert!((sample.ppm.unwrap() - 400.0).abs() < 0.1);
+    }
+    let _ = std::fs::remove_dir_all(root);
+  }
+}
@@ -0,0 +1,155 @@
+//! REST API of the hosted firmware images, and their download for the hats.
+
+use app::{
+  audit::AuditLog,
+  auth::Identity,
+  commands::{self, CommandHub},
+  firmware::{FirmwareError, FirmwareStore},
+  store::SettingsStore,
+};
+use axum::{
+  body::{Body, Bytes},
+  extract::{Path, Query, State},
+  http::{header, StatusCode},
+  response::IntoResponse,
+  Extension, Json,
+};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+use types::{
+  accounts::Role,
+  audit::AuditAction,
+  firmware::{validate_version, FirmwareImage, FirmwareOverview, Rollout, RolloutResult},
+};
+
+type FirmwareState = (FirmwareStore, CommandHub, SettingsStore, AuditLog);
+
+fn status_of(e: &FirmwareError) -> StatusCode {
+  match e {
+    FirmwareError::Invalid(_) => StatusCode::BAD_REQUEST,
+    FirmwareError::Exists(_) => StatusCode::CONFLICT,
+    FirmwareError::NotFound(_) => StatusCode::NOT_FOUND,
+    FirmwareError::NoPublicUrl => StatusCode::SERVICE_UNAVAILABLE,
+    FirmwareError::Io(_) | FirmwareError::Json(_) => {
+      warn!(target = "firmware", case = "store", "{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    }
+  }
+}
+
+/// `GET /api/firmware`: the hosted images and the version each visible hat
+/// reported. Operators and admins only.
+pub(crate) async fn list(
+  State((store, _, settings, _)): State<FirmwareState>,
+  Extension(identity): Extension<Identity>,
+) -> Result<Json<FirmwareOverview>, StatusCode> {
+  if !identity.has_role(Role::Operator) {
+    return Err(StatusCode::FORBIDDEN);
+  }
+  let settings = settings.current();
+  Ok(Json(store.overview(|device_id| {
+    identity.can_see(device_id, &settings)
+  })))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct UploadOptions {
+  #[serde(default)]
+  notes: String,
+}
+
+/// `POST /api/firmware/{version}?notes=`: hosts the image in the body.
+/// Admins only, recorded in the audit log.
+pub(crate) async fn upload(
+  State((store, _, _, audit)): State<FirmwareState>,
+  Extension(identity): Extension<Identity>,
+  Path(version): Path<String>,
+  Query(options): Query<UploadOptions>,
+  body: Bytes,
+) -> Result<Json<FirmwareImage>, (StatusCode, String)> {
+  if !identity.has_role(Role::Admin) {
+    return Err((StatusCode::FORBIDDEN, "not allowed".to_string()));
+  }
+  let image = store
+    .add(&version, &options.notes, &identity.username, body)
+    .await
+    .map_err(|e| (status_of(&e), e.to_string()))?;
+  audit
+    .record(
+      &identity.username,
+      AuditAction::FirmwareUploaded,
+      None,
+      format!("{} {} bytes {}", image.version, image.size, image.sha256),
+    )
+    .await;
+  Ok(Json(image))
+}
+
+/// `DELETE /api/firmware/{version}`. Admins only, recorded in the audit log.
+pub(crate) async fn remove(
+  State((store, _, _, audit)): State<FirmwareState>,
+  Extension(identity): Extension<Identity>,
+  Path(version): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+  if !identity.has_role(Role::Admin) {
+    return Err((StatusCode::FORBIDDEN, "not allowed".to_string()));
+  }
+  let image = store
+    .remove(&version)
+    .await
+    .map_err(|e| (status_of(&e), e.to_string()))?;
+  audit
+    .record(
+      &identity.username,
+      AuditAction::FirmwareRemoved,
+      None,
+      format!("{} {}", image.version, image.sha256),
+    )
+    .await;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /api/rollouts`: tells the selected hats to update. Admins only,
+/// every command sent is recorded in the audit log.
+pub(crate) async fn roll_out(
+  State((store, hub, _, audit)): State<FirmwareState>,
+  Extension(identity): Extension<Identity>,
+  Json(rollout): Json<Rollout>,
+) -> Result<Json<Vec<RolloutResult>>, (StatusCode, String)> {
+  if !identity.has_role(Role::Admin) {
+    return Err((StatusCode::FORBIDDEN, "not allowed".to_string()));
+  }
+  let results = store
+    .roll_out(&hub, &identity.username, &rollout)
+    .map_err(|e| (status_of(&e), e.to_string()))?;
+  for record in results.iter().filter_map(|result| result.record.as_ref()) {
+    commands::audit(&audit, &identity.username, record).await;
+  }
+  Ok(Json(results))
+}
+
+/// `GET /firmware/{version}.bin`: the image, for hats that were told to
+/// update. Public, hats have no account; they check the checksum of the
+/// command before flashing.
+pub(crate) async fn download(
+  State((store, _, _, _)): State<FirmwareState>,
+  Path(file): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+  let version = file.strip_suffix(".bin").ok_or(StatusCode::NOT_FOUND)?;
+  validate_version(version).map_err(|_| StatusCode::NOT_FOUND)?;
+  let image = store.image(version).ok_or(StatusCode::NOT_FOUND)?;
+  let file = tokio::fs::File::open(store.image_path(version))
+    .await
+    .map_err(|e| {
+      warn!(target = "firmware", case = "download", "{:?}", e);
+      StatusCode::NOT_FOUND
+    })?;
+  Ok((
+    [
+      (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+      (header::CONTENT_LENGTH, image.size.to_string()),
+    ],
+    Body::from_stream(ReaderStream::new(file)),
+  ))
+}
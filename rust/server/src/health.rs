@@ -0,0 +1,320 @@
+//! `/healthz` and `/readyz` for container orchestration.
+
+use std::{
+  collections::BTreeMap,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use app::{auth::Identity, store::SettingsStore};
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::Utc;
+use serde::Serialize;
+use tokio::{
+  sync::{self, broadcast, mpsc, watch},
+  time::Instant,
+};
+use tracing::warn;
+use types::{accounts::Role, mqtt::MqttStatus, HatSample};
+
+use crate::{
+  alerts::AlertEvent,
+  pipeline::{self, Dropped},
+  storage::SampleStore,
+  supervisor::{Supervisor, WorkerState, WorkerStatus},
+};
+
+/// Timestamp of the latest sample of every device, seeded from the history
+/// and kept current by [`LastSamples::track`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LastSamples(Arc<Mutex<BTreeMap<String, u64>>>);
+
+impl LastSamples {
+  fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, u64>> {
+    self.0.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn update(&self, device_id: &str, timestamp: u64) {
+    let mut last = self.lock();
+    let entry = last.entry(device_id.to_string()).or_default();
+    *entry = (*entry).max(timestamp);
+  }
+
+  pub async fn track(self, mut samples: broadcast::Receiver<HatSample>, store: SampleStore) {
+    match store.devices().await {
+      Ok(devices) => {
+        for device_id in devices {
+          match store.latest_timestamp(&device_id).await {
+            Ok(Some(timestamp)) => self.update(&device_id, timestamp),
+            Ok(None) => {}
+            Err(e) => warn!(target = "health", case = "seed", "{device_id}: {:?}", e),
+          }
+        }
+      }
+      Err(e) => warn!(target = "health", case = "seed", "{:?}", e),
+    }
+    while let Some(sample) = pipeline::next(&mut samples, "health").await {
+      self.update(&sample.device_id, sample.timestamp);
+    }
+  }
+}
+
+/// How long the outcome of a write probe of the history is reused.
+const PROBE_TTL: Duration = Duration::from_secs(5);
+
+/// Whether the history was writable when last probed. `/readyz` is public,
+/// so probes are cached for [`PROBE_TTL`] rather than written on every
+/// request.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StorageProbe(Arc<sync::Mutex<Option<Probed>>>);
+
+/// When the history was probed, and the error if it wasn't writable.
+type Probed = (Instant, Result<(), String>);
+
+impl StorageProbe {
+  async fn check(&self, store: &SampleStore) -> Result<(), String> {
+    // Held across the probe, so concurrent requests wait for one probe.
+    let mut last = self.0.lock().await;
+    match &*last {
+      Some((at, outcome)) if at.elapsed() < PROBE_TTL => outcome.clone(),
+      _ => {
+        let outcome = store.check_writable().await.map_err(|e| e.to_string());
+        *last = Some((Instant::now(), outcome.clone()));
+        outcome
+      }
+    }
+  }
+}
+
+/// Everything `/readyz` looks at.
+#[derive(Debug, Clone)]
+pub(crate) struct Health {
+  pub mqtt: watch::Receiver<MqttStatus>,
+  /// Connections of the enabled integrations, by worker name.
+  pub integrations: BTreeMap<&'static str, watch::Receiver<MqttStatus>>,
+  pub store: SampleStore,
+  pub storage_probe: StorageProbe,
+  pub settings: SettingsStore,
+  pub last_samples: LastSamples,
+  pub alerts: mpsc::Sender<AlertEvent>,
+  pub supervisor: Supervisor,
+  pub dropped: Dropped,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Liveness {
+  status: &'static str,
+}
+
+/// Answer of the public `/readyz`: whether each subsystem is ok, without
+/// device ids, sample ages or errors.
+#[derive(Debug, Serialize)]
+pub(crate) struct ReadinessSummary {
+  ready: bool,
+  mqtt: bool,
+  integrations: bool,
+  storage: bool,
+  notifier: bool,
+  workers: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Readiness {
+  ready: bool,
+  mqtt: MqttStatus,
+  integrations: BTreeMap<&'static str, MqttStatus>,
+  storage: StorageReadiness,
+  devices: Vec<DeviceReadiness>,
+  notifier: NotifierReadiness,
+  workers: Vec<WorkerStatus>,
+  /// Samples storage or alerts fell too far behind to get.
+  dropped_samples: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct StorageReadiness {
+  writable: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceReadiness {
+  device_id: String,
+  /// Unix seconds of the latest sample, `None` before the first one.
+  last_sample: Option<u64>,
+  age_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct NotifierReadiness {
+  /// Alert events waiting to be notified.
+  backlog: usize,
+  capacity: usize,
+}
+
+/// `GET /healthz`: `200` for as long as the server answers requests.
+pub(crate) async fn liveness() -> Json<Liveness> {
+  Json(Liveness { status: "ok" })
+}
+
+/// `GET /readyz`: `503` unless MQTT is subscribed, every integration is
+/// connected to the broker, the history is writable, the alert queue has
+/// room and no worker is backing off, with whether each of them is ok.
+/// Public, so the details are left to `/api/readiness`.
+pub(crate) async fn readiness(
+  State(health): State<Health>,
+) -> (StatusCode, Json<ReadinessSummary>) {
+  let readiness = assess(&health).await;
+  let summary = ReadinessSummary {
+    ready: readiness.ready,
+    mqtt: readiness.mqtt.state.is_receiving(),
+    integrations: integrations_connected(&readiness.integrations),
+    storage: readiness.storage.writable,
+    notifier: readiness.notifier.backlog < readiness.notifier.capacity,
+    workers: readiness
+      .workers
+      .iter()
+      .all(|worker| worker.state != WorkerState::BackingOff),
+  };
+  (status_of(summary.ready), Json(summary))
+}
+
+/// `GET /api/readiness`: what `/readyz` decides on, with the state of every
+/// subsystem and the age of every device's latest sample. Sample ages don't
+/// fail it, a quiet hat is not the server's fault. Admins only.
+pub(crate) async fn details(
+  State(health): State<Health>,
+  Extension(identity): Extension<Identity>,
+) -> Result<(StatusCode, Json<Readiness>), StatusCode> {
+  if !identity.has_role(Role::Admin) {
+    return Err(StatusCode::FORBIDDEN);
+  }
+  let readiness = assess(&health).await;
+  Ok((status_of(readiness.ready), Json(readiness)))
+}
+
+fn integrations_connected(integrations: &BTreeMap<&'static str, MqttStatus>) -> bool {
+  integrations
+    .values()
+    .all(|status| status.state.is_connected())
+}
+
+fn status_of(ready: bool) -> StatusCode {
+  if ready {
+    StatusCode::OK
+  } else {
+    StatusCode::SERVICE_UNAVAILABLE
+  }
+}
+
+async fn assess(health: &Health) -> Readiness {
+  let mqtt = health.mqtt.borrow().clone();
+  let integrations = health
+    .integrations
+    .iter()
+    .map(|(name, status)| (*name, status.borrow().clone()))
+    .collect::<BTreeMap<_, _>>();
+  let storage = match health.storage_probe.check(&health.store).await {
+    Ok(()) => StorageReadiness {
+      writable: true,
+      error: None,
+    },
+    Err(e) => StorageReadiness {
+      writable: false,
+      error: Some(e),
+    },
+  };
+
+  let now = Utc::now().timestamp() as u64;
+  let mut last = health.last_samples.lock().clone();
+  health.settings.with(|settings| {
+    for device_id in settings.devices.keys() {
+      last.entry(device_id.clone()).or_default();
+    }
+  });
+  let devices = last
+    .into_iter()
+    .map(|(device_id, timestamp)| {
+      let last_sample = (timestamp != 0).then_some(timestamp);
+      DeviceReadiness {
+        device_id,
+        last_sample,
+        age_secs: last_sample.map(|timestamp| now.saturating_sub(timestamp)),
+      }
+    })
+    .collect();
+
+  let notifier = NotifierReadiness {
+    backlog: health.alerts.max_capacity() - health.alerts.capacity(),
+    capacity: health.alerts.max_capacity(),
+  };
+  let workers = health.supervisor.statuses();
+  let dropped_samples = health.dropped.count();
+
+  let ready = mqtt.state.is_receiving()
+    && integrations_connected(&integrations)
+    && storage.writable
+    && notifier.backlog < notifier.capacity
+    && workers
+      .iter()
+      .all(|worker| worker.state != WorkerState::BackingOff);
+  Readiness {
+    ready,
+    mqtt,
+    integrations,
+    storage,
+    devices,
+    notifier,
+    workers,
+    dropped_samples,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn samples_of_every_device_are_tracked() {
+    let store = SampleStore::new(
+      std::env::temp_dir().join(format!("hat-monitor-health-{}", std::process::id())),
+    );
+    let (live, samples) = broadcast::channel(16);
+    // Sent back to back, before the tracker gets to run.
+    for (device_id, timestamp) in [("hat-1", 10), ("hat-2", 20), ("hat-1", 5)] {
+      live
+        .send(HatSample {
+          device_id: device_id.to_string(),
+          timestamp,
+          ..HatSample::default()
+        })
+        .unwrap();
+    }
+    drop(live);
+    let last_samples = LastSamples::default();
+    last_samples.clone().track(samples, store).await;
+    assert_eq!(
+      *last_samples.lock(),
+      BTreeMap::from([("hat-1".to_string(), 10), ("hat-2".to_string(), 20)])
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn storage_probes_are_reused_until_they_expire() {
+    let root = std::env::temp_dir().join(format!("hat-monitor-probe-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let store = SampleStore::new(&root);
+    let probe = StorageProbe::default();
+    assert_eq!(probe.check(&store).await, Ok(()));
+
+    // A file where the history should be can't be written under.
+    std::fs::remove_dir_all(&root).unwrap();
+    std::fs::write(&root, b"").unwrap();
+    tokio::time::advance(PROBE_TTL / 2).await;
+    assert_eq!(probe.check(&store).await, Ok(()));
+    tokio::time::advance(PROBE_TTL).await;
+    assert!(probe.check(&store).await.is_err());
+    std::fs::remove_file(&root).unwrap();
+  }
+}
@@ -0,0 +1,471 @@
+//! Home Assistant MQTT discovery, so hats show up in Home Assistant as
+//! devices with their sensors without any YAML.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt::Write,
+};
+
+use app::store::SettingsStore;
+use clap::Args;
+use rumqttc::v5::{
+  mqttbytes::{v5::LastWill, QoS},
+  AsyncClient, Event, Incoming,
+};
+use serde::Serialize;
+use tokio::{
+  sync::{broadcast, watch},
+  time,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use types::{
+  measurement::MetricCatalog, mqtt::MqttStatus, schema::Field, settings::Settings, HatSample,
+};
+
+use crate::{
+  config::Config,
+  mqttc_worker::{self, Connection},
+  pipeline,
+  storage::SampleStore,
+};
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct HomeAssistantArgs {
+  /// Publishes Home Assistant discovery configs and the state of every hat
+  /// to the broker.
+  #[arg(
+    id = "ha_discovery",
+    long = "ha-discovery",
+    env = "HAT_MONITOR_HA_DISCOVERY"
+  )]
+  pub enabled: bool,
+  /// Topic prefix Home Assistant watches for discovery configs.
+  #[arg(
+    long = "ha-discovery-prefix",
+    env = "HAT_MONITOR_HA_DISCOVERY_PREFIX",
+    default_value = "homeassistant"
+  )]
+  pub discovery_prefix: String,
+  /// Prefix of the state topics, `<prefix>/<device id>/state`, and of the
+  /// `<prefix>/status` availability topic.
+  #[arg(
+    long = "ha-state-prefix",
+    env = "HAT_MONITOR_HA_STATE_PREFIX",
+    default_value = "hat-monitor"
+  )]
+  pub state_prefix: String,
+}
+
+/// A Home Assistant sensor of a hat.
+#[derive(Debug, Clone, PartialEq)]
+struct Sensor<'a> {
+  key: &'a str,
+  name: &'a str,
+  device_class: Option<&'static str>,
+  unit: &'a str,
+  /// Read from the `measurements` of the state rather than its top level.
+  measurement: bool,
+}
+
+/// Sensors of every hat.
+const SENSORS: [Sensor<'static>; 4] = [
+  Sensor {
+    key: "temperature",
+    name: "Temperature",
+    device_class: Some("temperature"),
+    unit: "°C",
+    measurement: false,
+  },
+  Sensor {
+    key: "humidity",
+    name: "Humidity",
+    device_class: Some("humidity"),
+    unit: "%",
+    measurement: false,
+  },
+  Sensor {
+    key: "co2",
+    name: "CO2",
+    device_class: Some("carbon_dioxide"),
+    unit: "ppm",
+    measurement: false,
+  },
+  Sensor {
+    key: "resistance",
+    name: "Sensor resistance",
+    device_class: None,
+    unit: "Ω",
+    measurement: false,
+  },
+];
+
+/// Home Assistant's device class of the other sensors the catalog comes
+/// with.
+fn device_class(metric: &str) -> Option<&'static str> {
+  match metric {
+    "pm25" => Some("pm25"),
+    "pressure" => Some("atmospheric_pressure"),
+    "light" => Some("illuminance"),
+    _ => None,
+  }
+}
+
+/// The sensors of a hat reporting the catalog `metrics` besides the fixed
+/// readings.
+fn sensors<'a>(catalog: &'a MetricCatalog, metrics: &BTreeSet<String>) -> Vec<Sensor<'a>> {
+  let measurements = metrics.iter().filter_map(|metric| {
+    let definition = catalog.get(metric)?;
+    Some(Sensor {
+      key: &definition.key,
+      name: &definition.label,
+      device_class: device_class(&definition.key),
+      unit: &definition.unit,
+      measurement: true,
+    })
+  });
+  SENSORS.into_iter().chain(measurements).collect()
+}
+
+/// Metrics of `sample` besides the fixed readings the `catalog` knows.
+fn catalog_metrics(catalog: &MetricCatalog, sample: &HatSample) -> BTreeSet<String> {
+  sample
+    .measurements
+    .iter()
+    .map(|measurement| &measurement.metric)
+    .filter(|metric| {
+      catalog.get(metric).is_some() && Field::ALL.iter().all(|field| field.name() != *metric)
+    })
+    .cloned()
+    .collect()
+}
+
+/// `device_id` in the characters Home Assistant allows in an object id,
+/// letters and digits as they are and every other byte as `_` and its hex
+/// code, so no two devices share one.
+fn object_id(device_id: &str) -> String {
+  let mut object_id = String::with_capacity(device_id.len());
+  for byte in device_id.bytes() {
+    if byte.is_ascii_alphanumeric() {
+      object_id.push(byte as char);
+    } else {
+      let _ = write!(object_id, "_{byte:02x}");
+    }
+  }
+  object_id
+}
+
+/// Payload of a `<discovery prefix>/sensor/.../config` topic.
+#[derive(Debug, Serialize)]
+struct SensorConfig<'a> {
+  name: &'a str,
+  unique_id: String,
+  state_topic: String,
+  value_template: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  device_class: Option<&'static str>,
+  unit_of_measurement: &'a str,
+  state_class: &'static str,
+  availability_topic: String,
+  device: DeviceConfig<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceConfig<'a> {
+  identifiers: [String; 1],
+  name: &'a str,
+  manufacturer: &'static str,
+  model: &'static str,
+  #[serde(skip_serializing_if = "str::is_empty")]
+  suggested_area: &'a str,
+}
+
+/// Payload of a state topic: the readings in canonical units, the gas
+/// reading humidity and temperature corrected, and the readings of other
+/// sensors by metric. Missing readings are `null`, which Home Assistant
+/// shows as unknown.
+#[derive(Debug, Serialize)]
+struct State<'a> {
+  timestamp: u64,
+  temperature: Option<f32>,
+  humidity: Option<f32>,
+  co2: Option<f32>,
+  resistance: Option<f32>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  measurements: BTreeMap<&'a str, Option<f32>>,
+}
+
+impl<'a> From<&'a HatSample> for State<'a> {
+  fn from(sample: &'a HatSample) -> Self {
+    Self {
+      timestamp: sample.timestamp,
+      temperature: sample.temperature,
+      humidity: sample.humidity,
+      co2: sample.corrected_ppm,
+      resistance: sample.resistance,
+      measurements: sample
+        .measurements
+        .iter()
+        .map(|measurement| (measurement.metric.as_str(), measurement.value))
+        .collect(),
+    }
+  }
+}
+
+struct Publisher<'a> {
+  args: &'a HomeAssistantArgs,
+  catalog: &'a MetricCatalog,
+  client: AsyncClient,
+}
+
+impl Publisher<'_> {
+  fn availability_topic(&self) -> String {
+    format!("{}/status", self.args.state_prefix)
+  }
+
+  fn state_topic(&self, device_id: &str) -> String {
+    format!("{}/{device_id}/state", self.args.state_prefix)
+  }
+
+  /// Publishes without waiting, the event loop runs on the same task.
+  fn publish(&self, topic: String, payload: Vec<u8>) {
+    if let Err(e) = self
+      .client
+      .try_publish(topic, QoS::AtLeastOnce, true, payload)
+    {
+      warn!(target = "homeassistant", case = "publish", "{:?}", e);
+    }
+  }
+
+  /// Configs of the sensors of `device_id`, which reported the catalog
+  /// `metrics` so far.
+  fn announce(&self, device_id: &str, metrics: &BTreeSet<String>, settings: &Settings) {
+    let info = settings.devices.get(device_id);
+    let name = info
+      .map(|info| info.name.as_str())
+      .filter(|name| !name.is_empty())
+      .unwrap_or(device_id);
+    let area = info.map(|info| info.location.as_str()).unwrap_or_default();
+    for sensor in sensors(self.catalog, metrics) {
+      let object_id = format!("{}_{}", object_id(device_id), sensor.key);
+      let path = if sensor.measurement {
+        format!("measurements.{}", sensor.key)
+      } else {
+        sensor.key.to_string()
+      };
+      let config = SensorConfig {
+        name: sensor.name,
+        unique_id: format!("hat_monitor_{object_id}"),
+        state_topic: self.state_topic(device_id),
+        value_template: format!("{{{{ value_json.{path} }}}}"),
+        device_class: sensor.device_class,
+        unit_of_measurement: sensor.unit,
+        state_class: "measurement",
+        availability_topic: self.availability_topic(),
+        device: DeviceConfig {
+          identifiers: [format!("hat-monitor-{device_id}")],
+          name,
+          manufacturer: "hat-monitor",
+          model: "Hat",
+          suggested_area: area,
+        },
+      };
+      let topic = format!(
+        "{}/sensor/hat-monitor/{object_id}/config",
+        self.args.discovery_prefix
+      );
+      match serde_json::to_vec(&config) {
+        Ok(payload) => self.publish(topic, payload),
+        Err(e) => warn!(target = "homeassistant", case = "serde json err", "{:?}", e),
+      }
+    }
+  }
+
+  fn state(&self, sample: &HatSample) {
+    match serde_json::to_vec(&State::from(sample)) {
+      Ok(payload) => self.publish(self.state_topic(&sample.device_id), payload),
+      Err(e) => warn!(target = "homeassistant", case = "serde json err", "{:?}", e),
+    }
+  }
+}
+
+/// Where the hats and their sensors come from.
+#[derive(Debug, Clone)]
+pub(crate) struct Inventory {
+  pub catalog: MetricCatalog,
+  pub settings: SettingsStore,
+  pub store: SampleStore,
+}
+
+/// Announces every registered, stored or reporting hat and republishes
+/// their samples until `shutdown`. Configs are announced again on every
+/// (re)connect and whenever the settings change, so renames reach Home
+/// Assistant.
+pub(crate) async fn run(
+  config: &Config,
+  args: &HomeAssistantArgs,
+  inventory: Inventory,
+  mut samples: broadcast::Receiver<HatSample>,
+  status: watch::Sender<MqttStatus>,
+  shutdown: CancellationToken,
+) {
+  let mut mqtt_options = config.mqtt_options("hat-monitor-homeassistant");
+  mqtt_options.set_last_will(LastWill::new(
+    format!("{}/status", args.state_prefix),
+    "offline",
+    QoS::AtLeastOnce,
+    true,
+    None,
+  ));
+  let (client, mut event_loop) = AsyncClient::new(mqtt_options, 1000);
+  let Inventory {
+    catalog,
+    settings,
+    store,
+  } = inventory;
+  let catalog = &catalog;
+  let publisher = Publisher {
+    args,
+    catalog,
+    client,
+  };
+  let mut connection = Connection::new(config, status);
+
+  // Catalog metrics of every hat, as far as it reported them since start.
+  let mut devices = store
+    .devices()
+    .await
+    .inspect_err(|e| warn!(target = "homeassistant", case = "devices", "{:?}", e))
+    .unwrap_or_default()
+    .into_iter()
+    .map(|device_id| (device_id, BTreeSet::new()))
+    .collect::<BTreeMap<_, _>>();
+  let mut settings = settings.subscribe();
+  let mut connected = false;
+  loop {
+    tokio::select! {
+      event = event_loop.poll() => match event {
+        Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+          info!(target = "homeassistant", case = "connected", "announcing {} hats", devices.len());
+          connected = true;
+          connection.connected();
+          publisher.publish(publisher.availability_topic(), b"online".to_vec());
+          let current = settings.borrow_and_update().clone();
+          for device_id in current.devices.keys() {
+            devices.entry(device_id.clone()).or_default();
+          }
+          for (device_id, metrics) in &devices {
+            publisher.announce(device_id, metrics, &current);
+          }
+        }
+        Ok(event) => debug!(target = "homeassistant", case = "ok", "{:?}", event),
+        Err(e) => {
+          connected = false;
+          let (error, delay) = connection.failed(&e);
+          warn!(
+            target = "homeassistant",
+            case = "err",
+            "failure {}: {:?}, retrying in {delay:?}",
+            connection.failures(),
+            error
+          );
+          tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = shutdown.cancelled() => break,
+          }
+          connection.retrying();
+        }
+      },
+      changed = settings.changed(), if connected => {
+        if changed.is_err() {
+          break;
+        }
+        let current = settings.borrow_and_update().clone();
+        for device_id in current.devices.keys() {
+          devices.entry(device_id.clone()).or_default();
+        }
+        for (device_id, metrics) in &devices {
+          publisher.announce(device_id, metrics, &current);
+        }
+      }
+      // Taken while disconnected too, new hats are announced on connect.
+      sample = pipeline::next(&mut samples, "homeassistant") => {
+        let Some(sample) = sample else {
+          break;
+        };
+        let reported = catalog_metrics(catalog, &sample);
+        let new = !devices.contains_key(&sample.device_id);
+        let metrics = devices.entry(sample.device_id.clone()).or_default();
+        let grown = !reported.is_subset(metrics);
+        metrics.extend(reported);
+        if !connected {
+          continue;
+        }
+        // Hats and their sensors are announced when first seen.
+        if new || grown {
+          publisher.announce(&sample.device_id, metrics, &settings.borrow());
+        }
+        publisher.state(&sample);
+      }
+      _ = shutdown.cancelled() => break,
+    }
+  }
+  // The will only fires on abrupt disconnects.
+  publisher.publish(publisher.availability_topic(), b"offline".to_vec());
+  mqttc_worker::disconnect(&publisher.client, &mut event_loop).await;
+  connection.stopped();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn catalog_metrics_become_sensors() {
+    let sample: HatSample = serde_json::from_str(
+      r#"{"timestamp":1760000000,"temperature":20.0,"measurements":[{"metric":"co","unit":"ppm","value":9.0},{"metric":"pm25","unit":"µg/m³","value":null},{"metric":"radon","unit":"Bq/m³","value":40.0}]}"#,
+    )
+    .unwrap();
+    let mut catalog = MetricCatalog::builtin();
+    catalog.extend(
+      serde_json::from_str::<Vec<_>>(r#"[{"key":"co","label":"CO","unit":"ppm"}]"#).unwrap(),
+    );
+    let metrics = catalog_metrics(&catalog, &sample);
+    assert_eq!(
+      metrics,
+      BTreeSet::from(["co".to_string(), "pm25".to_string()])
+    );
+    let sensors = sensors(&catalog, &metrics);
+    assert_eq!(sensors.len(), SENSORS.len() + 2);
+    assert_eq!(
+      sensors[SENSORS.len()],
+      Sensor {
+        key: "co",
+        name: "CO",
+        device_class: None,
+        unit: "ppm",
+        measurement: true,
+      }
+    );
+    assert_eq!(sensors[SENSORS.len() + 1].device_class, Some("pm25"));
+    let state = serde_json::to_value(State::from(&sample)).unwrap();
+    assert_eq!(state["measurements"]["co"], 9.0);
+    assert!(state["measurements"]["pm25"].is_null());
+  }
+
+  #[test]
+  fn object_ids_are_distinct() {
+    assert_eq!(object_id("hat1"), "hat1");
+    assert_eq!(object_id("hat-1"), "hat_2d1");
+    assert_eq!(object_id("hat_1"), "hat_5f1");
+    assert_eq!(object_id("bếp"), "b_e1_ba_bfp");
+    let ids = ["hat-1", "hat_1", "hat 1", "hat_2d1", "hat-2d1"]
+      .map(object_id)
+      .into_iter()
+      .collect::<BTreeSet<_>>();
+    assert_eq!(ids.len(), 5);
+    assert!(ids
+      .iter()
+      .all(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')));
+  }
+}
@@ -0,0 +1,670 @@
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  io,
+  path::PathBuf,
+  process::ExitCode,
+};
+
+use app::{audit::AuditLog, auth::Identity};
+use axum::{
+  body::Body,
+  extract::{Query, State},
+  http::StatusCode,
+  Extension, Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Args;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio_stream::StreamExt;
+use tokio_util::io::StreamReader;
+use tracing::warn;
+use types::{
+  accounts::Role,
+  audit::AuditAction,
+  export::{split_column_header, ExportFormat},
+  import::{validate_sample, ImportOptions, ImportReport},
+  measurement::{Measurement, MetricCatalog},
+  schema::{Field, Quality},
+  settings::validate_device_id,
+  units::{GasUnit, TemperatureUnit, Units},
+  HatSample, DEFAULT_DEVICE_ID,
+};
+
+use crate::{
+  config::Config,
+  storage::{day_of, SampleStore},
+};
+
+/// Accepted samples held before they are written.
+const BATCH_SIZE: usize = 10_000;
+
+/// Columns a CSV import must have, in any order. `device_id` is optional and
+/// other columns, such as derived metrics of an export, are ignored. Headers
+/// may carry the unit of the column as exported, e.g. `temperature (°F)`.
+const CSV_REQUIRED: [&str; 8] = [
+  "timestamp",
+  "temperature",
+  "humidity",
+  "r_zero",
+  "corrected_r_zero",
+  "resistance",
+  "ppm",
+  "corrected_ppm",
+];
+
+#[derive(Debug, Error)]
+pub(crate) enum ImportError {
+  #[error("import io: {0}")]
+  Io(#[from] io::Error),
+  #[error("{0} files can't be imported, use csv or ndjson")]
+  UnsupportedFormat(ExportFormat),
+  #[error("invalid device id: {0}")]
+  InvalidDevice(&'static str),
+  #[error("csv header misses the {0} column")]
+  MissingColumn(&'static str),
+  #[error("csv column {0} is in an unknown unit")]
+  UnknownUnit(String),
+}
+
+/// Reads CSV or NDJSON samples from `reader` into `store`, skipping samples
+/// the device already has at that timestamp. Readings outside the range of
+/// their `catalog` metric are kept as invalid, as on ingest.
+pub(crate) async fn import<R: AsyncBufRead + Unpin>(
+  store: &SampleStore,
+  catalog: &MetricCatalog,
+  reader: R,
+  options: &ImportOptions,
+) -> Result<ImportReport, ImportError> {
+  if options.format.is_columnar() {
+    return Err(ImportError::UnsupportedFormat(options.format));
+  }
+  let device = match &options.device {
+    Some(device) => {
+      validate_device_id(device).map_err(ImportError::InvalidDevice)?;
+      device.as_str()
+    }
+    None => DEFAULT_DEVICE_ID,
+  };
+  let mut importer = Importer {
+    store,
+    catalog,
+    dry_run: options.dry_run,
+    now: Utc::now().timestamp() as u64,
+    report: ImportReport {
+      dry_run: options.dry_run,
+      ..Default::default()
+    },
+    known: HashMap::new(),
+    pending: Vec::new(),
+  };
+  let mut header = None;
+  let mut lines = reader.lines();
+  let mut number = 0;
+  while let Some(line) = lines.next_line().await? {
+    number += 1;
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    let parsed = match options.format {
+      ExportFormat::Csv => match &header {
+        None => {
+          header = Some(CsvHeader::parse(line)?);
+          continue;
+        }
+        Some(header) => header.sample(line),
+      },
+      _ => ndjson_sample(line),
+    };
+    importer.report.lines += 1;
+    match parsed {
+      Ok(mut sample) => {
+        if sample.device_id.is_empty() {
+          sample.device_id = device.to_string();
+        }
+        importer.add(number, sample).await?;
+      }
+      Err(message) => importer.report.reject(number, message),
+    }
+  }
+  importer.flush().await?;
+  Ok(importer.report)
+}
+
+struct Importer<'a> {
+  store: &'a SampleStore,
+  catalog: &'a MetricCatalog,
+  dry_run: bool,
+  now: u64,
+  report: ImportReport,
+  /// Timestamps stored or accepted so far, per device and day.
+  known: HashMap<(String, NaiveDate), HashSet<u64>>,
+  pending: Vec<HatSample>,
+}
+
+impl Importer<'_> {
+  async fn add(&mut self, line: usize, mut sample: HatSample) -> io::Result<()> {
+    if let Err(message) = validate_sample(&sample, self.now) {
+      self.report.reject(line, message);
+      return Ok(());
+    }
+    self.catalog.assess(&mut sample);
+    let key = (sample.device_id.clone(), day_of(sample.timestamp));
+    let known = match self.known.get_mut(&key) {
+      Some(known) => known,
+      None => {
+        let stored = self.store.timestamps(&key.0, key.1).await?;
+        self.known.entry(key).or_insert(stored)
+      }
+    };
+    if !known.insert(sample.timestamp) {
+      self.report.duplicates += 1;
+      return Ok(());
+    }
+    self.report.imported += 1;
+    self.report.devices.insert(sample.device_id.clone());
+    self.pending.push(sample);
+    if self.pending.len() >= BATCH_SIZE {
+      self.flush().await?;
+    }
+    Ok(())
+  }
+
+  async fn flush(&mut self) -> io::Result<()> {
+    let pending = std::mem::take(&mut self.pending);
+    if self.dry_run {
+      return Ok(());
+    }
+    let mut days = BTreeMap::<_, Vec<HatSample>>::new();
+    for sample in pending {
+      days
+        .entry((sample.device_id.clone(), day_of(sample.timestamp)))
+        .or_default()
+        .push(sample);
+    }
+    for ((device_id, day), samples) in days {
+      self.store.append_day(&device_id, day, &samples).await?;
+    }
+    Ok(())
+  }
+}
+
+/// A sample of an NDJSON import, with its readings converted from the
+/// `units` of the line, as exported, into °C and ppm.
+fn ndjson_sample(line: &str) -> Result<HatSample, String> {
+  #[derive(Deserialize)]
+  struct Line {
+    #[serde(default)]
+    units: Units,
+  }
+  let mut sample = serde_json::from_str::<HatSample>(line).map_err(|e| e.to_string())?;
+  let Line { units } = serde_json::from_str(line).map_err(|e| e.to_string())?;
+  units.restore_sample(&mut sample);
+  Ok(sample)
+}
+
+/// Positions of the columns in a CSV import, and the units of the readings.
+struct CsvHeader {
+  columns: HashMap<String, usize>,
+  len: usize,
+  units: Units,
+}
+
+impl CsvHeader {
+  fn parse(line: &str) -> Result<Self, ImportError> {
+    let names = split_csv(line);
+    let mut columns = HashMap::new();
+    let mut units = Units::default();
+    for (i, header) in names.iter().enumerate() {
+      let (name, unit) = split_column_header(header);
+      let field = Field::ALL.into_iter().find(|field| field.name() == name);
+      if let (Some(field), Some(unit)) = (field, unit) {
+        if !read_unit(&mut units, field, unit) {
+          return Err(ImportError::UnknownUnit(header.clone()));
+        }
+      }
+      columns.insert(name.to_string(), i);
+    }
+    if let Some(missing) = CSV_REQUIRED
+      .into_iter()
+      .find(|name| !columns.contains_key(*name))
+    {
+      return Err(ImportError::MissingColumn(missing));
+    }
+    Ok(Self {
+      columns,
+      len: names.len(),
+      units,
+    })
+  }
+
+  fn sample(&self, line: &str) -> Result<HatSample, String> {
+    let fields = split_csv(line);
+    if fields.len() != self.len {
+      return Err(format!(
+        "expected {} fields, found {}",
+        self.len,
+        fields.len()
+      ));
+    }
+    let field = |name: &str| self.columns.get(name).map(|&i| fields[i].as_str());
+    // Empty fields are missing readings, as exported.
+    let reading = |name: &str| -> Result<Option<f32>, String> {
+      match field(name).unwrap_or_default() {
+        "" => Ok(None),
+        value => value
+          .parse()
+          .map(Some)
+          .map_err(|_| format!("{name} {value:?} is not a number")),
+      }
+    };
+    let mut sample = HatSample {
+      device_id: field("device_id").unwrap_or_default().to_string(),
+      timestamp: parse_timestamp(field("timestamp").unwrap_or_default())?,
+      temperature: reading("temperature")?,
+      humidity: reading("humidity")?,
+      r_zero: reading("r_zero")?,
+      corrected_r_zero: reading("corrected_r_zero")?,
+      resistance: reading("resistance")?,
+      ppm: reading("ppm")?,
+      corrected_ppm: reading("corrected_ppm")?,
+      measurements: measurements(field("measurements").unwrap_or_default())?,
+      ..HatSample::default()
+    };
+    self.units.restore_sample(&mut sample);
+    Ok(sample)
+  }
+}
+
+/// Takes the unit `symbol` of the `field` column into `units`, false if
+/// `field` is never in that unit.
+fn read_unit(units: &mut Units, field: Field, symbol: &str) -> bool {
+  match field {
+    Field::Temperature => match TemperatureUnit::ALL
+      .into_iter()
+      .find(|unit| unit.symbol() == symbol)
+    {
+      Some(unit) => units.temperature = unit,
+      None => return false,
+    },
+    Field::Ppm | Field::CorrectedPpm => {
+      match GasUnit::ALL
+        .into_iter()
+        .find(|unit| unit.symbol() == symbol)
+      {
+        Some(unit) => units.gas = unit,
+        None => return false,
+      }
+    }
+    field => return field.unit() == symbol,
+  }
+  true
+}
+
+/// The `measurements` field of a CSV line, a JSON array as exported. Empty
+/// when the hat reported no other readings.
+fn measurements(value: &str) -> Result<Vec<Measurement>, String> {
+  if value.is_empty() {
+    return Ok(Vec::new());
+  }
+  let mut measurements =
+    serde_json::from_str::<Vec<Measurement>>(value).map_err(|e| format!("measurements {e}"))?;
+  for measurement in &mut measurements {
+    if measurement.value.is_none() && measurement.quality == Quality::Good {
+      measurement.quality = Quality::Missing;
+    }
+    if measurement.quality != Quality::Good {
+      measurement.value = None;
+    }
+  }
+  Ok(measurements)
+}
+
+/// Fields of a CSV line. Quoted fields may hold commas, and `""` for a
+/// quote.
+fn split_csv(line: &str) -> Vec<String> {
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = line.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' if quoted && chars.peek() == Some(&'"') => {
+        field.push('"');
+        chars.next();
+      }
+      '"' => quoted = !quoted,
+      ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+      c => field.push(c),
+    }
+  }
+  fields.push(field.trim().to_string());
+  fields
+}
+
+/// Unix seconds, or an RFC 3339 time as logged over serial.
+pub(crate) fn parse_timestamp(value: &str) -> Result<u64, String> {
+  value
+    .parse()
+    .ok()
+    .or_else(|| {
+      DateTime::parse_from_rfc3339(value)
+        .ok()
+        .and_then(|time| u64::try_from(time.timestamp()).ok())
+    })
+    .ok_or_else(|| format!("timestamp {value:?} is neither Unix seconds nor RFC 3339"))
+}
+
+fn describe(report: &ImportReport) -> String {
+  format!(
+    "imported {} samples of {} ({} duplicates, {} rejected)",
+    report.imported,
+    report
+      .devices
+      .iter()
+      .cloned()
+      .collect::<Vec<_>>()
+      .join(", "),
+    report.duplicates,
+    report.rejected
+  )
+}
+
+/// `POST /api/import?format=csv|ndjson&device=&dry_run=`: stores the samples
+/// of the uploaded file and answers with an [`ImportReport`]. Admins only.
+/// The body is read as it arrives, so large files don't need to fit in
+/// memory.
+pub(crate) async fn upload(
+  State((store, catalog, audit)): State<(SampleStore, MetricCatalog, AuditLog)>,
+  Extension(identity): Extension<Identity>,
+  Query(options): Query<ImportOptions>,
+  body: Body,
+) -> Result<Json<ImportReport>, (StatusCode, String)> {
+  if !identity.has_role(Role::Admin) {
+    return Err((StatusCode::FORBIDDEN, "not allowed".to_string()));
+  }
+  let reader = StreamReader::new(
+    body
+      .into_data_stream()
+      .map(|chunk| chunk.map_err(io::Error::other)),
+  );
+  match import(&store, &catalog, reader, &options).await {
+    Ok(report) => {
+      if !report.dry_run && report.imported > 0 {
+        audit
+          .record(
+            &identity.username,
+            AuditAction::SamplesImported,
+            None,
+            describe(&report),
+          )
+          .await;
+      }
+      Ok(Json(report))
+    }
+    Err(ImportError::Io(e)) => {
+      warn!(target = "import", case = "upload", "{:?}", e);
+      Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+    }
+    Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+  }
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct ImportArgs {
+  file: PathBuf,
+  /// `csv` or `ndjson`, by default from the file extension.
+  #[arg(long)]
+  format: Option<ExportFormat>,
+  /// Device of rows that don't name one.
+  #[arg(long)]
+  device: Option<String>,
+  /// Validates and counts without storing anything.
+  #[arg(long)]
+  dry_run: bool,
+}
+
+/// `server import <file>`: imports a file into the data directory and prints
+/// the report as JSON.
+pub(crate) async fn cli(config: &Config, args: ImportArgs) -> ExitCode {
+  let options = ImportOptions {
+    format: args
+      .format
+      .or_else(|| {
+        args
+          .file
+          .extension()
+          .and_then(|extension| extension.to_str()?.parse().ok())
+      })
+      .unwrap_or_default(),
+    device: args.device,
+    dry_run: args.dry_run,
+  };
+  let path = args.file.display();
+  let file = match tokio::fs::File::open(&args.file).await {
+    Ok(file) => file,
+    Err(e) => {
+      eprintln!("{path}: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  let catalog = match config.metrics() {
+    Ok(catalog) => catalog,
+    Err(e) => {
+      eprintln!("metric catalog: {e}");
+      return ExitCode::FAILURE;
+    }
+  };
+  match import(&config.samples(), &catalog, BufReader::new(file), &options).await {
+    Ok(report) => {
+      if !report.dry_run && report.imported > 0 {
+        config
+          .audit_log()
+          .record("cli", AuditAction::SamplesImported, None, describe(&report))
+          .await;
+      }
+      println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("report should be serialized")
+      );
+      ExitCode::SUCCESS
+    }
+    Err(e) => {
+      eprintln!("{path}: {e}");
+      ExitCode::FAILURE
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  use super::*;
+  use crate::storage::read_day_file;
+
+  const HEADER: &str =
+    "timestamp,temperature,humidity,r_zero,corrected_r_zero,resistance,ppm,corrected_ppm";
+
+  /// Store in a new directory of its own.
+  fn store() -> (SampleStore, PathBuf) {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let root = std::env::temp_dir().join(format!(
+      "hat-monitor-import-{}-{}",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    (SampleStore::new(&root), root)
+  }
+
+  fn options(format: ExportFormat, dry_run: bool) -> ImportOptions {
+    ImportOptions {
+      format,
+      device: Some("hat-1".to_string()),
+      dry_run,
+    }
+  }
+
+  async fn stored(store: &SampleStore, device_id: &str) -> Vec<HatSample> {
+    let mut samples = Vec::new();
+    for (_, path) in store.day_files(device_id).await.unwrap() {
+      samples.extend(read_day_file(&path, 0, u64::MAX).await.unwrap());
+    }
+    samples
+  }
+
+  #[test]
+  fn quoted_csv_fields_keep_their_commas() {
+    assert_eq!(
+      split_csv(r#"a, "b,c" ,"say ""hi""","#),
+      ["a", "b,c", r#"say "hi""#, ""]
+    );
+  }
+
+  #[tokio::test]
+  async fn csv_columns_are_mapped_by_header() {
+    let (store, root) = store();
+    let csv = "note,corrected_ppm,ppm,resistance,corrected_r_zero,r_zero,humidity,temperature,timestamp,device_id\n\
+      \"warm, humid\",418.5,412,12.75,40.8,41.2,52.25,,1760000000,\"hat-2\"\n\
+      plain,,,,,,,21.5,1760000060,\n";
+    let report = import(
+      &store,
+      &MetricCatalog::builtin(),
+      csv.as_bytes(),
+      &options(ExportFormat::Csv, false),
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.lines, report.imported, report.rejected), (2, 2, 0));
+    let sample = &stored(&store, "hat-2").await[0];
+    assert_eq!(sample.timestamp, 1_760_000_000);
+    assert_eq!(sample.temperature, None);
+    assert_eq!(sample.humidity, Some(52.25));
+    assert_eq!(sample.corrected_ppm, Some(418.5));
+    // Rows without a device id go to the device of the options.
+    assert_eq!(stored(&store, "hat-1").await[0].temperature, Some(21.5));
+    assert!(matches!(
+      import(
+        &store,
+        &MetricCatalog::builtin(),
+        "timestamp,temperature\n".as_bytes(),
+        &options(ExportFormat::Csv, false)
+      )
+      .await,
+      Err(ImportError::MissingColumn("humidity"))
+    ));
+    let _ = std::fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn measurements_are_imported_and_ranges_checked() {
+    let (store, root) = store();
+    let csv = format!(
+      "{HEADER},measurements\n\
+      1760000000,200,50,,,,,,\"[{{\"\"metric\"\":\"\"pm25\"\",\"\"unit\"\":\"\"µg/m³\"\",\"\"value\"\":12.5}}]\"\n\
+      1760000060,20,50,,,,,,\n\
+      1760000120,20,50,,,,,,[1]\n"
+    );
+    let report = import(
+      &store,
+      &MetricCatalog::builtin(),
+      csv.as_bytes(),
+      &options(ExportFormat::Csv, false),
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.imported, report.rejected), (2, 1));
+    let samples = stored(&store, "hat-1").await;
+    assert_eq!(samples[0].measurement("pm25").unwrap().value, Some(12.5));
+    assert!(samples[1].measurements.is_empty());
+    // 200 °C is outside the catalog's range of the temperature.
+    assert_eq!(samples[0].temperature, None);
+    assert_eq!(
+      samples[0].measurement("temperature").unwrap().quality,
+      Quality::Invalid
+    );
+    let _ = std::fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn samples_already_stored_or_imported_are_skipped() {
+    let (store, root) = store();
+    store
+      .append(&HatSample {
+        device_id: "hat-1".to_string(),
+        timestamp: 1_760_000_000,
+        ..HatSample::default()
+      })
+      .await
+      .unwrap();
+    let ndjson = r#"{"timestamp":1760000000,"temperature":20.0}
+{"timestamp":1760000060,"temperature":21.0}
+{"timestamp":1760000060,"temperature":22.0}
+"#;
+    let report = import(
+      &store,
+      &MetricCatalog::builtin(),
+      ndjson.as_bytes(),
+      &options(ExportFormat::Ndjson, false),
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.imported, report.duplicates), (1, 2));
+    let samples = stored(&store, "hat-1").await;
+    assert_eq!(
+      samples
+        .iter()
+        .map(|sample| sample.temperature)
+        .collect::<Vec<_>>(),
+      [None, Some(21.0)]
+    );
+    let _ = std::fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn missing_and_future_timestamps_are_rejected() {
+    let (store, root) = store();
+    let future = Utc::now().timestamp() as u64 + 2 * 24 * 60 * 60;
+    let csv = format!(
+      "{HEADER}\n0,20,50,,,,,\n{future},20,50,,,,,\nnoon,20,50,,,,,\n1760000000,20,50,,,,,\n"
+    );
+    let report = import(
+      &store,
+      &MetricCatalog::builtin(),
+      csv.as_bytes(),
+      &options(ExportFormat::Csv, false),
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.imported, report.rejected), (1, 3));
+    assert_eq!(
+      report
+        .issues
+        .iter()
+        .map(|issue| issue.line)
+        .collect::<Vec<_>>(),
+      [2, 3, 4]
+    );
+    let _ = std::fs::remove_dir_all(root);
+  }
+
+  #[tokio::test]
+  async fn dry_runs_leave_the_store_untouched() {
+    let (store, root) = store();
+    let csv = format!("{HEADER}\n1760000000,20,50,,,,,\n1760000060,21,50,,,,,\n");
+    let report = import(
+      &store,
+      &MetricCatalog::builtin(),
+      csv.as_bytes(),
+      &options(ExportFormat::Csv, true),
+    )
+    .await
+    .unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.imported, 2);
+    assert!(stored(&store, "hat-1").await.is_empty());
+    assert!(!root.exists());
+  }
+}
@@ -1,29 +1,71 @@
+mod alerts;
+mod audit;
+mod cli;
+mod columnar;
+mod commands;
+mod config;
+mod export;
+mod firmware;
+mod health;
+mod homeassistant;
+mod import;
+mod metrics;
 mod mqttc_worker;
+mod notifier;
+mod pipeline;
+mod provisioning;
+mod replay;
+mod republish;
+mod simulate;
+mod storage;
+mod supervisor;
 
-use app::*;
+use app::{auth::Identity, store::SettingsStore, *};
 use axum::{
   extract::{
-    ws::{Message, WebSocket},
-    State, WebSocketUpgrade,
+    ws::{close_code, CloseFrame, Message, WebSocket},
+    DefaultBodyLimit, State, WebSocketUpgrade,
   },
   response::IntoResponse,
-  routing::any,
-  Router,
+  routing::{any, get, post},
+  Extension, Router,
 };
+use clap::Parser;
+use std::{collections::BTreeMap, path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
+
 use leptos::logging::log;
 use leptos::prelude::*;
 use leptos_axum::{generate_route_list, LeptosRoutes};
-use tokio::{join, select, sync::watch};
+use tokio::{
+  select,
+  sync::{broadcast, mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use types::HatSample;
+use types::{
+  encoding::Encoding,
+  firmware::MAX_IMAGE_BYTES,
+  mqtt::{ConnectionState, MqttStatus},
+  HatSample,
+};
+
+use crate::{
+  cli::{Cli, Command, DevicesCommand, IntegrationArgs, ServeArgs},
+  config::Config,
+  pipeline::Pipeline,
+  replay::ReplayArgs,
+  supervisor::Supervisor,
+};
 
-const MQTT_TOPIC: &'static str = "iot/hat";
+const ALERT_QUEUE: usize = 256;
+/// How long workers get to finish after the HTTP server stopped.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
   tracing_subscriber::registry()
     .with(
       tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -36,16 +78,246 @@ async fn main() {
         .into()
       }),
     )
-    .with(tracing_subscriber::fmt::layer())
+    // stdout is left to the output of subcommands.
+    .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
     .init();
 
+  let cli = Cli::parse();
+  let config = cli.config;
+  let (source, integrations) = match cli.command {
+    None => {
+      let args = ServeArgs::from_env();
+      (Source::Broker(args.record), args.integrations)
+    }
+    Some(Command::Serve(args)) => (Source::Broker(args.record), args.integrations),
+    Some(Command::Replay {
+      replay,
+      integrations,
+    }) => (Source::Replay(replay), integrations),
+    Some(Command::Simulate(args)) => return simulate::run(&config, args).await,
+    Some(Command::Import(args)) => return import::cli(&config, args).await,
+    Some(Command::Export(args)) => return export::cli(&config, args).await,
+    Some(Command::Calibrate(args)) => return cli::calibrate(&config, args).await,
+    Some(Command::CheckConfig) => return cli::check_config(&config),
+    Some(Command::Migrate(args)) => return cli::migrate(&config, args).await,
+    Some(Command::Devices(DevicesCommand::List)) => return cli::list_devices(&config).await,
+    Some(Command::Devices(DevicesCommand::Claim { device_id })) => {
+      return cli::create_claim(&config, &device_id)
+    }
+  };
+  serve(config, integrations, source).await
+}
+
+/// Where samples come from while serving.
+enum Source {
+  /// The broker, with the file to record its traffic to, if any.
+  Broker(Option<PathBuf>),
+  Replay(ReplayArgs),
+}
+
+async fn serve(config: Config, args: IntegrationArgs, source: Source) -> ExitCode {
+  let recorder = match &source {
+    Source::Broker(Some(path)) => Some(
+      replay::Recorder::open(path)
+        .await
+        .expect("recording should be writable"),
+    ),
+    _ => None,
+  };
+
   let conf = get_configuration(None).unwrap();
   let addr = conf.leptos_options.site_addr;
   let leptos_options = conf.leptos_options;
   // Generate the list of routes in your Leptos App
   let routes = generate_route_list(App);
 
-  let (tx, rx) = watch::channel(HatSample::default());
+  let settings = config.settings().expect("settings should be readable");
+  let samples = config.samples();
+  let auth = config.auth().expect("accounts should be readable");
+  let audit_log = config.audit_log();
+  let firmware_store = config.firmware().expect("firmware should be readable");
+  let registry = config.devices().expect("devices should be readable");
+  let catalog = config.metrics().expect("metric catalog should be readable");
+  let active_alerts = alerts::ActiveAlerts::default();
+
+  let shutdown = CancellationToken::new();
+  tokio::spawn(supervisor::watch_signals(shutdown.clone()));
+  let supervisor = Supervisor::new(shutdown.clone());
+
+  let Pipeline {
+    sink: tx,
+    latest: rx,
+    live,
+    storage: storage_queue,
+    alerts: alerts_queue,
+    dropped,
+  } = Pipeline::new();
+  let (alert_tx, alert_rx) = mpsc::channel(ALERT_QUEUE);
+  let alert_rx = Arc::new(tokio::sync::Mutex::new(alert_rx));
+  let (alert_events, _) = broadcast::channel(ALERT_QUEUE);
+  supervisor.spawn("alerts", {
+    let (alerts_queue, settings, catalog, active_alerts, alert_tx, alert_events) = (
+      alerts_queue.clone(),
+      settings.clone(),
+      catalog.clone(),
+      active_alerts.clone(),
+      alert_tx.clone(),
+      alert_events.clone(),
+    );
+    move |shutdown| {
+      let run = alerts::run(
+        alerts_queue.clone(),
+        settings.clone(),
+        catalog.clone(),
+        active_alerts.clone(),
+        alert_tx.clone(),
+        alert_events.clone(),
+      );
+      async move {
+        shutdown.run_until_cancelled(run).await;
+      }
+    }
+  });
+  supervisor.spawn("notifier", {
+    let settings = settings.clone();
+    move |shutdown| {
+      let (alert_rx, settings) = (alert_rx.clone(), settings.clone());
+      async move {
+        let mut alert_rx = alert_rx.lock().await;
+        shutdown
+          .run_until_cancelled(notifier::run(&mut alert_rx, settings))
+          .await;
+      }
+    }
+  });
+  supervisor.spawn("storage", {
+    let samples = samples.clone();
+    move |shutdown| storage::run(storage_queue.clone(), samples.clone(), shutdown)
+  });
+  supervisor.spawn("retention", {
+    let (samples, settings) = (samples.clone(), settings.clone());
+    move |shutdown| {
+      let run = storage::run_retention(samples.clone(), settings.clone());
+      async move {
+        shutdown.run_until_cancelled(run).await;
+      }
+    }
+  });
+  let last_samples = health::LastSamples::default();
+  supervisor.spawn("health", {
+    let (last_samples, live, samples) = (last_samples.clone(), live.clone(), samples.clone());
+    move |shutdown| {
+      let track = last_samples
+        .clone()
+        .track(live.subscribe(), samples.clone());
+      async move {
+        shutdown.run_until_cancelled(track).await;
+      }
+    }
+  });
+  let (mqtt_status, mqtt_status_rx) = watch::channel(MqttStatus::default());
+  let (command_hub, outbox) = app::commands::CommandHub::new(mqtt_status_rx.clone());
+  match source {
+    Source::Broker(_) => supervisor.spawn("mqtt", {
+      let (config, catalog, tx) = (config.clone(), catalog.clone(), tx.clone());
+      let link = mqttc_worker::DeviceLink {
+        commands: command_hub.clone(),
+        outbox: Arc::new(tokio::sync::Mutex::new(outbox)),
+        firmware: firmware_store.clone(),
+        registry: registry.clone(),
+      };
+      move |shutdown| {
+        let (config, catalog, tx, recorder, mqtt_status, link) = (
+          config.clone(),
+          catalog.clone(),
+          tx.clone(),
+          recorder.clone(),
+          mqtt_status.clone(),
+          link.clone(),
+        );
+        async move {
+          mqttc_worker::run(&config, &catalog, tx, recorder, mqtt_status, link, shutdown).await
+        }
+      }
+    }),
+    Source::Replay(args) => {
+      mqtt_status.send_modify(|status| status.state = ConnectionState::Replaying);
+      supervisor.spawn("replay", {
+        let (topic, catalog, tx) = (config.mqtt_topic.clone(), catalog.clone(), tx.clone());
+        move |shutdown| {
+          let run = replay::run(args.clone(), topic.clone(), catalog.clone(), tx.clone());
+          async move {
+            shutdown.run_until_cancelled(run).await;
+          }
+        }
+      })
+    }
+  }
+  let mut integrations = BTreeMap::new();
+  if args.home_assistant.enabled {
+    let (status, status_rx) = watch::channel(MqttStatus::default());
+    integrations.insert("homeassistant", status_rx);
+    supervisor.spawn("homeassistant", {
+      let inventory = homeassistant::Inventory {
+        catalog: catalog.clone(),
+        settings: settings.clone(),
+        store: samples.clone(),
+      };
+      let (config, home_assistant, live) =
+        (config.clone(), args.home_assistant.clone(), live.clone());
+      move |shutdown| {
+        let (config, home_assistant, inventory, live, status) = (
+          config.clone(),
+          home_assistant.clone(),
+          inventory.clone(),
+          live.subscribe(),
+          status.clone(),
+        );
+        async move {
+          homeassistant::run(&config, &home_assistant, inventory, live, status, shutdown).await
+        }
+      }
+    });
+  }
+  if args.republish.enabled {
+    let (status, status_rx) = watch::channel(MqttStatus::default());
+    integrations.insert("republish", status_rx);
+    supervisor.spawn("republish", {
+      let (config, republish, catalog, live) = (
+        config.clone(),
+        args.republish.clone(),
+        catalog.clone(),
+        live.clone(),
+      );
+      move |shutdown| {
+        let (config, republish, catalog, live, alerts, status) = (
+          config.clone(),
+          republish.clone(),
+          catalog.clone(),
+          live.subscribe(),
+          alert_events.subscribe(),
+          status.clone(),
+        );
+        async move {
+          republish::run(
+            &config, &republish, &catalog, live, alerts, status, shutdown,
+          )
+          .await
+        }
+      }
+    });
+  }
+  let health = health::Health {
+    mqtt: mqtt_status_rx.clone(),
+    integrations,
+    store: samples.clone(),
+    storage_probe: health::StorageProbe::default(),
+    settings: settings.clone(),
+    last_samples,
+    alerts: alert_tx.clone(),
+    supervisor: supervisor.clone(),
+    dropped,
+  };
   for i in 0..2 {
     let mut rx = rx.clone();
     tokio::spawn(async move {
@@ -61,39 +333,147 @@ async fn main() {
   }
 
   let app = Router::new()
-    .leptos_routes(&leptos_options, routes, {
-      let leptos_options = leptos_options.clone();
-      move || shell(leptos_options.clone())
-    })
+    .leptos_routes_with_context(
+      &leptos_options,
+      routes,
+      {
+        let auth = auth.clone();
+        let settings = settings.clone();
+        let audit_log = audit_log.clone();
+        let command_hub = command_hub.clone();
+        let firmware_store = firmware_store.clone();
+        let registry = registry.clone();
+        let catalog = catalog.clone();
+        move || {
+          provide_context(settings.clone());
+          provide_context(auth.clone());
+          provide_context(audit_log.clone());
+          provide_context(command_hub.clone());
+          provide_context(firmware_store.clone());
+          provide_context(registry.clone());
+          provide_context(catalog.clone());
+        }
+      },
+      {
+        let leptos_options = leptos_options.clone();
+        move || shell(leptos_options.clone())
+      },
+    )
     .fallback(leptos_axum::file_and_error_handler(shell))
     .with_state(leptos_options)
     .route("/ws", any(ws_handler))
-    .with_state(rx)
+    .with_state((rx, settings.clone(), shutdown.clone()))
+    .route("/ws/mqtt", any(mqtt_ws_handler))
+    .with_state((mqtt_status_rx, shutdown.clone()))
+    .route("/api/samples", get(storage::history))
+    .route("/api/export", get(export::export))
+    .with_state((samples.clone(), settings.clone()))
+    .route("/api/import", post(import::upload))
+    .with_state((samples, catalog.clone(), audit_log.clone()))
+    .route("/api/alerts", get(alerts::list))
+    .route("/api/alerts/ack", post(alerts::acknowledge))
+    .with_state((active_alerts, settings.clone(), audit_log.clone()))
+    .route(
+      "/api/devices/{device}/commands",
+      get(commands::list).post(commands::send),
+    )
+    .with_state((command_hub.clone(), settings.clone(), audit_log.clone()))
+    .route("/api/firmware", get(firmware::list))
+    .route(
+      "/api/firmware/{version}",
+      post(firmware::upload)
+        .delete(firmware::remove)
+        .layer(DefaultBodyLimit::max(MAX_IMAGE_BYTES as usize)),
+    )
+    .route("/api/rollouts", post(firmware::roll_out))
+    .route("/firmware/{file}", get(firmware::download))
+    .with_state((firmware_store, command_hub, settings, audit_log.clone()))
+    .route("/api/provision/claim", post(provisioning::claim))
+    .route("/api/mqtt/user", post(provisioning::user))
+    .route("/api/mqtt/superuser", post(provisioning::superuser))
+    .route("/api/mqtt/acl", post(provisioning::acl))
+    .with_state((
+      registry,
+      config.clone(),
+      audit_log.clone(),
+      provisioning::ClaimLimiter::default(),
+    ))
+    .route("/api/audit", get(audit::export))
+    .with_state(audit_log)
+    .route("/api/metrics", get(metrics::catalog))
+    .with_state(catalog)
+    .route("/api/workers", get(supervisor::list))
+    .with_state(supervisor.clone())
+    .route("/healthz", get(health::liveness))
+    .route("/readyz", get(health::readiness))
+    .route("/api/readiness", get(health::details))
+    .with_state(health)
     // .route(path, method_router)
+    .layer(axum::middleware::from_fn_with_state(
+      auth,
+      app::auth::require_auth,
+    ))
     .layer(TraceLayer::new_for_http());
 
   // run our app with hyper
   // `axum::Server` is a re-export of `hyper::Server`
   log!("listening on http://{}", &addr);
   let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-  let ret = join!(
-    mqttc_worker::run(MQTT_TOPIC, tx),
-    axum::serve(listener, app.into_make_service())
-  );
-  ret.1.unwrap();
+  // Open WebSockets close themselves on shutdown, so this returns once the
+  // in-flight requests are answered.
+  axum::serve(
+    listener,
+    app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+  )
+  .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+  .await
+  .unwrap();
+  supervisor.join(SHUTDOWN_GRACE).await;
+  info!("stopped");
+  ExitCode::SUCCESS
 }
 
 async fn ws_handler(
   ws: WebSocketUpgrade,
-  State(rx): State<watch::Receiver<HatSample>>,
+  State((rx, settings, shutdown)): State<(
+    watch::Receiver<HatSample>,
+    SettingsStore,
+    CancellationToken,
+  )>,
+  Extension(identity): Extension<Identity>,
 ) -> impl IntoResponse {
-  ws.on_upgrade(|socket| handle_ws(socket, rx))
+  // Clients offering the CBOR subprotocol get binary frames, everyone else
+  // JSON text.
+  let ws = ws.protocols([Encoding::Cbor.subprotocol(), Encoding::Json.subprotocol()]);
+  let encoding = ws
+    .selected_protocol()
+    .and_then(|protocol| protocol.to_str().ok())
+    .and_then(Encoding::from_subprotocol)
+    .unwrap_or_default();
+  ws.on_upgrade(move |socket| handle_ws(socket, rx, settings, identity, encoding, shutdown))
 }
 
-async fn handle_ws(mut socket: WebSocket, mut rx: watch::Receiver<HatSample>) {
-  // serde_json::to_string(value)
+/// Forwards the samples of devices `identity` may see, until the client or
+/// the server goes away.
+async fn handle_ws(
+  mut socket: WebSocket,
+  mut rx: watch::Receiver<HatSample>,
+  settings: SettingsStore,
+  identity: Identity,
+  encoding: Encoding,
+  shutdown: CancellationToken,
+) {
   loop {
     select! {
+      _ = shutdown.cancelled() => {
+        let _ = socket
+          .send(Message::Close(Some(CloseFrame {
+            code: close_code::AWAY,
+            reason: "server shutting down".into(),
+          })))
+          .await;
+        break;
+      },
       Some(msg) = socket.recv() => {
         let Ok(_) = msg else {
           break;
@@ -104,14 +484,24 @@ async fn handle_ws(mut socket: WebSocket, mut rx: watch::Receiver<HatSample>) {
           break;
         }
         let sample = rx.borrow().clone();
-        let json = match serde_json::to_string(&sample) {
-          Ok(json) => json,
+        if !settings.with(|settings| identity.can_see(&sample.device_id, settings)) {
+          continue;
+        }
+        let payload = match encoding.encode(&sample) {
+          Ok(payload) => payload,
           Err(e) => {
-            warn!(target = "handle_ws", case = "serde json err", "{:?}", e);
+            warn!(target = "handle_ws", case = "encode err", "{:?}", e);
             continue;
           }
         };
-        if socket.send(Message::Text(json.into())).await.is_err() {
+        let message = match encoding {
+          Encoding::Json => match String::from_utf8(payload) {
+            Ok(json) => Message::Text(json.into()),
+            Err(_) => continue,
+          },
+          Encoding::Cbor => Message::Binary(payload.into()),
+        };
+        if socket.send(message).await.is_err() {
           break;
         }
       }
@@ -119,3 +509,49 @@ async fn handle_ws(mut socket: WebSocket, mut rx: watch::Receiver<HatSample>) {
     }
   }
 }
+
+async fn mqtt_ws_handler(
+  ws: WebSocketUpgrade,
+  State((status, shutdown)): State<(watch::Receiver<MqttStatus>, CancellationToken)>,
+) -> impl IntoResponse {
+  ws.on_upgrade(|socket| handle_mqtt_ws(socket, status, shutdown))
+}
+
+/// Pushes the MQTT connection status, first the current one and then every
+/// change.
+async fn handle_mqtt_ws(
+  mut socket: WebSocket,
+  mut status: watch::Receiver<MqttStatus>,
+  shutdown: CancellationToken,
+) {
+  status.mark_changed();
+  loop {
+    select! {
+      _ = shutdown.cancelled() => {
+        let _ = socket
+          .send(Message::Close(Some(CloseFrame {
+            code: close_code::AWAY,
+            reason: "server shutting down".into(),
+          })))
+          .await;
+        break;
+      },
+      changed = status.changed() => {
+        if changed.is_err() {
+          break;
+        }
+        let json = serde_json::to_string(&*status.borrow_and_update())
+          .expect("status should be serialized");
+        if socket.send(Message::Text(json.into())).await.is_err() {
+          break;
+        }
+      },
+      msg = socket.recv() => {
+        match msg {
+          Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+          _ => {}
+        }
+      },
+    }
+  }
+}
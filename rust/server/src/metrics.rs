@@ -0,0 +1,10 @@
+//! `/api/metrics`, the metric catalog for clients that render samples.
+
+use axum::{extract::State, Json};
+use types::measurement::MetricCatalog;
+
+/// `GET /api/metrics`: every metric a sample can carry, with its unit and
+/// valid range.
+pub(crate) async fn catalog(State(catalog): State<MetricCatalog>) -> Json<MetricCatalog> {
+  Json(catalog)
+}
@@ -1,63 +1,553 @@
-use std::time::Duration;
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
 
+use app::{
+  commands::{CommandHub, Outbound},
+  firmware::FirmwareStore,
+  provisioning::DeviceRegistry,
+};
 use chrono::Utc;
-use rand::{Rng, SeedableRng};
-use rumqttc::v5::{mqttbytes::QoS, AsyncClient, Event, Incoming, MqttOptions};
+use rumqttc::{
+  v5::{
+    mqttbytes::{
+      v5::{ConnectReturnCode, Filter, SubscribeReasonCode},
+      QoS,
+    },
+    AsyncClient, ConnectionError, Event, EventLoop, Incoming, StateError,
+  },
+  Outgoing,
+};
 use tokio::{
-  sync::watch::{self},
-  task, time,
+  sync::{
+    mpsc,
+    watch::{self},
+    Mutex,
+  },
+  time,
 };
-use tracing::{debug, warn};
-use types::HatSample;
-
-pub(crate) async fn run(topic: &str, tx: watch::Sender<HatSample>) {
-  let mut mqtt_options = MqttOptions::new("hat-monitor", "localhost", 1883);
-  mqtt_options.set_keep_alive(Duration::from_secs(5));
-  let (client, mut event_loop) = AsyncClient::new(mqtt_options, 1000);
-  client.subscribe(topic, QoS::AtLeastOnce).await.unwrap();
-  let publish_client = client.clone();
-  let publish_topic = topic.to_string();
-  task::spawn(task::coop::cooperative(async move {
-    let mut rng = rand::rngs::StdRng::from_rng(&mut rand::rng());
-    let mut interval = time::interval(Duration::from_secs(5));
-    loop {
-      interval.tick().await;
-      let sample = HatSample {
-        timestamp: Utc::now().timestamp() as u64,
-        temperature: rng.random_range(27.0..=33.0),
-        humidity: rng.random_range(50.0..=55.5),
-        r_zero: rng.random_range(0.0..=50.0),
-        corrected_r_zero: rng.random_range(0.0..=50.0),
-        resistance: rng.random_range(0.0..=100.0),
-        ppm: rng.random_range(200.0..=300.0),
-        corrected_ppm: rng.random_range(200.0..=300.0),
-      };
-      let payload = serde_json::to_string(&sample).expect("should be serialized");
-      debug!(target = "publish", "{:#?}", payload);
-      publish_client
-        .publish(&publish_topic, QoS::AtLeastOnce, false, payload)
-        .await
-        .expect("should be published");
-    }
-  }));
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use types::{
+  commands::CommandAck,
+  encoding::Encoding,
+  firmware::FirmwareReport,
+  measurement::MetricCatalog,
+  mqtt::{self, ConnectionState, ErrorCategory, MqttStatus},
+  HatSample, DEFAULT_DEVICE_ID,
+};
+
+use crate::{config::Config, pipeline::SampleSink, replay::Recorder};
 
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the worker exchanges with the hats besides samples.
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceLink {
+  pub commands: CommandHub,
+  pub outbox: Arc<Mutex<mpsc::UnboundedReceiver<Outbound>>>,
+  pub firmware: FirmwareStore,
+  pub registry: DeviceRegistry,
+}
+
+/// Receives the hats' samples, command acknowledgements and firmware
+/// versions until `shutdown`, reconnecting with exponential backoff and
+/// subscribing again after every reconnect, since the broker forgets a clean
+/// session's subscriptions. Queued commands are published to
+/// `<topic>/<device id>/cmd`.
+pub(crate) async fn run(
+  config: &Config,
+  catalog: &MetricCatalog,
+  sink: SampleSink,
+  recorder: Option<Recorder>,
+  status: watch::Sender<MqttStatus>,
+  link: DeviceLink,
+  shutdown: CancellationToken,
+) {
+  let topic = config.mqtt_topic.as_str();
+  let filters = [
+    topic.to_string(),
+    format!("{topic}/+"),
+    format!("{topic}/+/cmd/ack"),
+    format!("{topic}/+/firmware"),
+  ];
+  // Only one run at a time, a restarted worker picks up the queue.
+  let mut outbox = link.outbox.lock().await;
+  let (client, mut event_loop) = AsyncClient::new(config.mqtt_options("hat-monitor"), 1000);
+
+  let mut connection = Connection::new(config, status);
+  // Devices already warned about, so a rejected hat doesn't flood the log.
+  let mut rejected = BTreeSet::new();
   loop {
-    match event_loop.poll().await {
+    let event = tokio::select! {
+      event = event_loop.poll() => event,
+      Some(outbound) = outbox.recv() => {
+        publish_command(&client, topic, outbound);
+        continue;
+      }
+      _ = shutdown.cancelled() => break,
+    };
+    match event {
       Ok(Event::Incoming(Incoming::Publish(publish))) => {
-        let hat_sample = serde_json::from_slice::<HatSample>(&publish.payload)
-          .inspect_err(|e| warn!(target = "event_loop", case = "publish", "{:?}", e))
-          .unwrap_or_default();
+        let publish_topic = String::from_utf8_lossy(&publish.topic);
+        if config.require_provisioning {
+          let device_id = device_of(topic, &publish_topic, "/cmd/ack")
+            .or_else(|| device_of(topic, &publish_topic, "/firmware"))
+            .map(str::to_string)
+            .unwrap_or_else(|| device_id(topic, &publish_topic));
+          if !link.registry.is_active(&device_id) {
+            if rejected.insert(device_id.clone()) {
+              warn!(
+                target = "event_loop",
+                case = "rejected",
+                "{device_id} is not provisioned"
+              );
+            }
+            continue;
+          }
+          rejected.remove(&device_id);
+        }
+        if let Some(device_id) = device_of(topic, &publish_topic, "/cmd/ack") {
+          match serde_json::from_slice::<CommandAck>(&publish.payload) {
+            Ok(ack) => {
+              let id = ack.id.clone();
+              if !link.commands.acknowledge(device_id, ack) {
+                debug!(
+                  target = "event_loop",
+                  case = "ack",
+                  "{device_id}: unknown command {id}"
+                );
+              }
+            }
+            Err(e) => warn!(target = "event_loop", case = "ack", "{:?}", e),
+          }
+          continue;
+        }
+        if let Some(device_id) = device_of(topic, &publish_topic, "/firmware") {
+          match serde_json::from_slice::<FirmwareReport>(&publish.payload) {
+            Ok(report) => link.firmware.report(device_id, &report.version),
+            Err(e) => warn!(target = "event_loop", case = "firmware", "{:?}", e),
+          }
+          continue;
+        }
+        if let Some(recorder) = &recorder {
+          recorder.record(&publish_topic, &publish.payload).await;
+        }
+        let content_type = publish
+          .properties
+          .as_ref()
+          .and_then(|properties| properties.content_type.as_deref());
+        let Some(hat_sample) = sample_of(
+          topic,
+          &publish_topic,
+          content_type,
+          &publish.payload,
+          catalog,
+        ) else {
+          continue;
+        };
         debug!(target = "event_loop", case = "publish", "{:#?}", hat_sample);
-        let _ = tx
-          .send(hat_sample)
-          .inspect_err(|e| warn!(target = "event_loop", case = "publish", "{:?}", e));
+        sink.offer(hat_sample);
+      }
+      Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+        info!(
+          target = "event_loop",
+          case = "connected",
+          "{}:{}",
+          config.mqtt_host,
+          config.mqtt_port
+        );
+        connection.connected();
+        let filters = filters
+          .iter()
+          .map(|filter| Filter::new(filter.as_str(), QoS::AtLeastOnce));
+        if let Err(e) = client.subscribe_many(filters).await {
+          warn!(target = "event_loop", case = "subscribe", "{:?}", e);
+        }
+      }
+      Ok(Event::Incoming(Incoming::SubAck(suback))) => {
+        match connection.subscribed(&suback.return_codes) {
+          None => info!(
+            target = "event_loop",
+            case = "subscribed",
+            "{}",
+            filters.join(", ")
+          ),
+          // Staying connected keeps whatever was accepted flowing, the
+          // broker won't accept the rest on a retry either.
+          Some(error) => warn!(target = "event_loop", case = "subscribe", "{:?}", error),
+        }
       }
       Ok(event) => {
-        debug!(targer = "event_loop", case = "ok", "{:?}", event);
+        debug!(target = "event_loop", case = "ok", "{:?}", event);
+      }
+      Err(e) => {
+        let (error, delay) = connection.failed(&e);
+        warn!(
+          target = "event_loop",
+          case = "err",
+          "failure {}: {:?}, retrying in {delay:?}",
+          connection.failures(),
+          error
+        );
+        tokio::select! {
+          _ = time::sleep(delay) => {}
+          _ = shutdown.cancelled() => break,
+        }
+        connection.retrying();
+      }
+    }
+  }
+  disconnect(&client, &mut event_loop).await;
+  connection.stopped();
+  debug!(target = "event_loop", case = "shutdown", "disconnected");
+}
+
+/// Backoff and [`MqttStatus`] of a client of the broker. Every worker with a
+/// connection of its own keeps one, so they all retry alike and report
+/// their state the same way.
+pub(crate) struct Connection {
+  status: watch::Sender<MqttStatus>,
+  min_backoff: Duration,
+  max_backoff: Duration,
+  backoff: Duration,
+  /// Failures since the client was last connected.
+  failures: u32,
+}
+
+impl Connection {
+  /// Starts out connecting, with the backoff of `config`.
+  pub fn new(config: &Config, status: watch::Sender<MqttStatus>) -> Self {
+    let min_backoff = Duration::from_secs(config.mqtt_backoff_min.max(1));
+    let connection = Self {
+      status,
+      min_backoff,
+      max_backoff: Duration::from_secs(config.mqtt_backoff_max).max(min_backoff),
+      backoff: min_backoff,
+      failures: 0,
+    };
+    connection.transition(ConnectionState::Connecting, None, None);
+    connection
+  }
+
+  pub fn failures(&self) -> u32 {
+    self.failures
+  }
+
+  /// The broker accepted the connection, the backoff starts over. Clients
+  /// that don't subscribe are done connecting here.
+  pub fn connected(&mut self) {
+    self.backoff = self.min_backoff;
+    self.failures = 0;
+    self.transition(ConnectionState::Connected, None, None);
+  }
+
+  /// Connected and subscribed, degraded when the broker rejected some of
+  /// the subscriptions, which is returned then.
+  pub fn subscribed(&mut self, codes: &[SubscribeReasonCode]) -> Option<mqtt::ConnectionError> {
+    let rejected = codes
+      .iter()
+      .filter(|code| !matches!(code, SubscribeReasonCode::Success(_)))
+      .collect::<Vec<_>>();
+    if rejected.is_empty() {
+      self.transition(ConnectionState::Subscribed, None, None);
+      return None;
+    }
+    let error = connection_error(ErrorCategory::Subscription, format!("{rejected:?}"));
+    self.transition(ConnectionState::Degraded, None, Some(error.clone()));
+    Some(error)
+  }
+
+  /// Backs off after `error`, returning the error as reported and how long
+  /// to wait before [`Connection::retrying`]. The wait doubles with every
+  /// failure up to the maximum.
+  pub fn failed(&mut self, error: &ConnectionError) -> (mqtt::ConnectionError, Duration) {
+    self.failures += 1;
+    let delay = self.backoff;
+    self.backoff = (self.backoff * 2).min(self.max_backoff);
+    let error = connection_error(categorize(error), error.to_string());
+    let retry_at = Utc::now().timestamp() as u64 + delay.as_secs();
+    self.transition(
+      ConnectionState::BackingOff,
+      Some(retry_at),
+      Some(error.clone()),
+    );
+    (error, delay)
+  }
+
+  pub fn retrying(&mut self) {
+    self.transition(ConnectionState::Connecting, None, None);
+  }
+
+  pub fn stopped(&mut self) {
+    self.transition(ConnectionState::Stopped, None, None);
+  }
+
+  /// Moves to `state`. The last error is kept until a new one replaces it.
+  fn transition(
+    &self,
+    state: ConnectionState,
+    retry_at: Option<u64>,
+    error: Option<mqtt::ConnectionError>,
+  ) {
+    self.status.send_modify(|status| {
+      status.state = state;
+      status.since = Utc::now().timestamp() as u64;
+      status.failures = self.failures;
+      status.retry_at = retry_at;
+      if error.is_some() {
+        status.last_error = error;
       }
-      Err(error) => {
-        debug!(targer = "event_loop", case = "err", "{:?}", error);
+    });
+  }
+}
+
+/// Sends DISCONNECT, after whatever was queued before, so the broker doesn't
+/// publish the will or wait for the keep alive to expire.
+pub(crate) async fn disconnect(client: &AsyncClient, event_loop: &mut EventLoop) {
+  if client.disconnect().await.is_ok() {
+    let _ = time::timeout(DISCONNECT_TIMEOUT, async {
+      loop {
+        match event_loop.poll().await {
+          Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+          Ok(_) => {}
+        }
       }
+    })
+    .await;
+  }
+}
+
+/// Publishes without waiting, the event loop runs on the same task.
+fn publish_command(client: &AsyncClient, base: &str, outbound: Outbound) {
+  let payload = match serde_json::to_vec(&outbound.request) {
+    Ok(payload) => payload,
+    Err(e) => {
+      warn!(target = "event_loop", case = "serde json err", "{:?}", e);
+      return;
+    }
+  };
+  let topic = format!("{base}/{}/cmd", outbound.device_id);
+  debug!(
+    target = "event_loop",
+    case = "command",
+    "{topic}: {}",
+    outbound.request.id
+  );
+  if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, false, payload) {
+    warn!(target = "event_loop", case = "command", "{:?}", e);
+  }
+}
+
+fn connection_error(category: ErrorCategory, message: String) -> mqtt::ConnectionError {
+  mqtt::ConnectionError {
+    category,
+    message,
+    at: Utc::now().timestamp() as u64,
+  }
+}
+
+fn categorize(error: &ConnectionError) -> ErrorCategory {
+  match error {
+    ConnectionError::Io(_) | ConnectionError::RequestsDone => ErrorCategory::Network,
+    ConnectionError::Timeout(_) => ErrorCategory::Timeout,
+    ConnectionError::ConnectionRefused(code)
+    | ConnectionError::MqttState(StateError::ConnFail { reason: code }) => match code {
+      ConnectReturnCode::BadUserNamePassword
+      | ConnectReturnCode::NotAuthorized
+      | ConnectReturnCode::BadClientId
+      | ConnectReturnCode::ClientIdentifierNotValid
+      | ConnectReturnCode::Banned
+      | ConnectReturnCode::BadAuthenticationMethod => ErrorCategory::Unauthorized,
+      _ => ErrorCategory::Refused,
+    },
+    ConnectionError::MqttState(state) => match state {
+      StateError::Io(_) | StateError::ConnectionAborted => ErrorCategory::Network,
+      StateError::AwaitPingResp | StateError::CollisionTimeout => ErrorCategory::Timeout,
+      StateError::ServerDisconnect { .. } => ErrorCategory::Disconnected,
+      _ => ErrorCategory::Protocol,
+    },
+    _ => ErrorCategory::Protocol,
+  }
+}
+
+/// The sample a hat published on `topic` under `base`, in the encoding of
+/// its MQTT 5 content type or, without one, the encoding it looks like.
+/// Samples without a timestamp are taken as measured on arrival, readings
+/// outside their range in `catalog` are invalid, and payloads that aren't
+/// samples, or carry no reading at all, are dropped.
+pub(crate) fn sample_of(
+  base: &str,
+  topic: &str,
+  content_type: Option<&str>,
+  payload: &[u8],
+  catalog: &MetricCatalog,
+) -> Option<HatSample> {
+  let encoding = content_type
+    .and_then(Encoding::from_content_type)
+    .unwrap_or_else(|| Encoding::detect(payload));
+  let mut hat_sample = encoding
+    .decode::<HatSample>(payload)
+    .inspect_err(|e| warn!(target = "event_loop", case = "publish", "{:?}", e))
+    .ok()?;
+  if !hat_sample.has_readings() {
+    warn!(
+      target = "event_loop",
+      case = "publish",
+      "{topic}: no readings"
+    );
+    return None;
+  }
+  hat_sample.device_id = device_id(base, topic);
+  if hat_sample.timestamp == 0 {
+    hat_sample.timestamp = Utc::now().timestamp() as u64;
+  }
+  catalog.assess(&mut hat_sample);
+  Some(hat_sample)
+}
+
+/// Hats publish either on the bare topic or on `<topic>/<device id>`.
+fn device_id(base: &str, topic: &str) -> String {
+  match topic
+    .strip_prefix(base)
+    .and_then(|rest| rest.strip_prefix('/'))
+  {
+    Some(id) if !id.is_empty() => id.to_string(),
+    _ => DEFAULT_DEVICE_ID.to_string(),
+  }
+}
+
+/// The device a `<topic>/<device id><suffix>` topic under `base` belongs to.
+fn device_of<'a>(base: &str, topic: &'a str, suffix: &str) -> Option<&'a str> {
+  topic
+    .strip_prefix(base)?
+    .strip_prefix('/')?
+    .strip_suffix(suffix)
+    .filter(|id| !id.is_empty() && !id.contains('/'))
+}
+
+#[cfg(test)]
+mod tests {
+  use clap::Parser;
+  use rumqttc::v5::mqttbytes::v5::DisconnectReasonCode;
+
+  use super::*;
+  use crate::cli::Cli;
+
+  fn connection(min: &str, max: &str) -> (Connection, watch::Receiver<MqttStatus>) {
+    let cli = Cli::try_parse_from([
+      "server",
+      "--mqtt-backoff-min",
+      min,
+      "--mqtt-backoff-max",
+      max,
+      "check-config",
+    ])
+    .unwrap();
+    let (tx, rx) = watch::channel(MqttStatus::default());
+    (Connection::new(&cli.config, tx), rx)
+  }
+
+  fn refused() -> ConnectionError {
+    ConnectionError::Io(std::io::ErrorKind::ConnectionRefused.into())
+  }
+
+  #[test]
+  fn errors_are_categorized() {
+    let cases = [
+      (refused(), ErrorCategory::Network),
+      (
+        ConnectionError::ConnectionRefused(ConnectReturnCode::NotAuthorized),
+        ErrorCategory::Unauthorized,
+      ),
+      (
+        ConnectionError::MqttState(StateError::ConnFail {
+          reason: ConnectReturnCode::BadUserNamePassword,
+        }),
+        ErrorCategory::Unauthorized,
+      ),
+      (
+        ConnectionError::ConnectionRefused(ConnectReturnCode::ServerUnavailable),
+        ErrorCategory::Refused,
+      ),
+      (
+        ConnectionError::MqttState(StateError::AwaitPingResp),
+        ErrorCategory::Timeout,
+      ),
+      (
+        ConnectionError::MqttState(StateError::ServerDisconnect {
+          reason_code: DisconnectReasonCode::ServerShuttingDown,
+          reason_string: None,
+        }),
+        ErrorCategory::Disconnected,
+      ),
+      (
+        ConnectionError::MqttState(StateError::WrongPacket),
+        ErrorCategory::Protocol,
+      ),
+    ];
+    for (error, category) in cases {
+      assert_eq!(categorize(&error), category, "{error}");
     }
   }
+
+  #[test]
+  fn backoff_doubles_up_to_the_maximum() {
+    let (mut connection, status) = connection("2", "5");
+    assert_eq!(status.borrow().state, ConnectionState::Connecting);
+
+    let delays = (0..4)
+      .map(|_| connection.failed(&refused()).1.as_secs())
+      .collect::<Vec<_>>();
+    assert_eq!(delays, [2, 4, 5, 5]);
+    let current = status.borrow().clone();
+    assert_eq!(current.state, ConnectionState::BackingOff);
+    assert_eq!(current.failures, 4);
+    assert!(current.retry_at.is_some());
+    assert_eq!(
+      current.last_error.map(|error| error.category),
+      Some(ErrorCategory::Network)
+    );
+
+    connection.retrying();
+    assert_eq!(status.borrow().state, ConnectionState::Connecting);
+    assert_eq!(status.borrow().retry_at, None);
+  }
+
+  #[test]
+  fn connecting_resets_the_backoff() {
+    let (mut connection, status) = connection("1", "60");
+    connection.failed(&refused());
+    connection.failed(&refused());
+    connection.retrying();
+    connection.connected();
+    assert_eq!(status.borrow().state, ConnectionState::Connected);
+    assert_eq!(connection.failures(), 0);
+    assert_eq!(status.borrow().failures, 0);
+
+    assert_eq!(
+      connection.subscribed(&[SubscribeReasonCode::Success(QoS::AtLeastOnce)]),
+      None
+    );
+    assert_eq!(status.borrow().state, ConnectionState::Subscribed);
+    assert_eq!(connection.failed(&refused()).1, Duration::from_secs(1));
+  }
+
+  #[test]
+  fn rejected_subscriptions_degrade() {
+    let (mut connection, status) = connection("1", "60");
+    connection.connected();
+    let error = connection
+      .subscribed(&[
+        SubscribeReasonCode::Success(QoS::AtMostOnce),
+        SubscribeReasonCode::NotAuthorized,
+      ])
+      .unwrap();
+    assert_eq!(error.category, ErrorCategory::Subscription);
+    let current = status.borrow().clone();
+    assert_eq!(current.state, ConnectionState::Degraded);
+    assert!(!current.state.is_receiving());
+    assert_eq!(current.last_error, Some(error));
+
+    connection.stopped();
+    assert_eq!(status.borrow().state, ConnectionState::Stopped);
+  }
 }
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use app::store::SettingsStore;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use types::{thresholds::Band, units::Units};
+
+use crate::alerts::AlertEvent;
+
+/// JSON body POSTed to notification webhooks.
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+  device_id: &'a str,
+  metric: &'a str,
+  previous: Band,
+  band: Band,
+  value: f32,
+  unit: String,
+  timestamp: u64,
+  message: String,
+}
+
+/// Delivers alert events to the webhooks configured in `settings`, in the
+/// units of each target. Targets are looked up per event, so edits apply to
+/// the next alert.
+pub(crate) async fn run(events: &mut mpsc::Receiver<AlertEvent>, settings: SettingsStore) {
+  let client = reqwest::Client::builder()
+    .timeout(Duration::from_secs(10))
+    .build()
+    .expect("http client should build");
+  while let Some(event) = events.recv().await {
+    let current = settings.current();
+    for target in current.notifications {
+      // Recoveries are sent to targets that were told about the problem.
+      let severity = event.band.severity().max(event.previous.severity());
+      if !target.enabled || severity < target.min_band.severity() {
+        continue;
+      }
+      let Ok(body) = serde_json::to_vec(&notification(
+        &event,
+        &target.units.unwrap_or(current.units),
+      )) else {
+        continue;
+      };
+      let client = client.clone();
+      tokio::spawn(async move {
+        let result = client
+          .post(&target.url)
+          .header(reqwest::header::CONTENT_TYPE, "application/json")
+          .body(body)
+          .send()
+          .await
+          .and_then(|response| response.error_for_status());
+        match result {
+          Ok(_) => debug!(target = "notifier", name = target.name, "delivered"),
+          Err(e) => warn!(target = "notifier", name = target.name, "{:?}", e),
+        }
+      });
+    }
+  }
+}
+
+fn notification<'a>(event: &'a AlertEvent, units: &Units) -> Notification<'a> {
+  let (value, unit) = event.display(units);
+  Notification {
+    device_id: &event.device_id,
+    metric: &event.metric,
+    previous: event.previous,
+    band: event.band,
+    value,
+    unit,
+    timestamp: event.timestamp,
+    message: event.message(units),
+  }
+}
@@ -0,0 +1,155 @@
+//! Fan-out of the samples received from the broker or a replay.
+
+use std::sync::{
+  atomic::{AtomicU64, Ordering},
+  Arc,
+};
+
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tracing::warn;
+use types::HatSample;
+
+/// Samples queued for storage or alerts before they're dropped or a replay
+/// waits, and samples a live consumer may fall behind by before it misses
+/// some.
+const QUEUE: usize = 1024;
+
+/// Queue of samples for a worker that needs every one of them. Shared so a
+/// restarted worker picks up where the previous one stopped.
+pub(crate) type SampleQueue = Arc<Mutex<mpsc::Receiver<HatSample>>>;
+
+/// Samples storage or alerts had no room for since the start.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Dropped(Arc<AtomicU64>);
+
+impl Dropped {
+  pub fn count(&self) -> u64 {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+/// Where received samples go. Storage and alerts get every sample in order,
+/// up to [`QUEUE`] behind. The integrations and the health check get every
+/// sample of every device as it arrives, and the dashboard only follows the
+/// latest one.
+#[derive(Debug, Clone)]
+pub(crate) struct SampleSink {
+  latest: watch::Sender<HatSample>,
+  live: broadcast::Sender<HatSample>,
+  storage: mpsc::Sender<HatSample>,
+  alerts: mpsc::Sender<HatSample>,
+  dropped: Dropped,
+}
+
+impl SampleSink {
+  /// Hands `sample` on without waiting, for the MQTT event loop, which has
+  /// to keep polling to stay connected. A sample storage or alerts have no
+  /// room for is dropped for that worker and counted.
+  pub fn offer(&self, sample: HatSample) {
+    for (worker, queue) in [("storage", &self.storage), ("alerts", &self.alerts)] {
+      // Closed only once the worker is gone for good, at shutdown.
+      if let Err(mpsc::error::TrySendError::Full(_)) = queue.try_send(sample.clone()) {
+        let dropped = self.dropped.0.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+          target = "pipeline",
+          case = "overflow",
+          "{worker} is {QUEUE} samples behind, dropped {dropped} samples so far"
+        );
+      }
+    }
+    self.publish(sample);
+  }
+
+  /// Hands `sample` on, waiting for storage and alerts to have room, for a
+  /// replay, which may go as fast as they keep up.
+  pub async fn send(&self, sample: HatSample) {
+    // Fails only once the worker is gone for good, at shutdown.
+    let _ = self.storage.send(sample.clone()).await;
+    let _ = self.alerts.send(sample.clone()).await;
+    self.publish(sample);
+  }
+
+  fn publish(&self, sample: HatSample) {
+    // No receivers while every live consumer is disabled or restarting.
+    let _ = self.live.send(sample.clone());
+    self.latest.send_replace(sample);
+  }
+}
+
+/// Both ends of the sample fan-out.
+pub(crate) struct Pipeline {
+  pub sink: SampleSink,
+  pub latest: watch::Receiver<HatSample>,
+  /// Subscribed to by every live consumer when it starts.
+  pub live: broadcast::Sender<HatSample>,
+  pub storage: SampleQueue,
+  pub alerts: SampleQueue,
+  pub dropped: Dropped,
+}
+
+impl Pipeline {
+  pub fn new() -> Self {
+    let (latest_tx, latest) = watch::channel(HatSample::default());
+    let (live, _) = broadcast::channel(QUEUE);
+    let (storage_tx, storage) = mpsc::channel(QUEUE);
+    let (alerts_tx, alerts) = mpsc::channel(QUEUE);
+    let dropped = Dropped::default();
+    Self {
+      sink: SampleSink {
+        latest: latest_tx,
+        live: live.clone(),
+        storage: storage_tx,
+        alerts: alerts_tx,
+        dropped: dropped.clone(),
+      },
+      latest,
+      live,
+      storage: Arc::new(Mutex::new(storage)),
+      alerts: Arc::new(Mutex::new(alerts)),
+      dropped,
+    }
+  }
+}
+
+/// The next sample of `samples`, `None` once the pipeline is gone. Samples
+/// `consumer` fell too far behind to get are logged and skipped.
+pub(crate) async fn next(
+  samples: &mut broadcast::Receiver<HatSample>,
+  consumer: &str,
+) -> Option<HatSample> {
+  loop {
+    match samples.recv().await {
+      Ok(sample) => return Some(sample),
+      Err(broadcast::error::RecvError::Lagged(skipped)) => {
+        warn!(
+          target = "pipeline",
+          case = "lagged",
+          "{consumer} missed {skipped} samples"
+        );
+      }
+      Err(broadcast::error::RecvError::Closed) => return None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn offers_past_a_full_queue_are_dropped_and_counted() {
+    let pipeline = Pipeline::new();
+    for timestamp in 0..QUEUE as u64 + 2 {
+      pipeline.sink.offer(HatSample {
+        timestamp,
+        ..HatSample::default()
+      });
+    }
+    // Storage and alerts both missed the last two.
+    assert_eq!(pipeline.dropped.count(), 4);
+    let mut storage = pipeline.storage.lock().await;
+    assert_eq!(storage.recv().await.unwrap().timestamp, 0);
+    assert_eq!(storage.len(), QUEUE - 1);
+    assert_eq!(pipeline.latest.borrow().timestamp, QUEUE as u64 + 1);
+  }
+}
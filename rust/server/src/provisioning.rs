@@ -0,0 +1,291 @@
+//! Claim code redemption for hats, and the authentication and ACL endpoints
+//! of brokers delegating to HTTP (e.g. mosquitto-go-auth's HTTP backend),
+//! so hats connect with their own credentials and only use their own
+//! topics. Only the brokers of [`Config::mqtt_auth_allow`] may ask.
+
+use std::{
+  collections::HashMap,
+  net::{IpAddr, SocketAddr},
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use app::{
+  audit::AuditLog,
+  provisioning::{DeviceRegistry, ProvisioningError},
+};
+use axum::{
+  extract::{ConnectInfo, State},
+  http::StatusCode,
+  Json,
+};
+use serde::Deserialize;
+use tracing::warn;
+use types::{
+  audit::AuditAction,
+  provisioning::{Claim, DeviceCredentials},
+  DEFAULT_DEVICE_ID,
+};
+
+use crate::config::Config;
+
+/// Claim attempts an address may make per [`CLAIM_WINDOW`].
+const CLAIM_ATTEMPTS: u32 = 10;
+const CLAIM_WINDOW: Duration = Duration::from_secs(60);
+
+pub(crate) type ProvisioningState = (DeviceRegistry, Config, AuditLog, ClaimLimiter);
+
+/// Claim attempts of every address in the current window, so claim codes
+/// can't be guessed by brute force.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClaimLimiter(Arc<Mutex<HashMap<IpAddr, (Instant, u32)>>>);
+
+impl ClaimLimiter {
+  /// Counts an attempt of `ip`, `false` once it made too many.
+  fn attempt(&self, ip: IpAddr, now: Instant) -> bool {
+    let mut attempts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+    attempts.retain(|_, (start, _)| now.duration_since(*start) < CLAIM_WINDOW);
+    let (_, count) = attempts.entry(ip).or_insert((now, 0));
+    *count += 1;
+    *count <= CLAIM_ATTEMPTS
+  }
+}
+
+/// `POST /api/provision/claim`: trades a claim code for the device's token.
+/// Public, the hat has no other credentials yet, but rate limited per
+/// address.
+pub(crate) async fn claim(
+  State((registry, config, audit, limiter)): State<ProvisioningState>,
+  ConnectInfo(peer): ConnectInfo<SocketAddr>,
+  Json(claim): Json<Claim>,
+) -> Result<Json<DeviceCredentials>, (StatusCode, String)> {
+  if !limiter.attempt(peer.ip().to_canonical(), Instant::now()) {
+    warn!(
+      target = "provisioning",
+      case = "claim",
+      "{peer}: too many attempts"
+    );
+    return Err((
+      StatusCode::TOO_MANY_REQUESTS,
+      "too many attempts, try again later".to_string(),
+    ));
+  }
+  let (device_id, token) = registry.claim(&claim.code).map_err(|e| match e {
+    ProvisioningError::Store(e) => {
+      warn!(target = "provisioning", case = "claim", "{:?}", e);
+      (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+    e => (StatusCode::BAD_REQUEST, e.to_string()),
+  })?;
+  audit
+    .record(&device_id, AuditAction::DeviceClaimed, Some(&device_id), "")
+    .await;
+  Ok(Json(DeviceCredentials {
+    topic: format!("{}/{device_id}", config.mqtt_topic),
+    device_id,
+    token,
+  }))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UserCheck {
+  username: String,
+  #[serde(default)]
+  password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AclCheck {
+  username: String,
+  topic: String,
+  /// 1 read, 2 write, 3 both, 4 subscribe.
+  acc: u8,
+}
+
+/// Whether `peer` is an allowed broker.
+fn is_broker(config: &Config, peer: SocketAddr) -> bool {
+  let allowed = config.mqtt_auth_allow.contains(&peer.ip().to_canonical());
+  if !allowed {
+    warn!(
+      target = "provisioning",
+      case = "broker",
+      "{peer} is not an allowed broker"
+    );
+  }
+  allowed
+}
+
+fn is_server(config: &Config, username: &str) -> bool {
+  config.mqtt_username.as_deref() == Some(username)
+}
+
+fn allowed(allow: bool) -> StatusCode {
+  if allow {
+    StatusCode::OK
+  } else {
+    StatusCode::FORBIDDEN
+  }
+}
+
+/// `POST /api/mqtt/user`: whether the credentials are the server's or a
+/// device id and its token.
+pub(crate) async fn user(
+  State((registry, config, _, _)): State<ProvisioningState>,
+  ConnectInfo(peer): ConnectInfo<SocketAddr>,
+  Json(check): Json<UserCheck>,
+) -> StatusCode {
+  if !is_broker(&config, peer) {
+    return StatusCode::FORBIDDEN;
+  }
+  let server = is_server(&config, &check.username)
+    && config.mqtt_password.as_deref() == Some(check.password.as_str());
+  allowed(server || registry.verify(&check.username, &check.password))
+}
+
+/// `POST /api/mqtt/superuser`: only the server's own clients may use every
+/// topic.
+pub(crate) async fn superuser(
+  State((_, config, _, _)): State<ProvisioningState>,
+  ConnectInfo(peer): ConnectInfo<SocketAddr>,
+  Json(check): Json<UserCheck>,
+) -> StatusCode {
+  allowed(is_broker(&config, peer) && is_server(&config, &check.username))
+}
+
+/// `POST /api/mqtt/acl`: hats may publish samples, acknowledgements and
+/// firmware reports under their own device id, and read its commands.
+pub(crate) async fn acl(
+  State((registry, config, _, _)): State<ProvisioningState>,
+  ConnectInfo(peer): ConnectInfo<SocketAddr>,
+  Json(check): Json<AclCheck>,
+) -> StatusCode {
+  allowed(is_broker(&config, peer) && permits(&registry, &config, &check))
+}
+
+fn permits(registry: &DeviceRegistry, config: &Config, check: &AclCheck) -> bool {
+  if is_server(config, &check.username) {
+    return true;
+  }
+  let device_id = check.username.as_str();
+  if !registry.is_active(device_id) {
+    return false;
+  }
+  let base = format!("{}/{device_id}", config.mqtt_topic);
+  let publishes = [
+    base.clone(),
+    format!("{base}/cmd/ack"),
+    format!("{base}/firmware"),
+  ];
+  match check.acc {
+    2 => {
+      publishes.contains(&check.topic)
+        || (device_id == DEFAULT_DEVICE_ID && check.topic == config.mqtt_topic)
+    }
+    1 | 4 => check.topic == format!("{base}/cmd"),
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU64, Ordering};
+
+  use clap::Parser;
+
+  use super::*;
+  use crate::cli::Cli;
+
+  fn config(data_dir: &std::path::Path) -> Config {
+    Cli::try_parse_from([
+      "server",
+      "--data-dir",
+      data_dir.to_str().unwrap(),
+      "--mqtt-username",
+      "server",
+      "--mqtt-auth-allow",
+      "10.0.0.2",
+      "check-config",
+    ])
+    .unwrap()
+    .config
+  }
+
+  fn data_dir() -> std::path::PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir().join(format!(
+      "hat-monitor-provisioning-{}-{}",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+  }
+
+  fn check(username: &str, topic: &str, acc: u8) -> AclCheck {
+    AclCheck {
+      username: username.to_string(),
+      topic: topic.to_string(),
+      acc,
+    }
+  }
+
+  #[test]
+  fn hats_only_use_their_own_topics() {
+    let dir = data_dir();
+    let config = config(&dir);
+    let registry = config.devices().unwrap();
+    for device_id in ["hat-1", DEFAULT_DEVICE_ID, "hat-3"] {
+      let claim = registry.create_claim(device_id, "admin").unwrap();
+      registry.claim(&claim.code).unwrap();
+    }
+    registry.revoke("hat-3").unwrap();
+    let cases = [
+      ("server", "anything/#", 4, true),
+      ("hat-1", "iot/hat/hat-1", 2, true),
+      ("hat-1", "iot/hat/hat-1/cmd/ack", 2, true),
+      ("hat-1", "iot/hat/hat-1/firmware", 2, true),
+      ("hat-1", "iot/hat/hat-1/cmd", 4, true),
+      ("hat-1", "iot/hat/hat-1/cmd", 1, true),
+      ("hat-1", "iot/hat/hat-1/cmd", 2, false),
+      ("hat-1", "iot/hat/hat-1", 1, false),
+      ("hat-1", "iot/hat/hat-2", 2, false),
+      ("hat-1", "iot/hat", 2, false),
+      ("hat-1", "iot/hat/+/cmd", 4, false),
+      ("hat-1", "iot/hat/hat-1", 3, false),
+      (DEFAULT_DEVICE_ID, "iot/hat", 2, true),
+      ("hat-3", "iot/hat/hat-3", 2, false),
+      ("hat-4", "iot/hat/hat-4", 2, false),
+    ];
+    for (username, topic, acc, allow) in cases {
+      assert_eq!(
+        permits(&registry, &config, &check(username, topic, acc)),
+        allow,
+        "{username} {topic} {acc}"
+      );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn only_allowed_brokers_may_ask() {
+    let config = config(&data_dir());
+    assert!(is_broker(&config, "10.0.0.2:5000".parse().unwrap()));
+    assert!(is_broker(
+      &config,
+      "[::ffff:10.0.0.2]:5000".parse().unwrap()
+    ));
+    assert!(!is_broker(&config, "10.0.0.3:5000".parse().unwrap()));
+    assert!(!is_broker(&config, "127.0.0.1:5000".parse().unwrap()));
+  }
+
+  #[test]
+  fn claims_are_rate_limited_per_address() {
+    let limiter = ClaimLimiter::default();
+    let (first, second) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+    let start = Instant::now();
+    for _ in 0..CLAIM_ATTEMPTS {
+      assert!(limiter.attempt(first, start));
+    }
+    assert!(!limiter.attempt(first, start));
+    assert!(limiter.attempt(second, start));
+    assert!(limiter.attempt(first, start + CLAIM_WINDOW));
+  }
+}
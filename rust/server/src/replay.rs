@@ -0,0 +1,284 @@
+//! Recording of raw MQTT traffic and replaying it in place of the broker.
+
+use std::{
+  io,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use tokio::{
+  fs::{File, OpenOptions},
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+  sync::Mutex,
+  time,
+};
+use tracing::{info, warn};
+use types::measurement::MetricCatalog;
+
+use crate::{mqttc_worker, pipeline::SampleSink};
+
+/// One received publish, a line of a recording.
+#[derive(Debug, Serialize, Deserialize)]
+struct Recorded {
+  /// Unix milliseconds the server received the publish at.
+  received_at: u64,
+  topic: String,
+  /// The payload when it's UTF-8, as JSON samples are.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  payload: Option<String>,
+  /// Any other payload, e.g. CBOR samples.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  payload_base64: Option<String>,
+}
+
+impl Recorded {
+  fn payload(&self) -> Result<Vec<u8>, base64::DecodeError> {
+    match (&self.payload, &self.payload_base64) {
+      (Some(payload), _) => Ok(payload.clone().into_bytes()),
+      (None, Some(encoded)) => STANDARD.decode(encoded),
+      (None, None) => Ok(Vec::new()),
+    }
+  }
+}
+
+/// Appends every publish the worker receives to an NDJSON recording.
+#[derive(Debug, Clone)]
+pub(crate) struct Recorder {
+  file: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+  pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .await?;
+    Ok(Self {
+      file: Arc::new(Mutex::new(file)),
+    })
+  }
+
+  pub async fn record(&self, topic: &str, payload: &[u8]) {
+    let (payload, payload_base64) = match std::str::from_utf8(payload) {
+      Ok(text) => (Some(text.to_string()), None),
+      Err(_) => (None, Some(STANDARD.encode(payload))),
+    };
+    let recorded = Recorded {
+      received_at: Utc::now().timestamp_millis() as u64,
+      topic: topic.to_string(),
+      payload,
+      payload_base64,
+    };
+    let mut line = match serde_json::to_vec(&recorded) {
+      Ok(line) => line,
+      Err(e) => {
+        warn!(target = "recorder", case = "serde json err", "{:?}", e);
+        return;
+      }
+    };
+    line.push(b'\n');
+    if let Err(e) = self.file.lock().await.write_all(&line).await {
+      warn!(target = "recorder", case = "write", "{:?}", e);
+    }
+  }
+}
+
+/// How fast a recording is fed back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Pace {
+  /// The gaps between publishes as they were received.
+  Original,
+  /// The original gaps divided by the factor.
+  Accelerated(f64),
+  /// One publish per line read from stdin.
+  Stepped,
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct ReplayArgs {
+  /// Recording made with `serve --record`.
+  path: PathBuf,
+  /// Plays the recording this many times faster than it was received, e.g.
+  /// `10` or `10x`.
+  #[arg(long, conflicts_with = "step", value_parser = parse_speed)]
+  speed: Option<f64>,
+  /// Publishes one message per line read from stdin.
+  #[arg(long)]
+  step: bool,
+}
+
+impl ReplayArgs {
+  fn pace(&self) -> Pace {
+    match self.speed {
+      _ if self.step => Pace::Stepped,
+      Some(factor) => Pace::Accelerated(factor),
+      None => Pace::Original,
+    }
+  }
+}
+
+fn parse_speed(value: &str) -> Result<f64, String> {
+  value
+    .trim_end_matches('x')
+    .parse::<f64>()
+    .ok()
+    .filter(|factor| factor.is_finite() && *factor > 0.0)
+    .ok_or_else(|| format!("{value:?} is not a positive factor"))
+}
+
+/// Feeds the publishes of a recording into `sink` as if they came from the
+/// broker, then idles so the dashboard stays up. Every sample is stored and
+/// checked for alerts, also when the pace is faster than they are consumed;
+/// the dashboard only shows the latest.
+pub(crate) async fn run(
+  replay: ReplayArgs,
+  topic: String,
+  catalog: MetricCatalog,
+  sink: SampleSink,
+) {
+  if let Err(e) = feed(&replay, &topic, &catalog, &sink).await {
+    warn!(target = "replay", case = "read", "{:?}", e);
+  }
+  info!(target = "replay", "finished {}", replay.path.display());
+  // Returning would have the supervisor restart the replay.
+  std::future::pending::<()>().await;
+}
+
+async fn feed(
+  replay: &ReplayArgs,
+  topic: &str,
+  catalog: &MetricCatalog,
+  sink: &SampleSink,
+) -> io::Result<()> {
+  let mut lines = BufReader::new(File::open(&replay.path).await?).lines();
+  let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+  let mut previous = None;
+  let mut number = 0;
+  while let Some(line) = lines.next_line().await? {
+    number += 1;
+    if line.trim().is_empty() {
+      continue;
+    }
+    let recorded = match serde_json::from_str::<Recorded>(&line) {
+      Ok(recorded) => recorded,
+      Err(e) => {
+        warn!(target = "replay", case = "line", "line {number}: {:?}", e);
+        continue;
+      }
+    };
+    let gap = previous.map_or(0, |previous| recorded.received_at.saturating_sub(previous));
+    previous = Some(recorded.received_at);
+    match replay.pace() {
+      Pace::Original => time::sleep(Duration::from_millis(gap)).await,
+      Pace::Accelerated(factor) => {
+        time::sleep(Duration::from_secs_f64(gap as f64 / 1000.0 / factor)).await
+      }
+      Pace::Stepped => {
+        println!(
+          "line {number}: {} at {} ms, press enter to publish",
+          recorded.topic, recorded.received_at
+        );
+        if stdin.next_line().await?.is_none() {
+          return Ok(());
+        }
+      }
+    }
+    match recorded.payload() {
+      Ok(payload) => {
+        if let Some(sample) =
+          mqttc_worker::sample_of(topic, &recorded.topic, None, &payload, catalog)
+        {
+          sink.send(sample).await;
+        }
+      }
+      Err(e) => warn!(
+        target = "replay",
+        case = "payload",
+        "line {number}: {:?}",
+        e
+      ),
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use tokio_util::sync::CancellationToken;
+
+  use super::*;
+  use crate::{
+    pipeline::Pipeline,
+    storage::{self, SampleStore},
+  };
+
+  #[test]
+  fn speeds_are_positive_factors() {
+    assert_eq!(parse_speed("10x"), Ok(10.0));
+    assert_eq!(parse_speed("0.5"), Ok(0.5));
+    for speed in ["0", "-2", "x", "inf", "NaN"] {
+      assert!(parse_speed(speed).is_err(), "{speed}");
+    }
+  }
+
+  #[tokio::test]
+  async fn every_replayed_sample_is_stored() {
+    const SAMPLES: u64 = 3000;
+    let dir = std::env::temp_dir().join(format!("hat-monitor-replay-{}", std::process::id()));
+    let recording = dir.join("recording.ndjson");
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let lines = (0..SAMPLES)
+      .map(|i| {
+        let recorded = Recorded {
+          received_at: 1_760_000_000_000 + i * 1000,
+          topic: "hat/hat-1".to_string(),
+          payload: Some(format!(
+            r#"{{"timestamp":{},"temperature":21.5}}"#,
+            1_760_000_000 + i
+          )),
+          payload_base64: None,
+        };
+        serde_json::to_string(&recorded).unwrap() + "\n"
+      })
+      .collect::<String>();
+    tokio::fs::write(&recording, lines).await.unwrap();
+
+    let pipeline = Pipeline::new();
+    let store = SampleStore::new(dir.join("samples"));
+    let shutdown = CancellationToken::new();
+    let storage = tokio::spawn(storage::run(
+      pipeline.storage.clone(),
+      store.clone(),
+      shutdown.clone(),
+    ));
+    // Nothing evaluates alerts here, their queue mustn't hold the replay up.
+    let alerts = pipeline.alerts.clone();
+    tokio::spawn(async move { while alerts.lock().await.recv().await.is_some() {} });
+    let replay = ReplayArgs {
+      path: recording,
+      speed: Some(1_000_000.0),
+      step: false,
+    };
+    feed(&replay, "hat", &MetricCatalog::builtin(), &pipeline.sink)
+      .await
+      .unwrap();
+    shutdown.cancel();
+    storage.await.unwrap();
+
+    let mut stored = 0;
+    for (_, path) in store.day_files("hat-1").await.unwrap() {
+      stored += storage::read_day_file(&path, 0, u64::MAX)
+        .await
+        .unwrap()
+        .len();
+    }
+    assert_eq!(stored as u64, SAMPLES);
+    tokio::fs::remove_dir_all(dir).await.unwrap();
+  }
+}
@@ -0,0 +1,283 @@
+//! Output stage republishing validated, calibrated readings, derived metrics
+//! and alert events to the broker for other consumers.
+
+use chrono::Utc;
+use clap::Args;
+use rumqttc::v5::{mqttbytes::QoS, AsyncClient, Event, Incoming};
+use serde::Serialize;
+use tokio::{
+  sync::{broadcast, watch},
+  time,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use types::{
+  derived::DerivedMetrics, import::validate_sample, measurement::MetricCatalog, mqtt::MqttStatus,
+  thresholds::Band, HatSample,
+};
+
+use crate::{
+  alerts::AlertEvent,
+  config::Config,
+  mqttc_worker::{self, Connection},
+  pipeline,
+};
+
+/// A topic with `{device}` and `{metric}` placeholders.
+#[derive(Debug, Clone)]
+pub(crate) struct TopicTemplate(String);
+
+impl TopicTemplate {
+  fn parse(value: &str, required: &[&str]) -> Result<Self, String> {
+    if value.is_empty() || value.contains(['+', '#']) {
+      return Err(format!("{value:?} must be a plain topic without wildcards"));
+    }
+    if let Some(missing) = required
+      .iter()
+      .find(|placeholder| !value.contains(*placeholder))
+    {
+      return Err(format!("{value:?} must contain {missing}"));
+    }
+    Ok(Self(value.to_string()))
+  }
+
+  fn render(&self, device_id: &str, metric: &str) -> String {
+    self
+      .0
+      .replace("{device}", device_id)
+      .replace("{metric}", metric)
+  }
+}
+
+fn parse_metric_topic(value: &str) -> Result<TopicTemplate, String> {
+  TopicTemplate::parse(value, &["{device}", "{metric}"])
+}
+
+fn parse_alert_topic(value: &str) -> Result<TopicTemplate, String> {
+  TopicTemplate::parse(value, &["{device}"])
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct RepublishArgs {
+  /// Republishes validated readings, derived metrics and alert events to the
+  /// broker.
+  #[arg(id = "republish", long = "republish", env = "HAT_MONITOR_REPUBLISH")]
+  pub enabled: bool,
+  /// Retained topic of the latest value of each metric of each device.
+  #[arg(
+    long = "republish-metric-topic",
+    env = "HAT_MONITOR_REPUBLISH_METRIC_TOPIC",
+    default_value = "hat-monitor/{device}/{metric}",
+    value_parser = parse_metric_topic
+  )]
+  pub metric_topic: TopicTemplate,
+  /// Topic of alert events, `{metric}` is the metric that changed band.
+  #[arg(
+    long = "republish-alert-topic",
+    env = "HAT_MONITOR_REPUBLISH_ALERT_TOPIC",
+    default_value = "hat-monitor/{device}/alerts",
+    value_parser = parse_alert_topic
+  )]
+  pub alert_topic: TopicTemplate,
+}
+
+/// Payload of a metric topic.
+#[derive(Debug, Serialize)]
+struct Reading<'a> {
+  value: f32,
+  unit: &'a str,
+  timestamp: u64,
+}
+
+/// Payload of an alert topic, in the canonical unit of the metric.
+#[derive(Debug, Serialize)]
+struct Alert<'a> {
+  device_id: &'a str,
+  metric: &'a str,
+  previous: Band,
+  band: Band,
+  value: f32,
+  unit: &'a str,
+  timestamp: u64,
+}
+
+/// Measured and derived metrics of `sample`, with their units, then the
+/// readings of other sensors the `catalog` knows. Gas readings are the
+/// humidity and temperature corrected ones. Metrics without a usable reading
+/// are left out.
+fn readings<'a>(sample: &'a HatSample, catalog: &'a MetricCatalog) -> Vec<(&'a str, f32, &'a str)> {
+  let derived = DerivedMetrics::of(sample);
+  let measurements = sample.measurements.iter().filter_map(|measurement| {
+    let definition = catalog.get(&measurement.metric)?;
+    Some((
+      definition.key.as_str(),
+      measurement.value,
+      definition.unit.as_str(),
+    ))
+  });
+  [
+    ("temperature", sample.temperature, "°C"),
+    ("humidity", sample.humidity, "%"),
+    ("ppm", sample.corrected_ppm, "ppm"),
+    ("resistance", sample.resistance, "Ω"),
+    ("dew_point", derived.map(|d| d.dew_point), "°C"),
+    (
+      "absolute_humidity",
+      derived.map(|d| d.absolute_humidity),
+      "g/m³",
+    ),
+    ("heat_index", derived.map(|d| d.heat_index), "°C"),
+  ]
+  .into_iter()
+  .chain(measurements)
+  .filter_map(|(metric, value, unit)| Some((metric, value?, unit)))
+  .collect()
+}
+
+/// Publishes without waiting, the event loop runs on the same task.
+fn publish(client: &AsyncClient, topic: String, retain: bool, payload: &impl Serialize) {
+  let payload = match serde_json::to_vec(payload) {
+    Ok(payload) => payload,
+    Err(e) => {
+      warn!(target = "republish", case = "serde json err", "{:?}", e);
+      return;
+    }
+  };
+  if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, retain, payload) {
+    warn!(target = "republish", case = "publish", "{:?}", e);
+  }
+}
+
+/// Republishes every plausible sample and every alert event until
+/// `shutdown`. Samples that fail the import checks are not passed on, nor
+/// are the ones received while the broker is unreachable.
+pub(crate) async fn run(
+  config: &Config,
+  args: &RepublishArgs,
+  catalog: &MetricCatalog,
+  mut samples: broadcast::Receiver<HatSample>,
+  mut alerts: broadcast::Receiver<AlertEvent>,
+  status: watch::Sender<MqttStatus>,
+  shutdown: CancellationToken,
+) {
+  let mqtt_options = config.mqtt_options("hat-monitor-republish");
+  let (client, mut event_loop) = AsyncClient::new(mqtt_options, 1000);
+  let mut connection = Connection::new(config, status);
+  let mut connected = false;
+  loop {
+    tokio::select! {
+      event = event_loop.poll() => match event {
+        Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+          connected = true;
+          connection.connected();
+        }
+        Ok(event) => debug!(target = "republish", case = "ok", "{:?}", event),
+        Err(e) => {
+          connected = false;
+          let (error, delay) = connection.failed(&e);
+          warn!(
+            target = "republish",
+            case = "err",
+            "failure {}: {:?}, retrying in {delay:?}",
+            connection.failures(),
+            error
+          );
+          tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = shutdown.cancelled() => break,
+          }
+          connection.retrying();
+        }
+      },
+      sample = pipeline::next(&mut samples, "republish") => {
+        let Some(sample) = sample else {
+          break;
+        };
+        if !connected {
+          continue;
+        }
+        if let Err(e) = validate_sample(&sample, Utc::now().timestamp() as u64) {
+          debug!(target = "republish", case = "invalid", "{}: {e}", sample.device_id);
+          continue;
+        }
+        for (metric, value, unit) in readings(&sample, catalog) {
+          let reading = Reading {
+            value,
+            unit,
+            timestamp: sample.timestamp,
+          };
+          publish(&client, args.metric_topic.render(&sample.device_id, metric), true, &reading);
+        }
+      }
+      event = alerts.recv(), if connected => match event {
+        Ok(event) => {
+          let alert = Alert {
+            device_id: &event.device_id,
+            metric: &event.metric,
+            previous: event.previous,
+            band: event.band,
+            value: event.value,
+            unit: &event.unit,
+            timestamp: event.timestamp,
+          };
+          let topic = args.alert_topic.render(&event.device_id, &event.metric);
+          publish(&client, topic, false, &alert);
+        }
+        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+          warn!(target = "republish", case = "lagged", "dropped {skipped} alert events");
+        }
+        Err(broadcast::error::RecvError::Closed) => break,
+      },
+      _ = shutdown.cancelled() => break,
+    }
+  }
+  mqttc_worker::disconnect(&client, &mut event_loop).await;
+  connection.stopped();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn templates_need_their_placeholders() {
+    assert!(parse_metric_topic("hat-monitor/{device}/{metric}").is_ok());
+    assert!(parse_alert_topic("hat-monitor/{device}/alerts").is_ok());
+    for topic in ["", "hat-monitor/{device}", "hat-monitor/{metric}"] {
+      assert!(parse_metric_topic(topic).is_err(), "{topic:?}");
+    }
+    assert!(parse_alert_topic("hat-monitor/alerts").is_err());
+  }
+
+  #[test]
+  fn templates_reject_wildcards() {
+    for topic in ["+/{device}/{metric}", "hat-monitor/{device}/{metric}/#"] {
+      assert!(parse_metric_topic(topic).is_err(), "{topic:?}");
+    }
+  }
+
+  #[test]
+  fn readings_of_catalog_metrics_are_republished() {
+    let sample: HatSample = serde_json::from_str(
+      r#"{"timestamp":1760000000,"temperature":20.0,"measurements":[{"metric":"co","unit":"ppm","value":9.0},{"metric":"pm25","unit":"µg/m³","value":null},{"metric":"radon","unit":"Bq/m³","value":40.0}]}"#,
+    )
+    .unwrap();
+    let mut catalog = MetricCatalog::builtin();
+    catalog.extend(
+      serde_json::from_str::<Vec<_>>(r#"[{"key":"co","label":"CO","unit":"ppm"}]"#).unwrap(),
+    );
+    // Missing readings and metrics outside the catalog are left out.
+    assert_eq!(
+      readings(&sample, &catalog),
+      [("temperature", 20.0, "°C"), ("co", 9.0, "ppm")]
+    );
+  }
+
+  #[test]
+  fn templates_render_every_placeholder() {
+    let template = parse_metric_topic("{device}/{metric}/{device}").unwrap();
+    assert_eq!(template.render("hat-1", "ppm"), "hat-1/ppm/hat-1");
+    let template = parse_alert_topic("alerts/{device}").unwrap();
+    assert_eq!(template.render("hat-1", "ppm"), "alerts/hat-1");
+  }
+}
@@ -0,0 +1,99 @@
+use std::{process::ExitCode, time::Duration};
+
+use chrono::Utc;
+use clap::Args;
+use rand::{Rng, SeedableRng};
+use rumqttc::v5::{
+  mqttbytes::{v5::PublishProperties, QoS},
+  AsyncClient,
+};
+use tokio::time;
+use tracing::{debug, warn};
+use types::{encoding::Encoding, settings::validate_device_id, HatSample};
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct SimulateArgs {
+  /// Devices to publish for, on `<topic>/<device id>`. Without any, one hat
+  /// publishes on the bare topic.
+  #[arg(long, value_delimiter = ',')]
+  devices: Vec<String>,
+  /// Seconds between samples of each device.
+  #[arg(long, default_value_t = 5)]
+  interval: u64,
+  /// Payload encoding, `json` or `cbor`, declared as the MQTT 5 content
+  /// type.
+  #[arg(long, default_value_t = Encoding::Json)]
+  encoding: Encoding,
+}
+
+/// `server simulate`: publishes random samples to the broker as fake hats,
+/// for running the dashboard without hardware.
+pub(crate) async fn run(config: &Config, args: SimulateArgs) -> ExitCode {
+  if let Some(e) = args.devices.iter().find_map(|device| {
+    validate_device_id(device)
+      .err()
+      .map(|e| format!("{device:?} {e}"))
+  }) {
+    eprintln!("invalid device id: {e}");
+    return ExitCode::from(2);
+  }
+  let mqtt_options = config.mqtt_options("hat-monitor-simulator");
+  let (client, mut event_loop) = AsyncClient::new(mqtt_options, 1000);
+  tokio::spawn(async move {
+    loop {
+      if let Err(error) = event_loop.poll().await {
+        debug!(target = "simulate", case = "err", "{:?}", error);
+        time::sleep(Duration::from_secs(1)).await;
+      }
+    }
+  });
+
+  let topics = if args.devices.is_empty() {
+    vec![config.mqtt_topic.clone()]
+  } else {
+    args
+      .devices
+      .iter()
+      .map(|device| format!("{}/{device}", config.mqtt_topic))
+      .collect()
+  };
+  let mut rng = rand::rngs::StdRng::from_rng(&mut rand::rng());
+  let mut interval = time::interval(Duration::from_secs(args.interval.max(1)));
+  loop {
+    interval.tick().await;
+    for topic in &topics {
+      let payload = args
+        .encoding
+        .encode(&sample(&mut rng))
+        .expect("should be encoded");
+      debug!(target = "publish", "{topic}: {} bytes", payload.len());
+      let properties = PublishProperties {
+        content_type: Some(args.encoding.content_type().to_string()),
+        ..Default::default()
+      };
+      if let Err(e) = client
+        .publish_with_properties(topic.as_str(), QoS::AtLeastOnce, false, payload, properties)
+        .await
+      {
+        warn!(target = "simulate", case = "publish", "{:?}", e);
+      }
+    }
+  }
+}
+
+fn sample(rng: &mut impl Rng) -> HatSample {
+  HatSample {
+    device_id: String::new(),
+    timestamp: Utc::now().timestamp() as u64,
+    temperature: Some(rng.random_range(27.0..=33.0)),
+    humidity: Some(rng.random_range(50.0..=55.5)),
+    r_zero: Some(rng.random_range(0.0..=50.0)),
+    corrected_r_zero: Some(rng.random_range(0.0..=50.0)),
+    resistance: Some(rng.random_range(0.0..=100.0)),
+    ppm: Some(rng.random_range(200.0..=300.0)),
+    corrected_ppm: Some(rng.random_range(200.0..=300.0)),
+    ..HatSample::default()
+  }
+}
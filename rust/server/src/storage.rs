@@ -0,0 +1,542 @@
+use std::{
+  collections::HashSet,
+  io,
+  path::{Path, PathBuf},
+  slice,
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+
+use app::{auth::Identity, store::SettingsStore};
+use axum::{
+  extract::{Query, State},
+  http::StatusCode,
+  Extension, Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use tokio::{
+  fs::{self, OpenOptions},
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+  time,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use types::{
+  measurement::MetricCatalog, settings::validate_device_id, HatSample, DEFAULT_DEVICE_ID,
+};
+
+use crate::pipeline::SampleQueue;
+
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Outcome of [`SampleStore::migrate`].
+#[derive(Debug, Default)]
+pub(crate) struct Migration {
+  /// Day files rewritten, or to rewrite on a dry run.
+  pub files: usize,
+  pub samples: usize,
+  /// Lines that aren't samples in any schema version.
+  pub unreadable: usize,
+}
+
+/// Append-only sample history: one NDJSON file per device and UTC day under
+/// `<root>/<device id>/<YYYY-MM-DD>.ndjson`.
+#[derive(Debug, Clone)]
+pub(crate) struct SampleStore {
+  root: PathBuf,
+}
+
+impl SampleStore {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self { root: root.into() }
+  }
+
+  fn device_dir(&self, device_id: &str) -> PathBuf {
+    if validate_device_id(device_id).is_ok() {
+      return self.root.join(device_id);
+    }
+    let sanitized: String = device_id
+      .chars()
+      .map(|c| {
+        if c.is_ascii_alphanumeric() || c == '-' {
+          c
+        } else {
+          '_'
+        }
+      })
+      .collect();
+    self.root.join(sanitized)
+  }
+
+  fn day_file(&self, device_id: &str, day: NaiveDate) -> PathBuf {
+    self
+      .device_dir(device_id)
+      .join(format!("{}.ndjson", day.format("%Y-%m-%d")))
+  }
+
+  pub async fn append(&self, sample: &HatSample) -> io::Result<()> {
+    self
+      .append_day(
+        &sample.device_id,
+        day_of(sample.timestamp),
+        slice::from_ref(sample),
+      )
+      .await
+  }
+
+  /// Appends samples of `device_id` that all fall on `day` in one write.
+  pub async fn append_day(
+    &self,
+    device_id: &str,
+    day: NaiveDate,
+    samples: &[HatSample],
+  ) -> io::Result<()> {
+    let path = self.day_file(device_id, day);
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).await?;
+    }
+    let mut lines = Vec::new();
+    for sample in samples {
+      serde_json::to_writer(&mut lines, sample)?;
+      lines.push(b'\n');
+    }
+    let mut file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .await?;
+    file.write_all(&lines).await
+  }
+
+  /// Timestamps already stored for `device_id` on `day`.
+  pub async fn timestamps(&self, device_id: &str, day: NaiveDate) -> io::Result<HashSet<u64>> {
+    match read_day_file(&self.day_file(device_id, day), 0, u64::MAX).await {
+      Ok(samples) => Ok(samples.iter().map(|sample| sample.timestamp).collect()),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Day files of `device_id`, oldest first.
+  pub async fn day_files(&self, device_id: &str) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+    day_files_in(&self.device_dir(device_id)).await
+  }
+
+  /// Timestamp of the latest stored sample of `device_id`.
+  pub async fn latest_timestamp(&self, device_id: &str) -> io::Result<Option<u64>> {
+    let Some((_, path)) = self.day_files(device_id).await?.pop() else {
+      return Ok(None);
+    };
+    let samples = read_day_file(&path, 0, u64::MAX).await?;
+    Ok(samples.last().map(|sample| sample.timestamp))
+  }
+
+  /// Fails unless a file can be created and written under the root. Every
+  /// check writes a file of its own, so concurrent checks don't interfere.
+  pub async fn check_writable(&self) -> io::Result<()> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    fs::create_dir_all(&self.root).await?;
+    let probe = self.root.join(format!(
+      ".probe-{}-{}",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&probe, b"ok").await?;
+    fs::remove_file(probe).await
+  }
+
+  /// Rewrites the day files holding samples of older schema versions in the
+  /// current one. Lines that can't be read as samples, or carry no reading,
+  /// are moved to a `<day>.ndjson.unreadable` file next to their day file
+  /// rather than dropped. Readings outside the range of their `catalog`
+  /// metric are kept as invalid, as on ingest. Both are written to temporary
+  /// files first, so an interrupted run can be repeated, and a rerun finishes
+  /// moving the unreadable lines. Meant to run while the server is stopped.
+  pub async fn migrate(&self, catalog: &MetricCatalog, dry_run: bool) -> io::Result<Migration> {
+    let mut migration = Migration::default();
+    for device_id in self.devices().await? {
+      for (_, path) in day_files_in(&self.root.join(&device_id)).await? {
+        let content = fs::read_to_string(&path).await?;
+        let (mut migrated, mut unreadable) = (Vec::new(), Vec::new());
+        let mut outdated = false;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+          match serde_json::from_str::<HatSample>(line) {
+            Ok(mut sample) if sample.has_readings() => {
+              catalog.assess(&mut sample);
+              let current = serde_json::to_string(&sample)?;
+              outdated |= current != line;
+              migrated.push(current);
+            }
+            _ => unreadable.push(line),
+          }
+        }
+        let rejected = path.with_extension("ndjson.unreadable");
+        let rejected_temporary = path.with_extension("ndjson.unreadable.tmp");
+        // A day file without unreadable lines next to a temporary one means
+        // the last run stopped between the two renames, the temporary file
+        // already holds every unreadable line.
+        if unreadable.is_empty() && !dry_run && fs::try_exists(&rejected_temporary).await? {
+          fs::rename(&rejected_temporary, &rejected).await?;
+        }
+        if !outdated && unreadable.is_empty() {
+          continue;
+        }
+        migration.files += 1;
+        migration.samples += migrated.len();
+        migration.unreadable += unreadable.len();
+        if dry_run {
+          continue;
+        }
+        if !unreadable.is_empty() {
+          let mut lines = match fs::read_to_string(&rejected).await {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+          };
+          lines.push_str(&lines_of(&unreadable));
+          fs::write(&rejected_temporary, lines).await?;
+        }
+        let temporary = path.with_extension("ndjson.tmp");
+        fs::write(&temporary, lines_of(&migrated)).await?;
+        fs::rename(temporary, &path).await?;
+        if !unreadable.is_empty() {
+          fs::rename(rejected_temporary, rejected).await?;
+        }
+      }
+    }
+    Ok(migration)
+  }
+
+  /// Device ids that have stored history.
+  pub async fn devices(&self) -> io::Result<Vec<String>> {
+    let mut devices = Vec::new();
+    let mut entries = match fs::read_dir(&self.root).await {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(devices),
+      Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+      if entry.file_type().await?.is_dir() {
+        devices.push(entry.file_name().to_string_lossy().into_owned());
+      }
+    }
+    devices.sort();
+    Ok(devices)
+  }
+
+  /// Samples of `device_id` with `from <= timestamp <= to`, oldest first.
+  pub async fn read_range(
+    &self,
+    device_id: &str,
+    from: u64,
+    to: u64,
+  ) -> io::Result<Vec<HatSample>> {
+    let mut samples = Vec::new();
+    for path in self.day_files_between(device_id, from, to).await? {
+      samples.extend(read_day_file(&path, from, to).await?);
+    }
+    Ok(samples)
+  }
+
+  /// Day files of `device_id` that may hold samples between `from` and
+  /// `to`, oldest first.
+  pub async fn day_files_between(
+    &self,
+    device_id: &str,
+    from: u64,
+    to: u64,
+  ) -> io::Result<Vec<PathBuf>> {
+    let (first, last) = (day_of(from), day_of(to));
+    Ok(
+      self
+        .day_files(device_id)
+        .await?
+        .into_iter()
+        .filter(|(day, _)| (first..=last).contains(day))
+        .map(|(_, path)| path)
+        .collect(),
+    )
+  }
+
+  /// Deletes day files older than `retention_days` before `now`. `0` keeps
+  /// everything.
+  pub async fn prune(&self, retention_days: u32, now: DateTime<Utc>) -> io::Result<usize> {
+    if retention_days == 0 {
+      return Ok(0);
+    }
+    let cutoff = now.date_naive() - chrono::Days::new(u64::from(retention_days));
+    let mut removed = 0;
+    for device_id in self.devices().await? {
+      for (day, path) in day_files_in(&self.root.join(&device_id)).await? {
+        if day < cutoff {
+          fs::remove_file(path).await?;
+          removed += 1;
+        }
+      }
+    }
+    Ok(removed)
+  }
+}
+
+pub(crate) fn day_of(timestamp: u64) -> NaiveDate {
+  DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+    .unwrap_or_default()
+    .date_naive()
+}
+
+/// Samples of one day file with `from <= timestamp <= to`, oldest first.
+pub(crate) async fn read_day_file(path: &Path, from: u64, to: u64) -> io::Result<Vec<HatSample>> {
+  let mut samples = Vec::new();
+  let mut lines = BufReader::new(fs::File::open(path).await?).lines();
+  while let Some(line) = lines.next_line().await? {
+    match serde_json::from_str::<HatSample>(&line) {
+      Ok(sample) if (from..=to).contains(&sample.timestamp) => samples.push(sample),
+      Ok(_) => {}
+      Err(e) => warn!(target = "storage", case = "read", "{:?}", e),
+    }
+  }
+  samples.sort_by_key(|sample| sample.timestamp);
+  Ok(samples)
+}
+
+fn lines_of(lines: &[impl AsRef<str>]) -> String {
+  lines
+    .iter()
+    .map(|line| format!("{}\n", line.as_ref()))
+    .collect()
+}
+
+async fn day_files_in(dir: &Path) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+  let mut files = Vec::new();
+  let mut entries = match fs::read_dir(dir).await {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(files),
+    Err(e) => return Err(e),
+  };
+  while let Some(entry) = entries.next_entry().await? {
+    let path = entry.path();
+    let day = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| name.strip_suffix(".ndjson"))
+      .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+    if let Some(day) = day {
+      files.push((day, path));
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+/// Persists every sample queued on `samples`. On shutdown the samples
+/// still queued are stored too.
+pub(crate) async fn run(samples: SampleQueue, store: SampleStore, shutdown: CancellationToken) {
+  let mut samples = samples.lock().await;
+  loop {
+    let sample = tokio::select! {
+      sample = samples.recv() => match sample {
+        Some(sample) => sample,
+        None => break,
+      },
+      _ = shutdown.cancelled() => {
+        while let Ok(sample) = samples.try_recv() {
+          append(&store, &sample).await;
+        }
+        break;
+      }
+    };
+    append(&store, &sample).await;
+  }
+}
+
+async fn append(store: &SampleStore, sample: &HatSample) {
+  if let Err(e) = store.append(sample).await {
+    warn!(target = "storage", case = "append", "{:?}", e);
+  }
+}
+
+/// Applies the retention setting hourly and whenever it changes.
+pub(crate) async fn run_retention(store: SampleStore, settings: SettingsStore) {
+  let mut settings = settings.subscribe();
+  let mut interval = time::interval(RETENTION_INTERVAL);
+  loop {
+    tokio::select! {
+      _ = interval.tick() => {},
+      changed = settings.changed() => {
+        if changed.is_err() {
+          break;
+        }
+      }
+    }
+    let retention_days = settings.borrow_and_update().retention_days;
+    match store.prune(retention_days, Utc::now()).await {
+      Ok(0) => debug!(target = "storage", case = "retention", "nothing to prune"),
+      Ok(removed) => info!(
+        target = "storage",
+        case = "retention",
+        "removed {removed} day files"
+      ),
+      Err(e) => warn!(target = "storage", case = "retention", "{:?}", e),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct HistoryQuery {
+  device: Option<String>,
+  from: Option<u64>,
+  to: Option<u64>,
+}
+
+/// `GET /api/samples?device=&from=&to=`: stored samples of a device, the last
+/// 24 hours by default. Devices the caller isn't granted are `403`.
+pub(crate) async fn history(
+  State((store, settings)): State<(SampleStore, SettingsStore)>,
+  Extension(identity): Extension<Identity>,
+  Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<HatSample>>, StatusCode> {
+  let to = query.to.unwrap_or_else(|| Utc::now().timestamp() as u64);
+  let from = query.from.unwrap_or(to.saturating_sub(24 * 60 * 60));
+  if from > to {
+    return Err(StatusCode::BAD_REQUEST);
+  }
+  let device_id = query.device.as_deref().unwrap_or(DEFAULT_DEVICE_ID);
+  if !settings.with(|settings| identity.can_see(device_id, settings)) {
+    return Err(StatusCode::FORBIDDEN);
+  }
+  store
+    .read_range(device_id, from, to)
+    .await
+    .map(Json)
+    .map_err(|e| {
+      warn!(target = "storage", case = "history", "{:?}", e);
+      StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const V1: &str = r#"{"timestamp":1760000000,"temperature":29.5,"humidity":52.25,"r_zero":41.2,"corrected_r_zero":40.8,"resistance":12.75,"ppm":412.0,"corrected_ppm":418.5}"#;
+
+  #[tokio::test]
+  async fn outdated_and_unreadable_lines_are_migrated() {
+    let dir = std::env::temp_dir().join(format!("hat-monitor-migrate-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir).await;
+    let store = SampleStore::new(&dir);
+    let current = serde_json::to_string(&serde_json::from_str::<HatSample>(V1).unwrap()).unwrap();
+    let v1 = store.day_file("hat-1", day_of(1_760_000_000));
+    let mixed = store.day_file("hat-2", day_of(1_760_000_000));
+    let up_to_date = store.day_file("hat-3", day_of(1_760_000_000));
+    let out_of_range = store.day_file("hat-4", day_of(1_760_000_000));
+    for path in [&v1, &mixed, &up_to_date, &out_of_range] {
+      fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+    fs::write(&v1, lines_of(&[V1, V1])).await.unwrap();
+    fs::write(&mixed, lines_of(&[&current, "not json", "{}", V1]))
+      .await
+      .unwrap();
+    fs::write(&up_to_date, lines_of(&[&current])).await.unwrap();
+    fs::write(
+      &out_of_range,
+      lines_of(&[r#"{"timestamp":1760000000,"temperature":20.0,"humidity":-999.0}"#]),
+    )
+    .await
+    .unwrap();
+
+    let dry_run = store
+      .migrate(&MetricCatalog::builtin(), true)
+      .await
+      .unwrap();
+    assert_eq!(
+      (dry_run.files, dry_run.samples, dry_run.unreadable),
+      (3, 5, 2)
+    );
+    assert_eq!(fs::read_to_string(&v1).await.unwrap(), lines_of(&[V1, V1]));
+
+    let migration = store
+      .migrate(&MetricCatalog::builtin(), false)
+      .await
+      .unwrap();
+    assert_eq!(
+      (migration.files, migration.samples, migration.unreadable),
+      (3, 5, 2)
+    );
+    assert_eq!(
+      fs::read_to_string(&v1).await.unwrap(),
+      lines_of(&[&current, &current])
+    );
+    assert_eq!(
+      fs::read_to_string(&mixed).await.unwrap(),
+      lines_of(&[&current, &current])
+    );
+    assert_eq!(
+      fs::read_to_string(mixed.with_extension("ndjson.unreadable"))
+        .await
+        .unwrap(),
+      lines_of(&["not json", "{}"])
+    );
+    assert!(!mixed.with_extension("ndjson.unreadable.tmp").exists());
+    assert_eq!(
+      fs::read_to_string(&up_to_date).await.unwrap(),
+      lines_of(&[&current])
+    );
+
+    let sample = &read_day_file(&out_of_range, 0, u64::MAX).await.unwrap()[0];
+    assert_eq!((sample.temperature, sample.humidity), (Some(20.0), None));
+
+    let again = store
+      .migrate(&MetricCatalog::builtin(), false)
+      .await
+      .unwrap();
+    assert_eq!((again.files, again.unreadable), (0, 0));
+    fs::remove_dir_all(&dir).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn a_rerun_recovers_unreadable_lines_left_in_a_temporary_file() {
+    let dir = std::env::temp_dir().join(format!(
+      "hat-monitor-migrate-interrupted-{}",
+      std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir).await;
+    let store = SampleStore::new(&dir);
+    let current = serde_json::to_string(&serde_json::from_str::<HatSample>(V1).unwrap()).unwrap();
+    let path = store.day_file("hat-1", day_of(1_760_000_000));
+    fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+    // As left by a run stopped after renaming the day file.
+    fs::write(&path, lines_of(&[&current])).await.unwrap();
+    fs::write(
+      path.with_extension("ndjson.unreadable.tmp"),
+      lines_of(&["not json"]),
+    )
+    .await
+    .unwrap();
+
+    let dry_run = store
+      .migrate(&MetricCatalog::builtin(), true)
+      .await
+      .unwrap();
+    assert_eq!(dry_run.files, 0);
+    assert!(path.with_extension("ndjson.unreadable.tmp").exists());
+
+    store
+      .migrate(&MetricCatalog::builtin(), false)
+      .await
+      .unwrap();
+    assert_eq!(
+      fs::read_to_string(path.with_extension("ndjson.unreadable"))
+        .await
+        .unwrap(),
+      lines_of(&["not json"])
+    );
+    assert!(!path.with_extension("ndjson.unreadable.tmp").exists());
+    assert_eq!(
+      fs::read_to_string(&path).await.unwrap(),
+      lines_of(&[&current])
+    );
+    fs::remove_dir_all(&dir).await.unwrap();
+  }
+}
@@ -0,0 +1,335 @@
+//! Restarts background workers that fail and stops them on shutdown.
+
+use std::{
+  collections::BTreeMap,
+  future::Future,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use app::auth::Identity;
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::Utc;
+use serde::Serialize;
+use tokio::{
+  signal,
+  task::{JoinHandle, JoinSet},
+  time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use types::accounts::Role;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A worker that ran this long before failing starts over from the initial
+/// backoff.
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WorkerState {
+  Running,
+  /// Failed and waiting to be restarted.
+  BackingOff,
+  Stopped,
+}
+
+/// What the supervisor knows about a worker, served by `/api/workers`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WorkerStatus {
+  pub name: &'static str,
+  pub state: WorkerState,
+  /// Unix seconds of the last state change.
+  pub since: u64,
+  pub restarts: u32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub last_error: Option<String>,
+}
+
+/// Runs workers until shutdown, restarting the ones that panic or return
+/// early with exponential backoff.
+#[derive(Debug, Clone)]
+pub(crate) struct Supervisor {
+  shutdown: CancellationToken,
+  statuses: Arc<Mutex<BTreeMap<&'static str, WorkerStatus>>>,
+  tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Supervisor {
+  pub fn new(shutdown: CancellationToken) -> Self {
+    Self {
+      shutdown,
+      statuses: Arc::default(),
+      tasks: Arc::default(),
+    }
+  }
+
+  pub fn statuses(&self) -> Vec<WorkerStatus> {
+    self.lock().values().cloned().collect()
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<&'static str, WorkerStatus>> {
+    self.statuses.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn set(&self, name: &'static str, state: WorkerState, error: Option<String>) {
+    let mut statuses = self.lock();
+    let status = statuses.entry(name).or_insert(WorkerStatus {
+      name,
+      state,
+      since: 0,
+      restarts: 0,
+      last_error: None,
+    });
+    if state == WorkerState::Running && status.since != 0 {
+      status.restarts += 1;
+    }
+    status.state = state;
+    status.since = Utc::now().timestamp() as u64;
+    if error.is_some() {
+      status.last_error = error;
+    }
+  }
+
+  /// Runs the future made by `worker` under supervision. Workers get the
+  /// shutdown token and must return soon after it fires, finishing what
+  /// they were doing first.
+  pub fn spawn<F, Fut>(&self, name: &'static str, worker: F)
+  where
+    F: Fn(CancellationToken) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+  {
+    let supervisor = self.clone();
+    let handle = tokio::spawn(async move {
+      let mut backoff = INITIAL_BACKOFF;
+      loop {
+        supervisor.set(name, WorkerState::Running, None);
+        let started = Instant::now();
+        let error = match tokio::spawn(worker(supervisor.shutdown.clone())).await {
+          Ok(()) => "exited".to_string(),
+          Err(e) => panic_message(e),
+        };
+        if supervisor.shutdown.is_cancelled() {
+          break;
+        }
+        if started.elapsed() >= HEALTHY_AFTER {
+          backoff = INITIAL_BACKOFF;
+        }
+        warn!(
+          target = "supervisor",
+          worker = name,
+          "{error}, restarting in {backoff:?}"
+        );
+        supervisor.set(name, WorkerState::BackingOff, Some(error));
+        tokio::select! {
+          _ = time::sleep(backoff) => {}
+          _ = supervisor.shutdown.cancelled() => break,
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+      }
+      supervisor.set(name, WorkerState::Stopped, None);
+    });
+    self
+      .tasks
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .push(handle);
+  }
+
+  /// Waits up to `grace` for every worker to stop after shutdown.
+  pub async fn join(&self, grace: Duration) {
+    let handles = std::mem::take(&mut *self.tasks.lock().unwrap_or_else(|e| e.into_inner()));
+    let mut set = JoinSet::new();
+    for handle in handles {
+      set.spawn(async move {
+        let _ = handle.await;
+      });
+    }
+    if time::timeout(grace, set.join_all()).await.is_err() {
+      let running = self
+        .statuses()
+        .into_iter()
+        .filter(|status| status.state != WorkerState::Stopped)
+        .map(|status| status.name)
+        .collect::<Vec<_>>();
+      warn!(
+        target = "supervisor",
+        "gave up waiting for {}",
+        running.join(", ")
+      );
+    }
+  }
+}
+
+fn panic_message(error: tokio::task::JoinError) -> String {
+  match error.try_into_panic() {
+    Ok(payload) => payload
+      .downcast_ref::<&str>()
+      .map(|message| message.to_string())
+      .or_else(|| payload.downcast_ref::<String>().cloned())
+      .map(|message| format!("panicked: {message}"))
+      .unwrap_or_else(|| "panicked".to_string()),
+    Err(error) => error.to_string(),
+  }
+}
+
+/// Cancels `shutdown` on SIGINT or SIGTERM.
+pub(crate) async fn watch_signals(shutdown: CancellationToken) {
+  let interrupt = async {
+    if let Err(e) = signal::ctrl_c().await {
+      warn!(target = "supervisor", case = "sigint", "{:?}", e);
+      std::future::pending::<()>().await;
+    }
+  };
+  #[cfg(unix)]
+  let terminate = async {
+    match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+      Ok(mut terminate) => {
+        terminate.recv().await;
+      }
+      Err(e) => {
+        warn!(target = "supervisor", case = "sigterm", "{:?}", e);
+        std::future::pending::<()>().await;
+      }
+    }
+  };
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+  tokio::select! {
+    _ = interrupt => info!(target = "supervisor", "interrupted, shutting down"),
+    _ = terminate => info!(target = "supervisor", "terminated, shutting down"),
+  }
+  shutdown.cancel();
+}
+
+/// `GET /api/workers`: the status of every supervised worker. Admins only.
+pub(crate) async fn list(
+  State(supervisor): State<Supervisor>,
+  Extension(identity): Extension<Identity>,
+) -> Result<Json<Vec<WorkerStatus>>, StatusCode> {
+  if !identity.has_role(Role::Admin) {
+    return Err(StatusCode::FORBIDDEN);
+  }
+  Ok(Json(supervisor.statuses()))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicU32, Ordering};
+
+  use super::*;
+
+  /// Seconds after `origin` at which each run of a worker started.
+  type Starts = Arc<Mutex<Vec<u64>>>;
+
+  /// Records a start, returning which run of the worker it is.
+  fn record(origin: Instant, starts: &Starts) -> u32 {
+    let mut starts = starts.lock().unwrap();
+    starts.push(origin.elapsed().as_secs());
+    starts.len() as u32 - 1
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn failing_workers_restart_with_doubling_backoff() {
+    let shutdown = CancellationToken::new();
+    let supervisor = Supervisor::new(shutdown.clone());
+    let origin = Instant::now();
+    let runs = Starts::default();
+    supervisor.spawn("flaky", {
+      let runs = runs.clone();
+      move |_| {
+        let run = record(origin, &runs);
+        async move {
+          if run.is_multiple_of(2) {
+            panic!("boom");
+          }
+        }
+      }
+    });
+
+    time::sleep(Duration::from_secs(200)).await;
+    assert_eq!(*runs.lock().unwrap(), [0, 1, 3, 7, 15, 31, 63, 123, 183]);
+    let status = &supervisor.statuses()[0];
+    assert_eq!(status.restarts, 8);
+    assert_eq!(status.last_error.as_deref(), Some("panicked: boom"));
+
+    shutdown.cancel();
+    supervisor.join(Duration::from_secs(1)).await;
+    assert_eq!(supervisor.statuses()[0].state, WorkerState::Stopped);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn workers_that_ran_long_enough_restart_from_the_initial_backoff() {
+    let shutdown = CancellationToken::new();
+    let supervisor = Supervisor::new(shutdown.clone());
+    let origin = Instant::now();
+    let runs = Starts::default();
+    supervisor.spawn("steady", {
+      let runs = runs.clone();
+      move |_| {
+        let run = record(origin, &runs);
+        async move {
+          if run == 2 {
+            time::sleep(HEALTHY_AFTER).await;
+          }
+        }
+      }
+    });
+
+    time::sleep(Duration::from_secs(71)).await;
+    // Fails at 0 and 1, runs from 3 to 63, then backs off 1s, 2s, 4s again.
+    assert_eq!(*runs.lock().unwrap(), [0, 1, 3, 64, 66, 70]);
+    shutdown.cancel();
+    supervisor.join(Duration::from_secs(1)).await;
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn cancelling_stops_running_and_backing_off_workers() {
+    let shutdown = CancellationToken::new();
+    let supervisor = Supervisor::new(shutdown.clone());
+    let finished = Arc::new(AtomicU32::new(0));
+    supervisor.spawn("running", {
+      let finished = finished.clone();
+      move |shutdown| {
+        let finished = finished.clone();
+        async move {
+          shutdown.cancelled().await;
+          finished.fetch_add(1, Ordering::Relaxed);
+        }
+      }
+    });
+    supervisor.spawn("failing", |_| async {});
+
+    time::sleep(Duration::from_secs(10)).await;
+    let states = supervisor
+      .statuses()
+      .into_iter()
+      .map(|status| (status.name, status.state))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      states,
+      [
+        ("failing", WorkerState::BackingOff),
+        ("running", WorkerState::Running)
+      ]
+    );
+
+    let cancelled = Instant::now();
+    shutdown.cancel();
+    supervisor.join(Duration::from_secs(30)).await;
+    assert!(cancelled.elapsed() < Duration::from_secs(1));
+    assert_eq!(finished.load(Ordering::Relaxed), 1);
+    for status in supervisor.statuses() {
+      assert_eq!(status.state, WorkerState::Stopped, "{}", status.name);
+    }
+    assert_eq!(
+      supervisor
+        .statuses()
+        .iter()
+        .map(|status| status.restarts)
+        .collect::<Vec<_>>(),
+      [3, 0]
+    );
+  }
+}
@@ -0,0 +1,125 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+/// Shortest password accepted for a dashboard account.
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// What an account may do, each role includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+  /// Sees the live data and history of granted devices.
+  #[default]
+  Viewer,
+  /// Also handles day-to-day operation of granted devices.
+  Operator,
+  /// Sees every device and edits settings, thresholds, calibration and
+  /// accounts.
+  Admin,
+}
+
+impl Role {
+  pub const ALL: [Role; 3] = [Role::Viewer, Role::Operator, Role::Admin];
+}
+
+impl fmt::Display for Role {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Role::Viewer => "viewer",
+      Role::Operator => "operator",
+      Role::Admin => "admin",
+    })
+  }
+}
+
+impl FromStr for Role {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "viewer" => Ok(Role::Viewer),
+      "operator" => Ok(Role::Operator),
+      "admin" => Ok(Role::Admin),
+      _ => Err(format!("unknown role: {s}")),
+    }
+  }
+}
+
+/// Devices a viewer or operator may see, either by id or by the location set
+/// on the settings page. Admins see every device.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Grants {
+  /// Every device, including ones added later.
+  #[serde(default)]
+  pub all: bool,
+  #[serde(default)]
+  pub devices: BTreeSet<String>,
+  #[serde(default)]
+  pub locations: BTreeSet<String>,
+}
+
+impl Grants {
+  pub fn everything() -> Self {
+    Self {
+      all: true,
+      ..Self::default()
+    }
+  }
+
+  /// Whether `device_id` is granted, looking its location up in `settings`.
+  pub fn allows(&self, device_id: &str, settings: &Settings) -> bool {
+    self.all
+      || self.devices.contains(device_id)
+      || settings
+        .devices
+        .get(device_id)
+        .is_some_and(|info| self.locations.contains(info.location.trim()))
+  }
+}
+
+/// A dashboard account as listed on the settings page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserSummary {
+  pub username: String,
+  pub role: Role,
+  pub grants: Grants,
+}
+
+/// An API token without its secret, which is only shown once on creation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTokenSummary {
+  pub id: String,
+  pub name: String,
+  /// Unix seconds.
+  pub created_at: u64,
+}
+
+pub fn validate_username(username: &str) -> Result<(), &'static str> {
+  if username.is_empty() || username.len() > 32 {
+    return Err("must be between 1 and 32 characters");
+  }
+  if !username
+    .chars()
+    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+  {
+    return Err("may only contain letters, digits, '-', '_' and '.'");
+  }
+  Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), &'static str> {
+  if password.chars().count() < MIN_PASSWORD_LEN {
+    return Err("must be at least 8 characters");
+  }
+  Ok(())
+}
+
+pub fn validate_token_name(name: &str) -> Result<(), &'static str> {
+  if name.trim().is_empty() || name.chars().count() > 64 {
+    return Err("must be between 1 and 64 characters");
+  }
+  Ok(())
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+use crate::thresholds::Band;
+
+/// A reading currently outside the normal band.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveAlert {
+  pub device_id: String,
+  /// A [`crate::thresholds::Metric`] name, or the key of a catalog metric
+  /// with a threshold.
+  pub metric: String,
+  pub band: Band,
+  /// Latest reading, in the canonical unit of the metric.
+  pub value: f32,
+  /// Unix seconds of the sample that raised the alert.
+  pub since: u64,
+  /// Account that silenced the alert. Notifications stay quiet until the
+  /// alert escalates or clears.
+  #[serde(default)]
+  pub acknowledged_by: Option<String>,
+}
+
+/// Body of `POST /api/alerts/ack`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Acknowledgement {
+  pub device_id: String,
+  pub metric: String,
+}
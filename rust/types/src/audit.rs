@@ -0,0 +1,152 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Kinds of actions recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+  Login,
+  LoginFailed,
+  Logout,
+  DevicesChanged,
+  ThresholdsChanged,
+  NotificationsChanged,
+  RetentionChanged,
+  UnitsChanged,
+  UserAdded,
+  UserRemoved,
+  UserAccessChanged,
+  PasswordChanged,
+  TokenCreated,
+  TokenRevoked,
+  CalibrationRun,
+  AlertAcknowledged,
+  SamplesImported,
+  CommandSent,
+  FirmwareUploaded,
+  FirmwareRemoved,
+  ClaimCodeCreated,
+  DeviceClaimed,
+  DeviceRevoked,
+}
+
+impl AuditAction {
+  pub const ALL: [AuditAction; 23] = [
+    AuditAction::Login,
+    AuditAction::LoginFailed,
+    AuditAction::Logout,
+    AuditAction::DevicesChanged,
+    AuditAction::ThresholdsChanged,
+    AuditAction::NotificationsChanged,
+    AuditAction::RetentionChanged,
+    AuditAction::UnitsChanged,
+    AuditAction::UserAdded,
+    AuditAction::UserRemoved,
+    AuditAction::UserAccessChanged,
+    AuditAction::PasswordChanged,
+    AuditAction::TokenCreated,
+    AuditAction::TokenRevoked,
+    AuditAction::CalibrationRun,
+    AuditAction::AlertAcknowledged,
+    AuditAction::SamplesImported,
+    AuditAction::CommandSent,
+    AuditAction::FirmwareUploaded,
+    AuditAction::FirmwareRemoved,
+    AuditAction::ClaimCodeCreated,
+    AuditAction::DeviceClaimed,
+    AuditAction::DeviceRevoked,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      AuditAction::Login => "login",
+      AuditAction::LoginFailed => "login_failed",
+      AuditAction::Logout => "logout",
+      AuditAction::DevicesChanged => "devices_changed",
+      AuditAction::ThresholdsChanged => "thresholds_changed",
+      AuditAction::NotificationsChanged => "notifications_changed",
+      AuditAction::RetentionChanged => "retention_changed",
+      AuditAction::UnitsChanged => "units_changed",
+      AuditAction::UserAdded => "user_added",
+      AuditAction::UserRemoved => "user_removed",
+      AuditAction::UserAccessChanged => "user_access_changed",
+      AuditAction::PasswordChanged => "password_changed",
+      AuditAction::TokenCreated => "token_created",
+      AuditAction::TokenRevoked => "token_revoked",
+      AuditAction::CalibrationRun => "calibration_run",
+      AuditAction::AlertAcknowledged => "alert_acknowledged",
+      AuditAction::SamplesImported => "samples_imported",
+      AuditAction::CommandSent => "command_sent",
+      AuditAction::FirmwareUploaded => "firmware_uploaded",
+      AuditAction::FirmwareRemoved => "firmware_removed",
+      AuditAction::ClaimCodeCreated => "claim_code_created",
+      AuditAction::DeviceClaimed => "device_claimed",
+      AuditAction::DeviceRevoked => "device_revoked",
+    }
+  }
+}
+
+impl fmt::Display for AuditAction {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for AuditAction {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    AuditAction::ALL
+      .into_iter()
+      .find(|action| action.name() == s)
+      .ok_or_else(|| format!("unknown audit action: {s}"))
+  }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+  /// Unix seconds.
+  pub timestamp: u64,
+  /// Account that acted, or the username tried for failed logins.
+  pub actor: String,
+  pub action: AuditAction,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub device_id: Option<String>,
+  /// Human readable summary of what changed.
+  #[serde(default)]
+  pub detail: String,
+}
+
+/// Criteria of the audit log viewer and export, unset fields match
+/// everything. `from` and `to` are inclusive Unix seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+  #[serde(default)]
+  pub actor: Option<String>,
+  #[serde(default)]
+  pub action: Option<AuditAction>,
+  #[serde(default)]
+  pub device: Option<String>,
+  #[serde(default)]
+  pub from: Option<u64>,
+  #[serde(default)]
+  pub to: Option<u64>,
+}
+
+impl AuditFilter {
+  pub fn matches(&self, entry: &AuditEntry) -> bool {
+    self
+      .actor
+      .as_ref()
+      .is_none_or(|actor| &entry.actor == actor)
+      && self.action.is_none_or(|action| entry.action == action)
+      && self
+        .device
+        .as_ref()
+        .is_none_or(|device| entry.device_id.as_ref() == Some(device))
+      && self.from.is_none_or(|from| entry.timestamp >= from)
+      && self.to.is_none_or(|to| entry.timestamp <= to)
+  }
+}
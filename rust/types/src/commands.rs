@@ -0,0 +1,177 @@
+//! Downlink commands the server sends to hats on
+//! `<topic>/<device id>/cmd`, acknowledged on `<topic>/<device id>/cmd/ack`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  accounts::Role,
+  firmware::{MAX_IMAGE_BYTES, validate_version},
+};
+
+/// Commands without an acknowledgement after this long are
+/// [`CommandState::TimedOut`].
+pub const ACK_TIMEOUT_SECS: u64 = 60;
+pub const MAX_INTERVAL_SECS: u32 = 60 * 60;
+pub const MAX_CALIBRATION_MINUTES: u32 = 24 * 60;
+pub const MAX_IDENTIFY_SECS: u32 = 5 * 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+  /// Publishes a sample every `seconds`.
+  SetInterval {
+    seconds: u32,
+  },
+  /// Averages R0 over `minutes` of clean air and keeps it.
+  StartCalibration {
+    minutes: u32,
+  },
+  /// Uses `r_zero` as the clean air resistance from now on.
+  SetRZero {
+    r_zero: f32,
+  },
+  Reboot,
+  /// Blinks the LED for `seconds` so the hat can be found.
+  Identify {
+    seconds: u32,
+  },
+  /// Downloads the image at `url`, checks its `size` and `sha256`, flashes
+  /// it and reboots into it.
+  UpdateFirmware {
+    version: String,
+    url: String,
+    sha256: String,
+    size: u64,
+  },
+}
+
+impl Command {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Command::SetInterval { .. } => "set_interval",
+      Command::StartCalibration { .. } => "start_calibration",
+      Command::SetRZero { .. } => "set_r_zero",
+      Command::Reboot => "reboot",
+      Command::Identify { .. } => "identify",
+      Command::UpdateFirmware { .. } => "update_firmware",
+    }
+  }
+
+  /// Whether the command changes the gas sensor calibration.
+  pub fn is_calibration(&self) -> bool {
+    matches!(
+      self,
+      Command::StartCalibration { .. } | Command::SetRZero { .. }
+    )
+  }
+
+  /// Who may send the command. Only admins change the calibration, which
+  /// every later CO2 reading depends on.
+  pub fn required_role(&self) -> Role {
+    if self.is_calibration() {
+      Role::Admin
+    } else {
+      Role::Operator
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    match self {
+      Command::SetInterval { seconds } if !(1..=MAX_INTERVAL_SECS).contains(seconds) => {
+        Err(format!("interval must be 1 to {MAX_INTERVAL_SECS} seconds"))
+      }
+      Command::StartCalibration { minutes } if !(1..=MAX_CALIBRATION_MINUTES).contains(minutes) => {
+        Err(format!(
+          "calibration must last 1 to {MAX_CALIBRATION_MINUTES} minutes"
+        ))
+      }
+      Command::SetRZero { r_zero } if !r_zero.is_finite() || *r_zero <= 0.0 => {
+        Err("R0 must be a positive number".to_string())
+      }
+      Command::Identify { seconds } if !(1..=MAX_IDENTIFY_SECS).contains(seconds) => Err(format!(
+        "identify must last 1 to {MAX_IDENTIFY_SECS} seconds"
+      )),
+      Command::UpdateFirmware {
+        version,
+        url,
+        sha256,
+        size,
+      } => {
+        validate_version(version).map_err(|e| format!("version {e}"))?;
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+          return Err("firmware URL must be http or https".to_string());
+        }
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+          return Err("checksum must be a hex SHA-256".to_string());
+        }
+        if *size == 0 || *size > MAX_IMAGE_BYTES {
+          return Err(format!("image must be 1 to {MAX_IMAGE_BYTES} bytes"));
+        }
+        Ok(())
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Payload of a command topic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRequest {
+  /// Echoed back in the [`CommandAck`].
+  pub id: String,
+  /// Unix seconds.
+  pub issued_at: u64,
+  #[serde(flatten)]
+  pub command: Command,
+}
+
+/// Payload a hat publishes on its ack topic once it handled a command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandAck {
+  pub id: String,
+  /// Whether the hat carried the command out.
+  #[serde(default = "succeeded")]
+  pub ok: bool,
+  #[serde(default)]
+  pub message: Option<String>,
+}
+
+fn succeeded() -> bool {
+  true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandState {
+  Pending,
+  Acknowledged,
+  Failed,
+  TimedOut,
+}
+
+/// A sent command and what became of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRecord {
+  pub id: String,
+  pub device_id: String,
+  pub command: Command,
+  pub issued_by: String,
+  pub issued_at: u64,
+  pub state: CommandState,
+  #[serde(default)]
+  pub acked_at: Option<u64>,
+  /// What the hat said in its acknowledgement.
+  #[serde(default)]
+  pub message: Option<String>,
+}
+
+impl CommandRecord {
+  /// The state at `now`, pending commands past [`ACK_TIMEOUT_SECS`] count as
+  /// timed out. A late acknowledgement still settles them.
+  pub fn state_at(&self, now: u64) -> CommandState {
+    match self.state {
+      CommandState::Pending if now > self.issued_at + ACK_TIMEOUT_SECS => CommandState::TimedOut,
+      state => state,
+    }
+  }
+}
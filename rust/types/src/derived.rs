@@ -0,0 +1,78 @@
+//! Metrics computed from a [`HatSample`] rather than measured by the hat.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HatSample, units::Units};
+
+/// Magnus formula coefficients, valid from -45 °C to 60 °C.
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct DerivedMetrics {
+  /// °C.
+  pub dew_point: f32,
+  /// g/m³ of water vapour.
+  pub absolute_humidity: f32,
+  /// Apparent temperature in °C.
+  pub heat_index: f32,
+}
+
+impl DerivedMetrics {
+  pub const NAMES: [&'static str; 3] = ["dew_point", "absolute_humidity", "heat_index"];
+
+  /// `None` unless `sample` has both a temperature and a humidity.
+  pub fn of(sample: &HatSample) -> Option<Self> {
+    let (celsius, humidity) = (sample.temperature?, sample.humidity?);
+    Some(Self {
+      dew_point: dew_point(celsius, humidity),
+      absolute_humidity: absolute_humidity(celsius, humidity),
+      heat_index: heat_index(celsius, humidity),
+    })
+  }
+
+  /// The metrics with their temperatures in the chosen `units`.
+  pub fn in_units(self, units: &Units) -> Self {
+    Self {
+      dew_point: units.temperature(self.dew_point),
+      absolute_humidity: self.absolute_humidity,
+      heat_index: units.temperature(self.heat_index),
+    }
+  }
+
+  /// Units of the metrics of [`DerivedMetrics::NAMES`] in `units`.
+  pub fn symbols(units: &Units) -> [&'static str; 3] {
+    let temperature = units.temperature.symbol();
+    [temperature, "g/m³", temperature]
+  }
+}
+
+/// Dew point in °C of air at `celsius` and `humidity` % relative humidity.
+pub fn dew_point(celsius: f32, humidity: f32) -> f32 {
+  let gamma = (humidity.max(0.01) / 100.0).ln() + MAGNUS_B * celsius / (MAGNUS_C + celsius);
+  MAGNUS_C * gamma / (MAGNUS_B - gamma)
+}
+
+/// Water vapour density in g/m³ of air at `celsius` and `humidity` %.
+pub fn absolute_humidity(celsius: f32, humidity: f32) -> f32 {
+  let saturation_hpa = 6.112 * (MAGNUS_B * celsius / (MAGNUS_C + celsius)).exp();
+  216.7 * saturation_hpa * humidity / 100.0 / (273.15 + celsius)
+}
+
+/// NOAA heat index in °C. Below 27 °C, where the regression doesn't apply,
+/// this is the temperature itself.
+pub fn heat_index(celsius: f32, humidity: f32) -> f32 {
+  if celsius < 27.0 {
+    return celsius;
+  }
+  let t = celsius * 9.0 / 5.0 + 32.0;
+  let rh = humidity;
+  let fahrenheit = -42.379 + 2.049_015_3 * t + 10.143_332 * rh
+    - 0.224_755_4 * t * rh
+    - 0.006_837_83 * t * t
+    - 0.054_817_17 * rh * rh
+    + 0.001_228_74 * t * t * rh
+    + 0.000_852_82 * t * rh * rh
+    - 0.000_001_99 * t * t * rh * rh;
+  (fahrenheit - 32.0) * 5.0 / 9.0
+}
@@ -0,0 +1,151 @@
+//! Wire encodings of samples. JSON is the default; CBOR carries the same
+//! fields in roughly half the bytes, for hats on weak WiFi and for
+//! `/ws` clients that ask for it.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EncodingError {
+  #[error("json: {0}")]
+  Json(#[from] serde_json::Error),
+  #[error("cbor: {0}")]
+  Cbor(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+  #[default]
+  Json,
+  Cbor,
+}
+
+impl Encoding {
+  pub const ALL: [Encoding; 2] = [Encoding::Json, Encoding::Cbor];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Encoding::Json => "json",
+      Encoding::Cbor => "cbor",
+    }
+  }
+
+  /// MIME type, also the MQTT 5 content type of a publish.
+  pub fn content_type(self) -> &'static str {
+    match self {
+      Encoding::Json => "application/json",
+      Encoding::Cbor => "application/cbor",
+    }
+  }
+
+  /// The encoding of a content type, ignoring parameters like `charset`.
+  pub fn from_content_type(content_type: &str) -> Option<Self> {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    Encoding::ALL
+      .into_iter()
+      .find(|encoding| essence.eq_ignore_ascii_case(encoding.content_type()))
+  }
+
+  /// WebSocket subprotocol a client offers to receive this encoding.
+  pub fn subprotocol(self) -> &'static str {
+    match self {
+      Encoding::Json => "hat-monitor.json",
+      Encoding::Cbor => "hat-monitor.cbor",
+    }
+  }
+
+  pub fn from_subprotocol(subprotocol: &str) -> Option<Self> {
+    Encoding::ALL
+      .into_iter()
+      .find(|encoding| encoding.subprotocol() == subprotocol)
+  }
+
+  /// The encoding of `payload` when nothing declares it. Samples are maps,
+  /// which start with `{` in JSON and with major type 5 in CBOR.
+  pub fn detect(payload: &[u8]) -> Self {
+    match payload.iter().find(|byte| !byte.is_ascii_whitespace()) {
+      Some(0xa0..=0xbf) => Encoding::Cbor,
+      _ => Encoding::Json,
+    }
+  }
+
+  pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, EncodingError> {
+    match self {
+      Encoding::Json => Ok(serde_json::to_vec(value)?),
+      Encoding::Cbor => {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(|e| EncodingError::Cbor(e.to_string()))?;
+        Ok(bytes)
+      }
+    }
+  }
+
+  pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, EncodingError> {
+    match self {
+      Encoding::Json => match serde_json::from_slice(bytes) {
+        Ok(value) => Ok(value),
+        // Firmware JSON libraries print failed float reads as a bare `NaN`,
+        // which isn't JSON. Quoted, they are read as invalid readings rather
+        // than losing the readings that did work.
+        Err(e) => match quote_non_finite(bytes) {
+          Some(patched) => serde_json::from_slice(&patched).map_err(|_| e.into()),
+          None => Err(e.into()),
+        },
+      },
+      Encoding::Cbor => {
+        ciborium::from_reader(bytes).map_err(|e| EncodingError::Cbor(e.to_string()))
+      }
+    }
+  }
+}
+
+/// `json` with `NaN`, `Infinity` and `-Infinity` outside strings quoted,
+/// `None` if there are none.
+fn quote_non_finite(json: &[u8]) -> Option<Vec<u8>> {
+  const TOKENS: [&[u8]; 3] = [b"-Infinity", b"Infinity", b"NaN"];
+  let mut patched = Vec::with_capacity(json.len());
+  let (mut in_string, mut escaped, mut replaced) = (false, false, false);
+  let mut i = 0;
+  while i < json.len() {
+    let byte = json[i];
+    if in_string {
+      match byte {
+        _ if escaped => escaped = false,
+        b'\\' => escaped = true,
+        b'"' => in_string = false,
+        _ => {}
+      }
+    } else if byte == b'"' {
+      in_string = true;
+    } else if let Some(token) = TOKENS.iter().find(|token| json[i..].starts_with(token)) {
+      patched.push(b'"');
+      patched.extend_from_slice(token);
+      patched.push(b'"');
+      i += token.len();
+      replaced = true;
+      continue;
+    }
+    patched.push(byte);
+    i += 1;
+  }
+  replaced.then_some(patched)
+}
+
+impl fmt::Display for Encoding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for Encoding {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Encoding::ALL
+      .into_iter()
+      .find(|encoding| encoding.name() == s)
+      .ok_or_else(|| format!("unknown encoding: {s}, expected json or cbor"))
+  }
+}
@@ -0,0 +1,151 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+use crate::units::Units;
+
+/// File formats of the sample history export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+  #[default]
+  Csv,
+  Ndjson,
+  /// Apache Parquet file, one per device.
+  Parquet,
+  /// Apache Arrow IPC stream, one record batch per device and day. Named
+  /// after its file extension like the other formats.
+  #[serde(rename = "arrows", alias = "arrow")]
+  Arrow,
+}
+
+impl ExportFormat {
+  pub const ALL: [ExportFormat; 4] = [
+    ExportFormat::Csv,
+    ExportFormat::Ndjson,
+    ExportFormat::Parquet,
+    ExportFormat::Arrow,
+  ];
+
+  pub fn extension(self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "csv",
+      ExportFormat::Ndjson => "ndjson",
+      ExportFormat::Parquet => "parquet",
+      ExportFormat::Arrow => "arrows",
+    }
+  }
+
+  pub fn content_type(self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "text/csv; charset=utf-8",
+      ExportFormat::Ndjson => "application/x-ndjson",
+      ExportFormat::Parquet => "application/vnd.apache.parquet",
+      ExportFormat::Arrow => "application/vnd.apache.arrow.stream",
+    }
+  }
+
+  /// Name shown in the download menu.
+  pub fn label(self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "CSV",
+      ExportFormat::Ndjson => "NDJSON",
+      ExportFormat::Parquet => "Parquet",
+      ExportFormat::Arrow => "Arrow IPC",
+    }
+  }
+
+  /// Columnar formats keep typed columns, including a UTC timestamp.
+  pub fn is_columnar(self) -> bool {
+    matches!(self, ExportFormat::Parquet | ExportFormat::Arrow)
+  }
+}
+
+impl fmt::Display for ExportFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.extension())
+  }
+}
+
+impl FromStr for ExportFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    ExportFormat::ALL
+      .into_iter()
+      .find(|format| format.extension() == s)
+      .ok_or_else(|| format!("unknown export format: {s}"))
+  }
+}
+
+/// Query of `GET /api/export`. `from` and `to` are inclusive Unix seconds,
+/// `to` defaults to now and `from` to a day before `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ExportRequest {
+  /// Device to export, empty for every device the account may see.
+  #[serde(default)]
+  pub device: String,
+  #[serde(default)]
+  pub from: Option<u64>,
+  #[serde(default)]
+  pub to: Option<u64>,
+  #[serde(default)]
+  pub format: ExportFormat,
+  /// Adds the columns of [`crate::derived::DerivedMetrics`].
+  #[serde(default)]
+  pub derived: bool,
+  /// Units of the readings, `<temperature>,<gas>`. The stored °C and ppm
+  /// by default.
+  #[serde(
+    default,
+    serialize_with = "serialize_units",
+    deserialize_with = "deserialize_units"
+  )]
+  pub units: Units,
+}
+
+impl ExportRequest {
+  /// Path and query of the export endpoint for this request.
+  pub fn href(&self) -> String {
+    let mut href = format!("/api/export?device={}&format={}", self.device, self.format);
+    if let Some(from) = self.from {
+      href.push_str(&format!("&from={from}"));
+    }
+    if let Some(to) = self.to {
+      href.push_str(&format!("&to={to}"));
+    }
+    if self.derived {
+      href.push_str("&derived=true");
+    }
+    if self.units != Units::default() {
+      href.push_str(&format!("&units={}", self.units));
+    }
+    href
+  }
+}
+
+fn serialize_units<S: Serializer>(units: &Units, serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.collect_str(units)
+}
+
+fn deserialize_units<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Units, D::Error> {
+  String::deserialize(deserializer)?
+    .parse()
+    .map_err(de::Error::custom)
+}
+
+/// Header of an export column of values in `unit`, e.g. `temperature (°F)`.
+pub fn column_header(name: &str, unit: &str) -> String {
+  format!("{name} ({unit})")
+}
+
+/// Name and unit of a column header, without a unit for a bare name.
+pub fn split_column_header(header: &str) -> (&str, Option<&str>) {
+  match header
+    .strip_suffix(')')
+    .and_then(|header| header.split_once(" ("))
+  {
+    Some((name, unit)) => (name, Some(unit)),
+    None => (header, None),
+  }
+}
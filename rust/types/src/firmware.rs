@@ -0,0 +1,94 @@
+//! Firmware images the server hosts for over-the-air updates. Hats report
+//! the version they run, retained, on `<topic>/<device id>/firmware` and
+//! are told to update with [`Command::UpdateFirmware`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{Command, CommandRecord};
+
+/// Larger than any ESP32 app partition.
+pub const MAX_IMAGE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// A hosted image, downloadable from `/firmware/<version>.bin`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareImage {
+  pub version: String,
+  /// Bytes.
+  pub size: u64,
+  /// Hex SHA-256 of the image, checked by the hat before it flashes.
+  pub sha256: String,
+  pub uploaded_by: String,
+  /// Unix seconds.
+  pub uploaded_at: u64,
+  #[serde(default)]
+  pub notes: String,
+}
+
+/// Payload of a firmware topic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareReport {
+  pub version: String,
+}
+
+/// The version a hat last reported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceFirmware {
+  pub device_id: String,
+  pub version: String,
+  /// Unix seconds.
+  pub reported_at: u64,
+}
+
+/// Images and what the hats run, for the settings page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmwareOverview {
+  /// Newest upload first.
+  pub images: Vec<FirmwareImage>,
+  pub devices: Vec<DeviceFirmware>,
+}
+
+/// Tells the selected hats, and only those, to update to `version`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rollout {
+  pub version: String,
+  pub devices: Vec<String>,
+}
+
+/// Outcome of a [`Rollout`] for one hat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RolloutResult {
+  pub device_id: String,
+  /// The update command, when it was sent.
+  #[serde(default)]
+  pub record: Option<CommandRecord>,
+  #[serde(default)]
+  pub error: Option<String>,
+}
+
+/// Versions double as file names, so they are kept to letters, digits and
+/// `.`, `-`, `_`, `+`.
+pub fn validate_version(version: &str) -> Result<(), &'static str> {
+  if version.is_empty() || version.len() > 32 {
+    return Err("must be between 1 and 32 characters");
+  }
+  if version.starts_with('.')
+    || !version
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+'))
+  {
+    return Err("may only contain letters, digits, '.', '-', '_' and '+'");
+  }
+  Ok(())
+}
+
+impl FirmwareImage {
+  /// The command telling a hat to fetch this image from `url`.
+  pub fn command(&self, url: String) -> Command {
+    Command::UpdateFirmware {
+      version: self.version.clone(),
+      url,
+      sha256: self.sha256.clone(),
+      size: self.size,
+    }
+  }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HatSample, export::ExportFormat, schema::Field, settings::validate_device_id};
+
+/// Most rejected lines listed in an [`ImportReport`], the rest are only
+/// counted.
+pub const MAX_REPORTED_ISSUES: usize = 100;
+
+/// How far past the importer's clock a timestamp may lie, to allow for
+/// skewed device clocks.
+const FUTURE_TOLERANCE_SECS: u64 = 24 * 60 * 60;
+
+/// Query of `POST /api/import`. Only the text formats can be imported.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ImportOptions {
+  #[serde(default)]
+  pub format: ExportFormat,
+  /// Device of rows that don't name one, the default hat when unset.
+  #[serde(default)]
+  pub device: Option<String>,
+  /// Validates and counts without storing anything.
+  #[serde(default)]
+  pub dry_run: bool,
+}
+
+/// A line that couldn't be imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportIssue {
+  /// 1-based line number in the uploaded file.
+  pub line: usize,
+  pub message: String,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+  pub dry_run: bool,
+  /// Data lines read, not counting the CSV header and blank lines.
+  pub lines: usize,
+  /// Samples stored, or that would be stored in a dry run.
+  pub imported: usize,
+  /// Samples skipped because the device already has one at that timestamp.
+  pub duplicates: usize,
+  pub rejected: usize,
+  /// The first [`MAX_REPORTED_ISSUES`] rejected lines.
+  pub issues: Vec<ImportIssue>,
+  pub devices: BTreeSet<String>,
+}
+
+impl ImportReport {
+  pub fn reject(&mut self, line: usize, message: impl Into<String>) {
+    self.rejected += 1;
+    if self.issues.len() < MAX_REPORTED_ISSUES {
+      self.issues.push(ImportIssue {
+        line,
+        message: message.into(),
+      });
+    }
+  }
+}
+
+/// Checks that an imported sample is plausible: a valid device id, a
+/// timestamp no later than a day after `now` and finite readings with the
+/// humidity in percent. Missing readings are fine.
+pub fn validate_sample(sample: &HatSample, now: u64) -> Result<(), String> {
+  validate_device_id(&sample.device_id).map_err(|e| format!("device_id {e}"))?;
+  if sample.timestamp == 0 {
+    return Err("timestamp is missing".to_string());
+  }
+  if sample.timestamp > now.saturating_add(FUTURE_TOLERANCE_SECS) {
+    return Err(format!("timestamp {} is in the future", sample.timestamp));
+  }
+  if let Some(field) = Field::ALL.into_iter().find(|&field| {
+    sample
+      .reading(field)
+      .is_some_and(|value| !value.is_finite())
+  }) {
+    return Err(format!("{field} is not a number"));
+  }
+  if let Some(humidity) = sample
+    .humidity
+    .filter(|humidity| !(0.0..=100.0).contains(humidity))
+  {
+    return Err(format!("humidity {humidity} is not a percentage"));
+  }
+  Ok(())
+}
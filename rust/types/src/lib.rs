@@ -1,13 +1,157 @@
+pub mod accounts;
+pub mod alerts;
+pub mod audit;
+pub mod commands;
+pub mod derived;
+pub mod encoding;
+pub mod export;
+pub mod firmware;
+pub mod import;
+pub mod measurement;
+pub mod mqtt;
+pub mod provisioning;
+pub mod schema;
+pub mod settings;
+pub mod thresholds;
+pub mod units;
+
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+use crate::{
+  measurement::Measurement,
+  schema::{Field, Quality, SCHEMA_VERSION, WireSample},
+};
+
+/// Device id given to samples published on the bare `iot/hat` topic.
+pub const DEFAULT_DEVICE_ID: &str = "hat";
+
+/// A sample as published by a hat, in the current [`schema`] version
+/// whatever version it was published in. Readings are `None` when the hat
+/// didn't report them or reported garbage, see [`HatSample::quality`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(from = "WireSample")]
 pub struct HatSample {
+  pub schema_version: u32,
+  /// Filled in by the server from the MQTT topic, the firmware omits it.
+  pub device_id: String,
   pub timestamp: u64,
-  pub temperature: f32,
-  pub humidity: f32,
-  pub r_zero: f32,
-  pub corrected_r_zero: f32,
-  pub resistance: f32,
-  pub ppm: f32,
-  pub corrected_ppm: f32,
+  pub temperature: Option<f32>,
+  pub humidity: Option<f32>,
+  pub r_zero: Option<f32>,
+  pub corrected_r_zero: Option<f32>,
+  pub resistance: Option<f32>,
+  pub ppm: Option<f32>,
+  pub corrected_ppm: Option<f32>,
+  /// Why readings are `None`. Good readings aren't listed.
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub quality: BTreeMap<Field, Quality>,
+  /// Readings of sensors other than the DHT11 and MQ135.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub measurements: Vec<Measurement>,
+}
+
+impl HatSample {
+  pub fn reading(&self, field: Field) -> Option<f32> {
+    match field {
+      Field::Temperature => self.temperature,
+      Field::Humidity => self.humidity,
+      Field::RZero => self.r_zero,
+      Field::CorrectedRZero => self.corrected_r_zero,
+      Field::Resistance => self.resistance,
+      Field::Ppm => self.ppm,
+      Field::CorrectedPpm => self.corrected_ppm,
+    }
+  }
+
+  pub fn reading_mut(&mut self, field: Field) -> &mut Option<f32> {
+    match field {
+      Field::Temperature => &mut self.temperature,
+      Field::Humidity => &mut self.humidity,
+      Field::RZero => &mut self.r_zero,
+      Field::CorrectedRZero => &mut self.corrected_r_zero,
+      Field::Resistance => &mut self.resistance,
+      Field::Ppm => &mut self.ppm,
+      Field::CorrectedPpm => &mut self.corrected_ppm,
+    }
+  }
+
+  pub fn quality(&self, field: Field) -> Quality {
+    match (self.quality.get(&field), self.reading(field)) {
+      (Some(&quality), _) => quality,
+      (None, Some(_)) => Quality::Good,
+      (None, None) => Quality::Missing,
+    }
+  }
+
+  /// The reading of the metric `key`, a field or one of
+  /// [`HatSample::measurements`]. Fields are always there, other metrics only
+  /// if the hat reports them.
+  pub fn measurement(&self, key: &str) -> Option<Measurement> {
+    match Field::ALL.into_iter().find(|field| field.name() == key) {
+      Some(field) => Some(Measurement {
+        metric: field.name().to_string(),
+        unit: field.unit().to_string(),
+        value: self.reading(field),
+        quality: self.quality(field),
+      }),
+      None => self
+        .measurements
+        .iter()
+        .find(|measurement| measurement.metric == key)
+        .cloned(),
+    }
+  }
+
+  /// Whether the hat reported anything at all, good or not. `{}` decodes,
+  /// but with every reading missing.
+  pub fn has_readings(&self) -> bool {
+    self
+      .all_measurements()
+      .iter()
+      .any(|measurement| measurement.value.is_some() || measurement.quality != Quality::Missing)
+  }
+
+  /// Every reading of the sample, fields first.
+  pub fn all_measurements(&self) -> Vec<Measurement> {
+    Field::ALL
+      .into_iter()
+      .filter_map(|field| self.measurement(field.name()))
+      .chain(self.measurements.iter().cloned())
+      .collect()
+  }
+
+  /// Drops the reading of the metric `key` as [`Quality::Invalid`].
+  pub fn invalidate(&mut self, key: &str) {
+    if let Some(field) = Field::ALL.into_iter().find(|field| field.name() == key) {
+      *self.reading_mut(field) = None;
+      self.quality.insert(field, Quality::Invalid);
+    }
+    for measurement in self.measurements.iter_mut() {
+      if measurement.metric == key {
+        measurement.value = None;
+        measurement.quality = Quality::Invalid;
+      }
+    }
+  }
+}
+
+impl Default for HatSample {
+  fn default() -> Self {
+    Self {
+      schema_version: SCHEMA_VERSION,
+      device_id: String::new(),
+      timestamp: 0,
+      temperature: None,
+      humidity: None,
+      r_zero: None,
+      corrected_r_zero: None,
+      resistance: None,
+      ppm: None,
+      corrected_ppm: None,
+      quality: BTreeMap::new(),
+      measurements: Vec::new(),
+    }
+  }
 }
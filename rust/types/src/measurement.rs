@@ -0,0 +1,235 @@
+//! Sensor-agnostic readings. The DHT11 and MQ135 readings stay fields of
+//! [`HatSample`]; readings of any other sensor a hat carries, e.g. PM2.5,
+//! pressure or light, come as [`Measurement`]s. [`HatSample::measurement`]
+//! reads both alike, and the [`MetricCatalog`] tells how to check and show
+//! every metric.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  HatSample,
+  schema::{Field, Quality},
+  thresholds::{Metric, Threshold},
+};
+
+/// One reading of one metric.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+  /// Key of the metric in the catalog, e.g. `pm25`.
+  pub metric: String,
+  /// Unit of `value`, e.g. `µg/m³`.
+  pub unit: String,
+  pub value: Option<f32>,
+  #[serde(default)]
+  pub quality: Quality,
+}
+
+/// How to check and show a metric.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricDefinition {
+  /// Lower case letters, digits and `_`, e.g. `pm25`. The fixed readings
+  /// use their field names.
+  pub key: String,
+  /// Name on the dashboard. The metrics thresholds can be set on are shown
+  /// with their translated names instead.
+  pub label: String,
+  /// Unit measurements of this metric are published in.
+  pub unit: String,
+  /// Decimals shown.
+  #[serde(default = "default_precision")]
+  pub precision: usize,
+  /// Readings below are invalid.
+  #[serde(default)]
+  pub min: Option<f32>,
+  /// Readings above are invalid.
+  #[serde(default)]
+  pub max: Option<f32>,
+  /// Whether the dashboard has a card and a chart for the metric.
+  #[serde(default = "default_dashboard")]
+  pub dashboard: bool,
+  /// Bands alerts are raised on, in `unit`. The metrics of the settings'
+  /// thresholds use those instead.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub threshold: Option<Threshold>,
+}
+
+fn default_precision() -> usize {
+  1
+}
+
+fn default_dashboard() -> bool {
+  true
+}
+
+impl MetricDefinition {
+  fn new(key: &str, label: &str, unit: &str, precision: usize, dashboard: bool) -> Self {
+    Self {
+      key: key.to_string(),
+      label: label.to_string(),
+      unit: unit.to_string(),
+      precision,
+      min: None,
+      max: None,
+      dashboard,
+      threshold: None,
+    }
+  }
+
+  fn range(self, min: f32, max: f32) -> Self {
+    Self {
+      min: Some(min),
+      max: (max < f32::MAX).then_some(max),
+      ..self
+    }
+  }
+
+  fn field(field: Field, label: &str, precision: usize, dashboard: bool) -> Self {
+    let range = field.valid_range();
+    Self::new(field.name(), label, field.unit(), precision, dashboard)
+      .range(*range.start(), *range.end())
+  }
+
+  /// The metric thresholds are set on for this one, if any.
+  pub fn threshold_metric(&self) -> Option<Metric> {
+    Metric::ALL
+      .into_iter()
+      .find(|metric| metric.field().name() == self.key)
+  }
+
+  /// Whether `value` is within the metric's range.
+  pub fn accepts(&self, value: f32) -> bool {
+    value.is_finite()
+      && self.min.is_none_or(|min| value >= min)
+      && self.max.is_none_or(|max| value <= max)
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    if self.key.is_empty()
+      || self.key.len() > 32
+      || !self
+        .key
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+      return Err(format!(
+        "metric key {:?} must be 1 to 32 lower case letters, digits or _",
+        self.key
+      ));
+    }
+    if self.label.trim().is_empty() {
+      return Err(format!("{}: label is empty", self.key));
+    }
+    if let (Some(min), Some(max)) = (self.min, self.max)
+      && min >= max
+    {
+      return Err(format!("{}: min must be below max", self.key));
+    }
+    if let Some(threshold) = &self.threshold {
+      if self.threshold_metric().is_some() {
+        return Err(format!("{}: thresholds are set in the settings", self.key));
+      }
+      threshold.validate(&self.key).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+  }
+}
+
+/// Every metric the server knows about: the fixed readings, common extra
+/// sensors, and whatever the operator adds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricCatalog {
+  pub metrics: Vec<MetricDefinition>,
+}
+
+impl Default for MetricCatalog {
+  fn default() -> Self {
+    Self::builtin()
+  }
+}
+
+impl MetricCatalog {
+  pub fn builtin() -> Self {
+    Self {
+      metrics: vec![
+        MetricDefinition::field(Field::Temperature, "Temperature", 1, true),
+        MetricDefinition::field(Field::Humidity, "Humidity", 1, true),
+        MetricDefinition::field(Field::CorrectedPpm, "CO2", 1, true),
+        MetricDefinition::field(Field::Ppm, "CO2, uncorrected", 1, false),
+        MetricDefinition::field(Field::Resistance, "Resistance", 1, false),
+        MetricDefinition::field(Field::RZero, "R0", 1, false),
+        MetricDefinition::field(Field::CorrectedRZero, "R0, corrected", 1, false),
+        MetricDefinition::new("pm25", "PM2.5", "µg/m³", 0, true).range(0.0, 1000.0),
+        MetricDefinition::new("pressure", "Pressure", "hPa", 1, true).range(300.0, 1100.0),
+        MetricDefinition::new("light", "Light", "lx", 0, true).range(0.0, 200_000.0),
+      ],
+    }
+  }
+
+  pub fn get(&self, key: &str) -> Option<&MetricDefinition> {
+    self.metrics.iter().find(|definition| definition.key == key)
+  }
+
+  /// Adds `definitions`, replacing the ones with the same key.
+  pub fn extend(&mut self, definitions: impl IntoIterator<Item = MetricDefinition>) {
+    for definition in definitions {
+      match self
+        .metrics
+        .iter_mut()
+        .find(|existing| existing.key == definition.key)
+      {
+        Some(existing) => *existing = definition,
+        None => self.metrics.push(definition),
+      }
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    self.metrics.iter().try_for_each(MetricDefinition::validate)
+  }
+
+  /// Marks the readings of `sample` outside their metric's range invalid.
+  pub fn assess(&self, sample: &mut HatSample) {
+    let rejected = sample
+      .all_measurements()
+      .into_iter()
+      .filter(|measurement| {
+        measurement.value.is_some_and(|value| {
+          self
+            .get(&measurement.metric)
+            .is_some_and(|definition| !definition.accepts(value))
+        })
+      })
+      .map(|measurement| measurement.metric)
+      .collect::<Vec<_>>();
+    for metric in rejected {
+      sample.invalidate(&metric);
+    }
+  }
+
+  /// Metrics the dashboard shows for `samples`: the fixed readings always,
+  /// other metrics once one of the samples reports them.
+  pub fn dashboard<'a>(
+    &self,
+    samples: impl IntoIterator<Item = &'a HatSample>,
+  ) -> Vec<MetricDefinition> {
+    let reported = samples
+      .into_iter()
+      .flat_map(|sample| sample.measurements.iter())
+      .map(|measurement| measurement.metric.as_str())
+      .collect::<BTreeSet<_>>();
+    self
+      .metrics
+      .iter()
+      .filter(|definition| definition.dashboard)
+      .filter(|definition| {
+        Field::ALL
+          .iter()
+          .any(|field| field.name() == definition.key)
+          || reported.contains(definition.key.as_str())
+      })
+      .cloned()
+      .collect()
+  }
+}
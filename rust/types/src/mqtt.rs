@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the server's MQTT client is in its connection lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+  /// Opening the connection to the broker.
+  #[default]
+  Connecting,
+  /// The broker accepted the connection, subscriptions aren't confirmed yet.
+  Connected,
+  /// Every subscription was acknowledged, samples are flowing.
+  Subscribed,
+  /// Connected, but the broker rejected some of the subscriptions.
+  Degraded,
+  /// The connection failed, waiting before the next attempt.
+  BackingOff,
+  /// Samples come from a recording, there is no broker.
+  Replaying,
+  /// The client disconnected for good, the server is shutting down.
+  Stopped,
+}
+
+impl ConnectionState {
+  /// Whether samples can reach the server in this state.
+  pub fn is_receiving(self) -> bool {
+    matches!(self, Self::Subscribed | Self::Replaying)
+  }
+
+  /// Whether the client can publish to the broker in this state.
+  pub fn is_connected(self) -> bool {
+    matches!(self, Self::Connected | Self::Subscribed | Self::Degraded)
+  }
+}
+
+/// Broad cause of a connection failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+  /// The broker is unreachable or the connection dropped.
+  Network,
+  /// The broker stopped answering in time.
+  Timeout,
+  /// The broker rejected the credentials or the client.
+  Unauthorized,
+  /// The broker refused the connection for another reason.
+  Refused,
+  /// The broker rejected a subscription.
+  Subscription,
+  /// The broker closed the connection.
+  Disconnected,
+  /// The broker or the client broke the protocol.
+  Protocol,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionError {
+  pub category: ErrorCategory,
+  pub message: String,
+  /// Unix seconds.
+  pub at: u64,
+}
+
+/// The MQTT connection as seen by the dashboard and `/readyz`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MqttStatus {
+  pub state: ConnectionState,
+  /// Unix seconds the state was entered at.
+  pub since: u64,
+  /// Connection failures since the client was last connected.
+  pub failures: u32,
+  /// Unix seconds of the next attempt while backing off.
+  #[serde(default)]
+  pub retry_at: Option<u64>,
+  #[serde(default)]
+  pub last_error: Option<ConnectionError>,
+}
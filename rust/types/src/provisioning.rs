@@ -0,0 +1,82 @@
+//! Per-device identities. An admin creates a one-time claim code for a
+//! device id, the hat trades it for its own token during setup, and the
+//! server only admits samples from claimed, unrevoked devices when
+//! provisioning is required.
+
+use serde::{Deserialize, Serialize};
+
+/// Claim codes not used within this long expire.
+pub const CLAIM_CODE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// A provisioned device, without its token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvisionedDevice {
+  pub device_id: String,
+  pub created_by: String,
+  /// Unix seconds.
+  pub created_at: u64,
+  /// Unix seconds the hat last redeemed a claim code.
+  #[serde(default)]
+  pub claimed_at: Option<u64>,
+  #[serde(default)]
+  pub revoked_at: Option<u64>,
+}
+
+impl ProvisionedDevice {
+  /// Whether the device holds a valid token.
+  pub fn is_active(&self) -> bool {
+    self.claimed_at.is_some() && self.revoked_at.is_none()
+  }
+}
+
+/// A claim code waiting for its hat, without the code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingClaim {
+  pub device_id: String,
+  pub created_by: String,
+  /// Unix seconds.
+  pub expires_at: u64,
+}
+
+/// A freshly created claim code. The code is not stored and can't be shown
+/// again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimCode {
+  pub device_id: String,
+  /// Eight characters shown as `XXXX-XXXX`, dashes and case don't matter.
+  pub code: String,
+  pub expires_at: u64,
+}
+
+/// Devices and open claim codes, for the settings page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvisioningOverview {
+  pub devices: Vec<ProvisionedDevice>,
+  pub claims: Vec<PendingClaim>,
+}
+
+/// Body of `POST /api/provision/claim`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claim {
+  pub code: String,
+}
+
+/// What a hat receives for a valid claim code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCredentials {
+  pub device_id: String,
+  /// Also the MQTT password, with the device id as the user name, for
+  /// brokers that authenticate through `/api/mqtt/user`.
+  pub token: String,
+  /// Where the hat publishes its samples.
+  pub topic: String,
+}
+
+/// `code` without dashes and spaces, in upper case.
+pub fn normalize_claim_code(code: &str) -> String {
+  code
+    .chars()
+    .filter(|c| !matches!(c, '-' | ' '))
+    .map(|c| c.to_ascii_uppercase())
+    .collect()
+}
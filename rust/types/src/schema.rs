@@ -0,0 +1,260 @@
+//! Versions of the [`HatSample`] payload. Firmware declares the version it
+//! publishes in `schema_version`; payloads without one are version 1, the
+//! flat layout every reading was required in.
+//!
+//! Samples are decoded leniently: fields of newer versions are ignored, and
+//! readings a payload lacks or that can't be real are left out with their
+//! [`Quality`] in [`HatSample::quality`] instead of being taken as zero. A
+//! decoded sample is always in the current version, which is also the
+//! version stored history is written in, so history from any version reads
+//! back through the same path.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt,
+  ops::RangeInclusive,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, de::IgnoredAny};
+
+use crate::{HatSample, measurement::Measurement};
+
+/// Version samples are serialized in.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// Version of payloads without `schema_version`.
+pub const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+/// A reading of a [`HatSample`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+  Temperature,
+  Humidity,
+  RZero,
+  CorrectedRZero,
+  Resistance,
+  Ppm,
+  CorrectedPpm,
+}
+
+impl Field {
+  pub const ALL: [Field; 7] = [
+    Field::Temperature,
+    Field::Humidity,
+    Field::RZero,
+    Field::CorrectedRZero,
+    Field::Resistance,
+    Field::Ppm,
+    Field::CorrectedPpm,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Field::Temperature => "temperature",
+      Field::Humidity => "humidity",
+      Field::RZero => "r_zero",
+      Field::CorrectedRZero => "corrected_r_zero",
+      Field::Resistance => "resistance",
+      Field::Ppm => "ppm",
+      Field::CorrectedPpm => "corrected_ppm",
+    }
+  }
+
+  /// Values the sensors can report, the range of the field in
+  /// [`MetricCatalog::builtin`](crate::measurement::MetricCatalog::builtin).
+  /// Operators may widen or narrow it in their catalog, which judges the
+  /// readings on ingest.
+  pub fn valid_range(self) -> RangeInclusive<f32> {
+    match self {
+      Field::Temperature => -40.0..=85.0,
+      Field::Humidity => 0.0..=100.0,
+      Field::Ppm | Field::CorrectedPpm => 0.0..=1_000_000.0,
+      Field::RZero | Field::CorrectedRZero | Field::Resistance => 0.0..=f32::MAX,
+    }
+  }
+
+  pub fn unit(self) -> &'static str {
+    match self {
+      Field::Temperature => "°C",
+      Field::Humidity => "%",
+      Field::RZero | Field::CorrectedRZero | Field::Resistance => "Ω",
+      Field::Ppm | Field::CorrectedPpm => "ppm",
+    }
+  }
+}
+
+impl fmt::Display for Field {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// Whether a reading of a sample can be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+  #[default]
+  Good,
+  /// The hat didn't report the reading.
+  Missing,
+  /// The hat reported something that isn't a reading: NaN, infinity or a
+  /// value outside the range of its metric in the catalog.
+  Invalid,
+}
+
+impl Quality {
+  pub fn name(self) -> &'static str {
+    match self {
+      Quality::Good => "good",
+      Quality::Missing => "missing",
+      Quality::Invalid => "invalid",
+    }
+  }
+}
+
+impl fmt::Display for Quality {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// A sample payload of any version, every field optional.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct WireSample {
+  schema_version: Option<u32>,
+  #[serde(default)]
+  device_id: String,
+  timestamp: Option<u64>,
+  #[serde(default, deserialize_with = "published_reading")]
+  temperature: Option<f32>,
+  #[serde(default, deserialize_with = "published_reading")]
+  humidity: Option<f32>,
+  #[serde(default, deserialize_with = "published_reading")]
+  r_zero: Option<f32>,
+  #[serde(default, deserialize_with = "published_reading")]
+  corrected_r_zero: Option<f32>,
+  #[serde(default, deserialize_with = "published_reading")]
+  resistance: Option<f32>,
+  #[serde(default, deserialize_with = "published_reading")]
+  ppm: Option<f32>,
+  #[serde(default, deserialize_with = "published_reading")]
+  corrected_ppm: Option<f32>,
+  /// Version 2 only.
+  #[serde(default)]
+  missing: BTreeSet<Field>,
+  #[serde(default)]
+  quality: BTreeMap<Field, Quality>,
+  /// Version 4 on.
+  #[serde(default)]
+  measurements: Vec<WireMeasurement>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WireMeasurement {
+  metric: String,
+  #[serde(default)]
+  unit: String,
+  #[serde(default, deserialize_with = "published_reading")]
+  value: Option<f32>,
+  #[serde(default)]
+  quality: Quality,
+}
+
+/// A reading as published: a number, `null` when there is none, or
+/// anything else, which is garbage and read as NaN to mark it invalid.
+fn published_reading<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Published {
+    Number(f32),
+    Garbage(IgnoredAny),
+  }
+  Ok(
+    Option::<Published>::deserialize(deserializer)?.map(|published| match published {
+      Published::Number(value) => value,
+      Published::Garbage(_) => f32::NAN,
+    }),
+  )
+}
+
+impl WireSample {
+  /// The version the payload declares.
+  fn version(&self) -> u32 {
+    self.schema_version.unwrap_or(LEGACY_SCHEMA_VERSION)
+  }
+
+  /// Brings the payload to [`SCHEMA_VERSION`], one version at a time. A new
+  /// version adds a step here for the version before it.
+  fn migrate(mut self) -> Self {
+    if self.version() == LEGACY_SCHEMA_VERSION {
+      // Version 2 only added `schema_version` and `missing`.
+      self.schema_version = Some(2);
+    }
+    if self.version() == 2 {
+      // Version 3 made readings nullable, with a quality for each one left
+      // out.
+      for field in std::mem::take(&mut self.missing) {
+        self.quality.insert(field, Quality::Missing);
+      }
+      self.schema_version = Some(3);
+    }
+    if self.version() == 3 {
+      // Version 4 added `measurements` of other sensors.
+      self.schema_version = Some(4);
+    }
+    self
+  }
+}
+
+impl From<WireSample> for HatSample {
+  fn from(wire: WireSample) -> Self {
+    let wire = wire.migrate();
+    let mut quality = BTreeMap::new();
+    let mut reading = |field: Field, value: Option<f32>| {
+      let reported = match (wire.quality.get(&field), value) {
+        (Some(&reported), _) if reported != Quality::Good => reported,
+        (_, None) => Quality::Missing,
+        (_, Some(value)) if !value.is_finite() => Quality::Invalid,
+        _ => Quality::Good,
+      };
+      if reported == Quality::Good {
+        return value;
+      }
+      quality.insert(field, reported);
+      None
+    };
+    HatSample {
+      schema_version: SCHEMA_VERSION,
+      device_id: wire.device_id.clone(),
+      timestamp: wire.timestamp.unwrap_or_default(),
+      temperature: reading(Field::Temperature, wire.temperature),
+      humidity: reading(Field::Humidity, wire.humidity),
+      r_zero: reading(Field::RZero, wire.r_zero),
+      corrected_r_zero: reading(Field::CorrectedRZero, wire.corrected_r_zero),
+      resistance: reading(Field::Resistance, wire.resistance),
+      ppm: reading(Field::Ppm, wire.ppm),
+      corrected_ppm: reading(Field::CorrectedPpm, wire.corrected_ppm),
+      quality,
+      // Ranges are in the catalog, which checks them on ingest.
+      measurements: wire
+        .measurements
+        .into_iter()
+        .map(|measurement| {
+          let quality = match measurement.value {
+            _ if measurement.quality != Quality::Good => measurement.quality,
+            None => Quality::Missing,
+            Some(value) if !value.is_finite() => Quality::Invalid,
+            Some(_) => Quality::Good,
+          };
+          Measurement {
+            metric: measurement.metric,
+            unit: measurement.unit,
+            value: measurement.value.filter(|_| quality == Quality::Good),
+            quality,
+          }
+        })
+        .collect(),
+    }
+  }
+}
@@ -0,0 +1,209 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  thresholds::{Band, ThresholdConfig, ThresholdError, Thresholds},
+  units::Units,
+};
+
+/// Longest retention the settings page accepts, roughly ten years.
+pub const MAX_RETENTION_DAYS: u32 = 3650;
+
+/// Runtime configuration persisted by the server and edited from the
+/// settings page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+  #[serde(default)]
+  pub devices: BTreeMap<String, DeviceInfo>,
+  #[serde(default)]
+  pub thresholds: ThresholdConfig,
+  #[serde(default)]
+  pub notifications: Vec<NotificationTarget>,
+  /// Days of sample history to keep, `0` keeps everything.
+  #[serde(default = "default_retention_days")]
+  pub retention_days: u32,
+  /// Units used for server generated output such as alert messages.
+  #[serde(default)]
+  pub units: Units,
+}
+
+fn default_retention_days() -> u32 {
+  90
+}
+
+impl Default for Settings {
+  fn default() -> Self {
+    Self {
+      devices: BTreeMap::new(),
+      thresholds: ThresholdConfig::default(),
+      notifications: Vec::new(),
+      retention_days: default_retention_days(),
+      units: Units::default(),
+    }
+  }
+}
+
+/// Human facing metadata of a hat.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+  #[serde(default)]
+  pub name: String,
+  #[serde(default)]
+  pub location: String,
+  #[serde(default)]
+  pub notes: String,
+}
+
+impl DeviceInfo {
+  pub fn validate(&self) -> Result<(), FieldErrors> {
+    let mut errors = FieldErrors::default();
+    if self.name.chars().count() > 64 {
+      errors.push("name", "must be at most 64 characters");
+    }
+    if self.location.chars().count() > 64 {
+      errors.push("location", "must be at most 64 characters");
+    }
+    errors.into_result()
+  }
+}
+
+/// A webhook that alert events are POSTed to as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotificationTarget {
+  pub name: String,
+  pub url: String,
+  /// Only transitions into this band or a more severe one are sent.
+  #[serde(default = "default_min_band")]
+  pub min_band: Band,
+  #[serde(default = "default_enabled")]
+  pub enabled: bool,
+  /// Units of the values sent to this target, the server's [`Settings::units`]
+  /// when unset.
+  #[serde(default)]
+  pub units: Option<Units>,
+}
+
+fn default_min_band() -> Band {
+  Band::Warning
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+impl Default for NotificationTarget {
+  fn default() -> Self {
+    Self {
+      name: String::new(),
+      url: String::new(),
+      min_band: default_min_band(),
+      enabled: default_enabled(),
+      units: None,
+    }
+  }
+}
+
+/// Device ids end up in MQTT topics and file names, so keep them simple.
+pub fn validate_device_id(device_id: &str) -> Result<(), &'static str> {
+  if device_id.is_empty() || device_id.len() > 64 {
+    return Err("must be between 1 and 64 characters");
+  }
+  if !device_id
+    .chars()
+    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    || device_id.starts_with('.')
+  {
+    return Err("may only contain letters, digits, '-', '_' and '.'");
+  }
+  Ok(())
+}
+
+pub fn validate_notifications(targets: &[NotificationTarget]) -> Result<(), FieldErrors> {
+  let mut errors = FieldErrors::default();
+  for (i, target) in targets.iter().enumerate() {
+    if target.name.trim().is_empty() {
+      errors.push(format!("notifications.{i}.name"), "is required");
+    }
+    let url = target.url.trim();
+    if !(url.starts_with("http://") || url.starts_with("https://"))
+      || url.contains(char::is_whitespace)
+    {
+      errors.push(
+        format!("notifications.{i}.url"),
+        "must be an http:// or https:// URL",
+      );
+    }
+  }
+  errors.into_result()
+}
+
+pub fn validate_retention(days: u32) -> Result<(), FieldErrors> {
+  let mut errors = FieldErrors::default();
+  if days > MAX_RETENTION_DAYS {
+    errors.push(
+      "retention_days",
+      format!("must be at most {MAX_RETENTION_DAYS} days"),
+    );
+  }
+  errors.into_result()
+}
+
+pub fn validate_thresholds(thresholds: &Thresholds) -> Result<(), FieldErrors> {
+  thresholds.validate().map_err(|e| {
+    let metric = match &e {
+      ThresholdError::NotFinite(metric) | ThresholdError::Unordered(metric) => metric,
+    };
+    let mut errors = FieldErrors::default();
+    errors.push(format!("thresholds.{metric}"), e.to_string());
+    errors
+  })
+}
+
+/// Validation failure of a single form field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+  pub field: String,
+  pub message: String,
+}
+
+/// Validation failures keyed by field path, e.g. `notifications.0.url`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FieldErrors(pub Vec<FieldError>);
+
+impl FieldErrors {
+  pub fn push(&mut self, field: impl Into<String>, message: impl Into<String>) {
+    self.0.push(FieldError {
+      field: field.into(),
+      message: message.into(),
+    });
+  }
+
+  pub fn get(&self, field: &str) -> Option<&str> {
+    self
+      .0
+      .iter()
+      .find(|error| error.field == field)
+      .map(|error| error.message.as_str())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  pub fn into_result(self) -> Result<(), FieldErrors> {
+    if self.is_empty() { Ok(()) } else { Err(self) }
+  }
+}
+
+impl fmt::Display for FieldErrors {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (i, error) in self.0.iter().enumerate() {
+      if i > 0 {
+        f.write_str("; ")?;
+      }
+      write!(f, "{} {}", error.field, error.message)?;
+    }
+    Ok(())
+  }
+}
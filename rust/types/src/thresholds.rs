@@ -0,0 +1,217 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{HatSample, schema::Field};
+
+/// A reading of a [`HatSample`] that thresholds can be set on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+  Temperature,
+  Humidity,
+  Ppm,
+}
+
+impl Metric {
+  pub const ALL: [Metric; 3] = [Metric::Temperature, Metric::Humidity, Metric::Ppm];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Metric::Temperature => "temperature",
+      Metric::Humidity => "humidity",
+      Metric::Ppm => "ppm",
+    }
+  }
+
+  /// The metric named `name`, see [`Metric::name`].
+  pub fn from_name(name: &str) -> Option<Metric> {
+    Metric::ALL.into_iter().find(|metric| metric.name() == name)
+  }
+
+  /// Symbol of the canonical unit thresholds are expressed in.
+  pub fn unit(self) -> &'static str {
+    match self {
+      Metric::Temperature => "°C",
+      Metric::Humidity => "%",
+      Metric::Ppm => "ppm",
+    }
+  }
+
+  /// The value of this metric in `sample`, in the canonical unit (°C, %RH,
+  /// ppm). `None` when the hat has no usable reading.
+  pub fn value(self, sample: &HatSample) -> Option<f32> {
+    sample.reading(self.field())
+  }
+
+  /// The reading this metric is, `ppm` being the corrected one.
+  pub fn field(self) -> Field {
+    match self {
+      Metric::Temperature => Field::Temperature,
+      Metric::Humidity => Field::Humidity,
+      Metric::Ppm => Field::CorrectedPpm,
+    }
+  }
+}
+
+impl fmt::Display for Metric {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// Where a reading falls relative to its [`Threshold`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Band {
+  Low,
+  Normal,
+  Warning,
+  Critical,
+}
+
+impl Band {
+  /// How urgent a reading in this band is: both out-of-range directions are
+  /// worth attention, critical the most.
+  pub fn severity(self) -> u8 {
+    match self {
+      Band::Normal => 0,
+      Band::Low | Band::Warning => 1,
+      Band::Critical => 2,
+    }
+  }
+}
+
+/// Bounds for a single metric, in the metric's canonical unit. Any bound may
+/// be left unset.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Threshold {
+  /// Readings below this are [`Band::Low`].
+  #[serde(default)]
+  pub low: Option<f32>,
+  /// Readings above this are [`Band::Warning`].
+  #[serde(default)]
+  pub warning: Option<f32>,
+  /// Readings above this are [`Band::Critical`].
+  #[serde(default)]
+  pub critical: Option<f32>,
+}
+
+impl Threshold {
+  pub fn classify(&self, value: f32) -> Band {
+    if self.critical.is_some_and(|critical| value > critical) {
+      Band::Critical
+    } else if self.warning.is_some_and(|warning| value > warning) {
+      Band::Warning
+    } else if self.low.is_some_and(|low| value < low) {
+      Band::Low
+    } else {
+      Band::Normal
+    }
+  }
+
+  /// Upper edge of the normal band: the warning bound, else the critical one.
+  pub fn normal_high(&self) -> Option<f32> {
+    self.warning.or(self.critical)
+  }
+
+  /// Checks the bounds of the threshold of the metric named `metric`.
+  pub fn validate(&self, metric: &str) -> Result<(), ThresholdError> {
+    let bounds = [self.low, self.warning, self.critical];
+    if bounds.iter().flatten().any(|bound| !bound.is_finite()) {
+      return Err(ThresholdError::NotFinite(metric.to_string()));
+    }
+    let set: Vec<f32> = bounds.into_iter().flatten().collect();
+    if set.windows(2).any(|pair| pair[0] >= pair[1]) {
+      return Err(ThresholdError::Unordered(metric.to_string()));
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ThresholdError {
+  #[error("{0}: thresholds must be finite numbers")]
+  NotFinite(String),
+  #[error("{0}: thresholds must increase from low to warning to critical")]
+  Unordered(String),
+}
+
+/// Thresholds for every metric of one device.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Thresholds {
+  pub temperature: Threshold,
+  pub humidity: Threshold,
+  pub ppm: Threshold,
+}
+
+impl Default for Thresholds {
+  fn default() -> Self {
+    Self {
+      temperature: Threshold {
+        low: Some(20.0),
+        warning: None,
+        critical: Some(30.0),
+      },
+      humidity: Threshold {
+        low: Some(30.0),
+        warning: Some(60.0),
+        critical: Some(70.0),
+      },
+      ppm: Threshold {
+        low: None,
+        warning: Some(1000.0),
+        critical: Some(2000.0),
+      },
+    }
+  }
+}
+
+impl Thresholds {
+  pub fn get(&self, metric: Metric) -> &Threshold {
+    match metric {
+      Metric::Temperature => &self.temperature,
+      Metric::Humidity => &self.humidity,
+      Metric::Ppm => &self.ppm,
+    }
+  }
+
+  pub fn get_mut(&mut self, metric: Metric) -> &mut Threshold {
+    match metric {
+      Metric::Temperature => &mut self.temperature,
+      Metric::Humidity => &mut self.humidity,
+      Metric::Ppm => &mut self.ppm,
+    }
+  }
+
+  /// The band of `metric` in `sample`, `None` without a reading to
+  /// classify.
+  pub fn classify(&self, metric: Metric, sample: &HatSample) -> Option<Band> {
+    metric
+      .value(sample)
+      .map(|value| self.get(metric).classify(value))
+  }
+
+  pub fn validate(&self) -> Result<(), ThresholdError> {
+    Metric::ALL
+      .into_iter()
+      .try_for_each(|metric| self.get(metric).validate(metric.name()))
+  }
+}
+
+/// Server-wide threshold configuration: a default set plus per-device
+/// overrides keyed by device id.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ThresholdConfig {
+  #[serde(default)]
+  pub default: Thresholds,
+  #[serde(default)]
+  pub devices: BTreeMap<String, Thresholds>,
+}
+
+impl ThresholdConfig {
+  pub fn for_device(&self, device_id: &str) -> &Thresholds {
+    self.devices.get(device_id).unwrap_or(&self.default)
+  }
+}
@@ -0,0 +1,230 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{HatSample, schema::Field, thresholds::Metric};
+
+/// Molar mass of CO2 in g/mol.
+const CO2_MOLAR_MASS: f32 = 44.01;
+/// Molar volume of an ideal gas at 25 °C and 1 atm in L/mol.
+const MOLAR_VOLUME: f32 = 24.45;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemperatureUnit {
+  #[default]
+  Celsius,
+  Fahrenheit,
+  Kelvin,
+}
+
+impl TemperatureUnit {
+  pub const ALL: [TemperatureUnit; 3] = [
+    TemperatureUnit::Celsius,
+    TemperatureUnit::Fahrenheit,
+    TemperatureUnit::Kelvin,
+  ];
+
+  pub fn symbol(self) -> &'static str {
+    match self {
+      TemperatureUnit::Celsius => "°C",
+      TemperatureUnit::Fahrenheit => "°F",
+      TemperatureUnit::Kelvin => "K",
+    }
+  }
+
+  /// Converts a reading in °C, as reported by the hats, into this unit.
+  pub fn from_celsius(self, celsius: f32) -> f32 {
+    match self {
+      TemperatureUnit::Celsius => celsius,
+      TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+      TemperatureUnit::Kelvin => celsius + 273.15,
+    }
+  }
+
+  pub fn to_celsius(self, value: f32) -> f32 {
+    match self {
+      TemperatureUnit::Celsius => value,
+      TemperatureUnit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+      TemperatureUnit::Kelvin => value - 273.15,
+    }
+  }
+}
+
+/// Unit of the MQ135 gas reading, which the firmware reports as CO2 ppm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GasUnit {
+  #[default]
+  Ppm,
+  #[serde(rename = "mg_per_m3")]
+  MilligramsPerCubicMetre,
+}
+
+impl GasUnit {
+  pub const ALL: [GasUnit; 2] = [GasUnit::Ppm, GasUnit::MilligramsPerCubicMetre];
+
+  pub fn symbol(self) -> &'static str {
+    match self {
+      GasUnit::Ppm => "ppm",
+      GasUnit::MilligramsPerCubicMetre => "mg/m³",
+    }
+  }
+
+  /// Converts a CO2 concentration in ppm into this unit, assuming 25 °C and
+  /// 1 atm.
+  pub fn from_ppm(self, ppm: f32) -> f32 {
+    match self {
+      GasUnit::Ppm => ppm,
+      GasUnit::MilligramsPerCubicMetre => ppm * CO2_MOLAR_MASS / MOLAR_VOLUME,
+    }
+  }
+
+  pub fn to_ppm(self, value: f32) -> f32 {
+    match self {
+      GasUnit::Ppm => value,
+      GasUnit::MilligramsPerCubicMetre => value * MOLAR_VOLUME / CO2_MOLAR_MASS,
+    }
+  }
+}
+
+/// Units a user wants readings rendered in. Samples are always stored and
+/// compared against thresholds in °C and ppm; conversion happens on output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Units {
+  #[serde(default)]
+  pub temperature: TemperatureUnit,
+  #[serde(default)]
+  pub gas: GasUnit,
+}
+
+impl Units {
+  pub fn temperature(&self, celsius: f32) -> f32 {
+    self.temperature.from_celsius(celsius)
+  }
+
+  pub fn gas(&self, ppm: f32) -> f32 {
+    self.gas.from_ppm(ppm)
+  }
+
+  /// Converts a canonical reading of `metric` into the chosen unit.
+  pub fn convert(&self, metric: Metric, value: f32) -> f32 {
+    match metric {
+      Metric::Temperature => self.temperature(value),
+      Metric::Humidity => value,
+      Metric::Ppm => self.gas(value),
+    }
+  }
+
+  pub fn symbol(&self, metric: Metric) -> &'static str {
+    match metric {
+      Metric::Temperature => self.temperature.symbol(),
+      Metric::Humidity => "%",
+      Metric::Ppm => self.gas.symbol(),
+    }
+  }
+
+  /// Converts a canonical reading of `field` into the chosen unit.
+  pub fn convert_field(&self, field: Field, value: f32) -> f32 {
+    match field {
+      Field::Temperature => self.temperature(value),
+      Field::Ppm | Field::CorrectedPpm => self.gas(value),
+      _ => value,
+    }
+  }
+
+  /// Converts a reading of `field` in the chosen unit back into the
+  /// canonical one.
+  pub fn restore_field(&self, field: Field, value: f32) -> f32 {
+    match field {
+      Field::Temperature => self.temperature.to_celsius(value),
+      Field::Ppm | Field::CorrectedPpm => self.gas.to_ppm(value),
+      _ => value,
+    }
+  }
+
+  pub fn field_symbol(&self, field: Field) -> &'static str {
+    match field {
+      Field::Temperature => self.temperature.symbol(),
+      Field::Ppm | Field::CorrectedPpm => self.gas.symbol(),
+      field => field.unit(),
+    }
+  }
+
+  /// `sample` with its readings in the chosen units.
+  pub fn convert_sample(&self, sample: &HatSample) -> HatSample {
+    let mut sample = sample.clone();
+    for field in Field::ALL {
+      if let Some(value) = sample.reading_mut(field) {
+        *value = self.convert_field(field, *value);
+      }
+    }
+    sample
+  }
+
+  /// Converts the readings of `sample`, in the chosen units, back into °C
+  /// and ppm.
+  pub fn restore_sample(&self, sample: &mut HatSample) {
+    for field in Field::ALL {
+      if let Some(value) = sample.reading_mut(field) {
+        *value = self.restore_field(field, *value);
+      }
+    }
+  }
+}
+
+/// `<temperature>,<gas>`, as in `fahrenheit,mg_per_m3`, the form of the
+/// `units` query parameter.
+impl fmt::Display for Units {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{},{}", self.temperature, self.gas)
+  }
+}
+
+impl FromStr for Units {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (temperature, gas) = s
+      .split_once(',')
+      .ok_or_else(|| format!("units must be <temperature>,<gas>: {s}"))?;
+    Ok(Self {
+      temperature: temperature.parse()?,
+      gas: gas.parse()?,
+    })
+  }
+}
+
+macro_rules! impl_unit_str {
+  ($ty:ty { $($variant:ident => $name:literal),+ $(,)? }) => {
+    impl fmt::Display for $ty {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+          $(Self::$variant => $name,)+
+        })
+      }
+    }
+
+    impl FromStr for $ty {
+      type Err = String;
+
+      fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+          $($name => Ok(Self::$variant),)+
+          _ => Err(format!("unknown {}: {s}", stringify!($ty))),
+        }
+      }
+    }
+  };
+}
+
+impl_unit_str!(TemperatureUnit {
+  Celsius => "celsius",
+  Fahrenheit => "fahrenheit",
+  Kelvin => "kelvin",
+});
+
+impl_unit_str!(GasUnit {
+  Ppm => "ppm",
+  MilligramsPerCubicMetre => "mg_per_m3",
+});
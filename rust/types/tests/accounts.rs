@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use types::{
+  accounts::{Grants, Role, validate_password, validate_token_name, validate_username},
+  settings::{DeviceInfo, Settings},
+};
+
+/// `hat-1` in building A, `hat-2` in building B, `hat-3` without a location.
+fn settings() -> Settings {
+  let mut settings = Settings::default();
+  for (device_id, location) in [
+    ("hat-1", "Building A"),
+    ("hat-2", "Building B"),
+    ("hat-3", ""),
+  ] {
+    settings.devices.insert(
+      device_id.to_string(),
+      DeviceInfo {
+        location: location.to_string(),
+        ..DeviceInfo::default()
+      },
+    );
+  }
+  settings
+}
+
+#[test]
+fn grants_allow_their_devices_and_locations() {
+  let settings = settings();
+  let grants = Grants {
+    devices: BTreeSet::from(["hat-3".to_string(), "hat-9".to_string()]),
+    locations: BTreeSet::from(["Building A".to_string()]),
+    ..Grants::default()
+  };
+  assert!(grants.allows("hat-1", &settings));
+  assert!(!grants.allows("hat-2", &settings));
+  assert!(grants.allows("hat-3", &settings));
+  // Devices granted by id needn't be on the settings page.
+  assert!(grants.allows("hat-9", &settings));
+  assert!(!grants.allows("hat-10", &settings));
+}
+
+#[test]
+fn locations_match_after_trimming() {
+  let mut settings = settings();
+  settings.devices.get_mut("hat-2").unwrap().location = " Building A ".to_string();
+  let grants = Grants {
+    locations: BTreeSet::from(["Building A".to_string()]),
+    ..Grants::default()
+  };
+  assert!(grants.allows("hat-2", &settings));
+  // Locations match case-sensitively, devices without one match none.
+  let lowercase = Grants {
+    locations: BTreeSet::from(["building a".to_string()]),
+    ..Grants::default()
+  };
+  assert!(!lowercase.allows("hat-1", &settings));
+  assert!(!lowercase.allows("hat-3", &settings));
+}
+
+#[test]
+fn granting_everything_covers_unknown_devices() {
+  let settings = settings();
+  assert!(Grants::everything().allows("hat-2", &settings));
+  assert!(Grants::everything().allows("added-later", &settings));
+  assert!(!Grants::default().allows("hat-1", &settings));
+}
+
+#[test]
+fn roles_are_ordered_and_parse_back() {
+  assert!(Role::Viewer < Role::Operator && Role::Operator < Role::Admin);
+  for role in Role::ALL {
+    assert_eq!(role.to_string().parse(), Ok(role));
+  }
+  assert!("root".parse::<Role>().is_err());
+}
+
+#[test]
+fn account_fields_are_validated() {
+  assert_eq!(validate_username("ana.nguyen_2"), Ok(()));
+  for username in ["", &"a".repeat(33), "ana nguyen", "ân"] {
+    assert!(validate_username(username).is_err(), "{username}");
+  }
+  assert_eq!(validate_password("mật khẩu"), Ok(()));
+  assert!(validate_password("short").is_err());
+  assert_eq!(validate_token_name("grafana"), Ok(()));
+  assert!(validate_token_name("  ").is_err());
+  assert!(validate_token_name(&"t".repeat(65)).is_err());
+}
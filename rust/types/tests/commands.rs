@@ -0,0 +1,113 @@
+use types::{
+  accounts::Role,
+  commands::{
+    ACK_TIMEOUT_SECS, Command, CommandRecord, CommandState, MAX_IDENTIFY_SECS, MAX_INTERVAL_SECS,
+  },
+};
+
+fn firmware(version: &str, url: &str, sha256: &str, size: u64) -> Command {
+  Command::UpdateFirmware {
+    version: version.to_string(),
+    url: url.to_string(),
+    sha256: sha256.to_string(),
+    size,
+  }
+}
+
+#[test]
+fn commands_are_validated() {
+  let valid = [
+    Command::SetInterval { seconds: 1 },
+    Command::SetInterval {
+      seconds: MAX_INTERVAL_SECS,
+    },
+    Command::StartCalibration { minutes: 30 },
+    Command::SetRZero { r_zero: 76.6 },
+    Command::Reboot,
+    Command::Identify {
+      seconds: MAX_IDENTIFY_SECS,
+    },
+    firmware("1.2.0", "https://example.com/fw.bin", &"a".repeat(64), 1024),
+  ];
+  for command in valid {
+    assert_eq!(command.validate(), Ok(()), "{command:?}");
+  }
+  let invalid = [
+    Command::SetInterval { seconds: 0 },
+    Command::SetInterval {
+      seconds: MAX_INTERVAL_SECS + 1,
+    },
+    Command::StartCalibration { minutes: 0 },
+    Command::SetRZero { r_zero: 0.0 },
+    Command::SetRZero { r_zero: f32::NAN },
+    Command::Identify { seconds: 0 },
+    firmware("", "https://example.com/fw.bin", &"a".repeat(64), 1024),
+    firmware("1.2.0", "ftp://example.com/fw.bin", &"a".repeat(64), 1024),
+    firmware("1.2.0", "https://example.com/fw.bin", &"g".repeat(64), 1024),
+    firmware("1.2.0", "https://example.com/fw.bin", &"a".repeat(63), 1024),
+    firmware("1.2.0", "https://example.com/fw.bin", &"a".repeat(64), 0),
+  ];
+  for command in invalid {
+    assert!(command.validate().is_err(), "{command:?}");
+  }
+}
+
+#[test]
+fn requests_carry_the_command_tag() {
+  let json = serde_json::to_value(Command::SetInterval { seconds: 10 }).unwrap();
+  assert_eq!(
+    json,
+    serde_json::json!({"command": "set_interval", "seconds": 10})
+  );
+  assert_eq!(Command::Reboot.name(), "reboot");
+  assert!(Command::SetRZero { r_zero: 1.0 }.is_calibration());
+  assert!(!Command::Reboot.is_calibration());
+}
+
+#[test]
+fn pending_commands_time_out() {
+  let record = CommandRecord {
+    id: "1".to_string(),
+    device_id: "hat".to_string(),
+    command: Command::Reboot,
+    issued_by: "admin".to_string(),
+    issued_at: 1000,
+    state: CommandState::Pending,
+    acked_at: None,
+    message: None,
+  };
+  assert_eq!(record.state_at(1000), CommandState::Pending);
+  assert_eq!(
+    record.state_at(1000 + ACK_TIMEOUT_SECS),
+    CommandState::Pending
+  );
+  assert_eq!(
+    record.state_at(1001 + ACK_TIMEOUT_SECS),
+    CommandState::TimedOut
+  );
+  let acknowledged = CommandRecord {
+    state: CommandState::Acknowledged,
+    ..record
+  };
+  assert_eq!(
+    acknowledged.state_at(u64::MAX / 2),
+    CommandState::Acknowledged
+  );
+}
+
+#[test]
+fn only_admins_calibrate() {
+  for command in [
+    Command::StartCalibration { minutes: 30 },
+    Command::SetRZero { r_zero: 76.6 },
+  ] {
+    assert_eq!(command.required_role(), Role::Admin, "{command:?}");
+  }
+  for command in [
+    Command::SetInterval { seconds: 10 },
+    Command::Reboot,
+    Command::Identify { seconds: 5 },
+  ] {
+    assert_eq!(command.required_role(), Role::Operator, "{command:?}");
+  }
+}
@@ -0,0 +1,73 @@
+use types::{
+  HatSample,
+  derived::{DerivedMetrics, absolute_humidity, dew_point, heat_index},
+};
+
+fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+  assert!(
+    (actual - expected).abs() <= tolerance,
+    "{actual} is not within {tolerance} of {expected}"
+  );
+}
+
+fn fahrenheit(celsius: f32) -> f32 {
+  celsius * 9.0 / 5.0 + 32.0
+}
+
+fn celsius(fahrenheit: f32) -> f32 {
+  (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+#[test]
+fn dew_point_follows_the_magnus_formula() {
+  assert_near(dew_point(20.0, 50.0), 9.26, 0.01);
+  assert_near(dew_point(25.0, 60.0), 16.69, 0.01);
+  assert_near(dew_point(0.0, 80.0), -3.0, 0.05);
+  // Saturated air is at its dew point.
+  assert_near(dew_point(30.0, 100.0), 30.0, 0.001);
+  assert!(dew_point(20.0, 0.0).is_finite());
+}
+
+#[test]
+fn absolute_humidity_is_the_water_vapour_density() {
+  assert_near(absolute_humidity(20.0, 100.0), 17.3, 0.1);
+  assert_near(absolute_humidity(25.0, 50.0), 11.5, 0.1);
+  assert_eq!(absolute_humidity(25.0, 0.0), 0.0);
+}
+
+#[test]
+fn heat_index_matches_the_noaa_table() {
+  // °F temperature, % relative humidity and °F heat index of the table.
+  for (t, rh, expected) in [
+    (90.0, 60.0, 100.0),
+    (100.0, 40.0, 109.0),
+    (86.0, 90.0, 105.0),
+    (96.0, 50.0, 108.0),
+  ] {
+    let index = heat_index(celsius(t), rh);
+    assert!(
+      (fahrenheit(index) - expected).abs() <= 1.0,
+      "{t} °F at {rh} %: {} °F, the table says {expected} °F",
+      fahrenheit(index)
+    );
+  }
+}
+
+#[test]
+fn heat_index_is_the_temperature_below_27_degrees() {
+  assert_eq!(heat_index(26.9, 90.0), 26.9);
+  assert_eq!(heat_index(-5.0, 50.0), -5.0);
+}
+
+#[test]
+fn derived_metrics_need_temperature_and_humidity() {
+  let sample: HatSample =
+    serde_json::from_str(r#"{"timestamp":1760000000,"temperature":20.0,"humidity":50.0}"#).unwrap();
+  let metrics = DerivedMetrics::of(&sample).unwrap();
+  assert_eq!(metrics.dew_point, dew_point(20.0, 50.0));
+  assert_eq!(metrics.absolute_humidity, absolute_humidity(20.0, 50.0));
+  assert_eq!(metrics.heat_index, 20.0);
+  let without_humidity: HatSample =
+    serde_json::from_str(r#"{"timestamp":1760000000,"temperature":20.0}"#).unwrap();
+  assert_eq!(DerivedMetrics::of(&without_humidity), None);
+}
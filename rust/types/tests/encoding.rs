@@ -0,0 +1,116 @@
+use types::{
+  HatSample,
+  commands::{Command, CommandRequest},
+  encoding::Encoding,
+};
+
+fn sample() -> HatSample {
+  HatSample {
+    device_id: "lab".to_string(),
+    timestamp: 1_760_000_000,
+    temperature: Some(29.5),
+    humidity: Some(52.25),
+    r_zero: Some(41.2),
+    corrected_r_zero: Some(40.8),
+    resistance: Some(12.75),
+    ppm: Some(412.0),
+    corrected_ppm: Some(418.5),
+    ..HatSample::default()
+  }
+}
+
+fn assert_same(a: &HatSample, b: &HatSample) {
+  assert_eq!(
+    serde_json::to_value(a).unwrap(),
+    serde_json::to_value(b).unwrap()
+  );
+}
+
+#[test]
+fn sample_round_trips_through_every_encoding() {
+  for encoding in Encoding::ALL {
+    let bytes = encoding.encode(&sample()).unwrap();
+    let decoded: HatSample = encoding.decode(&bytes).unwrap();
+    assert_same(&decoded, &sample());
+  }
+}
+
+#[test]
+fn json_and_cbor_convert_into_each_other() {
+  let json = Encoding::Json.encode(&sample()).unwrap();
+  let from_json: HatSample = Encoding::Json.decode(&json).unwrap();
+  let cbor = Encoding::Cbor.encode(&from_json).unwrap();
+  let from_cbor: HatSample = Encoding::Cbor.decode(&cbor).unwrap();
+  assert_eq!(Encoding::Json.encode(&from_cbor).unwrap(), json);
+}
+
+#[test]
+fn cbor_is_smaller_than_json() {
+  let json = Encoding::Json.encode(&sample()).unwrap();
+  let cbor = Encoding::Cbor.encode(&sample()).unwrap();
+  assert!(cbor.len() < json.len(), "{} >= {}", cbor.len(), json.len());
+}
+
+#[test]
+fn firmware_payload_without_device_id_decodes_from_cbor() {
+  let mut published = sample();
+  published.device_id.clear();
+  let value = serde_json::to_value(&published).unwrap();
+  let mut map = value.as_object().unwrap().clone();
+  map.remove("device_id");
+  let cbor = Encoding::Cbor.encode(&map).unwrap();
+  let decoded: HatSample = Encoding::Cbor.decode(&cbor).unwrap();
+  assert_same(&decoded, &published);
+}
+
+#[test]
+fn commands_round_trip_through_every_encoding() {
+  let request = CommandRequest {
+    id: "0123456789abcdef".to_string(),
+    issued_at: 1_760_000_000,
+    command: Command::SetInterval { seconds: 30 },
+  };
+  for encoding in Encoding::ALL {
+    let bytes = encoding.encode(&request).unwrap();
+    assert_eq!(encoding.decode::<CommandRequest>(&bytes).unwrap(), request);
+  }
+}
+
+#[test]
+fn detects_the_encoding_of_a_payload() {
+  for encoding in Encoding::ALL {
+    let bytes = encoding.encode(&sample()).unwrap();
+    assert_eq!(Encoding::detect(&bytes), encoding);
+  }
+  assert_eq!(Encoding::detect(b"  \n{\"timestamp\":1}"), Encoding::Json);
+  assert_eq!(Encoding::detect(b""), Encoding::Json);
+}
+
+#[test]
+fn negotiates_by_content_type_and_subprotocol() {
+  assert_eq!(
+    Encoding::from_content_type("application/cbor"),
+    Some(Encoding::Cbor)
+  );
+  assert_eq!(
+    Encoding::from_content_type("Application/JSON; charset=utf-8"),
+    Some(Encoding::Json)
+  );
+  assert_eq!(Encoding::from_content_type("text/plain"), None);
+  for encoding in Encoding::ALL {
+    assert_eq!(
+      Encoding::from_subprotocol(encoding.subprotocol()),
+      Some(encoding)
+    );
+    assert_eq!(encoding.name().parse::<Encoding>(), Ok(encoding));
+  }
+  assert_eq!(Encoding::default(), Encoding::Json);
+}
+
+#[test]
+fn rejects_payloads_of_the_other_encoding() {
+  let json = Encoding::Json.encode(&sample()).unwrap();
+  let cbor = Encoding::Cbor.encode(&sample()).unwrap();
+  assert!(Encoding::Cbor.decode::<HatSample>(&json).is_err());
+  assert!(Encoding::Json.decode::<HatSample>(&cbor).is_err());
+}
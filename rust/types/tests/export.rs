@@ -0,0 +1,83 @@
+use types::{
+  export::{ExportFormat, ExportRequest, column_header, split_column_header},
+  units::{GasUnit, TemperatureUnit, Units},
+};
+
+#[test]
+fn formats_parse_back_from_their_names() {
+  for format in ExportFormat::ALL {
+    assert_eq!(format.to_string().parse::<ExportFormat>(), Ok(format));
+    assert_eq!(format.extension().parse::<ExportFormat>(), Ok(format));
+  }
+  assert!("xlsx".parse::<ExportFormat>().is_err());
+  assert!("CSV".parse::<ExportFormat>().is_err());
+  assert!("".parse::<ExportFormat>().is_err());
+}
+
+#[test]
+fn formats_deserialize_from_the_query() {
+  let request: ExportRequest = serde_json::from_str(r#"{"format":"arrow"}"#).unwrap();
+  assert_eq!(request.format, ExportFormat::Arrow);
+  let request: ExportRequest = serde_json::from_str("{}").unwrap();
+  assert_eq!(request.format, ExportFormat::Csv);
+}
+
+#[test]
+fn href_has_only_the_set_parameters() {
+  assert_eq!(
+    ExportRequest::default().href(),
+    "/api/export?device=&format=csv"
+  );
+  let request = ExportRequest {
+    device: "hat-1".to_string(),
+    from: Some(1_760_000_000),
+    to: Some(1_760_086_399),
+    format: ExportFormat::Arrow,
+    derived: true,
+    units: Units {
+      temperature: TemperatureUnit::Fahrenheit,
+      gas: GasUnit::Ppm,
+    },
+  };
+  assert_eq!(
+    request.href(),
+    "/api/export?device=hat-1&format=arrows&from=1760000000&to=1760086399&derived=true&units=fahrenheit,ppm"
+  );
+  let request = ExportRequest {
+    device: "hat-1".to_string(),
+    format: ExportFormat::Ndjson,
+    to: Some(5),
+    ..ExportRequest::default()
+  };
+  assert_eq!(
+    request.href(),
+    "/api/export?device=hat-1&format=ndjson&to=5"
+  );
+}
+
+#[test]
+fn units_deserialize_from_the_query() {
+  let request: ExportRequest = serde_json::from_str(r#"{"units":"kelvin,mg_per_m3"}"#).unwrap();
+  assert_eq!(
+    request.units,
+    Units {
+      temperature: TemperatureUnit::Kelvin,
+      gas: GasUnit::MilligramsPerCubicMetre,
+    }
+  );
+  let request: ExportRequest = serde_json::from_str("{}").unwrap();
+  assert_eq!(request.units, Units::default());
+  assert!(serde_json::from_str::<ExportRequest>(r#"{"units":"kelvin"}"#).is_err());
+  assert!(serde_json::from_str::<ExportRequest>(r#"{"units":"kelvin,bar"}"#).is_err());
+}
+
+#[test]
+fn column_headers_split_into_name_and_unit() {
+  assert_eq!(column_header("temperature", "°F"), "temperature (°F)");
+  assert_eq!(
+    split_column_header("temperature (°F)"),
+    ("temperature", Some("°F"))
+  );
+  assert_eq!(split_column_header("ppm (mg/m³)"), ("ppm", Some("mg/m³")));
+  assert_eq!(split_column_header("device_id"), ("device_id", None));
+}
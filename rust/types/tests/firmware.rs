@@ -0,0 +1,20 @@
+use types::firmware::validate_version;
+
+#[test]
+fn versions_are_plain_file_names() {
+  for version in ["1.0.0", "2.1.0-rc.1", "v3_beta+build.7", &"9".repeat(32)] {
+    assert_eq!(validate_version(version), Ok(()), "{version}");
+  }
+  for version in [
+    "",
+    &"9".repeat(33),
+    ".hidden",
+    "../1.0.0",
+    "1.0/0",
+    "1.0 0",
+    "1.0.0\\x",
+    "phiên-bản",
+  ] {
+    assert!(validate_version(version).is_err(), "{version}");
+  }
+}
@@ -0,0 +1,178 @@
+use types::{
+  HatSample,
+  encoding::Encoding,
+  measurement::{MetricCatalog, MetricDefinition},
+  schema::{Field, Quality},
+  thresholds::{Metric, Threshold},
+};
+
+const WITH_MEASUREMENTS: &str = r#"{"schema_version":4,"timestamp":1760000000,"temperature":29.5,"measurements":[{"metric":"pm25","unit":"µg/m³","value":12.0},{"metric":"pressure","unit":"hPa","value":null},{"metric":"light","unit":"lx","value":"err"}]}"#;
+
+fn keys(definitions: Vec<MetricDefinition>) -> Vec<String> {
+  definitions
+    .into_iter()
+    .map(|definition| definition.key)
+    .collect()
+}
+
+#[test]
+fn measurements_decode_with_their_quality() {
+  let sample: HatSample = serde_json::from_str(WITH_MEASUREMENTS).unwrap();
+  let pm25 = sample.measurement("pm25").unwrap();
+  assert_eq!((pm25.value, pm25.quality), (Some(12.0), Quality::Good));
+  assert_eq!(pm25.unit, "µg/m³");
+  assert_eq!(
+    sample.measurement("pressure").unwrap().quality,
+    Quality::Missing
+  );
+  let light = sample.measurement("light").unwrap();
+  assert_eq!((light.value, light.quality), (None, Quality::Invalid));
+  assert!(sample.measurement("co").is_none());
+}
+
+#[test]
+fn fields_read_as_measurements() {
+  let sample: HatSample = serde_json::from_str(WITH_MEASUREMENTS).unwrap();
+  let temperature = sample.measurement("temperature").unwrap();
+  assert_eq!(temperature.value, Some(29.5));
+  assert_eq!(temperature.unit, "°C");
+  assert_eq!(
+    sample.measurement("humidity").unwrap().quality,
+    Quality::Missing
+  );
+  assert_eq!(sample.all_measurements().len(), Field::ALL.len() + 3);
+}
+
+#[test]
+fn measurements_survive_a_round_trip() {
+  let sample: HatSample = serde_json::from_str(WITH_MEASUREMENTS).unwrap();
+  for encoding in Encoding::ALL {
+    let bytes = encoding.encode(&sample).unwrap();
+    let decoded: HatSample = encoding.decode(&bytes).unwrap();
+    assert_eq!(decoded.measurements, sample.measurements);
+  }
+  let legacy: HatSample = serde_json::from_str(r#"{"timestamp":1760000000}"#).unwrap();
+  assert!(legacy.measurements.is_empty());
+  assert!(
+    !serde_json::to_string(&legacy)
+      .unwrap()
+      .contains("measurements")
+  );
+}
+
+#[test]
+fn the_catalog_invalidates_readings_out_of_range() {
+  let mut sample: HatSample = serde_json::from_str(
+    r#"{"timestamp":1760000000,"temperature":60.0,"humidity":40.0,"measurements":[{"metric":"pm25","unit":"µg/m³","value":5000.0},{"metric":"co","unit":"ppm","value":9.0}]}"#,
+  )
+  .unwrap();
+  let mut catalog = MetricCatalog::builtin();
+  catalog.extend([MetricDefinition {
+    max: Some(50.0),
+    ..catalog.get("temperature").unwrap().clone()
+  }]);
+  catalog.assess(&mut sample);
+  assert_eq!(sample.temperature, None);
+  assert_eq!(sample.quality(Field::Temperature), Quality::Invalid);
+  assert_eq!(sample.humidity, Some(40.0));
+  let pm25 = sample.measurement("pm25").unwrap();
+  assert_eq!((pm25.value, pm25.quality), (None, Quality::Invalid));
+  // Metrics the catalog doesn't know are kept as reported.
+  assert_eq!(sample.measurement("co").unwrap().value, Some(9.0));
+}
+
+#[test]
+fn catalogs_may_widen_the_ranges_of_fields() {
+  let mut sample: HatSample =
+    serde_json::from_str(r#"{"timestamp":1760000000,"temperature":120.0}"#).unwrap();
+  let mut catalog = MetricCatalog::builtin();
+  catalog.extend([MetricDefinition {
+    max: Some(150.0),
+    ..catalog.get("temperature").unwrap().clone()
+  }]);
+  catalog.assess(&mut sample);
+  assert_eq!(sample.temperature, Some(120.0));
+  MetricCatalog::builtin().assess(&mut sample);
+  assert_eq!(sample.quality(Field::Temperature), Quality::Invalid);
+}
+
+#[test]
+fn catalog_definitions_extend_and_validate() {
+  let mut catalog = MetricCatalog::builtin();
+  assert!(catalog.validate().is_ok());
+  assert_eq!(
+    catalog.get("corrected_ppm").unwrap().threshold_metric(),
+    Some(Metric::Ppm)
+  );
+  assert_eq!(catalog.get("ppm").unwrap().threshold_metric(), None);
+  let definitions: Vec<MetricDefinition> = serde_json::from_str(
+    r#"[{"key":"co","label":"CO","unit":"ppm","max":1000},{"key":"light","label":"Light","unit":"lx","dashboard":false}]"#,
+  )
+  .unwrap();
+  let len = catalog.metrics.len();
+  catalog.extend(definitions);
+  assert_eq!(catalog.metrics.len(), len + 1);
+  assert_eq!(catalog.get("co").unwrap().precision, 1);
+  assert!(!catalog.get("light").unwrap().dashboard);
+  for key in ["", "PM25", "pm 25", "pm2.5"] {
+    catalog.extend([MetricDefinition {
+      key: key.to_string(),
+      ..catalog.get("co").unwrap().clone()
+    }]);
+    assert!(catalog.validate().is_err(), "{key:?}");
+    catalog.metrics.pop();
+  }
+}
+
+#[test]
+fn catalog_thresholds_are_checked() {
+  let pm25: MetricDefinition = serde_json::from_str(
+    r#"{"key":"pm25","label":"PM2.5","unit":"µg/m³","threshold":{"warning":35.0,"critical":55.0}}"#,
+  )
+  .unwrap();
+  assert!(pm25.validate().is_ok());
+  let unordered = MetricDefinition {
+    threshold: Some(Threshold {
+      warning: Some(60.0),
+      ..pm25.threshold.unwrap()
+    }),
+    ..pm25.clone()
+  };
+  assert!(unordered.validate().is_err());
+  // Thresholds of the fixed readings are in the settings.
+  let co2 = MetricDefinition {
+    threshold: pm25.threshold,
+    ..MetricCatalog::builtin()
+      .get("corrected_ppm")
+      .unwrap()
+      .clone()
+  };
+  assert!(co2.validate().is_err());
+  assert!(
+    !serde_json::to_string(MetricCatalog::builtin().get("pm25").unwrap())
+      .unwrap()
+      .contains("threshold")
+  );
+}
+
+#[test]
+fn the_dashboard_shows_other_sensors_once_reported() {
+  let catalog = MetricCatalog::builtin();
+  assert_eq!(
+    keys(catalog.dashboard([])),
+    ["temperature", "humidity", "corrected_ppm"]
+  );
+  let sample: HatSample = serde_json::from_str(WITH_MEASUREMENTS).unwrap();
+  let legacy: HatSample = serde_json::from_str(r#"{"timestamp":1760000000}"#).unwrap();
+  assert_eq!(
+    keys(catalog.dashboard([&sample, &legacy])),
+    [
+      "temperature",
+      "humidity",
+      "corrected_ppm",
+      "pm25",
+      "pressure",
+      "light"
+    ]
+  );
+}
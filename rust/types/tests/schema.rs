@@ -0,0 +1,137 @@
+use types::{
+  HatSample,
+  encoding::Encoding,
+  measurement::MetricCatalog,
+  schema::{Field, Quality, SCHEMA_VERSION},
+};
+
+const LEGACY: &str = r#"{"timestamp":1760000000,"temperature":29.5,"humidity":52.25,"r_zero":41.2,"corrected_r_zero":40.8,"resistance":12.75,"ppm":412.0,"corrected_ppm":418.5}"#;
+
+#[test]
+fn legacy_payloads_decode_in_the_current_version() {
+  let sample: HatSample = serde_json::from_str(LEGACY).unwrap();
+  assert_eq!(sample.schema_version, SCHEMA_VERSION);
+  assert_eq!(sample.timestamp, 1_760_000_000);
+  assert_eq!(sample.corrected_ppm, Some(418.5));
+  assert_eq!(sample.quality(Field::Humidity), Quality::Good);
+}
+
+#[test]
+fn version_2_missing_readings_migrate_to_qualities() {
+  let sample: HatSample = serde_json::from_str(
+    r#"{"schema_version":2,"timestamp":1760000000,"temperature":0.0,"humidity":0.0,"r_zero":1.0,"corrected_r_zero":1.0,"resistance":1.0,"ppm":400.0,"corrected_ppm":400.0,"missing":["temperature","humidity"]}"#,
+  )
+  .unwrap();
+  assert_eq!(sample.temperature, None);
+  assert_eq!(sample.quality(Field::Humidity), Quality::Missing);
+  assert_eq!(sample.ppm, Some(400.0));
+}
+
+#[test]
+fn absent_readings_are_missing_rather_than_zero() {
+  let sample: HatSample =
+    serde_json::from_str(r#"{"schema_version":3,"timestamp":1760000000,"temperature":0.0}"#)
+      .unwrap();
+  assert_eq!(sample.reading(Field::Temperature), Some(0.0));
+  assert_eq!(sample.quality(Field::Temperature), Quality::Good);
+  assert_eq!(sample.reading(Field::Humidity), None);
+  assert_eq!(sample.quality(Field::Humidity), Quality::Missing);
+  assert_eq!(sample.quality.len(), Field::ALL.len() - 1);
+}
+
+#[test]
+fn failed_dht_reads_are_invalid_without_losing_the_gas_readings() {
+  for payload in [
+    r#"{"timestamp":1760000000,"temperature":NaN,"humidity":-999.0,"ppm":412.0}"#,
+    r#"{"timestamp":1760000000,"temperature":"nan","humidity":250,"ppm":412.0}"#,
+  ] {
+    let mut sample: HatSample = Encoding::Json.decode(payload.as_bytes()).unwrap();
+    MetricCatalog::builtin().assess(&mut sample);
+    assert_eq!(sample.temperature, None, "{payload}");
+    assert_eq!(sample.humidity, None, "{payload}");
+    assert_eq!(
+      sample.quality(Field::Humidity),
+      Quality::Invalid,
+      "{payload}"
+    );
+    assert_eq!(sample.ppm, Some(412.0), "{payload}");
+  }
+  let sample: HatSample = Encoding::Json
+    .decode(br#"{"timestamp":1760000000,"temperature":NaN,"humidity":"err"}"#)
+    .unwrap();
+  assert_eq!(sample.quality(Field::Temperature), Quality::Invalid);
+  assert_eq!(sample.quality(Field::Humidity), Quality::Invalid);
+}
+
+#[test]
+fn ranges_are_left_to_the_catalog() {
+  let sample: HatSample = Encoding::Json
+    .decode(br#"{"timestamp":1760000000,"temperature":120.0,"humidity":-999.0}"#)
+    .unwrap();
+  assert_eq!(sample.temperature, Some(120.0));
+  assert_eq!(sample.humidity, Some(-999.0));
+  assert_eq!(sample.quality(Field::Humidity), Quality::Good);
+}
+
+#[test]
+fn nan_inside_strings_is_left_alone() {
+  let sample: HatSample = Encoding::Json
+    .decode(br#"{"device_id":"NaN \"Infinity\"","timestamp":1760000000,"ppm":Infinity}"#)
+    .unwrap();
+  assert_eq!(sample.device_id, r#"NaN "Infinity""#);
+  assert_eq!(sample.quality(Field::Ppm), Quality::Invalid);
+}
+
+#[test]
+fn newer_payloads_keep_the_readings_they_share() {
+  let sample: HatSample = serde_json::from_str(
+    r#"{"schema_version":9,"timestamp":1760000000,"temperature":30.0,"humidity":50.0,"pm25":12.0,"battery":{"volts":3.7}}"#,
+  )
+  .unwrap();
+  assert_eq!(sample.schema_version, SCHEMA_VERSION);
+  assert_eq!(sample.temperature, Some(30.0));
+  assert_eq!(sample.humidity, Some(50.0));
+  assert_eq!(sample.quality(Field::Ppm), Quality::Missing);
+}
+
+#[test]
+fn qualities_survive_a_round_trip() {
+  let sample: HatSample = Encoding::Json
+    .decode(br#"{"timestamp":1760000000,"temperature":30.0,"humidity":NaN}"#)
+    .unwrap();
+  for encoding in Encoding::ALL {
+    let bytes = encoding.encode(&sample).unwrap();
+    let decoded: HatSample = encoding.decode(&bytes).unwrap();
+    assert_eq!(decoded.quality, sample.quality);
+    assert_eq!(decoded.quality(Field::Humidity), Quality::Invalid);
+    assert_eq!(decoded.temperature, Some(30.0));
+  }
+}
+
+#[test]
+fn migrated_samples_serialize_stably() {
+  let sample: HatSample = serde_json::from_str(LEGACY).unwrap();
+  let current = serde_json::to_string(&sample).unwrap();
+  assert!(current.contains(&format!("\"schema_version\":{SCHEMA_VERSION}")));
+  let again: HatSample = serde_json::from_str(&current).unwrap();
+  assert_eq!(serde_json::to_string(&again).unwrap(), current);
+}
+
+#[test]
+fn payloads_that_are_not_samples_fail() {
+  assert!(serde_json::from_str::<HatSample>("[1,2,3]").is_err());
+  assert!(serde_json::from_str::<HatSample>(r#"{"timestamp":"now"}"#).is_err());
+  assert!(Encoding::Json.decode::<HatSample>(b"NaN").is_err());
+}
+
+#[test]
+fn samples_without_any_reading_are_empty() {
+  let empty: HatSample = serde_json::from_str("{}").unwrap();
+  assert!(!empty.has_readings());
+  let sample: HatSample = serde_json::from_str(LEGACY).unwrap();
+  assert!(sample.has_readings());
+  let invalid: HatSample =
+    serde_json::from_str(r#"{"schema_version":3,"timestamp":1760000000,"temperature":"NaN"}"#)
+      .unwrap();
+  assert!(invalid.has_readings(), "{invalid:?}");
+}
@@ -0,0 +1,106 @@
+use types::{
+  settings::{
+    DeviceInfo, MAX_RETENTION_DAYS, NotificationTarget, Settings, validate_device_id,
+    validate_notifications, validate_retention, validate_thresholds,
+  },
+  thresholds::{Metric, Thresholds},
+};
+
+#[test]
+fn device_ids_are_safe_in_topics_and_paths() {
+  for device_id in ["hat", "hat-1", "lab_2.west", &"a".repeat(64)] {
+    assert_eq!(validate_device_id(device_id), Ok(()), "{device_id}");
+  }
+  for device_id in [
+    "",
+    &"a".repeat(65),
+    ".hidden",
+    "..",
+    "a/b",
+    "a b",
+    "hat#",
+    "hạt",
+  ] {
+    assert!(validate_device_id(device_id).is_err(), "{device_id}");
+  }
+}
+
+#[test]
+fn device_names_and_locations_are_bounded() {
+  let info = DeviceInfo {
+    name: "ạ".repeat(64),
+    location: "Tầng 3".to_string(),
+    notes: "x".repeat(1000),
+  };
+  assert_eq!(info.validate(), Ok(()));
+  let errors = DeviceInfo {
+    name: "a".repeat(65),
+    location: "a".repeat(65),
+    ..info
+  }
+  .validate()
+  .unwrap_err();
+  assert!(errors.get("name").is_some());
+  assert!(errors.get("location").is_some());
+}
+
+#[test]
+fn notification_targets_need_a_name_and_a_web_url() {
+  let target = |name: &str, url: &str| NotificationTarget {
+    name: name.to_string(),
+    url: url.to_string(),
+    ..NotificationTarget::default()
+  };
+  assert_eq!(
+    validate_notifications(&[
+      target("ops", "https://example.com/hook"),
+      target("lab", " http://10.0.0.2:8080/alerts "),
+    ]),
+    Ok(())
+  );
+
+  let errors = validate_notifications(&[
+    target("ops", "https://example.com/hook"),
+    target(" ", "ftp://example.com"),
+    target("lab", "https://example.com/a b"),
+  ])
+  .unwrap_err();
+  assert!(errors.get("notifications.0.url").is_none());
+  assert_eq!(errors.get("notifications.1.name"), Some("is required"));
+  assert!(errors.get("notifications.1.url").is_some());
+  assert!(errors.get("notifications.2.url").is_some());
+  assert_eq!(errors.0.len(), 3);
+}
+
+#[test]
+fn retention_is_bounded() {
+  assert_eq!(validate_retention(0), Ok(()));
+  assert_eq!(validate_retention(MAX_RETENTION_DAYS), Ok(()));
+  assert!(
+    validate_retention(MAX_RETENTION_DAYS + 1)
+      .unwrap_err()
+      .get("retention_days")
+      .is_some()
+  );
+}
+
+#[test]
+fn threshold_errors_name_their_metric() {
+  assert_eq!(validate_thresholds(&Thresholds::default()), Ok(()));
+  let mut thresholds = Thresholds::default();
+  thresholds.get_mut(Metric::Humidity).warning = Some(80.0);
+  let errors = validate_thresholds(&thresholds).unwrap_err();
+  assert!(errors.get("thresholds.humidity").is_some());
+  assert!(errors.to_string().starts_with("thresholds.humidity "));
+}
+
+#[test]
+fn missing_settings_take_their_defaults() {
+  let settings: Settings = serde_json::from_str("{}").unwrap();
+  assert_eq!(settings, Settings::default());
+  assert_eq!(settings.retention_days, 90);
+  let target: NotificationTarget =
+    serde_json::from_str(r#"{"name":"ops","url":"https://example.com"}"#).unwrap();
+  assert!(target.enabled);
+  assert_eq!(target.units, None);
+}
@@ -0,0 +1,114 @@
+use types::{
+  HatSample,
+  thresholds::{Band, Metric, Threshold, ThresholdConfig, ThresholdError, Thresholds},
+};
+
+const HUMIDITY: Threshold = Threshold {
+  low: Some(30.0),
+  warning: Some(60.0),
+  critical: Some(70.0),
+};
+
+#[test]
+fn bounds_belong_to_the_band_below_them() {
+  let bands = [
+    (29.9, Band::Low),
+    (30.0, Band::Normal),
+    (60.0, Band::Normal),
+    (60.1, Band::Warning),
+    (70.0, Band::Warning),
+    (70.1, Band::Critical),
+  ];
+  for (value, band) in bands {
+    assert_eq!(HUMIDITY.classify(value), band, "{value}");
+  }
+}
+
+#[test]
+fn unset_bounds_never_match() {
+  assert_eq!(Threshold::default().classify(-1000.0), Band::Normal);
+  assert_eq!(Threshold::default().classify(1000.0), Band::Normal);
+  let critical_only = Threshold {
+    critical: Some(30.0),
+    ..Threshold::default()
+  };
+  assert_eq!(critical_only.classify(31.0), Band::Critical);
+  assert_eq!(critical_only.normal_high(), Some(30.0));
+  assert_eq!(HUMIDITY.normal_high(), Some(60.0));
+}
+
+#[test]
+fn samples_are_classified_on_their_metric() {
+  let thresholds = Thresholds::default();
+  let sample = HatSample {
+    temperature: Some(31.0),
+    ppm: Some(2500.0),
+    corrected_ppm: Some(900.0),
+    ..HatSample::default()
+  };
+  assert_eq!(
+    thresholds.classify(Metric::Temperature, &sample),
+    Some(Band::Critical)
+  );
+  // Thresholds apply to the corrected reading.
+  assert_eq!(
+    thresholds.classify(Metric::Ppm, &sample),
+    Some(Band::Normal)
+  );
+  assert_eq!(thresholds.classify(Metric::Humidity, &sample), None);
+}
+
+#[test]
+fn bounds_must_be_finite_and_increasing() {
+  assert_eq!(HUMIDITY.validate("humidity"), Ok(()));
+  assert_eq!(Threshold::default().validate("humidity"), Ok(()));
+  let gapped = Threshold {
+    warning: None,
+    ..HUMIDITY
+  };
+  assert_eq!(gapped.validate("humidity"), Ok(()));
+
+  let equal = Threshold {
+    warning: Some(70.0),
+    ..HUMIDITY
+  };
+  assert_eq!(
+    equal.validate("humidity"),
+    Err(ThresholdError::Unordered("humidity".to_string()))
+  );
+  let inverted = Threshold {
+    low: Some(80.0),
+    warning: None,
+    ..HUMIDITY
+  };
+  assert_eq!(
+    inverted.validate("humidity"),
+    Err(ThresholdError::Unordered("humidity".to_string()))
+  );
+  let infinite = Threshold {
+    critical: Some(f32::INFINITY),
+    ..HUMIDITY
+  };
+  assert_eq!(
+    infinite.validate("humidity"),
+    Err(ThresholdError::NotFinite("humidity".to_string()))
+  );
+
+  let mut thresholds = Thresholds::default();
+  assert_eq!(thresholds.validate(), Ok(()));
+  thresholds.get_mut(Metric::Ppm).low = Some(f32::NAN);
+  assert_eq!(
+    thresholds.validate(),
+    Err(ThresholdError::NotFinite("ppm".to_string()))
+  );
+}
+
+#[test]
+fn devices_without_overrides_use_the_default() {
+  let mut config = ThresholdConfig::default();
+  let mut strict = Thresholds::default();
+  strict.get_mut(Metric::Ppm).warning = Some(800.0);
+  config.devices.insert("hat-1".to_string(), strict);
+  assert_eq!(config.for_device("hat-1"), &strict);
+  assert_eq!(config.for_device("hat-2"), &Thresholds::default());
+}
@@ -0,0 +1,55 @@
+use types::{
+  HatSample,
+  schema::Field,
+  units::{GasUnit, TemperatureUnit, Units},
+};
+
+fn every_units() -> impl Iterator<Item = Units> {
+  TemperatureUnit::ALL.into_iter().flat_map(|temperature| {
+    GasUnit::ALL
+      .into_iter()
+      .map(move |gas| Units { temperature, gas })
+  })
+}
+
+#[test]
+fn units_parse_back_from_their_names() {
+  for units in every_units() {
+    assert_eq!(units.to_string().parse::<Units>(), Ok(units));
+  }
+  assert_eq!(Units::default().to_string(), "celsius,ppm");
+  assert!("celsius".parse::<Units>().is_err());
+  assert!("ppm,celsius".parse::<Units>().is_err());
+}
+
+#[test]
+fn samples_convert_and_restore() {
+  let sample = HatSample {
+    temperature: Some(25.0),
+    humidity: Some(40.0),
+    resistance: Some(12.5),
+    ppm: Some(400.0),
+    corrected_ppm: None,
+    ..HatSample::default()
+  };
+  let units = Units {
+    temperature: TemperatureUnit::Fahrenheit,
+    gas: GasUnit::MilligramsPerCubicMetre,
+  };
+  let mut converted = units.convert_sample(&sample);
+  assert_eq!(converted.temperature, Some(77.0));
+  assert_eq!(converted.humidity, Some(40.0));
+  assert_eq!(converted.resistance, Some(12.5));
+  assert!((converted.ppm.unwrap() - 720.0).abs() < 0.1);
+  assert_eq!(converted.corrected_ppm, None);
+  assert_eq!(units.field_symbol(Field::CorrectedPpm), "mg/m³");
+  assert_eq!(units.field_symbol(Field::Resistance), "Ω");
+
+  units.restore_sample(&mut converted);
+  for field in Field::ALL {
+    match (converted.reading(field), sample.reading(field)) {
+      (Some(restored), Some(original)) => assert!((restored - original).abs() < 0.01),
+      (restored, original) => assert_eq!(restored, original),
+    }
+  }
+}
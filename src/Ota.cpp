@@ -0,0 +1,97 @@
+#include <Ota.h>
+#include <HTTPClient.h>
+#include <Update.h>
+#include <mbedtls/sha256.h>
+
+// Gives up on a download that stalled for this long.
+unsigned long const downloadTimeoutMs = 10000;
+
+// Streams the image at `url` into the spare OTA partition, hashing it on the
+// way. The partition only becomes the boot partition when both the size and
+// the SHA-256 match, otherwise the running firmware stays.
+bool updateFirmware(const String &url, const String &sha256, size_t size, String &error)
+{
+  HTTPClient http;
+  http.begin(url);
+  int status = http.GET();
+  if (status != 200)
+  {
+    error = "download failed, status " + String(status);
+    http.end();
+    return false;
+  }
+  int length = http.getSize();
+  if (length >= 0 && (size_t)length != size)
+  {
+    error = "size mismatch";
+    http.end();
+    return false;
+  }
+  if (!Update.begin(size))
+  {
+    error = Update.errorString();
+    http.end();
+    return false;
+  }
+
+  mbedtls_sha256_context sha;
+  mbedtls_sha256_init(&sha);
+  mbedtls_sha256_starts(&sha, 0);
+  WiFiClient *stream = http.getStreamPtr();
+  uint8_t buf[1024];
+  size_t written = 0;
+  unsigned long lastData = millis();
+  while (written < size)
+  {
+    size_t available = stream->available();
+    if (available == 0)
+    {
+      if (!http.connected() || millis() - lastData > downloadTimeoutMs)
+      {
+        break;
+      }
+      delay(1);
+      continue;
+    }
+    size_t n = stream->readBytes(buf, std::min(std::min(available, sizeof(buf)), size - written));
+    mbedtls_sha256_update(&sha, buf, n);
+    if (Update.write(buf, n) != n)
+    {
+      break;
+    }
+    written += n;
+    lastData = millis();
+  }
+  http.end();
+  uint8_t digest[32];
+  mbedtls_sha256_finish(&sha, digest);
+  mbedtls_sha256_free(&sha);
+
+  if (written != size)
+  {
+    error = Update.hasError() ? String(Update.errorString()) : String("download incomplete");
+    Update.abort();
+    return false;
+  }
+  String hex;
+  for (int i = 0; i < 32; i++)
+  {
+    char byte[3];
+    sprintf(byte, "%02x", digest[i]);
+    hex += byte;
+  }
+  String expected = sha256;
+  expected.toLowerCase();
+  if (hex != expected)
+  {
+    error = "checksum mismatch";
+    Update.abort();
+    return false;
+  }
+  if (!Update.end())
+  {
+    error = Update.errorString();
+    return false;
+  }
+  return true;
+}
@@ -0,0 +1,8 @@
+#ifndef OTA_H
+#define OTA_H
+
+#include <Arduino.h>
+
+bool updateFirmware(const String &url, const String &sha256, size_t size, String &error);
+
+#endif
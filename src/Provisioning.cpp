@@ -0,0 +1,186 @@
+#include <Provisioning.h>
+#include <Preferences.h>
+#include <HTTPClient.h>
+#include <ArduinoJson.h>
+
+Preferences preferences;
+String setupLine;
+
+void loadConfig(DeviceConfig &config)
+{
+  preferences.begin("hat", true);
+  config.ssid = preferences.getString("ssid", "");
+  config.passphrase = preferences.getString("passphrase", "");
+  config.server = preferences.getString("server", "");
+  config.mqttHost = preferences.getString("mqtt_host", "");
+  config.mqttPort = preferences.getUShort("mqtt_port", 1883);
+  config.claimCode = preferences.getString("claim_code", "");
+  config.deviceId = preferences.getString("device_id", "");
+  config.token = preferences.getString("token", "");
+  config.topic = preferences.getString("topic", "");
+  preferences.end();
+}
+
+void saveConfig(const DeviceConfig &config)
+{
+  preferences.begin("hat", false);
+  preferences.putString("ssid", config.ssid);
+  preferences.putString("passphrase", config.passphrase);
+  preferences.putString("server", config.server);
+  preferences.putString("mqtt_host", config.mqttHost);
+  preferences.putUShort("mqtt_port", config.mqttPort);
+  preferences.putString("claim_code", config.claimCode);
+  preferences.putString("device_id", config.deviceId);
+  preferences.putString("token", config.token);
+  preferences.putString("topic", config.topic);
+  preferences.end();
+}
+
+bool isClaimed(const DeviceConfig &config)
+{
+  return config.deviceId.length() > 0 && config.token.length() > 0;
+}
+
+// Trades the pending claim code for the device's id, token and topic.
+bool claimDevice(DeviceConfig &config)
+{
+  if (config.claimCode.length() == 0 || config.server.length() == 0)
+  {
+    return false;
+  }
+  Serial.print("Claim " + config.claimCode + "...");
+  JsonDocument body;
+  body["code"] = config.claimCode;
+  String buf;
+  serializeJson(body, buf);
+
+  HTTPClient http;
+  http.begin(config.server + "/api/provision/claim");
+  http.addHeader("Content-Type", "application/json");
+  int status = http.POST(buf);
+  String response = http.getString();
+  http.end();
+
+  if (status != 200)
+  {
+    Serial.printf("Loi status=%d %s\n", status, response.c_str());
+    // A code the server refused won't work next time either, only keep
+    // it when the server was unreachable or asked to slow down.
+    if (status >= 400 && status < 500 && status != 429)
+    {
+      config.claimCode = "";
+      saveConfig(config);
+    }
+    return false;
+  }
+  JsonDocument credentials;
+  if (deserializeJson(credentials, response))
+  {
+    Serial.println("Loi: khong doc duoc thong tin thiet bi");
+    return false;
+  }
+  config.deviceId = credentials["device_id"] | "";
+  config.token = credentials["token"] | "";
+  config.topic = credentials["topic"] | "";
+  config.claimCode = "";
+  saveConfig(config);
+  Serial.println("OK, device " + config.deviceId);
+  return true;
+}
+
+void printConfig(const DeviceConfig &config)
+{
+  Serial.println("wifi   " + config.ssid);
+  Serial.println("server " + config.server);
+  Serial.println("mqtt   " + config.mqttHost + " " + String(config.mqttPort));
+  if (isClaimed(config))
+  {
+    Serial.println("device " + config.deviceId + " on " + config.topic);
+  }
+  else if (config.claimCode.length() > 0)
+  {
+    Serial.println("claim  " + config.claimCode + " (pending)");
+  }
+  else
+  {
+    Serial.println("device not claimed");
+  }
+}
+
+// Reads setup commands from the serial monitor, one per line:
+//   wifi <ssid>, pass <passphrase>, server <url>, mqtt <host> [port],
+//   claim <code>, forget, show
+// Returns true when the config changed and was saved.
+bool readSetupCommand(DeviceConfig &config)
+{
+  while (Serial.available() > 0)
+  {
+    char c = Serial.read();
+    if (c != '\n')
+    {
+      setupLine += c;
+      continue;
+    }
+    String line = setupLine;
+    setupLine = "";
+    line.trim();
+    int space = line.indexOf(' ');
+    String command = space < 0 ? line : line.substring(0, space);
+    String value = space < 0 ? "" : line.substring(space + 1);
+    value.trim();
+
+    if (command == "wifi" && value.length() > 0)
+    {
+      config.ssid = value;
+    }
+    else if (command == "pass")
+    {
+      config.passphrase = value;
+    }
+    else if (command == "server" && value.length() > 0)
+    {
+      if (value.endsWith("/"))
+      {
+        value.remove(value.length() - 1);
+      }
+      config.server = value;
+    }
+    else if (command == "mqtt" && value.length() > 0)
+    {
+      int port = value.indexOf(' ');
+      config.mqttHost = port < 0 ? value : value.substring(0, port);
+      config.mqttPort = port < 0 ? 1883 : value.substring(port + 1).toInt();
+    }
+    else if (command == "claim" && value.length() > 0)
+    {
+      config.claimCode = value;
+      config.deviceId = "";
+      config.token = "";
+      config.topic = "";
+    }
+    else if (command == "forget")
+    {
+      config.claimCode = "";
+      config.deviceId = "";
+      config.token = "";
+      config.topic = "";
+    }
+    else if (command == "show")
+    {
+      printConfig(config);
+      continue;
+    }
+    else
+    {
+      if (line.length() > 0)
+      {
+        Serial.println("!! Lenh khong hop le: " + line);
+      }
+      continue;
+    }
+    saveConfig(config);
+    Serial.println("Saved");
+    return true;
+  }
+  return false;
+}
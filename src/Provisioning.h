@@ -0,0 +1,29 @@
+#ifndef PROVISIONING_H
+#define PROVISIONING_H
+
+#include <Arduino.h>
+
+// What the hat needs to reach the network and the server, kept in flash.
+struct DeviceConfig
+{
+  String ssid;
+  String passphrase;
+  // Base URL of the web server, e.g. http://192.168.137.1:3000.
+  String server;
+  String mqttHost;
+  uint16_t mqttPort;
+  // Set by the `claim` command, cleared once the server took the code.
+  String claimCode;
+  // What the server issued for the claim code.
+  String deviceId;
+  String token;
+  String topic;
+};
+
+void loadConfig(DeviceConfig &);
+void saveConfig(const DeviceConfig &);
+bool isClaimed(const DeviceConfig &);
+bool claimDevice(DeviceConfig &);
+bool readSetupCommand(DeviceConfig &);
+
+#endif
@@ -6,23 +6,37 @@
 #include <time.h>
 #include <PubSubClient.h>
 #include <ArduinoJson.h>
+#include <Provisioning.h>
+#include <Ota.h>
+
+// Reported to the server on every connect, set it in platformio.ini.
+#ifndef FIRMWARE_VERSION
+#define FIRMWARE_VERSION "dev"
+#endif
 
 #define DHTPIN 4
 #define DHTTYPE DHT11
 
 #define PIN_MQ135 34
+#define PIN_LED 2
 
 MQ135 mq135_sensor(PIN_MQ135);
 
-String ssid = "iot_network";
-String passphrase = "@Iotnet914";
+DeviceConfig config;
 
 WiFiClient espClient;
 PubSubClient client(espClient);
 
-const char *mqttServer = "192.168.137.1";
-uint16_t const mqttPort = 1883;
+// Until it's claimed the hat publishes on the bare topic, which the server
+// files under "hat".
 const char *mqttTopic = "iot/hat";
+String dataTopic;
+String cmdTopic;
+String ackTopic;
+String firmwareTopic;
+
+Ticker blinker;
+int blinksLeft = 0;
 
 const char *ntpServer = "time.google.com";
 long const gmtOffset_sec = 0;
@@ -35,6 +49,9 @@ void printAirQuality(float rZero, float correctedRZero, float resistance, float
 unsigned long getEpochTime();
 void reconnectMQTT();
 void callback(char* topic, byte* payload, unsigned int length);
+void handleCommand(byte* payload, unsigned int length);
+void sendAck(const String &id, bool ok, const char *message);
+void blink();
 
 void setup()
 {
@@ -42,18 +59,43 @@ void setup()
 
   analogReadResolution(10);
   analogSetAttenuation(ADC_11db);
+  pinMode(PIN_LED, OUTPUT);
+  loadConfig(config);
+  if (config.ssid.length() == 0 || config.mqttHost.length() == 0)
+  {
+    Serial.println("Chua cau hinh, nhap: wifi <ssid>, pass <passphrase>, server <url>, mqtt <host> [port], claim <code>");
+    while (config.ssid.length() == 0 || config.mqttHost.length() == 0)
+    {
+      readSetupCommand(config);
+      delay(100);
+    }
+  }
   setupWiFi();
+  if (!isClaimed(config))
+  {
+    claimDevice(config);
+  }
+  dataTopic = isClaimed(config) ? config.topic : String(mqttTopic);
+  String deviceTopic = isClaimed(config) ? config.topic : String(mqttTopic) + "/hat";
+  cmdTopic = deviceTopic + "/cmd";
+  ackTopic = cmdTopic + "/ack";
+  firmwareTopic = deviceTopic + "/firmware";
   setupDht11(DHTPIN);
   initTask();
   taskEnabled = true;
-  client.setServer(mqttServer, mqttPort);
+  client.setServer(config.mqttHost.c_str(), config.mqttPort);
   client.setCallback(callback);
   configTime(gmtOffset_sec, daylightOffset_sec, ntpServer);
 }
 
 void loop()
 {
-  yield();
+  // Settings take effect on the next boot.
+  if (readSetupCommand(config))
+  {
+    ESP.restart();
+  }
+  delay(100);
 }
 
 void doTask()
@@ -93,13 +135,12 @@ void doTask()
     String buf;
     serializeJson(doc, buf);
     Serial.println(buf);
-    if (client.publish(mqttTopic, buf.c_str())) {
+    if (client.publish(dataTopic.c_str(), buf.c_str())) {
       Serial.print(">> Gui MQTT: ");
       Serial.println(buf);
     } else {
       Serial.println("!! Gui MQTT THAT BAI");
     }
-    client.subscribe(mqttTopic);
   }
   else
   {
@@ -162,9 +203,14 @@ void setupWiFi()
   IPAddress dns(8, 8, 8, 8);
   WiFi.config(INADDR_NONE, INADDR_NONE, INADDR_NONE, dns);
 
-  WiFi.begin(ssid, passphrase, 6);
+  WiFi.begin(config.ssid.c_str(), config.passphrase.c_str());
   while (WiFi.status() != WL_CONNECTED)
   {
+    // A wrong network can be fixed from the serial monitor.
+    if (readSetupCommand(config))
+    {
+      ESP.restart();
+    }
     delay(100);
     Serial.print(".");
   }
@@ -211,12 +257,34 @@ unsigned long getEpochTime() {
 void reconnectMQTT() {
   if (!client.connected()) {
     Serial.print("Ket noi MQTT...");
-    String clientId = "ESP32-" + String(random(0xffff), HEX);
-    if (client.connect(clientId.c_str())) {
+    if (!isClaimed(config) && claimDevice(config)) {
+      // The claimed topics only take effect after a restart.
+      ESP.restart();
+    }
+    bool connected;
+    if (isClaimed(config)) {
+      // The broker checks the token with the server, see /api/mqtt/user.
+      connected = client.connect(config.deviceId.c_str(), config.deviceId.c_str(), config.token.c_str());
+    } else {
+      String clientId = "ESP32-" + String(random(0xffff), HEX);
+      connected = client.connect(clientId.c_str());
+    }
+    if (connected) {
       Serial.println("OK");
+      client.subscribe(cmdTopic.c_str(), 1);
+      JsonDocument report;
+      report["version"] = FIRMWARE_VERSION;
+      String buf;
+      serializeJson(report, buf);
+      if (!client.publish(firmwareTopic.c_str(), buf.c_str(), true)) {
+        Serial.println("!! Gui phien ban THAT BAI");
+      }
     } else {
       Serial.print("Loi rc=");
       Serial.println(client.state());
+      if (client.state() == MQTT_CONNECT_BAD_CREDENTIALS || client.state() == MQTT_CONNECT_UNAUTHORIZED) {
+        Serial.println("Token bi tu choi, thiet bi da bi thu hoi? Nhap forget roi claim <code>");
+      }
     }
   }
 }
@@ -230,4 +298,82 @@ void callback(char* topic, byte* payload, unsigned int length) {
     Serial.print((char)payload[i]);
   }
   Serial.println();
+  if (cmdTopic == topic) {
+    handleCommand(payload, length);
+  }
+}
+
+void handleCommand(byte* payload, unsigned int length) {
+  JsonDocument doc;
+  if (deserializeJson(doc, payload, length)) {
+    Serial.println("!! Lenh khong hop le");
+    return;
+  }
+  // Publishing reuses the buffer the payload is in, copy what's needed first.
+  String id = doc["id"] | "";
+  String command = doc["command"] | "";
+  if (command == "set_interval") {
+    ticker.detach();
+    ticker.attach(doc["seconds"] | 5, triggerTask);
+    sendAck(id, true, nullptr);
+  } else if (command == "set_r_zero") {
+    mq135_sensor = MQ135(PIN_MQ135, doc["r_zero"] | 76.63f);
+    sendAck(id, true, nullptr);
+  } else if (command == "identify") {
+    blinksLeft = (doc["seconds"] | 10) * 4;
+    blinker.attach_ms(250, blink);
+    sendAck(id, true, nullptr);
+  } else if (command == "reboot") {
+    sendAck(id, true, nullptr);
+    client.loop();
+    delay(100);
+    ESP.restart();
+  } else if (command == "update_firmware") {
+    String version = doc["version"] | "";
+    String url = doc["url"] | "";
+    String sha256 = doc["sha256"] | "";
+    size_t size = doc["size"] | 0;
+    Serial.println("Cap nhat firmware " + version + "...");
+    String error;
+    bool ok = updateFirmware(url, sha256, size, error);
+    // The download may have outlasted the MQTT keep-alive.
+    if (!client.connected()) {
+      reconnectMQTT();
+    }
+    if (!ok) {
+      Serial.println("!! Cap nhat THAT BAI: " + error);
+      sendAck(id, false, error.c_str());
+      return;
+    }
+    sendAck(id, true, nullptr);
+    client.loop();
+    delay(100);
+    ESP.restart();
+  } else {
+    // start_calibration isn't supported yet.
+    sendAck(id, false, "unsupported command");
+  }
+}
+
+void sendAck(const String &id, bool ok, const char *message) {
+  JsonDocument ack;
+  ack["id"] = id;
+  ack["ok"] = ok;
+  if (message != nullptr) {
+    ack["message"] = message;
+  }
+  String buf;
+  serializeJson(ack, buf);
+  if (!client.publish(ackTopic.c_str(), buf.c_str())) {
+    Serial.println("!! Gui ack THAT BAI");
+  }
+}
+
+void blink() {
+  if (--blinksLeft <= 0) {
+    blinker.detach();
+    digitalWrite(PIN_LED, LOW);
+    return;
+  }
+  digitalWrite(PIN_LED, !digitalRead(PIN_LED));
 }